@@ -0,0 +1,42 @@
+use serde::{Deserialize, Serialize};
+
+/// Defines how many copies of a routing have to be acknowledged before the Kernel acknowledges the incoming CloudEvent.
+/// The router sets it in `RoutingResult.args`, it only matters for delivery guarantees that require an acknowledgment.
+///
+/// A copy that failed does not fail the routing as long as the policy can still be satisfied by the other copies,
+/// it is sent to the dead-letter port (if there is one) without waiting for its acknowledgment.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default, Serialize, Deserialize)]
+pub enum AckPolicy {
+    /// every copy has to be acknowledged, the default
+    #[default]
+    All,
+    /// one acknowledged copy is enough, e.g. for redundant outputs
+    Any,
+    /// at least the given number of copies have to be acknowledged, e.g. a majority; all of them if fewer were routed
+    AtLeast(usize),
+}
+
+impl AckPolicy {
+    /// the number of acknowledged copies that satisfy the policy for a routing with the given number of copies
+    pub fn required(&self, copies: usize) -> usize {
+        match self {
+            AckPolicy::All => copies,
+            AckPolicy::Any => copies.min(1),
+            AckPolicy::AtLeast(n) => copies.min(*n),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn required_should_not_exceed_the_copies() {
+        assert_eq!(AckPolicy::All.required(3), 3);
+        assert_eq!(AckPolicy::Any.required(3), 1);
+        assert_eq!(AckPolicy::AtLeast(2).required(3), 2);
+        assert_eq!(AckPolicy::AtLeast(5).required(3), 3);
+        assert_eq!(AckPolicy::Any.required(0), 0);
+    }
+}
@@ -0,0 +1,167 @@
+use anyhow::{Context, Result};
+use std::fs::{self, File, OpenOptions};
+use std::io::{BufRead, BufReader, Write};
+use std::path::{Path, PathBuf};
+
+/// escapes the separators of the lines
+fn escape(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('\t', "\\t")
+        .replace('\n', "\\n")
+        .replace('\r', "\\r")
+}
+
+fn unescape(value: &str) -> String {
+    let mut result = String::with_capacity(value.len());
+    let mut chars = value.chars();
+    while let Some(c) = chars.next() {
+        if c != '\\' {
+            result.push(c);
+            continue;
+        }
+        match chars.next() {
+            Some('t') => result.push('\t'),
+            Some('n') => result.push('\n'),
+            Some('r') => result.push('\r'),
+            Some(other) => result.push(other),
+            None => result.push('\\'),
+        }
+    }
+    result
+}
+
+/// the line of a record with the escaped fields separated by tabs
+pub(super) fn to_line(fields: &[String]) -> String {
+    let fields: Vec<String> = fields.iter().map(|field| escape(field)).collect();
+    format!("{}\n", fields.join("\t"))
+}
+
+/// An append-only file of records, each record is a line of tab separated fields.
+///
+/// It is used by the idempotency store, the journal and the spools of the Kernel.
+/// The owner replays the records when the log is opened and compacts it from time to time,
+/// i.e. it replaces the file with the records that are still relevant.
+pub(super) struct AppendLog {
+    path: PathBuf,
+    /// sync every record to the disk
+    sync: bool,
+    file: File,
+    /// number of records in the file
+    lines: usize,
+}
+
+impl AppendLog {
+    /// Opens the log, it is created if it does not exist.
+    /// `replay` is called with the fields of every record, a record it fails for is ignored.
+    pub fn open(
+        path: PathBuf,
+        sync: bool,
+        mut replay: impl FnMut(&[String]) -> Result<()>,
+    ) -> Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(&path)
+            .with_context(|| format!("failed to open {}", path.display()))?;
+        let reader =
+            File::open(&path).with_context(|| format!("failed to open {}", path.display()))?;
+        let mut lines = 0;
+        for (number, line) in BufReader::new(reader).lines().enumerate() {
+            let line = line.with_context(|| format!("failed to read {}", path.display()))?;
+            let fields: Vec<String> = line.split('\t').map(unescape).collect();
+            if let Err(e) = replay(&fields) {
+                warn!(
+                    "line {} of {} is ignored: {}",
+                    number + 1,
+                    path.display(),
+                    e
+                );
+            }
+            lines += 1;
+        }
+        Ok(AppendLog {
+            path,
+            sync,
+            file,
+            lines,
+        })
+    }
+
+    pub fn path(&self) -> &Path {
+        &self.path
+    }
+
+    /// number of records in the file, including the ones that are not relevant anymore
+    pub fn lines(&self) -> usize {
+        self.lines
+    }
+
+    /// Appends a record.
+    pub fn append(&mut self, fields: &[String]) -> Result<()> {
+        self.file.write_all(to_line(fields).as_bytes())?;
+        if self.sync {
+            self.file.sync_data()?;
+        }
+        self.lines += 1;
+        Ok(())
+    }
+
+    /// Replaces the file with the given records.
+    pub fn compact(&mut self, records: impl IntoIterator<Item = Vec<String>>) -> Result<()> {
+        let temp_path = self.path.with_extension("tmp");
+        let mut temp = File::create(&temp_path)
+            .with_context(|| format!("failed to create {}", temp_path.display()))?;
+        let mut lines = 0;
+        for fields in records {
+            temp.write_all(to_line(&fields).as_bytes())?;
+            lines += 1;
+        }
+        temp.sync_all()?;
+        fs::rename(&temp_path, &self.path)
+            .with_context(|| format!("failed to replace {}", self.path.display()))?;
+        self.file = OpenOptions::new()
+            .append(true)
+            .open(&self.path)
+            .with_context(|| format!("failed to open {}", self.path.display()))?;
+        self.lines = lines;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn fields(values: &[&str]) -> Vec<String> {
+        values.iter().map(|value| value.to_string()).collect()
+    }
+
+    #[test]
+    fn records_should_be_replayed_after_compaction() -> Result<()> {
+        let path =
+            std::env::temp_dir().join(format!("cerk_append_log_test_{}.log", std::process::id()));
+        let tricky = fields(&["a\tb", "c\\n\nd\r", "\\"]);
+        {
+            let mut log = AppendLog::open(path.clone(), false, |_| Ok(()))?;
+            log.append(&fields(&["1"]))?;
+            log.append(&tricky)?;
+            log.append(&fields(&["3"]))?;
+            log.compact(vec![tricky.clone(), fields(&["4"])])?;
+            log.append(&fields(&["invalid"]))?;
+            log.append(&fields(&["5", ""]))?;
+        }
+        let mut replayed = Vec::new();
+        let log = AppendLog::open(path.clone(), false, |fields| {
+            if fields[0] == "invalid" {
+                bail!("invalid record");
+            }
+            replayed.push(fields.to_vec());
+            Ok(())
+        })?;
+        fs::remove_file(&path)?;
+        assert_eq!(replayed, vec![tricky, fields(&["4"]), fields(&["5", ""])]);
+        assert_eq!(log.lines(), 4);
+        Ok(())
+    }
+}
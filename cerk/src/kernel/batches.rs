@@ -0,0 +1,220 @@
+use crate::kernel::broker_event::OutgoingCloudEvent;
+use crate::kernel::{BatchOptions, BrokerEvent};
+use crate::runtime::channel::{BoxedSender, Sender};
+use crate::runtime::InternalServerId;
+use std::collections::HashMap;
+use std::mem;
+use std::ops::Add;
+use std::sync::{Arc, Mutex};
+use std::time::SystemTime;
+
+/// the CloudEvents that wait to be sent to one port
+struct Batch {
+    options: BatchOptions,
+    outbox: BoxedSender,
+    events: Vec<OutgoingCloudEvent>,
+    /// time at which the batch is sent even if it is not full
+    deadline: Option<SystemTime>,
+}
+
+impl Batch {
+    fn flush(&mut self) {
+        self.deadline = None;
+        if !self.events.is_empty() {
+            let events = mem::take(&mut self.events);
+            self.outbox
+                .send(BrokerEvent::OutgoingCloudEventBatch(events));
+        }
+    }
+}
+
+/// Collects the `OutgoingCloudEvent`s per destination, see `PortOptions.batch`.
+/// The outboxes of the ports with batches write to it, the Kernel sends the batches whose deadline passed.
+#[derive(Clone, Default)]
+pub(crate) struct Batches {
+    batches: Arc<Mutex<HashMap<InternalServerId, Batch>>>,
+}
+
+impl Batches {
+    /// Returns an outbox that collects the `OutgoingCloudEvent`s into batches.
+    /// Without options, the outbox is returned unchanged.
+    pub(crate) fn wrap(
+        &self,
+        id: &str,
+        outbox: BoxedSender,
+        options: Option<&BatchOptions>,
+    ) -> BoxedSender {
+        let options = match options {
+            Some(options) => options.clone(),
+            None => return outbox,
+        };
+        // a restarted port gets a new outbox, the CloudEvents of the old one already failed
+        self.batches.lock().unwrap().insert(
+            id.to_string(),
+            Batch {
+                options,
+                outbox,
+                events: Vec::new(),
+                deadline: None,
+            },
+        );
+        Box::new(BatchingSender {
+            id: id.to_string(),
+            batches: self.clone(),
+        })
+    }
+
+    /// forgets the batch of an exited port
+    pub(crate) fn remove(&self, id: &str) {
+        self.batches.lock().unwrap().remove(id);
+    }
+
+    /// sends all batches whose deadline passed
+    pub(crate) fn flush_due(&self) {
+        let now = SystemTime::now();
+        for batch in self.batches.lock().unwrap().values_mut() {
+            if batch.deadline.is_some_and(|deadline| deadline <= now) {
+                batch.flush();
+            }
+        }
+    }
+
+    /// the earliest deadline of all batches
+    pub(crate) fn next_deadline(&self) -> Option<SystemTime> {
+        self.batches
+            .lock()
+            .unwrap()
+            .values()
+            .filter_map(|batch| batch.deadline)
+            .min()
+    }
+}
+
+/// Outbox of a port with batches, see `Batches::wrap`.
+struct BatchingSender {
+    id: InternalServerId,
+    batches: Batches,
+}
+
+impl Sender for BatchingSender {
+    fn send(&self, event: BrokerEvent) {
+        let mut batches = self.batches.batches.lock().unwrap();
+        let batch = match batches.get_mut(&self.id) {
+            Some(batch) => batch,
+            None => {
+                warn!("{} has no batch anymore, {} dropped", self.id, event);
+                return;
+            }
+        };
+        match event {
+            BrokerEvent::OutgoingCloudEvent(event) => {
+                if batch.events.is_empty() {
+                    batch.deadline = Some(SystemTime::now().add(batch.options.max_delay));
+                }
+                batch.events.push(event);
+                if batch.events.len() >= batch.options.max_size {
+                    batch.flush();
+                }
+            }
+            event => {
+                // keep the order, e.g., `Shutdown` has to arrive after the CloudEvents
+                batch.flush();
+                batch.outbox.send(event);
+            }
+        }
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Sender + Send> {
+        Box::new(BatchingSender {
+            id: self.id.clone(),
+            batches: self.batches.clone(),
+        })
+    }
+
+    fn queue_length(&self) -> Option<usize> {
+        let batches = self.batches.batches.lock().unwrap();
+        let batch = batches.get(&self.id)?;
+        batch
+            .outbox
+            .queue_length()
+            .map(|length| length + batch.events.len())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::CloudEventRoutingArgs;
+    use crate::runtime::channel::RecordingSender;
+    use cloudevents::Event;
+    use std::time::Duration;
+
+    fn outgoing(routing_id: &str) -> BrokerEvent {
+        BrokerEvent::OutgoingCloudEvent(OutgoingCloudEvent {
+            routing_id: routing_id.to_string(),
+            delivery_id: 0,
+            cloud_event: Arc::new(Event::default()),
+            destination_id: "port".to_string(),
+            args: CloudEventRoutingArgs::default(),
+        })
+    }
+
+    fn wrap(batches: &Batches, port: &RecordingSender, max_delay: Duration) -> BoxedSender {
+        batches.wrap(
+            "port",
+            port.clone_boxed(),
+            Some(&BatchOptions {
+                max_size: 2,
+                max_delay,
+            }),
+        )
+    }
+
+    #[test]
+    fn full_batch_should_be_sent() {
+        let batches = Batches::default();
+        let port = RecordingSender::default();
+        let outbox = wrap(&batches, &port, Duration::from_secs(60));
+
+        outbox.send(outgoing("1"));
+        assert!(port.events().is_empty());
+        assert!(batches.next_deadline().is_some());
+        outbox.send(outgoing("2"));
+        assert_eq!(
+            port.events(),
+            vec!["OutgoingCloudEventBatch destination_id=port size=2"]
+        );
+        assert_eq!(batches.next_deadline(), None);
+    }
+
+    #[test]
+    fn batch_should_be_sent_after_its_deadline() {
+        let batches = Batches::default();
+        let port = RecordingSender::default();
+        let outbox = wrap(&batches, &port, Duration::from_secs(0));
+
+        outbox.send(outgoing("1"));
+        batches.flush_due();
+        assert_eq!(
+            port.events(),
+            vec!["OutgoingCloudEventBatch destination_id=port size=1"]
+        );
+    }
+
+    #[test]
+    fn other_events_should_be_sent_after_the_batch() {
+        let batches = Batches::default();
+        let port = RecordingSender::default();
+        let outbox = wrap(&batches, &port, Duration::from_secs(60));
+
+        outbox.send(outgoing("1"));
+        outbox.send(BrokerEvent::Shutdown);
+        assert_eq!(
+            port.events(),
+            vec![
+                "OutgoingCloudEventBatch destination_id=port size=1",
+                "Shutdown"
+            ]
+        );
+    }
+}
@@ -1,6 +1,8 @@
 pub use super::kernel_start::kernel_start;
+use super::kernel_start::STARTUP_FAILED_EXIT_CODE;
 use super::start_options::StartOptions;
 use crate::runtime::channel::{BoxedReceiver, BoxedSender};
+use anyhow::Result;
 
 /// Function signature for the Kernel.
 ///
@@ -10,17 +12,31 @@ use crate::runtime::channel::{BoxedReceiver, BoxedSender};
 /// * `inbox` - The inbox channel of the Kernel, every component should send there events for the Kernel to this channel.
 /// * `sender_to_scheduler` - The outbox channel for messages for the Scheduler.
 ///
-pub type KernelFn =
-    fn(start_options: StartOptions, inbox: BoxedReceiver, sender_to_scheduler: BoxedSender);
+/// The Kernel fails if the router did not start.
+pub type KernelFn = fn(
+    start_options: StartOptions,
+    inbox: BoxedReceiver,
+    sender_to_scheduler: BoxedSender,
+) -> Result<()>;
 
 /// The `bootstrap` function is the entrance point of the CERK router.
 /// This function starts the Kernel with the help of the scheduler.
 /// Later, the Kernel starts all components, and the router starts working.
 ///
+/// The function returns after the router was shut down with a `BrokerEvent::Shutdown`.
+/// If the components do not start within `KernelOptions.startup_timeout`, the router is shut down
+/// and the process exits with `STARTUP_FAILED_EXIT_CODE` once the Scheduler returned.
+///
 /// # Arguments
 ///
 /// * `start_options` - The start options defining the components and the behavior of the router.
 ///
 pub fn bootstrap(start_options: StartOptions) {
-    (start_options.scheduler)(start_options, kernel_start);
+    if let Err(e) = (start_options.scheduler)(start_options, kernel_start) {
+        error!(
+            "the router did not start, exit with code {}: {:#}",
+            STARTUP_FAILED_EXIT_CODE, e
+        );
+        std::process::exit(STARTUP_FAILED_EXIT_CODE);
+    }
 }
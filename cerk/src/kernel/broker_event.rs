@@ -1,11 +1,14 @@
 use super::Config;
+use crate::kernel::metrics::{Metric, MetricUpdate};
 use crate::kernel::outgoing_processing_result::ProcessingResult;
 use crate::kernel::CloudEventRoutingArgs;
 use crate::runtime::channel::BoxedSender;
 use crate::runtime::{InternalServerFnRef, InternalServerId};
+use anyhow::Result;
 use cloudevents::Event;
 use serde::Serialize;
 use std::fmt;
+use std::sync::Arc;
 
 /// the unique identifier of the CloudEvent routing attempt
 /// this id is generated on a receiver per CloudEvent and routing attempt.
@@ -15,8 +18,21 @@ use std::fmt;
 ///
 /// This id should not be tried to be interpreted or to be pared.
 /// The generation is not defined globally and can be done differently by every port implementation.
+/// The Kernel scopes the id by the `incoming_id`, so it only has to be unique within the port that received the CloudEvent.
+/// `RoutingIdGenerator` generates such ids.
 pub type CloudEventMessageRoutingId = String;
 
+/// A CloudEvent that is shared between the broker events, e.g., by all copies of a routing.
+/// Use `Arc::make_mut` to change it, the CloudEvent is only copied if it is still shared.
+pub type SharedCloudEvent = Arc<Event>;
+
+/// Identifies a single copy of a CloudEvent within a routing attempt (`CloudEventMessageRoutingId`).
+///
+/// A router could send multiple copies of a CloudEvent to the same port (e.g., differently transformed).
+/// The Kernel assigns the id before the copy is sent to the port, the port echos it in `OutgoingCloudEventProcessed`.
+/// The id is unique among all copies that wait for an acknowledgment, so the Kernel finds the routing of a copy by it.
+pub type CloudEventDeliveryId = u32;
+
 /// Representation of all events which are exchanged between the components
 pub enum BrokerEvent {
     /// The ScheduleInternalServer event tells the Scheduler to schedule a new internal server.
@@ -42,10 +58,17 @@ pub enum BrokerEvent {
 
     /// The Init event indicates to the receiver that it should start interacting with the outside world.
     /// The event is produced by the Kernel when all components are scheduled.
+    /// The config loader gets it first, all other components get it after they received their config,
+    /// see `KernelOptions.startup_timeout`.
     Init,
 
+    /// The Ready event indicates that the startup is complete: all components are scheduled, configured and received `Init`.
+    /// The event is produced by the Kernel and sent to the Scheduler.
+    Ready,
+
     /// The ConfigUpdated event indicates to the receiver that the config has changed and a configuration update should be applied.
     /// The event is produced by the router and send to the Kernel and then to the component.
+    /// The component answers with `ConfigApplied` or `ConfigRejected`, see `BrokerEvent::config_result`.
     ///
     /// # Arguments
     ///
@@ -54,10 +77,38 @@ pub enum BrokerEvent {
     ///
     ConfigUpdated(Config, InternalServerId),
 
+    /// The ConfigApplied event is the answer of a component to `ConfigUpdated` after it applied the config.
+    /// The Kernel forwards it to the config loader.
+    ///
+    /// # Arguments
+    ///
+    /// * `InternalServerId` - id of the component that applied the config
+    ///
+    ConfigApplied(InternalServerId),
+
+    /// The ConfigRejected event is the answer of a component to `ConfigUpdated` if it could not apply the config.
+    /// The component should keep working with its previous config if it can.
+    /// The Kernel forwards it to the config loader, which decides whether the router can still run.
+    ///
+    /// # Arguments
+    ///
+    /// * `InternalServerId` - id of the component that rejected the config
+    /// * `String` - the reason why the config was rejected
+    ///
+    ConfigRejected(InternalServerId, String),
+
     /// The IncomingCloudEvent event indicates to the receiver that a new CloudEvent has been received from the outside world.
-    /// The event is produced by an input port and is sent to the Kernel. The Kernel sends the same event to the router.
+    /// The event is produced by an input port and is sent to the Kernel.
+    /// The Kernel sends the same event through the processors (`StartOptions.processors`) and then to the router.
     IncomingCloudEvent(IncomingCloudEvent),
 
+    /// The `ProcessorResult` is the result of a processor for one `IncomingCloudEvent`.
+    /// The event is sent from the processor to the Kernel, which passes the CloudEvent on to the next processor or the router.
+    ///
+    /// A processor can pass the CloudEvent on unchanged or changed, drop it (it is acknowledged as successfully processed) or fail.
+    /// Use `IncomingCloudEvent::pass_on`, `IncomingCloudEvent::drop_event` or `IncomingCloudEvent::fail` to build it.
+    ProcessorResult(ProcessorResult),
+
     /// The `RoutingResult` is the result of a routing from one `IncomingCloudEvent`.
     /// The event is sent from the router to the kernel and there forwarded as `OutgoingCloudEvent` to the ports.
     ///
@@ -69,6 +120,10 @@ pub enum BrokerEvent {
     /// One event for every output port which should forward the data is created.
     OutgoingCloudEvent(OutgoingCloudEvent),
 
+    /// Several `OutgoingCloudEvent`s for the same output port, the Kernel sends them instead of single events if `PortOptions.batch` is set for the port.
+    /// The port should publish them in bulk and answer with one `OutgoingCloudEventProcessed` per CloudEvent, e.g., together in a `BrokerEvent::Batch`.
+    OutgoingCloudEventBatch(Vec<OutgoingCloudEvent>),
+
     /// The OutgoingCloudEvent was processed.
     /// The OutgoingCloudEventProcessed notifies the kernel about the end of the processing and indicates whether the outcome was successful.
     /// This response is only used if the `CloudEventRoutingArgs` in the `OutgoingCloudEvent` event indicates  that a response is used  (`CloudEventRoutingArgs.delivery_guarantee.requires_acknowledgment()`).
@@ -92,17 +147,85 @@ pub enum BrokerEvent {
     ///
     Batch(Vec<BrokerEvent>),
 
-    /// A health check port sends `HealthCheckRequest` to some components, they should response with `HealthCheckResponse`
+    /// A health check port sends `HealthCheckRequest` to some components, they should response with `HealthCheckResponse`.
+    /// Components that do not check their health should answer with `HealthCheckStatus::Unsupported`.
+    /// Requests for `KERNEL_ID` are answered by the Kernel itself, as long as its loop is running.
     HealthCheckRequest(HealthCheckRequest),
 
     /// response for `HealthCheckRequest`, should go to a health check component
     HealthCheckResponse(HealthCheckResponse),
+
+    /// The Shutdown event indicates to the receiver that it should stop gracefully.
+    /// Any component can send it to the Kernel to stop the router (e.g., after the process received SIGTERM).
+    /// The Kernel stops accepting new CloudEvents, waits until the pending deliveries are settled and then sends the event to all components.
+    /// Once all components answered with `ShutdownComplete`, the Kernel sends the event to the Scheduler, which joins all threads.
+    ///
+    /// A component that receives `Shutdown` should finish its in-flight work, nack the deliveries it could not complete,
+    /// answer with `ShutdownComplete` and return from its start function.
+    Shutdown,
+
+    /// The ShutdownComplete event is the response of a component to `Shutdown`.
+    /// The component must not send any further events after it.
+    ///
+    /// # Arguments
+    ///
+    /// * `InternalServerId` - id of the component that completed the shutdown
+    ///
+    ShutdownComplete(InternalServerId),
+
+    /// The InternalServerExited event indicates that the start function of a component returned or panicked.
+    /// The event is produced by the Scheduler and sent to the Kernel.
+    /// Unless the router is shutting down, the Kernel fails the pending deliveries of the component and restarts it
+    /// with the backoff of `KernelOptions.restart`.
+    ///
+    /// # Arguments
+    ///
+    /// * `InternalServerId` - id of the component that exited
+    ///
+    InternalServerExited(InternalServerId),
+
+    /// Any component can send `MetricsUpdate` to the Kernel to add its own metrics to the ones the Kernel collects.
+    MetricsUpdate(MetricsUpdate),
+
+    /// Any component can send `MetricsRequest` to the Kernel to get a snapshot of all metrics (e.g., to export them).
+    /// The Kernel answers with `MetricsResponse`.
+    MetricsRequest(MetricsRequest),
+
+    /// response of the Kernel for `MetricsRequest`
+    MetricsResponse(MetricsResponse),
+
+    /// Any component can send `ComponentsRequest` to the Kernel to discover all scheduled components (e.g., to check their health).
+    /// The Kernel answers with `ComponentsResponse`.
+    ComponentsRequest(ComponentsRequest),
+
+    /// response of the Kernel for `ComponentsRequest`
+    ComponentsResponse(ComponentsResponse),
+
+    /// The Pause event tells an input port to stop receiving CloudEvents from the outside world until it gets `Resume`.
+    /// The Kernel sends it while it is overloaded, see `KernelOptions.flow_control`.
+    /// The port should still process all other events, e.g., `IncomingCloudEventProcessed`.
+    ///
+    /// # Arguments
+    ///
+    /// * `InternalServerId` - id of the port that should pause
+    ///
+    Pause(InternalServerId),
+
+    /// The Resume event tells a paused input port to receive CloudEvents again.
+    /// The Kernel sends it once it recovered, see `KernelOptions.flow_control`.
+    ///
+    /// # Arguments
+    ///
+    /// * `InternalServerId` - id of the port that should resume
+    ///
+    Resume(InternalServerId),
 }
 
 impl fmt::Display for BrokerEvent {
     fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
         match self {
             BrokerEvent::Init => write!(f, "Init"),
+            BrokerEvent::Ready => write!(f, "Ready"),
             BrokerEvent::ScheduleInternalServer(event) => {
                 write!(f, "ScheduleInternalServer server_id={}", event.id)
             }
@@ -112,15 +235,32 @@ impl fmt::Display for BrokerEvent {
             BrokerEvent::IncomingCloudEvent(event) => {
                 write!(f, "IncomingCloudEvent receiver_id={}", event.incoming_id)
             }
+            BrokerEvent::ProcessorResult(event) => write!(
+                f,
+                "ProcessorResult sender_id={} result={}",
+                event.sender_id, event.result
+            ),
             BrokerEvent::RoutingResult(event) => {
                 write!(f, "RoutingResult receiver_id={}", event.incoming_id)
             }
             BrokerEvent::ConfigUpdated(_, id) => write!(f, "ConfigUpdated destination_id={}", id),
+            BrokerEvent::ConfigApplied(id) => write!(f, "ConfigApplied sender_id={}", id),
+            BrokerEvent::ConfigRejected(id, reason) => {
+                write!(f, "ConfigRejected sender_id={} reason={}", id, reason)
+            }
             BrokerEvent::OutgoingCloudEvent(event) => write!(
                 f,
                 "OutgoingCloudEvent destination_id={}",
                 event.destination_id
             ),
+            BrokerEvent::OutgoingCloudEventBatch(events) => write!(
+                f,
+                "OutgoingCloudEventBatch destination_id={} size={}",
+                events
+                    .first()
+                    .map_or("", |event| event.destination_id.as_str()),
+                events.len()
+            ),
             BrokerEvent::OutgoingCloudEventProcessed(event) => {
                 write!(f, "OutgoingCloudEventProcessed result={}", event.result)
             }
@@ -130,6 +270,34 @@ impl fmt::Display for BrokerEvent {
             BrokerEvent::Batch(_) => write!(f, "Batch"),
             BrokerEvent::HealthCheckRequest(_) => write!(f, "HealthCheckRequest"),
             BrokerEvent::HealthCheckResponse(_) => write!(f, "HealthCheckResponse"),
+            BrokerEvent::Shutdown => write!(f, "Shutdown"),
+            BrokerEvent::ShutdownComplete(id) => {
+                write!(f, "ShutdownComplete server_id={}", id)
+            }
+            BrokerEvent::InternalServerExited(id) => {
+                write!(f, "InternalServerExited server_id={}", id)
+            }
+            BrokerEvent::MetricsUpdate(event) => {
+                write!(f, "MetricsUpdate sender_id={}", event.sender_id)
+            }
+            BrokerEvent::MetricsRequest(event) => {
+                write!(f, "MetricsRequest sender_id={}", event.sender_id)
+            }
+            BrokerEvent::MetricsResponse(event) => {
+                write!(f, "MetricsResponse destination_id={}", event.destination_id)
+            }
+            BrokerEvent::ComponentsRequest(event) => {
+                write!(f, "ComponentsRequest sender_id={}", event.sender_id)
+            }
+            BrokerEvent::ComponentsResponse(event) => {
+                write!(
+                    f,
+                    "ComponentsResponse destination_id={}",
+                    event.destination_id
+                )
+            }
+            BrokerEvent::Pause(id) => write!(f, "Pause server_id={}", id),
+            BrokerEvent::Resume(id) => write!(f, "Resume server_id={}", id),
         }
     }
 }
@@ -142,11 +310,72 @@ pub struct IncomingCloudEvent {
     /// the unique identifier of the CloudEvent routing attempt
     pub routing_id: CloudEventMessageRoutingId,
     /// the deserialized CloudEvent that the component has received
-    pub cloud_event: Event,
+    pub cloud_event: SharedCloudEvent,
     /// routing arguments to define how a CloudEvent should be routed
     pub args: CloudEventRoutingArgs,
 }
 
+impl BrokerEvent {
+    /// Builds the answer of a component to `ConfigUpdated`:
+    /// `ConfigApplied` if the config was applied, otherwise `ConfigRejected` with the error as reason.
+    pub fn config_result(id: InternalServerId, result: Result<()>) -> BrokerEvent {
+        match result {
+            Ok(()) => BrokerEvent::ConfigApplied(id),
+            Err(e) => BrokerEvent::ConfigRejected(id, format!("{:#}", e)),
+        }
+    }
+}
+
+impl IncomingCloudEvent {
+    /// Passes the (possibly changed) CloudEvent on to the next processor or the router.
+    pub fn pass_on(self, processor_id: InternalServerId) -> BrokerEvent {
+        self.into_processor_result(processor_id, true, ProcessingResult::Successful)
+    }
+
+    /// Drops the CloudEvent, the sender gets `ProcessingResult::Successful`.
+    pub fn drop_event(self, processor_id: InternalServerId) -> BrokerEvent {
+        self.into_processor_result(processor_id, false, ProcessingResult::Successful)
+    }
+
+    /// Stops the processing of the CloudEvent, the sender gets the given result.
+    pub fn fail(self, processor_id: InternalServerId, result: ProcessingResult) -> BrokerEvent {
+        self.into_processor_result(processor_id, false, result)
+    }
+
+    fn into_processor_result(
+        self,
+        processor_id: InternalServerId,
+        keep_event: bool,
+        result: ProcessingResult,
+    ) -> BrokerEvent {
+        BrokerEvent::ProcessorResult(ProcessorResult {
+            sender_id: processor_id,
+            incoming_id: self.incoming_id,
+            routing_id: self.routing_id,
+            cloud_event: Some(self.cloud_event).filter(|_| keep_event),
+            args: self.args,
+            result,
+        })
+    }
+}
+
+/// Struct for `BrokerEvent::ProcessorResult`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ProcessorResult {
+    /// the id of the processor
+    pub sender_id: InternalServerId,
+    /// the id of the component that received the event from the outside world
+    pub incoming_id: InternalServerId,
+    /// the unique identifier of the CloudEvent routing attempt
+    pub routing_id: CloudEventMessageRoutingId,
+    /// the CloudEvent that is passed on, `None` if the processor dropped it or failed
+    pub cloud_event: Option<SharedCloudEvent>,
+    /// routing arguments to define how a CloudEvent should be routed
+    pub args: CloudEventRoutingArgs,
+    /// outcome of the processing, the CloudEvent is only passed on if it was successful
+    pub result: ProcessingResult,
+}
+
 /// Struct for `BrokerEvent::RoutingResult`
 #[derive(Clone, Debug, PartialEq)]
 pub struct RoutingResult {
@@ -155,7 +384,8 @@ pub struct RoutingResult {
     /// the unique identifier of the CloudEvent routing attempt
     pub routing_id: CloudEventMessageRoutingId,
     /// The list of events that should be forwarded to the outgoing ports.
-    /// Multiple routing to the same destination_id with a `delivery_guarantee.requires_acknowledgment()` are currently not supported by the kernel.
+    /// The same destination_id could be used multiple times, every copy is acknowledged on its own.
+    /// If the routing failed, the list could contain the copies that could not be routed, the Kernel sends them to the dead-letter port.
     pub routing: Vec<OutgoingCloudEvent>,
     /// routing arguments to define how a CloudEvent should be routed - this config is used by the kernel; the args for the ports are inside the `Vec<OutgoingCloudEvent>`
     pub args: CloudEventRoutingArgs,
@@ -169,11 +399,13 @@ pub struct OutgoingCloudEvent {
     /// the unique identifier of the CloudEvent routing attempt
     pub routing_id: CloudEventMessageRoutingId,
     /// the CloudEvent which should be forwarded
-    pub cloud_event: Event,
+    pub cloud_event: SharedCloudEvent,
     /// the id of the component that should send the event
     pub destination_id: InternalServerId,
     /// routing arguments to define how a CloudEvent should be routed
     pub args: CloudEventRoutingArgs,
+    /// the id of this copy within the routing attempt, it is assigned by the Kernel (routers can leave it at `0`)
+    pub delivery_id: CloudEventDeliveryId,
 }
 
 /// Struct for `BrokerEvent::OutgoingCloudEventProcessed`
@@ -183,6 +415,8 @@ pub struct OutgoingCloudEventProcessed {
     pub sender_id: InternalServerId,
     /// the unique identifier of the CloudEvent routing attempt
     pub routing_id: CloudEventMessageRoutingId,
+    /// the id of the processed copy (`OutgoingCloudEvent.delivery_id`)
+    pub delivery_id: CloudEventDeliveryId,
     /// result of the processing, was the processing successful? Error?
     pub result: ProcessingResult,
 }
@@ -206,6 +440,18 @@ pub struct HealthCheckRequest {
     pub destination_id: InternalServerId,
 }
 
+impl HealthCheckRequest {
+    /// Builds the `BrokerEvent::HealthCheckResponse` of the requested component with the given status.
+    pub fn respond(self, status: HealthCheckStatus) -> BrokerEvent {
+        BrokerEvent::HealthCheckResponse(HealthCheckResponse {
+            id: self.id,
+            sender_id: self.destination_id,
+            destination_id: self.sender_id,
+            status,
+        })
+    }
+}
+
 /// Struct for `BrokerEvent::HealthCheckResponse`
 pub struct HealthCheckResponse {
     /// id of the health check
@@ -218,6 +464,66 @@ pub struct HealthCheckResponse {
     pub status: HealthCheckStatus,
 }
 
+/// Struct for `BrokerEvent::MetricsUpdate`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsUpdate {
+    /// the id of the component the metrics belong to
+    pub sender_id: InternalServerId,
+    /// the changes of the metrics
+    pub updates: Vec<MetricUpdate>,
+}
+
+/// Struct for `BrokerEvent::MetricsRequest`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsRequest {
+    /// id of the request
+    pub id: String,
+    /// the id of the component that created the request
+    pub sender_id: InternalServerId,
+}
+
+/// Struct for `BrokerEvent::MetricsResponse`
+#[derive(Clone, Debug, PartialEq)]
+pub struct MetricsResponse {
+    /// id of the request
+    pub id: String,
+    /// routing destination of the response (MetricsRequest.sender_id)
+    pub destination_id: InternalServerId,
+    /// snapshot of all metrics
+    pub metrics: Vec<Metric>,
+}
+
+/// Struct for `BrokerEvent::ComponentsRequest`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComponentsRequest {
+    /// id of the request
+    pub id: String,
+    /// the id of the component that created the request
+    pub sender_id: InternalServerId,
+}
+
+/// Struct for `BrokerEvent::ComponentsResponse`
+#[derive(Clone, Debug, PartialEq)]
+pub struct ComponentsResponse {
+    /// id of the request
+    pub id: String,
+    /// routing destination of the response (ComponentsRequest.sender_id)
+    pub destination_id: InternalServerId,
+    /// all components the Kernel scheduled, including the ones that are restarting
+    pub components: Vec<ComponentInfo>,
+}
+
+/// State of a component as seen by the Kernel, part of `ComponentsResponse`
+#[derive(Clone, Debug, PartialEq, Serialize)]
+pub struct ComponentInfo {
+    /// id of the component
+    pub id: InternalServerId,
+    /// the component received a `ConfigUpdated` (always `true` for the config loader)
+    pub configured: bool,
+    /// the component is scheduled and received `Init`
+    pub initialized: bool,
+}
+
 /// health check status
 #[derive(Debug, PartialEq, Clone, Serialize)]
 pub enum HealthCheckStatus {
@@ -225,6 +531,8 @@ pub enum HealthCheckStatus {
     Healthy,
     /// the component is unhealthy, message to indicate the problem
     Unhealthy(String),
+    /// the component does not check its health
+    Unsupported,
 }
 
 /// Fixed static lifetime for struct for `BrokerEvent::ScheduleInternalServer`
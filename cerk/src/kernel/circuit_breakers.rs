@@ -0,0 +1,210 @@
+use crate::kernel::{HealthCheckStatus, KernelOptions};
+use crate::runtime::InternalServerId;
+use std::collections::HashMap;
+use std::ops::Add;
+use std::time::SystemTime;
+
+/// state of the circuit of a port, see `CircuitBreakerOptions`
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum Circuit {
+    /// copies are sent, with the number of failures in a row
+    Closed(u32),
+    /// no copies are sent until the given time
+    Open(SystemTime),
+    /// A copy was sent to probe the port, no other copies are sent until it is answered.
+    /// If the probe is not answered within the delivery ttl of the port (e.g. it was not acknowledged), another one is sent.
+    HalfOpen(SystemTime),
+}
+
+/// The circuits of the ports that have `CircuitBreakerOptions`, only ports that failed at least once have one.
+#[derive(Default)]
+pub(crate) struct CircuitBreakers {
+    circuits: HashMap<InternalServerId, Circuit>,
+}
+
+impl CircuitBreakers {
+    /// Returns `false` if the circuit of the port is open and the copy must not be sent.
+    /// A half-open circuit allows a single copy to probe the port.
+    pub(crate) fn allows(&mut self, options: &KernelOptions, destination_id: &str) -> bool {
+        let circuit = match self.circuits.get_mut(destination_id) {
+            Some(circuit) => circuit,
+            None => return true,
+        };
+        let now = SystemTime::now();
+        match *circuit {
+            Circuit::Closed(_) => true,
+            Circuit::Open(until) | Circuit::HalfOpen(until) if until <= now => {
+                debug!("circuit of {} is half-open, send a probe", destination_id);
+                *circuit = Circuit::HalfOpen(now.add(options.delivery_ttl_of(destination_id)));
+                true
+            }
+            Circuit::Open(_) | Circuit::HalfOpen(_) => false,
+        }
+    }
+
+    /// Like `allows`, but for the copies of a single routing: the circuit of every port is asked once
+    /// and a half-open circuit only lets the first copy through to probe the port.
+    /// `admitted` holds the number of copies each port may still get within the routing.
+    pub(crate) fn allows_copy(
+        &mut self,
+        options: &KernelOptions,
+        destination_id: &str,
+        admitted: &mut HashMap<InternalServerId, usize>,
+    ) -> bool {
+        let remaining = match admitted.get_mut(destination_id) {
+            Some(remaining) => remaining,
+            None => {
+                let remaining = if !self.allows(options, destination_id) {
+                    0
+                } else if self.is_closed(destination_id) {
+                    usize::MAX
+                } else {
+                    1
+                };
+                admitted
+                    .entry(destination_id.to_string())
+                    .or_insert(remaining)
+            }
+        };
+        if *remaining == 0 {
+            return false;
+        }
+        *remaining -= 1;
+        true
+    }
+
+    /// the circuit of the port is neither open nor half-open
+    pub(crate) fn is_closed(&self, destination_id: &str) -> bool {
+        matches!(
+            self.circuits.get(destination_id),
+            None | Some(Circuit::Closed(_))
+        )
+    }
+
+    /// The port acknowledged a copy, its circuit is closed again.
+    pub(crate) fn record_success(&mut self, destination_id: &str) {
+        if let Some(circuit) = self.circuits.remove(destination_id) {
+            if circuit != Circuit::Closed(0) {
+                info!("circuit of {} is closed again", destination_id);
+            }
+        }
+    }
+
+    /// A copy for the port failed, its circuit is opened once the failure threshold is reached.
+    /// A failed probe opens the circuit again.
+    pub(crate) fn record_failure(&mut self, options: &KernelOptions, destination_id: &str) {
+        let circuit_breaker = match options.circuit_breaker_of(destination_id) {
+            Some(circuit_breaker) => circuit_breaker,
+            None => return,
+        };
+        let circuit = self
+            .circuits
+            .entry(destination_id.to_string())
+            .or_insert(Circuit::Closed(0));
+        let failures = match *circuit {
+            Circuit::Closed(failures) => failures + 1,
+            // a failure of a copy that was sent before the circuit opened
+            Circuit::Open(_) => return,
+            Circuit::HalfOpen(_) => circuit_breaker.failure_threshold,
+        };
+        *circuit = if failures >= circuit_breaker.failure_threshold {
+            warn!(
+                "{} failed {} times in a row, open its circuit for {:?}",
+                destination_id, failures, circuit_breaker.open_duration
+            );
+            Circuit::Open(SystemTime::now().add(circuit_breaker.open_duration))
+        } else {
+            Circuit::Closed(failures)
+        };
+    }
+
+    /// Adds the state of the circuit to the status the port reported, an open or half-open circuit makes it unhealthy.
+    pub(crate) fn health_of(
+        &self,
+        destination_id: &str,
+        status: HealthCheckStatus,
+    ) -> HealthCheckStatus {
+        let state = match self.circuits.get(destination_id) {
+            Some(Circuit::Open(_)) => "circuit is open",
+            Some(Circuit::HalfOpen(_)) => "circuit is half-open",
+            Some(Circuit::Closed(_)) | None => return status,
+        };
+        match status {
+            HealthCheckStatus::Unhealthy(reason) => {
+                HealthCheckStatus::Unhealthy(format!("{}, {}", reason, state))
+            }
+            HealthCheckStatus::Healthy | HealthCheckStatus::Unsupported => {
+                HealthCheckStatus::Unhealthy(state.to_string())
+            }
+        }
+    }
+
+    /// forgets the circuit of an exited port, it starts with a closed circuit after its restart
+    pub(crate) fn remove(&mut self, destination_id: &str) {
+        self.circuits.remove(destination_id);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::CircuitBreakerOptions;
+    use std::time::Duration;
+
+    fn options(open_duration: Duration) -> KernelOptions {
+        KernelOptions {
+            circuit_breaker: Some(CircuitBreakerOptions {
+                failure_threshold: 2,
+                open_duration,
+            }),
+            delivery_ttl: Duration::from_secs(60),
+            ..KernelOptions::default()
+        }
+    }
+
+    #[test]
+    fn circuit_should_open_after_the_failure_threshold() {
+        let options = options(Duration::from_secs(60));
+        let mut circuit_breakers = CircuitBreakers::default();
+        circuit_breakers.record_failure(&options, "port");
+        assert!(circuit_breakers.allows(&options, "port"));
+        circuit_breakers.record_failure(&options, "port");
+        assert!(!circuit_breakers.allows(&options, "port"));
+        assert_eq!(
+            circuit_breakers.health_of("port", HealthCheckStatus::Healthy),
+            HealthCheckStatus::Unhealthy("circuit is open".to_string())
+        );
+        assert!(circuit_breakers.allows(&options, "other"));
+    }
+
+    #[test]
+    fn half_open_circuit_should_allow_a_single_probe() {
+        let options = options(Duration::from_secs(0));
+        let mut circuit_breakers = CircuitBreakers::default();
+        circuit_breakers.record_failure(&options, "port");
+        circuit_breakers.record_failure(&options, "port");
+        assert!(circuit_breakers.allows(&options, "port"));
+        assert!(!circuit_breakers.allows(&options, "port"));
+        assert_eq!(
+            circuit_breakers.health_of("port", HealthCheckStatus::Unhealthy("down".to_string())),
+            HealthCheckStatus::Unhealthy("down, circuit is half-open".to_string())
+        );
+
+        circuit_breakers.record_success("port");
+        assert!(circuit_breakers.allows(&options, "port"));
+        assert_eq!(
+            circuit_breakers.health_of("port", HealthCheckStatus::Healthy),
+            HealthCheckStatus::Healthy
+        );
+    }
+
+    #[test]
+    fn ports_without_options_should_stay_closed() {
+        let options = KernelOptions::default();
+        let mut circuit_breakers = CircuitBreakers::default();
+        for _ in 0..10 {
+            circuit_breakers.record_failure(&options, "port");
+        }
+        assert!(circuit_breakers.allows(&options, "port"));
+    }
+}
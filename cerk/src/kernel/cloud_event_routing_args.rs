@@ -1,8 +1,11 @@
-use super::DeliveryGuarantee;
+use super::{AckPolicy, DeliveryGuarantee};
 
 /// CloudEventRoutingArgs specifies how a CloudEvent should be routed
 #[derive(Default, Debug, Clone, PartialEq)]
 pub struct CloudEventRoutingArgs {
     /// Message delivery guarantees with which the CloudEvent was received
     pub delivery_guarantee: DeliveryGuarantee,
+    /// How many copies have to be acknowledged, set by the router in `RoutingResult.args`.
+    /// The input ports leave it at the default (`AckPolicy::All`).
+    pub ack_policy: AckPolicy,
 }
@@ -0,0 +1,77 @@
+use crate::kernel::{OutgoingCloudEvent, ProcessingResult};
+use std::sync::Arc;
+
+/// CloudEvent extension attribute of a dead letter with the `ProcessingResult` of the failed delivery
+pub const DEAD_LETTER_REASON_EXTENSION: &str = "failurereason";
+/// CloudEvent extension attribute of a dead letter with the id of the port the CloudEvent could not be delivered to
+pub const DEAD_LETTER_DESTINATION_EXTENSION: &str = "faileddestination";
+/// CloudEvent extension attribute of a dead letter with the number of delivery attempts
+pub const DEAD_LETTER_ATTEMPTS_EXTENSION: &str = "deliveryattempts";
+/// CloudEvent extension attribute of a dead letter with the `CloudEventMessageRoutingId` of the failed routing
+pub const DEAD_LETTER_ROUTING_ID_EXTENSION: &str = "originalroutingid";
+
+/// Wraps a copy that could not be delivered, so it can be sent to the dead-letter port.
+pub(crate) fn to_dead_letter(
+    failed: &OutgoingCloudEvent,
+    reason: &ProcessingResult,
+    attempts: u32,
+    dead_letter_port: &str,
+) -> OutgoingCloudEvent {
+    let mut cloud_event = failed.cloud_event.clone();
+    // the failed copy could still be shared with other copies, they must not get the extensions
+    let dead_letter = Arc::make_mut(&mut cloud_event);
+    dead_letter.set_extension(DEAD_LETTER_REASON_EXTENSION, reason.to_string());
+    dead_letter.set_extension(
+        DEAD_LETTER_DESTINATION_EXTENSION,
+        failed.destination_id.clone(),
+    );
+    dead_letter.set_extension(DEAD_LETTER_ATTEMPTS_EXTENSION, attempts as i64);
+    dead_letter.set_extension(DEAD_LETTER_ROUTING_ID_EXTENSION, failed.routing_id.clone());
+    OutgoingCloudEvent {
+        routing_id: failed.routing_id.clone(),
+        cloud_event,
+        destination_id: dead_letter_port.to_string(),
+        args: failed.args.clone(),
+        delivery_id: failed.delivery_id,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::CloudEventRoutingArgs;
+    use cloudevents::event::ExtensionValue;
+    use cloudevents::Event;
+
+    #[test]
+    fn dead_letter_should_carry_the_failure() {
+        let failed = OutgoingCloudEvent {
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            destination_id: "output".to_string(),
+            args: CloudEventRoutingArgs::default(),
+            delivery_id: 3,
+        };
+        let dead_letter = to_dead_letter(&failed, &ProcessingResult::Timeout, 2, "dlq");
+
+        assert_eq!(dead_letter.destination_id, "dlq");
+        assert_eq!(dead_letter.routing_id, "1");
+        let event = &dead_letter.cloud_event;
+        assert_eq!(
+            event.extension(DEAD_LETTER_REASON_EXTENSION),
+            Some(&ExtensionValue::String("Timeout".to_string()))
+        );
+        assert_eq!(
+            event.extension(DEAD_LETTER_DESTINATION_EXTENSION),
+            Some(&ExtensionValue::String("output".to_string()))
+        );
+        assert_eq!(
+            event.extension(DEAD_LETTER_ATTEMPTS_EXTENSION),
+            Some(&ExtensionValue::Integer(2))
+        );
+        assert_eq!(
+            event.extension(DEAD_LETTER_ROUTING_ID_EXTENSION),
+            Some(&ExtensionValue::String("1".to_string()))
+        );
+    }
+}
@@ -7,15 +7,22 @@ use std::convert::TryFrom;
 pub enum DeliveryGuarantee {
     /// best effort: there is no guarantee that a message will be routed, the default
     BestEffort = 0,
+    /// At Most Once the message should be received at the destination.
+    /// The input acknowledges the message immediately, outputs do not wait for confirmations and nothing is retried or requeued.
+    AtMostOnce = 1,
     /// At Least Once the message should be received at the destination
     AtLeastOnce = 2,
+    /// Exactly Once the message should be received at the destination.
+    /// Like `AtLeastOnce`, but the Kernel does not forward a redelivered message again to a destination that already acknowledged it.
+    /// A message is identified by its source and id, see `KernelOptions.idempotency`.
+    ExactlyOnce = 3,
 }
 
 impl DeliveryGuarantee {
     /// Does the selected delivery guarantee requires an acknowledgment?
     pub fn requires_acknowledgment(&self) -> bool {
         match self {
-            DeliveryGuarantee::BestEffort => false,
+            DeliveryGuarantee::BestEffort | DeliveryGuarantee::AtMostOnce => false,
             _ => true,
         }
     }
@@ -40,7 +47,9 @@ impl TryFrom<&Config> for DeliveryGuarantee {
         if let Config::U8(number) = value {
             match number {
                 0 => Ok(DeliveryGuarantee::BestEffort),
+                1 => Ok(DeliveryGuarantee::AtMostOnce),
                 2 => Ok(DeliveryGuarantee::AtLeastOnce),
+                3 => Ok(DeliveryGuarantee::ExactlyOnce),
                 _ => bail!("number out of range"),
             }
         } else {
@@ -75,6 +84,14 @@ mod tests {
         Ok(())
     }
 
+    #[test]
+    fn at_most_once_should_not_require_acknowledgment() -> Result<(), Box<dyn Error>> {
+        let delivery_guarantee = DeliveryGuarantee::try_from(Config::U8(1))?;
+        assert_eq!(delivery_guarantee, DeliveryGuarantee::AtMostOnce);
+        assert!(!delivery_guarantee.requires_acknowledgment());
+        Ok(())
+    }
+
     #[test]
     fn failed_config_to_delivery_guarantee() -> Result<(), Box<dyn Error>> {
         let config = Config::U8(99);
@@ -0,0 +1,231 @@
+use super::append_log::AppendLog;
+use crate::kernel::IdempotencyOptions;
+use crate::runtime::InternalServerId;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::ops::Add;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+
+/// identifies a CloudEvent delivered to a destination: (destination_id, source, id)
+pub(crate) type IdempotencyKey = (InternalServerId, String, String);
+
+/// The CloudEvents that were delivered with `DeliveryGuarantee::ExactlyOnce`, kept by the Kernel.
+///
+/// The store keeps at most `IdempotencyOptions.max_entries` entries, the oldest ones are dropped first.
+/// With a path, every entry is appended to a journal file that is read again after a restart.
+pub(crate) struct IdempotencyStore {
+    options: IdempotencyOptions,
+    /// time of the delivery by key
+    entries: HashMap<IdempotencyKey, SystemTime>,
+    /// keys in the order they were inserted, a key could be contained multiple times
+    order: VecDeque<(IdempotencyKey, SystemTime)>,
+    /// the journal is compacted if it grows too large
+    journal: Option<AppendLog>,
+}
+
+fn to_fields(key: &IdempotencyKey, delivered_at: SystemTime) -> Vec<String> {
+    let millis = delivered_at
+        .duration_since(UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_millis();
+    vec![
+        millis.to_string(),
+        key.0.clone(),
+        key.1.clone(),
+        key.2.clone(),
+    ]
+}
+
+fn from_fields(fields: &[String]) -> Result<(IdempotencyKey, SystemTime)> {
+    if fields.len() != 4 {
+        bail!("expected 4 fields but got {}", fields.len());
+    }
+    let millis: u64 = fields[0].parse().context("invalid timestamp")?;
+    Ok((
+        (fields[1].clone(), fields[2].clone(), fields[3].clone()),
+        UNIX_EPOCH.add(Duration::from_millis(millis)),
+    ))
+}
+
+impl IdempotencyStore {
+    /// Creates the store and loads the entries of the journal, if a path is configured.
+    pub fn open(options: IdempotencyOptions) -> Result<Self> {
+        let mut store = IdempotencyStore {
+            options,
+            entries: HashMap::new(),
+            order: VecDeque::new(),
+            journal: None,
+        };
+        if let Some(path) = store.options.path.clone() {
+            let mut replayed = Vec::new();
+            let journal = AppendLog::open(path.clone(), false, |fields| {
+                replayed.push(from_fields(fields)?);
+                Ok(())
+            })?;
+            for (key, delivered_at) in replayed {
+                store.insert_entry(key, delivered_at);
+            }
+            store.remove_expired(SystemTime::now());
+            store.journal = Some(journal);
+            store.compact()?;
+            info!(
+                "idempotency store {} loaded with {} entries",
+                path.display(),
+                store.entries.len()
+            );
+        }
+        Ok(store)
+    }
+
+    /// Was the CloudEvent already delivered to the destination within the retention time?
+    pub fn contains(&self, key: &IdempotencyKey) -> bool {
+        let now = SystemTime::now();
+        self.entries
+            .get(key)
+            .is_some_and(|delivered_at| delivered_at.add(self.options.retention) > now)
+    }
+
+    /// Remembers that the CloudEvent was delivered to the destination.
+    pub fn insert(&mut self, key: IdempotencyKey) {
+        let now = SystemTime::now();
+        self.remove_expired(now);
+        if let Err(e) = self.append(&key, now) {
+            error!("failed to persist the delivery of {:?}: {:?}", key, e);
+        }
+        self.insert_entry(key, now);
+    }
+
+    fn insert_entry(&mut self, key: IdempotencyKey, delivered_at: SystemTime) {
+        self.entries.insert(key.clone(), delivered_at);
+        self.order.push_back((key, delivered_at));
+        while self.entries.len() > self.options.max_entries {
+            self.pop_oldest();
+        }
+        // the order contains outdated duplicates of keys that were inserted again
+        if self.order.len() > 2 * self.options.max_entries.max(1) {
+            let entries = &self.entries;
+            self.order
+                .retain(|(key, delivered_at)| entries.get(key) == Some(delivered_at));
+        }
+    }
+
+    fn pop_oldest(&mut self) {
+        if let Some((key, delivered_at)) = self.order.pop_front() {
+            // only remove it if the key was not inserted again later
+            if self.entries.get(&key) == Some(&delivered_at) {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn remove_expired(&mut self, now: SystemTime) {
+        while self
+            .order
+            .front()
+            .is_some_and(|(_, delivered_at)| delivered_at.add(self.options.retention) <= now)
+        {
+            self.pop_oldest();
+        }
+    }
+
+    fn append(&mut self, key: &IdempotencyKey, delivered_at: SystemTime) -> Result<()> {
+        let lines = match self.journal {
+            Some(ref journal) => journal.lines(),
+            None => return Ok(()),
+        };
+        if lines >= 2 * self.options.max_entries.max(1) {
+            self.compact()?;
+        }
+        if let Some(ref mut journal) = self.journal {
+            journal.append(&to_fields(key, delivered_at))?;
+        }
+        Ok(())
+    }
+
+    /// Rewrites the journal with the current entries only.
+    fn compact(&mut self) -> Result<()> {
+        if let Some(ref mut journal) = self.journal {
+            let entries = &self.entries;
+            journal.compact(
+                self.order
+                    .iter()
+                    .filter(|(key, delivered_at)| entries.get(key) == Some(delivered_at))
+                    .map(|(key, delivered_at)| to_fields(key, *delivered_at)),
+            )?;
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::fs;
+
+    fn key(id: &str) -> IdempotencyKey {
+        (
+            "port".to_string(),
+            "http://example.com".to_string(),
+            id.to_string(),
+        )
+    }
+
+    fn memory_options(max_entries: usize) -> IdempotencyOptions {
+        IdempotencyOptions {
+            max_entries,
+            ..IdempotencyOptions::default()
+        }
+    }
+
+    #[test]
+    fn store_should_drop_the_oldest_entries() -> Result<()> {
+        let mut store = IdempotencyStore::open(memory_options(2))?;
+        store.insert(key("1"));
+        store.insert(key("2"));
+        store.insert(key("1"));
+        store.insert(key("3"));
+        assert!(store.contains(&key("1")));
+        assert!(!store.contains(&key("2")), "2 is the oldest entry");
+        assert!(store.contains(&key("3")));
+        Ok(())
+    }
+
+    #[test]
+    fn entries_should_expire_after_the_retention() -> Result<()> {
+        let mut store = IdempotencyStore::open(IdempotencyOptions {
+            retention: Duration::from_millis(0),
+            ..IdempotencyOptions::default()
+        })?;
+        store.insert(key("1"));
+        assert!(!store.contains(&key("1")));
+        Ok(())
+    }
+
+    #[test]
+    fn entries_should_survive_a_restart() -> Result<()> {
+        let path = std::env::temp_dir().join(format!(
+            "cerk_idempotency_store_test_{}.log",
+            std::process::id()
+        ));
+        let options = IdempotencyOptions {
+            path: Some(path.clone()),
+            ..memory_options(10)
+        };
+        let tricky_key = (
+            "port".to_string(),
+            "http://example.com".to_string(),
+            "a\tb\\n\nc".to_string(),
+        );
+        {
+            let mut store = IdempotencyStore::open(options.clone())?;
+            store.insert(key("1"));
+            store.insert(tricky_key.clone());
+        }
+        let store = IdempotencyStore::open(options)?;
+        fs::remove_file(&path)?;
+        assert!(store.contains(&key("1")));
+        assert!(store.contains(&tricky_key));
+        assert!(!store.contains(&key("2")));
+        Ok(())
+    }
+}
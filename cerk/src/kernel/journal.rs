@@ -0,0 +1,473 @@
+use super::append_log::AppendLog;
+use crate::kernel::broker_event::{IncomingCloudEvent, RoutingResult};
+use crate::kernel::{
+    BrokerEvent, CloudEventMessageRoutingId, CloudEventRoutingArgs, Config, DeliveryGuarantee,
+    JournalOptions, ProcessingResult,
+};
+use crate::runtime::channel::{BoxedSender, Sender};
+use crate::runtime::InternalServerId;
+use anyhow::{Context, Result};
+use std::collections::HashMap;
+use std::convert::TryFrom;
+use std::fs;
+use std::sync::{Arc, Mutex};
+
+const JOURNAL_FILE_NAME: &str = "kernel.journal";
+/// the journal is not compacted before it has at least this many lines
+const MIN_COMPACT_LINES: usize = 10_000;
+
+/// identifies an incoming CloudEvent: (incoming_id, routing_id)
+type JournalKey = (InternalServerId, CloudEventMessageRoutingId);
+
+/// an incoming CloudEvent that was not completed yet
+struct JournalEntry {
+    /// position in the order the CloudEvents were accepted
+    sequence: u64,
+    event: IncomingCloudEvent,
+    /// destinations that acknowledged a copy, once per copy
+    acknowledged: Vec<InternalServerId>,
+    /// the CloudEvent was loaded from the journal, its input port does not know it anymore
+    replayed: bool,
+}
+
+enum Record {
+    Accepted(Box<IncomingCloudEvent>),
+    Acknowledged(JournalKey, InternalServerId),
+    Completed(JournalKey),
+}
+
+fn to_fields(record: &Record) -> Result<Vec<String>> {
+    Ok(match record {
+        Record::Accepted(event) => vec![
+            "accepted".to_string(),
+            event.incoming_id.clone(),
+            event.routing_id.clone(),
+            (event.args.delivery_guarantee as u8).to_string(),
+            serde_json::to_string(event.cloud_event.as_ref())?,
+        ],
+        Record::Acknowledged(key, destination_id) => vec![
+            "acknowledged".to_string(),
+            key.0.clone(),
+            key.1.clone(),
+            destination_id.clone(),
+        ],
+        Record::Completed(key) => vec!["completed".to_string(), key.0.clone(), key.1.clone()],
+    })
+}
+
+fn from_fields(fields: &[String]) -> Result<Record> {
+    let key = |fields: &[String]| (fields[1].clone(), fields[2].clone());
+    match (fields[0].as_str(), fields.len()) {
+        ("accepted", 5) => {
+            let delivery_guarantee = fields[3].parse().context("invalid delivery guarantee")?;
+            Ok(Record::Accepted(Box::new(IncomingCloudEvent {
+                incoming_id: fields[1].clone(),
+                routing_id: fields[2].clone(),
+                cloud_event: Arc::new(
+                    serde_json::from_str(&fields[4]).context("invalid CloudEvent")?,
+                ),
+                args: CloudEventRoutingArgs {
+                    delivery_guarantee: DeliveryGuarantee::try_from(Config::U8(
+                        delivery_guarantee,
+                    ))?,
+                    ..CloudEventRoutingArgs::default()
+                },
+            })))
+        }
+        ("acknowledged", 4) => Ok(Record::Acknowledged(key(fields), fields[3].clone())),
+        ("completed", 3) => Ok(Record::Completed(key(fields))),
+        (kind, count) => bail!("unknown record {} with {} fields", kind, count),
+    }
+}
+
+struct JournalFile {
+    /// the log is compacted if it grows too large
+    log: AppendLog,
+    entries: HashMap<JournalKey, JournalEntry>,
+    next_sequence: u64,
+}
+
+impl JournalFile {
+    fn apply(&mut self, record: Record) {
+        match record {
+            Record::Accepted(event) => {
+                let sequence = self.next_sequence;
+                self.next_sequence += 1;
+                // a redelivery with the same routing id starts from scratch
+                self.entries.insert(
+                    (event.incoming_id.clone(), event.routing_id.clone()),
+                    JournalEntry {
+                        sequence,
+                        event: *event,
+                        acknowledged: Vec::new(),
+                        replayed: false,
+                    },
+                );
+            }
+            Record::Acknowledged(key, destination_id) => {
+                if let Some(entry) = self.entries.get_mut(&key) {
+                    entry.acknowledged.push(destination_id);
+                }
+            }
+            Record::Completed(key) => {
+                self.entries.remove(&key);
+            }
+        }
+    }
+
+    fn append(&mut self, record: &Record) -> Result<()> {
+        if self.log.lines() >= MIN_COMPACT_LINES.max(4 * self.entries.len()) {
+            self.compact()?;
+        }
+        self.log.append(&to_fields(record)?)
+    }
+
+    fn write(&mut self, record: Record) {
+        if let Err(e) = self.append(&record) {
+            error!(
+                "failed to write to the journal {}: {:?}",
+                self.log.path().display(),
+                e
+            );
+        }
+        self.apply(record);
+    }
+
+    /// the CloudEvents that were not completed, in the order they were accepted
+    fn sorted_entries(&self) -> Vec<&JournalEntry> {
+        let mut entries: Vec<_> = self.entries.values().collect();
+        entries.sort_by_key(|entry| entry.sequence);
+        entries
+    }
+
+    /// Rewrites the journal with the CloudEvents that were not completed only.
+    fn compact(&mut self) -> Result<()> {
+        let mut records = Vec::new();
+        for entry in self.sorted_entries() {
+            records.push(to_fields(&Record::Accepted(Box::new(entry.event.clone())))?);
+            let key = (
+                entry.event.incoming_id.clone(),
+                entry.event.routing_id.clone(),
+            );
+            for destination_id in entry.acknowledged.iter() {
+                let record = Record::Acknowledged(key.clone(), destination_id.clone());
+                records.push(to_fields(&record)?);
+            }
+        }
+        self.log.compact(records)
+    }
+}
+
+/// The write-ahead journal of the Kernel, see `JournalOptions`.
+///
+/// A disabled journal (without directory) does nothing.
+/// The journal is shared with the outboxes of the components, see `Journal::wrap`.
+#[derive(Clone)]
+pub(crate) struct Journal {
+    file: Option<Arc<Mutex<JournalFile>>>,
+}
+
+impl Journal {
+    /// a journal that does not write anything
+    pub fn disabled() -> Self {
+        Journal { file: None }
+    }
+
+    /// Opens the journal in the configured directory and loads the CloudEvents that were not completed.
+    pub fn open(options: &JournalOptions) -> Result<Self> {
+        let directory = match options.directory {
+            Some(ref directory) => directory,
+            None => return Ok(Journal::disabled()),
+        };
+        fs::create_dir_all(directory)
+            .with_context(|| format!("failed to create {}", directory.display()))?;
+        let path = directory.join(JOURNAL_FILE_NAME);
+        let mut records = Vec::new();
+        let log = AppendLog::open(path.clone(), options.sync, |fields| {
+            records.push(from_fields(fields)?);
+            Ok(())
+        })?;
+        let mut journal = JournalFile {
+            log,
+            entries: HashMap::new(),
+            next_sequence: 0,
+        };
+        for record in records {
+            journal.apply(record);
+        }
+        for entry in journal.entries.values_mut() {
+            entry.replayed = true;
+        }
+        journal.compact()?;
+        info!(
+            "journal {} loaded with {} CloudEvents that were not completed",
+            path.display(),
+            journal.entries.len()
+        );
+        Ok(Journal {
+            file: Some(Arc::new(Mutex::new(journal))),
+        })
+    }
+
+    fn with_file<T>(&self, f: impl FnOnce(&mut JournalFile) -> T) -> Option<T> {
+        self.file.as_ref().map(|file| f(&mut file.lock().unwrap()))
+    }
+
+    /// The CloudEvents that were loaded from the journal and not completed, in the order they were accepted.
+    pub fn replayed_events(&self) -> Vec<IncomingCloudEvent> {
+        self.with_file(|file| {
+            file.sorted_entries()
+                .into_iter()
+                .filter(|entry| entry.replayed)
+                .map(|entry| entry.event.clone())
+                .collect()
+        })
+        .unwrap_or_default()
+    }
+
+    /// Writes the CloudEvent to the journal, if it requires an acknowledgment.
+    pub fn accept(&self, event: &IncomingCloudEvent) {
+        if event.args.delivery_guarantee.requires_acknowledgment() {
+            self.with_file(|file| file.write(Record::Accepted(Box::new(event.clone()))));
+        }
+    }
+
+    /// Writes that the destination acknowledged a copy of the CloudEvent.
+    pub fn acknowledge(&self, incoming_id: &str, routing_id: &str, destination_id: &str) {
+        self.with_file(|file| {
+            let key = (incoming_id.to_string(), routing_id.to_string());
+            if file.entries.contains_key(&key) {
+                file.write(Record::Acknowledged(key, destination_id.to_string()));
+            }
+        });
+    }
+
+    /// Writes that the input port got the result of the CloudEvent, it is not routed again.
+    /// Returns `true` if the CloudEvent was loaded from the journal.
+    pub fn complete(&self, incoming_id: &str, routing_id: &str) -> bool {
+        self.with_file(|file| {
+            let key = (incoming_id.to_string(), routing_id.to_string());
+            match file.entries.get(&key) {
+                Some(entry) => {
+                    let replayed = entry.replayed;
+                    file.write(Record::Completed(key));
+                    replayed
+                }
+                None => false,
+            }
+        })
+        .unwrap_or(false)
+    }
+
+    /// Removes the copies whose destinations already acknowledged them before the restart.
+    pub fn skip_acknowledged(&self, event: &mut RoutingResult) {
+        self.with_file(|file| {
+            let key = (event.incoming_id.clone(), event.routing_id.clone());
+            if let Some(entry) = file.entries.get(&key) {
+                let mut acknowledged = entry.acknowledged.clone();
+                event.routing.retain(|copy| {
+                    match acknowledged
+                        .iter()
+                        .position(|id| *id == copy.destination_id)
+                    {
+                        Some(index) => {
+                            debug!(
+                                "{} already acknowledged event_id={}, the copy is skipped",
+                                copy.destination_id, copy.routing_id
+                            );
+                            acknowledged.remove(index);
+                            false
+                        }
+                        None => true,
+                    }
+                });
+            }
+        });
+    }
+
+    /// Wraps the outbox of a component, so the results it gets for its CloudEvents complete them in the journal.
+    /// Results of CloudEvents that were loaded from the journal are not forwarded, the component does not know them.
+    pub fn wrap(&self, id: &str, outbox: BoxedSender) -> BoxedSender {
+        if self.file.is_none() {
+            return outbox;
+        }
+        Box::new(JournalingSender {
+            id: id.to_string(),
+            journal: self.clone(),
+            outbox,
+        })
+    }
+}
+
+/// Outbox of a component that completes the CloudEvents in the journal, see `Journal::wrap`.
+struct JournalingSender {
+    id: InternalServerId,
+    journal: Journal,
+    outbox: BoxedSender,
+}
+
+impl Sender for JournalingSender {
+    fn send(&self, event: BrokerEvent) {
+        if let BrokerEvent::IncomingCloudEventProcessed(ref routing_id, ref result) = event {
+            if self.journal.complete(&self.id, routing_id) {
+                if *result == ProcessingResult::Successful {
+                    info!(
+                        "replayed event_id={} from {} completed",
+                        routing_id, self.id
+                    );
+                } else {
+                    warn!(
+                        "replayed event_id={} from {} failed with {}, it is not routed again",
+                        routing_id, self.id, result
+                    );
+                }
+                return;
+            }
+        }
+        self.outbox.send(event);
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Sender + Send> {
+        Box::new(JournalingSender {
+            id: self.id.clone(),
+            journal: self.journal.clone(),
+            outbox: self.outbox.clone_boxed(),
+        })
+    }
+
+    fn queue_length(&self) -> Option<usize> {
+        self.outbox.queue_length()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::kernel::broker_event::OutgoingCloudEvent;
+    use crate::runtime::channel::RecordingSender;
+    use cloudevents::{AttributesReader, AttributesWriter, Event};
+
+    fn options(name: &str) -> JournalOptions {
+        JournalOptions {
+            directory: Some(std::env::temp_dir().join(format!(
+                "cerk_journal_test_{}_{}",
+                name,
+                std::process::id()
+            ))),
+            sync: false,
+        }
+    }
+
+    fn incoming(routing_id: &str) -> IncomingCloudEvent {
+        let mut cloud_event = Event::default();
+        cloud_event.set_id(format!("id\t{}", routing_id));
+        IncomingCloudEvent {
+            incoming_id: "input".to_string(),
+            routing_id: routing_id.to_string(),
+            cloud_event: Arc::new(cloud_event),
+            args: CloudEventRoutingArgs {
+                delivery_guarantee: DeliveryGuarantee::AtLeastOnce,
+                ..CloudEventRoutingArgs::default()
+            },
+        }
+    }
+
+    fn copy(destination_id: &str) -> OutgoingCloudEvent {
+        OutgoingCloudEvent {
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            destination_id: destination_id.to_string(),
+            args: CloudEventRoutingArgs::default(),
+            delivery_id: 0,
+        }
+    }
+
+    #[test]
+    fn incomplete_events_should_be_replayed_after_a_restart() -> Result<()> {
+        let options = options("replay");
+        {
+            let journal = Journal::open(&options)?;
+            journal.accept(&incoming("1"));
+            journal.accept(&incoming("2"));
+            journal.accept(&incoming("3"));
+            journal.complete("input", "2");
+            journal.accept(&IncomingCloudEvent {
+                args: CloudEventRoutingArgs::default(),
+                ..incoming("best-effort")
+            });
+        }
+        let journal = Journal::open(&options)?;
+        fs::remove_dir_all(options.directory.unwrap())?;
+        let replayed = journal.replayed_events();
+        assert_eq!(
+            replayed
+                .iter()
+                .map(|event| event.routing_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["1", "3"]
+        );
+        assert_eq!(replayed[0].cloud_event.id(), "id\t1");
+        assert_eq!(replayed[0].args, incoming("1").args);
+        Ok(())
+    }
+
+    #[test]
+    fn acknowledged_copies_should_be_skipped_after_a_restart() -> Result<()> {
+        let options = options("acknowledged");
+        {
+            let journal = Journal::open(&options)?;
+            journal.accept(&incoming("1"));
+            journal.acknowledge("input", "1", "output");
+        }
+        let journal = Journal::open(&options)?;
+        fs::remove_dir_all(options.directory.unwrap())?;
+        let mut routing_result = RoutingResult {
+            incoming_id: "input".to_string(),
+            routing_id: "1".to_string(),
+            routing: vec![copy("output"), copy("other"), copy("output")],
+            args: CloudEventRoutingArgs::default(),
+            result: ProcessingResult::Successful,
+        };
+        journal.skip_acknowledged(&mut routing_result);
+        assert_eq!(
+            routing_result
+                .routing
+                .iter()
+                .map(|copy| copy.destination_id.as_str())
+                .collect::<Vec<_>>(),
+            vec!["other", "output"]
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn results_of_replayed_events_should_not_be_forwarded() -> Result<()> {
+        let options = options("forward");
+        {
+            let journal = Journal::open(&options)?;
+            journal.accept(&incoming("old"));
+        }
+        let journal = Journal::open(&options)?;
+        let input = RecordingSender::default();
+        let outbox = journal.wrap("input", input.clone_boxed());
+        journal.accept(&incoming("new"));
+        outbox.send(BrokerEvent::IncomingCloudEventProcessed(
+            "old".to_string(),
+            ProcessingResult::Successful,
+        ));
+        outbox.send(BrokerEvent::IncomingCloudEventProcessed(
+            "new".to_string(),
+            ProcessingResult::Successful,
+        ));
+        assert!(journal.replayed_events().is_empty());
+        drop(journal);
+        let journal = Journal::open(&options)?;
+        fs::remove_dir_all(options.directory.unwrap())?;
+        assert!(journal.replayed_events().is_empty());
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+        Ok(())
+    }
+}
@@ -0,0 +1,370 @@
+use crate::runtime::InternalServerId;
+use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
+
+/// default time the Kernel waits for the acknowledgments of a delivery
+pub const DEFAULT_DELIVERY_TTL_MS: u64 = 100;
+
+/// Options that define how the Kernel brokers CloudEvents.
+#[derive(Debug, Clone, PartialEq)]
+pub struct KernelOptions {
+    /// Time the Kernel waits for all acknowledgments of a delivery that requires them.
+    /// Afterwards the sender gets a `ProcessingResult::Timeout`.
+    pub delivery_ttl: Duration,
+
+    /// how CloudEvents are resent to ports that answered with `ProcessingResult::TransientError`
+    pub retry: RetryPolicy,
+
+    /// Port that receives the CloudEvents that failed with `ProcessingResult::PermanentError` or `ProcessingResult::Timeout`.
+    /// The CloudEvents are extended with the attributes defined in `DEAD_LETTER_*_EXTENSION`.
+    /// Without a dead-letter port, the failure is only reported to the sender.
+    pub dead_letter_port: Option<InternalServerId>,
+
+    /// options for single ports that override the global options; key: port id
+    pub ports: HashMap<InternalServerId, PortOptions>,
+
+    /// How components are restarted after they exited unexpectedly (e.g., their thread panicked).
+    /// `max_attempts` limits the restarts in a row, afterwards the component stays down.
+    /// A component that runs longer than `max_backoff` after its restart starts again with the `initial_backoff`.
+    pub restart: RetryPolicy,
+
+    /// how the Kernel remembers the CloudEvents delivered with `DeliveryGuarantee::ExactlyOnce`
+    pub idempotency: IdempotencyOptions,
+
+    /// how the Kernel writes the accepted CloudEvents to disk, so their routing survives a restart
+    pub journal: JournalOptions,
+
+    /// when the Kernel pauses the input ports because it is overloaded
+    pub flow_control: FlowControlOptions,
+
+    /// Time the components have to start, counted from the start of the Kernel.
+    /// The Kernel stops the router if a component is not scheduled or did not get its config within this time,
+    /// the Kernel fails and `bootstrap` exits the process with `STARTUP_FAILED_EXIT_CODE` in that case.
+    pub startup_timeout: Duration,
+
+    /// Routes CloudEvents about the Kernel itself like any other CloudEvent, with the `incoming_id` `SYSTEM_EVENTS_ID`.
+    /// Their `type` is one of the `SYSTEM_EVENT_*` constants, e.g. a config was rejected or a copy was dead-lettered.
+    /// They are routed with `DeliveryGuarantee::BestEffort` once the startup is completed.
+    pub system_events: bool,
+
+    /// Stops sending CloudEvents to ports that failed too often in a row, see `CircuitBreakerOptions`.
+    /// Without options, the circuits of all ports stay closed.
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+}
+
+/// Defines the store of the CloudEvents that were delivered with `DeliveryGuarantee::ExactlyOnce`.
+/// A CloudEvent with the same source and id is not delivered to the same port again while it is in the store,
+/// the sender gets a `ProcessingResult::Successful` right away.
+#[derive(Debug, Clone, PartialEq)]
+pub struct IdempotencyOptions {
+    /// maximum number of remembered deliveries, the oldest ones are dropped first
+    pub max_entries: usize,
+    /// time a delivery is remembered
+    pub retention: Duration,
+    /// File the deliveries are written to, they are loaded again after a restart.
+    /// Without a path, the deliveries are only kept in memory.
+    pub path: Option<PathBuf>,
+}
+
+/// Defines the write-ahead journal of the Kernel.
+/// Every incoming CloudEvent that requires an acknowledgment is written to the journal before it is routed,
+/// together with the acknowledgments of its copies and the result that was sent back to the input port.
+/// After a restart, the Kernel routes the CloudEvents that were not completed again before it routes new ones.
+/// Copies that were already acknowledged by a port are not sent to this port again.
+/// The input ports have to use routing ids that are unique across restarts (see `RoutingIdGenerator`),
+/// otherwise a new CloudEvent could be mistaken for one of the journal.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct JournalOptions {
+    /// Directory of the journal file, it is created if it does not exist.
+    /// Without a directory, no journal is written.
+    pub directory: Option<PathBuf>,
+    /// Flush every entry to the disk before the CloudEvent is routed.
+    /// Without it, an entry survives a crash of the process, but not necessarily a crash of the machine.
+    pub sync: bool,
+}
+
+/// Defines when the Kernel sends `BrokerEvent::Pause` and `BrokerEvent::Resume` to the input ports.
+/// Without watermarks, the input ports are never paused.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FlowControlOptions {
+    /// Number of events that wait in the outbox of a component (e.g., an output port that is slower than the inputs).
+    /// All input ports are paused while any outbox is above the watermarks.
+    pub outbox_depth: Option<Watermarks>,
+    /// Number of pending deliveries of an input port, every input port is paused on its own.
+    pub pending_deliveries: Option<Watermarks>,
+}
+
+/// A port is paused once a value reaches `high` and resumed once it fell to `low` or below.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Watermarks {
+    /// the value at which the port is paused
+    pub high: usize,
+    /// the value at which the port is resumed, has to be lower than `high`
+    pub low: usize,
+}
+
+/// Options that define how the Kernel brokers CloudEvents to a single port.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct PortOptions {
+    /// overrides `KernelOptions.delivery_ttl` for CloudEvents sent to this port
+    pub delivery_ttl: Option<Duration>,
+
+    /// overrides `KernelOptions.retry` for CloudEvents sent to this port
+    pub retry: Option<RetryPolicy>,
+
+    /// Collects the CloudEvents sent to this port and sends them as `BrokerEvent::OutgoingCloudEventBatch`.
+    /// Only use it for ports that support batches.
+    pub batch: Option<BatchOptions>,
+
+    /// overrides `KernelOptions.circuit_breaker` for this port
+    pub circuit_breaker: Option<CircuitBreakerOptions>,
+
+    /// Writes the CloudEvents for this port to disk while it is unavailable, see `SpoolOptions`.
+    pub spool: Option<SpoolOptions>,
+}
+
+/// Defines the store-and-forward spool of a port.
+/// While the port is unavailable (its circuit is open, see `CircuitBreakerOptions`, or it restarts),
+/// the copies for it are appended to a file in `directory` instead.
+/// A spooled copy counts as acknowledged for the ack policy, i.e. the input port gets its acknowledgment
+/// as soon as the copy is written and the spool takes over the at-least-once delivery of the copy.
+/// Once the port is available again, the spooled copies are sent in the order they were spooled,
+/// a limited number of them wait for their acknowledgment at the same time.
+/// New copies are spooled until the spool is drained.
+#[derive(Debug, Clone, PartialEq)]
+pub struct SpoolOptions {
+    /// directory of the spool file, it is created if it does not exist
+    pub directory: PathBuf,
+    /// maximum size of the spooled copies in bytes
+    pub max_bytes: u64,
+    /// what happens to a copy that does not fit into the spool anymore
+    pub overflow: SpoolOverflow,
+    /// Flush every copy to the disk before it is acknowledged.
+    /// Without it, a copy survives a crash of the process, but not necessarily a crash of the machine.
+    pub sync: bool,
+}
+
+/// Defines what happens to a copy that does not fit into a full spool.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SpoolOverflow {
+    /// the oldest spooled copies are dropped until the copy fits
+    DropOldest,
+    /// the copy is not spooled, it fails like a copy for an open circuit
+    DropNewest,
+    /// like `DropNewest`, but the input ports are also paused until the spool is drained to half of `max_bytes`
+    Block,
+}
+
+/// Defines when the Kernel opens the circuit of a port.
+/// A copy that is acknowledged with any other result than `ProcessingResult::Successful` or that timed out is a failure,
+/// an acknowledged copy closes the circuit again.
+///
+/// While the circuit is open, the copies for the port are sent to the dead-letter port right away.
+/// Without a dead-letter port, they fail at once and the sender gets a `ProcessingResult::TransientError`,
+/// unless the ack policy of the routing can be satisfied by the other copies.
+/// Once `open_duration` passed, the circuit is half-open and a single copy is sent to probe the port.
+#[derive(Debug, Clone, PartialEq)]
+pub struct CircuitBreakerOptions {
+    /// number of failures in a row that open the circuit
+    pub failure_threshold: u32,
+    /// time the circuit stays open before a copy probes the port again
+    pub open_duration: Duration,
+}
+
+/// Defines how the Kernel collects the `OutgoingCloudEvent`s for a port into a batch.
+/// The batch is sent as soon as it is full or its first CloudEvent waited for `max_delay`.
+#[derive(Debug, Clone, PartialEq)]
+pub struct BatchOptions {
+    /// maximum number of CloudEvents in one batch
+    pub max_size: usize,
+    /// maximum time a CloudEvent waits in the batch
+    pub max_delay: Duration,
+}
+
+/// Defines if and when the Kernel resends a CloudEvent to a port that answered with `ProcessingResult::TransientError`.
+/// The sender gets the result only after the last attempt failed.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RetryPolicy {
+    /// maximum number of attempts including the first one, `1` disables the retries
+    pub max_attempts: u32,
+    /// wait time before the first retry
+    pub initial_backoff: Duration,
+    /// upper limit of the wait time between two attempts
+    pub max_backoff: Duration,
+    /// factor by which the wait time grows with every attempt
+    pub multiplier: f64,
+    /// Random part of the wait time to spread the retries of many CloudEvents.
+    /// `0.0` disables it, `1.0` allows to shorten the wait time down to zero.
+    pub jitter: f64,
+}
+
+impl Default for KernelOptions {
+    fn default() -> Self {
+        KernelOptions {
+            delivery_ttl: Duration::from_millis(DEFAULT_DELIVERY_TTL_MS),
+            retry: RetryPolicy::default(),
+            dead_letter_port: None,
+            ports: HashMap::new(),
+            restart: RetryPolicy {
+                max_attempts: u32::MAX,
+                initial_backoff: Duration::from_millis(100),
+                max_backoff: Duration::from_secs(30),
+                multiplier: 2.0,
+                jitter: 0.2,
+            },
+            idempotency: IdempotencyOptions::default(),
+            journal: JournalOptions::default(),
+            flow_control: FlowControlOptions::default(),
+            startup_timeout: Duration::from_secs(30),
+            system_events: false,
+            circuit_breaker: None,
+        }
+    }
+}
+
+impl Default for IdempotencyOptions {
+    fn default() -> Self {
+        IdempotencyOptions {
+            max_entries: 100_000,
+            retention: Duration::from_secs(24 * 60 * 60),
+            path: None,
+        }
+    }
+}
+
+impl Default for CircuitBreakerOptions {
+    fn default() -> Self {
+        CircuitBreakerOptions {
+            failure_threshold: 5,
+            open_duration: Duration::from_secs(30),
+        }
+    }
+}
+
+impl Default for RetryPolicy {
+    fn default() -> Self {
+        RetryPolicy {
+            max_attempts: 1,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_secs(10),
+            multiplier: 2.0,
+            jitter: 0.2,
+        }
+    }
+}
+
+impl RetryPolicy {
+    /// the wait time after the given number of failed attempts
+    pub fn backoff(&self, failed_attempts: u32) -> Duration {
+        let exponent = failed_attempts.saturating_sub(1).min(i32::MAX as u32) as i32;
+        let backoff = (self.initial_backoff.as_secs_f64() * self.multiplier.powi(exponent))
+            .min(self.max_backoff.as_secs_f64());
+        let jitter = backoff * self.jitter.clamp(0.0, 1.0) * fastrand::f64();
+        Duration::from_secs_f64((backoff - jitter).max(0.0))
+    }
+}
+
+impl KernelOptions {
+    /// the time the Kernel waits for an acknowledgment of the given destination port
+    pub fn delivery_ttl_of(&self, destination_id: &str) -> Duration {
+        self.ports
+            .get(destination_id)
+            .and_then(|options| options.delivery_ttl)
+            .unwrap_or(self.delivery_ttl)
+    }
+
+    /// the retry policy for CloudEvents sent to the given destination port
+    pub fn retry_policy_of(&self, destination_id: &str) -> &RetryPolicy {
+        self.ports
+            .get(destination_id)
+            .and_then(|options| options.retry.as_ref())
+            .unwrap_or(&self.retry)
+    }
+
+    /// the circuit breaker options of the given destination port, `None` if its circuit is never opened
+    pub fn circuit_breaker_of(&self, destination_id: &str) -> Option<&CircuitBreakerOptions> {
+        self.ports
+            .get(destination_id)
+            .and_then(|options| options.circuit_breaker.as_ref())
+            .or(self.circuit_breaker.as_ref())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn delivery_ttl_of_should_use_port_options() {
+        let mut options = KernelOptions::default();
+        options.ports.insert(
+            "slow".to_string(),
+            PortOptions {
+                delivery_ttl: Some(Duration::from_secs(5)),
+                ..PortOptions::default()
+            },
+        );
+        options
+            .ports
+            .insert("other".to_string(), PortOptions::default());
+
+        assert_eq!(options.delivery_ttl_of("slow"), Duration::from_secs(5));
+        assert_eq!(
+            options.delivery_ttl_of("other"),
+            Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)
+        );
+        assert_eq!(
+            options.delivery_ttl_of("unknown"),
+            Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)
+        );
+    }
+
+    #[test]
+    fn retry_policy_of_should_use_port_options() {
+        let mut options = KernelOptions::default();
+        let port_policy = RetryPolicy {
+            max_attempts: 5,
+            ..RetryPolicy::default()
+        };
+        options.ports.insert(
+            "flaky".to_string(),
+            PortOptions {
+                retry: Some(port_policy.clone()),
+                ..PortOptions::default()
+            },
+        );
+
+        assert_eq!(options.retry_policy_of("flaky"), &port_policy);
+        assert_eq!(options.retry_policy_of("other"), &RetryPolicy::default());
+    }
+
+    #[test]
+    fn backoff_should_grow_exponentially_until_max() {
+        let policy = RetryPolicy {
+            max_attempts: 10,
+            initial_backoff: Duration::from_millis(100),
+            max_backoff: Duration::from_millis(500),
+            multiplier: 2.0,
+            jitter: 0.0,
+        };
+        assert_eq!(policy.backoff(1), Duration::from_millis(100));
+        assert_eq!(policy.backoff(2), Duration::from_millis(200));
+        assert_eq!(policy.backoff(3), Duration::from_millis(400));
+        assert_eq!(policy.backoff(4), Duration::from_millis(500));
+        assert_eq!(policy.backoff(u32::MAX), Duration::from_millis(500));
+    }
+
+    #[test]
+    fn backoff_should_be_shortened_by_jitter() {
+        let policy = RetryPolicy {
+            jitter: 0.5,
+            ..RetryPolicy::default()
+        };
+        for _ in 0..100 {
+            let backoff = policy.backoff(1);
+            assert!(backoff <= policy.initial_backoff);
+            assert!(backoff >= policy.initial_backoff / 2);
+        }
+    }
+}
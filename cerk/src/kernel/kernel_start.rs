@@ -1,64 +1,514 @@
 //! Implementation of the Kernel
 
-use super::{BrokerEvent, StartOptions};
+use super::batches::Batches;
+use super::circuit_breakers::CircuitBreakers;
+use super::dead_letter::to_dead_letter;
+use super::idempotency_store::{IdempotencyKey, IdempotencyStore};
+use super::journal::Journal;
+use super::metrics::{
+    Metrics, METRIC_DUPLICATES, METRIC_INCOMING_EVENTS, METRIC_OUTGOING_EVENTS,
+    METRIC_PENDING_DELIVERIES, METRIC_ROUTED_EVENTS, METRIC_ROUTING_LATENCY, METRIC_TIMEOUTS,
+    METRIC_UNACKNOWLEDGED_EVENTS,
+};
+use super::pending_deliveries::{MissingDelivery, PendingDeliveries, PendingDelivery};
+use super::spool::{SpoolAcknowledgment, Spools};
+use super::system_events::{
+    SystemEvents, SYSTEM_EVENTS_ID, SYSTEM_EVENT_COMPONENT_SCHEDULED, SYSTEM_EVENT_CONFIG_APPLIED,
+    SYSTEM_EVENT_CONFIG_REJECTED, SYSTEM_EVENT_DEAD_LETTERED, SYSTEM_EVENT_DELIVERY_TIMEOUT,
+};
+use super::{BrokerEvent, IdempotencyOptions, KernelOptions, StartOptions};
 use crate::kernel::broker_event::{
-    OutgoingCloudEventProcessed, RoutingResult, ScheduleInternalServer,
+    ComponentInfo, ComponentsRequest, ComponentsResponse, HealthCheckRequest, HealthCheckStatus,
+    IncomingCloudEvent, MetricsRequest, MetricsResponse, OutgoingCloudEvent,
+    OutgoingCloudEventProcessed, ProcessorResult, RoutingResult, ScheduleInternalServer,
+};
+use crate::kernel::{
+    AckPolicy, CloudEventMessageRoutingId, Config, DeliveryGuarantee, ProcessingResult,
 };
-use crate::kernel::{CloudEventMessageRoutingId, ProcessingResult};
 use crate::runtime::channel::{BoxedReceiver, BoxedSender};
-use crate::runtime::InternalServerId;
-use std::collections::HashMap;
+use crate::runtime::{InternalServerFnRefStatic, InternalServerId};
+use anyhow::Result;
+use cloudevents::AttributesReader;
+use serde_json::json;
+use std::collections::{HashMap, HashSet};
 use std::ops::Add;
 use std::time::{Duration, SystemTime};
 
-const ROUTER_ID: &str = "router";
-const CONFIG_LOADER_ID: &str = "config_loader";
-const ROUTING_TTL_MS: u64 = 100;
+/// The id to address the Kernel itself, e.g. with a `HealthCheckRequest` to check that its loop is alive.
+/// No component can use it.
+pub const KERNEL_ID: &str = "kernel";
+/// The id of the router, no other component can use it.
+pub const ROUTER_ID: &str = "router";
+/// The id of the config loader, no other component can use it.
+pub const CONFIG_LOADER_ID: &str = "config_loader";
+/// The exit code `bootstrap` uses if the startup is not completed within `KernelOptions.startup_timeout`.
+pub const STARTUP_FAILED_EXIT_CODE: i32 = 1;
+const SHUTDOWN_TIMEOUT_MS: u64 = 5000;
+const SHUTDOWN_POLL_INTERVAL_MS: u64 = 10;
+/// interval in which the Kernel checks if the ports with spooled copies are available again
+const SPOOL_POLL_INTERVAL_MS: u64 = 100;
+/// spooled copies of a port that wait for their acknowledgment at the same time
+const MAX_DRAINED_COPIES: usize = 100;
+/// routings that take longer are not measured
+const MAX_ROUTING_LATENCY_MS: u64 = 60_000;
+
+type Outboxes = HashMap<InternalServerId, BoxedSender>;
+type Supervisions = HashMap<InternalServerId, Supervision>;
 
-struct PendingDelivery {
-    sender: InternalServerId,
-    missing_receivers: Vec<InternalServerId>,
-    ttl: SystemTime,
+/// status of a component that exited unexpectedly
+enum ComponentStatus {
+    /// the component is scheduled again at the given time
+    Restarting(SystemTime),
+    /// the Scheduler was asked to schedule the component again
+    Scheduling,
+    /// the component was scheduled again
+    Running,
+    /// the component exited too often and stays down
+    Failed,
 }
 
-type Outboxes = HashMap<InternalServerId, BoxedSender>;
-type PendingDeliveries = HashMap<CloudEventMessageRoutingId, PendingDelivery>;
+/// supervision of a component that exited at least once
+struct Supervision {
+    status: ComponentStatus,
+    /// restarts in a row, reset once the component ran longer than the maximal backoff
+    restarts: u32,
+    /// time of the last restart
+    restarted_at: SystemTime,
+}
 
-fn clean_pending_deliveries(outboxes: &Outboxes, pending_deliveries: &mut PendingDeliveries) {
-    let now = SystemTime::now();
-    if pending_deliveries.len() > 0 {
-        let to_remove: Vec<CloudEventMessageRoutingId> = {
-            let dead_messages: HashMap<&CloudEventMessageRoutingId, &PendingDelivery> =
-                pending_deliveries
+fn is_restarting(supervisions: &Supervisions, id: &str) -> bool {
+    matches!(
+        supervisions.get(id).map(|supervision| &supervision.status),
+        Some(ComponentStatus::Restarting(_)) | Some(ComponentStatus::Scheduling)
+    )
+}
+
+/// phases of the startup, each of them has to be completed until the deadline of `KernelOptions.startup_timeout`
+enum StartupPhase {
+    /// the Scheduler was asked to schedule all components, the Kernel waits until all of them are scheduled
+    Scheduling(SystemTime),
+    /// the config loader got `BrokerEvent::Init`, the Kernel waits until all other components got their config
+    Configuring(SystemTime),
+    /// `BrokerEvent::Init` was sent to all components
+    Completed,
+}
+
+/// phases of a graceful shutdown
+enum ShutdownPhase {
+    /// no new CloudEvents are accepted, the kernel waits until the pending deliveries are settled
+    Draining,
+    /// `BrokerEvent::Shutdown` was sent to all components, the kernel waits for their `ShutdownComplete` until the deadline
+    StoppingComponents(SystemTime),
+}
+
+struct KernelState {
+    outboxes: Outboxes,
+    pending_deliveries: PendingDeliveries,
+    shutdown: Option<ShutdownPhase>,
+    options: KernelOptions,
+    /// start functions of all components, used to restart them
+    functions: HashMap<InternalServerId, InternalServerFnRefStatic>,
+    /// the last config of each component, it is sent again after a restart
+    configs: HashMap<InternalServerId, Config>,
+    /// The config a component had before the last `ConfigUpdated`, until the component answered it.
+    /// It is restored if the component rejects the new config.
+    replaced_configs: HashMap<InternalServerId, Option<Config>>,
+    supervisions: Supervisions,
+    startup: StartupPhase,
+    sender_to_scheduler: BoxedSender,
+    metrics: Metrics,
+    /// time the CloudEvents were forwarded to the first stage, key: (incoming_id, routing_id)
+    routing_started: HashMap<(InternalServerId, CloudEventMessageRoutingId), SystemTime>,
+    /// ids of the processors in the order the CloudEvents pass them, the router is the last stage
+    pipeline: Vec<InternalServerId>,
+    /// the CloudEvents that were delivered with `DeliveryGuarantee::ExactlyOnce`
+    idempotency: IdempotencyStore,
+    /// write-ahead journal of the incoming CloudEvents, see `KernelOptions.journal`
+    journal: Journal,
+    /// the CloudEvents that wait to be sent to ports with `PortOptions.batch`
+    batches: Batches,
+    /// Incoming CloudEvents that wait until the startup is completed and the CloudEvents of the journal were routed again,
+    /// in the order they arrived. `None` once the journal was replayed.
+    held_back: Option<Vec<IncomingCloudEvent>>,
+    /// components that sent an `IncomingCloudEvent`
+    inputs: HashSet<InternalServerId>,
+    /// input ports that got `BrokerEvent::Pause`
+    paused: HashSet<InternalServerId>,
+    /// the CloudEvents the Kernel emitted about itself, see `KernelOptions.system_events`
+    system_events: SystemEvents,
+    /// the circuits of the ports, see `KernelOptions.circuit_breaker`
+    circuit_breakers: CircuitBreakers,
+    /// the copies for unavailable ports, see `PortOptions.spool`
+    spools: Spools,
+}
+
+/// The stage after the given processor, the first stage if `None`.
+/// The router follows the last processor.
+fn next_stage<'a>(pipeline: &'a [InternalServerId], processor_id: Option<&str>) -> &'a str {
+    let next = match processor_id {
+        Some(processor_id) => pipeline
+            .iter()
+            .skip_while(|id| id.as_str() != processor_id)
+            .nth(1),
+        None => pipeline.first(),
+    };
+    next.map_or(ROUTER_ID, |id| id.as_str())
+}
+
+/// A processor that dropped or failed to process a CloudEvent ends its routing.
+fn ends_routing(event: &ProcessorResult) -> bool {
+    event.cloud_event.is_none() || event.result != ProcessingResult::Successful
+}
+
+/// Sends the CloudEvent to the given stage, it is rejected if the stage is not scheduled.
+fn forward_to_stage(state: &KernelState, stage_id: &str, event: IncomingCloudEvent) -> Result<()> {
+    if state.outboxes.contains_key(stage_id) {
+        send_to_server(
+            &state.outboxes,
+            stage_id,
+            BrokerEvent::IncomingCloudEvent(event),
+        )
+    } else {
+        // while the stage restarts, the sender may redeliver the event later
+        let result = if is_restarting(&state.supervisions, stage_id) {
+            ProcessingResult::TransientError
+        } else {
+            ProcessingResult::PermanentError
+        };
+        reject_incoming_cloud_event(&state.outboxes, stage_id, event, result)
+    }
+}
+
+/// Passes the CloudEvent of a processor on to the next stage or answers the sender if the processor did not pass it on.
+fn process_processor_result(state: &mut KernelState, event: ProcessorResult) -> Result<()> {
+    if !state.pipeline.contains(&event.sender_id) {
+        bail!(
+            "{} is not a processor, its result for event_id={} was dropped",
+            event.sender_id,
+            event.routing_id
+        );
+    }
+    let ProcessorResult {
+        sender_id,
+        incoming_id,
+        routing_id,
+        cloud_event,
+        args,
+        result,
+    } = event;
+    match cloud_event {
+        Some(cloud_event) if result == ProcessingResult::Successful => {
+            let stage_id = next_stage(&state.pipeline, Some(&sender_id)).to_string();
+            let event = IncomingCloudEvent {
+                incoming_id,
+                routing_id,
+                cloud_event,
+                args,
+            };
+            forward_to_stage(state, &stage_id, event)
+        }
+        _ => {
+            debug!(
+                "{} did not pass event_id={} on, notify {} with {}",
+                sender_id, routing_id, incoming_id, result
+            );
+            if args.delivery_guarantee.requires_acknowledgment() {
+                send_to_server(
+                    &state.outboxes,
+                    &incoming_id,
+                    BrokerEvent::IncomingCloudEventProcessed(routing_id, result),
+                )?;
+            }
+            Ok(())
+        }
+    }
+}
+
+/// Sends the event to the component with the given id.
+/// Fails if no such component was scheduled, the event is dropped in that case.
+fn send_to_server(outboxes: &Outboxes, id: &str, event: BrokerEvent) -> Result<()> {
+    match outboxes.get(id) {
+        Some(outbox) => {
+            outbox.send(event);
+            Ok(())
+        }
+        None => bail!(
+            "no component with id={} is scheduled, {} dropped",
+            id,
+            event
+        ),
+    }
+}
+
+fn clean_pending_deliveries(
+    outboxes: &Outboxes,
+    pending_deliveries: &mut PendingDeliveries,
+    options: &KernelOptions,
+    metrics: &mut Metrics,
+    system_events: &mut SystemEvents,
+    circuit_breakers: &mut CircuitBreakers,
+) {
+    for key in pending_deliveries.expired(SystemTime::now()) {
+        if let Some(delivery) = pending_deliveries.remove(&key) {
+            let (_, routing_id) = key;
+            for missing in delivery.missing_deliveries().iter() {
+                metrics.increment(METRIC_TIMEOUTS, &missing.destination_id);
+                circuit_breakers.record_failure(options, &missing.destination_id);
+                system_events.emit(
+                    SYSTEM_EVENT_DELIVERY_TIMEOUT,
+                    &missing.destination_id,
+                    json!({
+                        "incoming_id": delivery.sender,
+                        "routing_id": routing_id,
+                        "attempts": missing.attempts,
+                    }),
+                );
+            }
+            let sender = delivery.sender.clone();
+            let failed: Vec<_> = delivery
+                .into_missing_deliveries()
+                .into_iter()
+                .filter_map(|missing| missing.event.map(|event| (event, missing.attempts)))
+                .collect();
+            if dead_letter(
+                outboxes,
+                pending_deliveries,
+                options,
+                metrics,
+                system_events,
+                &routing_id,
+                &sender,
+                true,
+                failed,
+                &ProcessingResult::Timeout,
+            ) {
+                continue;
+            }
+            warn!("ttl exceeded for routing_id={}, will send back to receiver={} with  ProcessingResult::Timeout", routing_id, sender);
+            if let Err(e) = send_to_server(
+                outboxes,
+                &sender,
+                BrokerEvent::IncomingCloudEventProcessed(
+                    routing_id.clone(),
+                    ProcessingResult::Timeout,
+                ),
+            ) {
+                error!(
+                    "failed to send timeout for routing_id={}: {}",
+                    routing_id, e
+                );
+            }
+        }
+    }
+}
+
+/// The key to recognize a redelivered copy, only copies with `DeliveryGuarantee::ExactlyOnce` have one.
+fn idempotency_key(event: &OutgoingCloudEvent) -> Option<IdempotencyKey> {
+    if event.args.delivery_guarantee == DeliveryGuarantee::ExactlyOnce {
+        Some((
+            event.destination_id.clone(),
+            event.cloud_event.source().to_string(),
+            event.cloud_event.id().to_string(),
+        ))
+    } else {
+        None
+    }
+}
+
+/// the copies are kept for retries and dead letters
+fn keep_copy(options: &KernelOptions, destination_id: &str) -> bool {
+    options.retry_policy_of(destination_id).max_attempts > 1
+        || options
+            .dead_letter_port
+            .as_ref()
+            .is_some_and(|port| port != destination_id)
+}
+
+/// Sends the copies to their destinations.
+/// If `ack_to` is set, the copies are tracked as pending delivery and the component gets the result of the delivery
+/// as soon as the ack policy is satisfied or can not be satisfied anymore.
+#[allow(clippy::too_many_arguments)]
+fn deliver(
+    outboxes: &Outboxes,
+    pending_deliveries: &mut PendingDeliveries,
+    options: &KernelOptions,
+    metrics: &mut Metrics,
+    routing_id: CloudEventMessageRoutingId,
+    ack_to: Option<InternalServerId>,
+    ack_policy: AckPolicy,
+    mut routing: Vec<OutgoingCloudEvent>,
+) -> Result<()> {
+    for subevent in routing.iter_mut() {
+        subevent.delivery_id = pending_deliveries.next_delivery_id();
+    }
+
+    if let Some(sender) = ack_to {
+        let missing_deliveries: Vec<_> = routing
+            .iter()
+            .map(|event| MissingDelivery {
+                destination_id: event.destination_id.clone(),
+                delivery_id: event.delivery_id,
+                attempts: 1,
+                event: if keep_copy(options, &event.destination_id) {
+                    Some(event.clone())
+                } else {
+                    None
+                },
+                retry_at: None,
+                idempotency_key: idempotency_key(event),
+            })
+            .collect();
+        // the delivery is only complete if all receivers answered, so the slowest one defines the ttl
+        let delivery_ttl = missing_deliveries
+            .iter()
+            .map(|missing| options.delivery_ttl_of(&missing.destination_id))
+            .max()
+            .unwrap_or(options.delivery_ttl);
+
+        if pending_deliveries
+            .insert(
+                (sender.clone(), routing_id.clone()),
+                PendingDelivery::new(
+                    sender.clone(),
+                    missing_deliveries,
+                    get_ttl(delivery_ttl),
+                    ack_policy,
+                ),
+            )
+            .is_some()
+        {
+            error!(
+                "a routing for event_id={} from {} already existed, the old one was overwritten",
+                &routing_id, sender
+            );
+        }
+    } else {
+        debug!("no acknowledgments needed for event_id={}", &routing_id)
+    }
+
+    for subevent in routing {
+        let destination_id = subevent.destination_id.clone();
+        send_to_server(
+            outboxes,
+            &destination_id,
+            BrokerEvent::OutgoingCloudEvent(subevent),
+        )?;
+        metrics.increment(METRIC_OUTGOING_EVENTS, &destination_id);
+    }
+    debug!("all routing sent for event_id={}", routing_id);
+    Ok(())
+}
+
+/// Sends the copies that failed with `ProcessingResult::PermanentError` or `ProcessingResult::Timeout` to the dead-letter port.
+/// If `replaces_delivery` is set, the dead letters replace the failed delivery and the sender gets the result of the dead-letter port.
+/// Otherwise, the delivery is still pending (its ack policy can be satisfied by the other copies) and the dead letters are not acknowledged.
+/// Copies that failed on the dead-letter port itself are not sent again.
+///
+/// Returns `false` if nothing was sent, the caller has to notify the sender about the failure in that case.
+#[allow(clippy::too_many_arguments)]
+fn dead_letter(
+    outboxes: &Outboxes,
+    pending_deliveries: &mut PendingDeliveries,
+    options: &KernelOptions,
+    metrics: &mut Metrics,
+    system_events: &mut SystemEvents,
+    routing_id: &CloudEventMessageRoutingId,
+    sender: &InternalServerId,
+    replaces_delivery: bool,
+    failed: Vec<(OutgoingCloudEvent, u32)>,
+    reason: &ProcessingResult,
+) -> bool {
+    let dead_letter_port = match options.dead_letter_port {
+        Some(ref port) => port,
+        None => return false,
+    };
+    if !matches!(
+        reason,
+        ProcessingResult::PermanentError | ProcessingResult::Timeout
+    ) {
+        return false;
+    }
+    let dead_letters: Vec<_> = failed
+        .iter()
+        .filter(|(event, _)| event.destination_id != *dead_letter_port)
+        .map(|(event, attempts)| to_dead_letter(event, reason, *attempts, dead_letter_port))
+        .collect();
+    if dead_letters.is_empty() {
+        return false;
+    }
+
+    warn!(
+        "{} copies of event_id={} failed with {} -> send them to the dead-letter port {}",
+        dead_letters.len(),
+        routing_id,
+        reason,
+        dead_letter_port
+    );
+    let requires_acknowledgment = replaces_delivery
+        && dead_letters
+            .iter()
+            .any(|event| event.args.delivery_guarantee.requires_acknowledgment());
+    match deliver(
+        outboxes,
+        pending_deliveries,
+        options,
+        metrics,
+        routing_id.clone(),
+        Some(sender.clone()).filter(|_| requires_acknowledgment),
+        AckPolicy::All,
+        dead_letters,
+    ) {
+        Ok(()) => {
+            // a system event that is dead-lettered must not cause another one
+            if sender != SYSTEM_EVENTS_ID {
+                for (event, attempts) in failed
                     .iter()
-                    .filter(|(_, v)| v.ttl < now)
-                    .collect();
-            for (routing_id, data) in dead_messages.iter() {
-                warn!("ttl exceeded for routing_id={}, will send back to receiver={} with  ProcessingResult::Timeout", routing_id, data.sender);
-                outboxes
-                    .get(&data.sender)
-                    .unwrap()
-                    .send(BrokerEvent::IncomingCloudEventProcessed(
-                        (*routing_id).clone(),
-                        ProcessingResult::Timeout,
-                    ));
+                    .filter(|(event, _)| event.destination_id != *dead_letter_port)
+                {
+                    system_events.emit(
+                        SYSTEM_EVENT_DEAD_LETTERED,
+                        &event.destination_id,
+                        json!({
+                            "incoming_id": sender,
+                            "routing_id": routing_id,
+                            "reason": reason.to_string(),
+                            "attempts": attempts,
+                            "dead_letter_port": dead_letter_port,
+                        }),
+                    );
+                }
             }
-            dead_messages.iter().map(|(k, _)| *k).cloned().collect()
-        };
-        for routing_id in to_remove {
-            pending_deliveries.remove_entry(&routing_id);
+            true
+        }
+        Err(e) => {
+            error!(
+                "failed to send dead letters for event_id={}: {}",
+                routing_id, e
+            );
+            if replaces_delivery {
+                pending_deliveries.remove(&(sender.clone(), routing_id.clone()));
+            }
+            false
         }
     }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_routing_result(
     event: RoutingResult,
     outboxes: &mut Outboxes,
     pending_deliveries: &mut PendingDeliveries,
-) {
+    options: &KernelOptions,
+    metrics: &mut Metrics,
+    system_events: &mut SystemEvents,
+    circuit_breakers: &mut CircuitBreakers,
+    spools: &mut Spools,
+    supervisions: &Supervisions,
+    idempotency: &IdempotencyStore,
+) -> Result<()> {
     let RoutingResult {
         routing_id,
-        routing,
+        mut routing,
         incoming_id: receiver_id,
         args,
         result,
@@ -70,252 +520,1603 @@ fn process_routing_result(
 
     match result {
         ProcessingResult::Successful => {
+            metrics.increment(METRIC_ROUTED_EVENTS, &receiver_id);
+            // redelivered copies are not sent again, they count as acknowledged
+            routing.retain(|event| match idempotency_key(event) {
+                Some(ref key) if idempotency.contains(key) => {
+                    debug!(
+                        "{} already received event_id={} (source={} id={}), the copy is skipped",
+                        event.destination_id, routing_id, key.1, key.2
+                    );
+                    metrics.increment(METRIC_DUPLICATES, &event.destination_id);
+                    false
+                }
+                _ => true,
+            });
             if routing.is_empty() {
                 debug!("routing is empty - nothing to do; ack if needed");
                 if args.delivery_guarantee.requires_acknowledgment() {
-                    outboxes.get(&receiver_id).unwrap().send(
+                    send_to_server(
+                        outboxes,
+                        &receiver_id,
                         BrokerEvent::IncomingCloudEventProcessed(
                             routing_id,
                             ProcessingResult::Successful,
                         ),
-                    );
+                    )?;
                 }
             } else {
-                if args.delivery_guarantee.requires_acknowledgment() {
-                    let missing_receivers: Vec<_> = routing
-                        .iter()
-                        .map(|event| event.destination_id.clone())
-                        .collect();
-
-                    clean_pending_deliveries(outboxes, pending_deliveries);
-                    if pending_deliveries
-                        .insert(
-                            routing_id.clone(),
-                            PendingDelivery {
-                                sender: receiver_id,
-                                missing_receivers,
-                                ttl: get_ttl(),
-                            },
-                        )
-                        .is_some()
+                let restarting_destinations: Vec<_> = routing
+                    .iter()
+                    .map(|event| event.destination_id.clone())
+                    .filter(|id| is_restarting(supervisions, id) && !spools.contains(id))
+                    .collect();
+                if !restarting_destinations.is_empty() {
+                    // the sender may redeliver the event once the destinations are back
+                    let error = anyhow!(
+                        "destinations {:?} of event_id={} are restarting, the event was rejected",
+                        restarting_destinations,
+                        routing_id
+                    );
+                    if args.delivery_guarantee.requires_acknowledgment() {
+                        send_to_server(
+                            outboxes,
+                            &receiver_id,
+                            BrokerEvent::IncomingCloudEventProcessed(
+                                routing_id,
+                                ProcessingResult::TransientError,
+                            ),
+                        )?;
+                    }
+                    return Err(error);
+                }
+                let unknown_destinations: Vec<_> = routing
+                    .iter()
+                    .map(|event| event.destination_id.clone())
+                    .filter(|id| !outboxes.contains_key(id) && !spools.contains(id))
+                    .collect();
+                match options.dead_letter_port {
+                    _ if unknown_destinations.is_empty() => {}
+                    Some(ref port) if outboxes.contains_key(port) => {
+                        warn!(
+                            "routing for event_id={} contains unknown destinations {:?}, they are replaced by dead letters",
+                            routing_id, unknown_destinations
+                        );
+                        if receiver_id != SYSTEM_EVENTS_ID {
+                            for destination_id in unknown_destinations.iter() {
+                                system_events.emit(
+                                    SYSTEM_EVENT_DEAD_LETTERED,
+                                    destination_id,
+                                    json!({
+                                        "incoming_id": receiver_id,
+                                        "routing_id": routing_id,
+                                        "reason": ProcessingResult::PermanentError.to_string(),
+                                        "attempts": 0,
+                                        "dead_letter_port": port,
+                                    }),
+                                );
+                            }
+                        }
+                        routing = routing
+                            .into_iter()
+                            .map(|event| {
+                                if outboxes.contains_key(&event.destination_id)
+                                    || spools.contains(&event.destination_id)
+                                {
+                                    event
+                                } else {
+                                    to_dead_letter(
+                                        &event,
+                                        &ProcessingResult::PermanentError,
+                                        0,
+                                        port,
+                                    )
+                                }
+                            })
+                            .collect();
+                    }
+                    _ => {
+                        // nothing is delivered, otherwise a redelivery would duplicate the event for the known destinations
+                        let error = anyhow!(
+                            "routing for event_id={} contains unknown destinations {:?}, the event was rejected",
+                            routing_id,
+                            unknown_destinations
+                        );
+                        if args.delivery_guarantee.requires_acknowledgment() {
+                            send_to_server(
+                                outboxes,
+                                &receiver_id,
+                                BrokerEvent::IncomingCloudEventProcessed(
+                                    routing_id,
+                                    ProcessingResult::PermanentError,
+                                ),
+                            )?;
+                        }
+                        return Err(error);
+                    }
+                }
+
+                // copies for unavailable ports with a spool are spooled, the copies that do not fit can not be delivered
+                let copies = routing.len();
+                let mut spooled = 0;
+                let mut undeliverable = Vec::new();
+                let mut admitted = HashMap::new();
+                let mut deliverable = Vec::with_capacity(copies);
+                for mut event in routing {
+                    let destination_id = &event.destination_id;
+                    let has_spool = spools.contains(destination_id);
+                    // a spool with copies keeps the order, the circuit is not asked for them
+                    let must_spool = has_spool
+                        && (spools.has_copies(destination_id)
+                            || !outboxes.contains_key(destination_id));
+                    if !must_spool
+                        && circuit_breakers.allows_copy(options, destination_id, &mut admitted)
                     {
-                        error!(
-                            "a routing for event_id={} already existed, the old one was overwritten",
-                            &routing_id
+                        deliverable.push(event);
+                        continue;
+                    }
+                    if has_spool {
+                        // the acknowledgments of the drained copies are told apart by it
+                        event.delivery_id = pending_deliveries.next_delivery_id();
+                        if spools.push(&receiver_id, &event) {
+                            spooled += 1;
+                            continue;
+                        }
+                    }
+                    undeliverable.push(event);
+                }
+                routing = deliverable;
+                if spooled > 0 {
+                    debug!("{} copies of event_id={} were spooled", spooled, routing_id);
+                }
+                let undeliverable_ids: Vec<_> = undeliverable
+                    .iter()
+                    .map(|event| event.destination_id.clone())
+                    .collect();
+                let mut failed_copies = 0;
+                match options.dead_letter_port {
+                    _ if undeliverable.is_empty() => {}
+                    Some(ref port)
+                        if outboxes.contains_key(port) && !undeliverable_ids.contains(port) =>
+                    {
+                        warn!(
+                            "{:?} are unavailable, their copies of event_id={} are replaced by dead letters",
+                            undeliverable_ids, routing_id
                         );
+                        if receiver_id != SYSTEM_EVENTS_ID {
+                            for destination_id in undeliverable_ids.iter() {
+                                system_events.emit(
+                                    SYSTEM_EVENT_DEAD_LETTERED,
+                                    destination_id,
+                                    json!({
+                                        "incoming_id": receiver_id,
+                                        "routing_id": routing_id,
+                                        "reason": ProcessingResult::TransientError.to_string(),
+                                        "attempts": 0,
+                                        "dead_letter_port": port,
+                                    }),
+                                );
+                            }
+                        }
+                        routing.extend(undeliverable.iter().map(|event| {
+                            to_dead_letter(event, &ProcessingResult::TransientError, 0, port)
+                        }));
                     }
-                } else {
-                    debug!("no acknowledgments needed for event_id={}", &routing_id)
+                    _ => {
+                        failed_copies = undeliverable.len();
+                        let requires_acknowledgment =
+                            args.delivery_guarantee.requires_acknowledgment();
+                        if routing.len() + spooled == 0
+                            || (requires_acknowledgment
+                                && routing.len() + spooled < args.ack_policy.required(copies))
+                        {
+                            // the sender may redeliver the event once the ports are available again
+                            let error = anyhow!(
+                                "{:?} are unavailable (open circuit or full spool), event_id={} was rejected",
+                                undeliverable_ids,
+                                routing_id
+                            );
+                            if requires_acknowledgment {
+                                send_to_server(
+                                    outboxes,
+                                    &receiver_id,
+                                    BrokerEvent::IncomingCloudEventProcessed(
+                                        routing_id,
+                                        ProcessingResult::TransientError,
+                                    ),
+                                )?;
+                            }
+                            return Err(error);
+                        }
+                        warn!(
+                            "{:?} are unavailable (open circuit or full spool), their copies of event_id={} failed at once",
+                            undeliverable_ids, routing_id
+                        );
+                    }
+                }
+                if routing.is_empty() {
+                    debug!(
+                        "all copies of event_id={} were spooled; ack if needed",
+                        routing_id
+                    );
+                    if args.delivery_guarantee.requires_acknowledgment() {
+                        send_to_server(
+                            outboxes,
+                            &receiver_id,
+                            BrokerEvent::IncomingCloudEventProcessed(
+                                routing_id,
+                                ProcessingResult::Successful,
+                            ),
+                        )?;
+                    }
+                    return Ok(());
                 }
 
-                for subevent in routing {
-                    outboxes
-                        .get(&subevent.destination_id)
-                        .unwrap()
-                        .send(BrokerEvent::OutgoingCloudEvent(subevent));
+                let key = (receiver_id.clone(), routing_id.clone());
+                deliver(
+                    outboxes,
+                    pending_deliveries,
+                    options,
+                    metrics,
+                    routing_id,
+                    Some(receiver_id).filter(|_| args.delivery_guarantee.requires_acknowledgment()),
+                    args.ack_policy,
+                    routing,
+                )?;
+                // the ack policy still has to count the copies that were not sent, the spooled ones are acknowledged
+                if let Some(delivery) = pending_deliveries.get_mut(&key) {
+                    delivery.failed += failed_copies;
+                    delivery.acknowledged += spooled;
+                    if delivery.is_acknowledged() {
+                        pending_deliveries.remove(&key);
+                        send_to_server(
+                            outboxes,
+                            &key.0,
+                            BrokerEvent::IncomingCloudEventProcessed(
+                                key.1,
+                                ProcessingResult::Successful,
+                            ),
+                        )?;
+                    }
                 }
-                debug!("all routing sent for event_id={}", routing_id);
             }
+            Ok(())
         }
         s @ ProcessingResult::PermanentError
         | s @ ProcessingResult::TransientError
         | s @ ProcessingResult::Timeout => {
+            // a router could return the copies it failed to route, they are dead-lettered
+            let failed = routing.into_iter().map(|event| (event, 0)).collect();
+            if dead_letter(
+                outboxes,
+                pending_deliveries,
+                options,
+                metrics,
+                system_events,
+                &routing_id,
+                &receiver_id,
+                true,
+                failed,
+                &s,
+            ) {
+                return Ok(());
+            }
             if args.delivery_guarantee.requires_acknowledgment() {
-                outboxes
-                    .get(&receiver_id)
-                    .unwrap()
-                    .send(BrokerEvent::IncomingCloudEventProcessed(routing_id, s));
+                send_to_server(
+                    outboxes,
+                    &receiver_id,
+                    BrokerEvent::IncomingCloudEventProcessed(routing_id, s),
+                )?;
             }
+            Ok(())
         }
     }
 }
 
-fn get_ttl() -> SystemTime {
-    SystemTime::now().add(Duration::from_millis(ROUTING_TTL_MS))
+fn get_ttl(delivery_ttl: Duration) -> SystemTime {
+    SystemTime::now().add(delivery_ttl)
+}
+
+/// Time until the next pending delivery expires, a retry, restart or batch is due, the startup deadline passes,
+/// the spools or the shutdown have to be checked again.
+/// `None` if the Kernel can block until the next event arrives.
+fn get_receive_timeout(state: &KernelState) -> Option<Duration> {
+    let now = SystemTime::now();
+    let next_timer = state
+        .pending_deliveries
+        .next_deadline()
+        .into_iter()
+        .chain(
+            state
+                .supervisions
+                .values()
+                .filter_map(|supervision| match supervision.status {
+                    ComponentStatus::Restarting(restart_at) => Some(restart_at),
+                    _ => None,
+                }),
+        )
+        .chain(state.batches.next_deadline())
+        .chain(state.spools.next_deadline())
+        .chain(if state.spools.is_draining() {
+            Some(now.add(Duration::from_millis(SPOOL_POLL_INTERVAL_MS)))
+        } else {
+            None
+        })
+        .chain(match state.startup {
+            StartupPhase::Scheduling(deadline) | StartupPhase::Configuring(deadline)
+                if state.shutdown.is_none() =>
+            {
+                Some(deadline)
+            }
+            _ => None,
+        })
+        .map(|time| time.duration_since(now).unwrap_or_default())
+        .min();
+    let shutdown_poll = state
+        .shutdown
+        .as_ref()
+        .map(|_| Duration::from_millis(SHUTDOWN_POLL_INTERVAL_MS));
+    next_timer.into_iter().chain(shutdown_poll).min()
+}
+
+/// Resends the copies whose retry is due.
+/// If a copy can not be resent, the whole delivery fails with `ProcessingResult::PermanentError`.
+fn send_due_retries(
+    outboxes: &Outboxes,
+    pending_deliveries: &mut PendingDeliveries,
+    options: &KernelOptions,
+    metrics: &mut Metrics,
+    system_events: &mut SystemEvents,
+) {
+    let mut failed_deliveries = Vec::new();
+    for (key, delivery_id) in pending_deliveries.due_retries(SystemTime::now()) {
+        let missing = match pending_deliveries.start_retry(&key, delivery_id) {
+            Some(missing) => missing,
+            None => continue,
+        };
+        let (_, routing_id) = &key;
+        debug!(
+            "retry delivery_id={} of event_id={} to {} (attempt {})",
+            missing.delivery_id, routing_id, missing.destination_id, missing.attempts
+        );
+        let result = match missing.event.clone() {
+            Some(event) => send_to_server(
+                outboxes,
+                &missing.destination_id,
+                BrokerEvent::OutgoingCloudEvent(event),
+            ),
+            None => Err(anyhow!("no copy of the CloudEvent was kept")),
+        };
+        if result.is_ok() {
+            metrics.increment(METRIC_OUTGOING_EVENTS, &missing.destination_id);
+        }
+        if let Err(e) = result {
+            error!(
+                "failed to retry delivery_id={} of event_id={}: {}",
+                missing.delivery_id, routing_id, e
+            );
+            let failed_copy = missing.event.clone().map(|event| (event, missing.attempts));
+            failed_deliveries.push((key.clone(), failed_copy));
+        }
+    }
+    for (key, failed_copy) in failed_deliveries {
+        if let Some(delivery) = pending_deliveries.remove(&key) {
+            let (_, routing_id) = key;
+            if dead_letter(
+                outboxes,
+                pending_deliveries,
+                options,
+                metrics,
+                system_events,
+                &routing_id,
+                &delivery.sender,
+                true,
+                failed_copy.into_iter().collect(),
+                &ProcessingResult::PermanentError,
+            ) {
+                continue;
+            }
+            if let Err(e) = send_to_server(
+                outboxes,
+                &delivery.sender,
+                BrokerEvent::IncomingCloudEventProcessed(
+                    routing_id,
+                    ProcessingResult::PermanentError,
+                ),
+            ) {
+                error!("failed to notify the sender about a failed retry: {}", e);
+            }
+        }
+    }
 }
 
+#[allow(clippy::too_many_arguments)]
 fn process_outgoing_cloud_event_processed(
     event: OutgoingCloudEventProcessed,
     outboxes: &mut Outboxes,
     pending_deliveries: &mut PendingDeliveries,
-) {
+    options: &KernelOptions,
+    metrics: &mut Metrics,
+    system_events: &mut SystemEvents,
+    circuit_breakers: &mut CircuitBreakers,
+    spools: &mut Spools,
+    idempotency: &mut IdempotencyStore,
+    journal: &Journal,
+) -> Result<()> {
     let OutgoingCloudEventProcessed {
         routing_id,
         sender_id,
+        delivery_id,
         result,
     } = event;
     debug!(
-        "received OutgoingCloudEventProcessed from={} event_id={}",
-        sender_id, routing_id
+        "received OutgoingCloudEventProcessed from={} event_id={} delivery_id={}",
+        sender_id, routing_id, delivery_id
     );
+    metrics.count_ack(&sender_id, &result);
+    if result == ProcessingResult::Successful {
+        circuit_breakers.record_success(&sender_id);
+    } else {
+        circuit_breakers.record_failure(options, &sender_id);
+    }
+    if let Some(acknowledgment) = spools.acknowledge(&sender_id, &routing_id, delivery_id, &result)
+    {
+        if let SpoolAcknowledgment::Rejected(incoming_id, event, attempts) = acknowledgment {
+            if !dead_letter(
+                outboxes,
+                pending_deliveries,
+                options,
+                metrics,
+                system_events,
+                &routing_id,
+                &incoming_id,
+                false,
+                vec![(event, attempts)],
+                &result,
+            ) {
+                error!(
+                    "{} rejected the spooled event_id={} with {}, it was dropped",
+                    sender_id, routing_id, result
+                );
+            }
+        }
+        return Ok(());
+    }
     let mut resolved_missing_delivery = false;
-    if let Some(delivery) = pending_deliveries.get_mut(&routing_id) {
-        match result {
-            ProcessingResult::Successful => {
-                let size_before = delivery.missing_receivers.len();
-                delivery.missing_receivers.retain(|i| !i.eq(&sender_id));
-                let size = delivery.missing_receivers.len();
-                if size == 0 {
-                    debug!("delivery for event_id={} was successful (all out port processing were successful) -> ack to sender", routing_id);
-                    outboxes.get(&delivery.sender).unwrap().send(
-                        BrokerEvent::IncomingCloudEventProcessed(routing_id.clone(), result),
-                    );
+    let mut failed_copy = None;
+    let key = pending_deliveries.find(&routing_id, &sender_id, delivery_id);
+    match key {
+        Some(ref key) if result == ProcessingResult::Successful => {
+            let missing = pending_deliveries.remove_copy(key, delivery_id);
+            if let Some(idempotency_key) = missing.and_then(|missing| missing.idempotency_key) {
+                idempotency.insert(idempotency_key);
+            }
+            journal.acknowledge(&key.0, &routing_id, &sender_id);
+            if let Some(delivery) = pending_deliveries.get_mut(key) {
+                delivery.acknowledged += 1;
+                if delivery.is_acknowledged() {
+                    debug!("delivery for event_id={} was successful (ack policy {:?} is satisfied) -> ack to sender", routing_id, delivery.ack_policy);
                     resolved_missing_delivery = true
-                } else if size_before == size {
-                    warn!("{} sent OutgoingCloudEventProcessed for event_id={}, but was not expected to send this", sender_id, routing_id);
                 }
             }
-            _ => {
-                if delivery.missing_receivers.contains(&sender_id) {
-                    debug!("delivery for event_id={} was NOT successful ({}) -> immediately notify the sender", routing_id, result);
-                    outboxes.get(&delivery.sender).unwrap().send(
-                        BrokerEvent::IncomingCloudEventProcessed(routing_id.clone(), result),
+        }
+        Some(ref key) => {
+            let policy = options.retry_policy_of(&sender_id);
+            let retry = pending_deliveries.get(key).and_then(|delivery| {
+                delivery
+                    .missing_deliveries()
+                    .iter()
+                    .find(|missing| missing.delivery_id == delivery_id)
+                    .filter(|missing| {
+                        result == ProcessingResult::TransientError
+                            && missing.attempts < policy.max_attempts
+                            && missing.event.is_some()
+                    })
+                    .map(|missing| (missing.attempts, delivery.ttl()))
+            });
+            match retry {
+                Some((attempts, _)) => {
+                    let backoff = policy.backoff(attempts);
+                    debug!(
+                        "delivery_id={} for event_id={} failed on attempt {}/{} -> retry in {:?}",
+                        delivery_id, routing_id, attempts, policy.max_attempts, backoff
                     );
-                    resolved_missing_delivery = true
-                } else {
-                    warn!("{} sent OutgoingCloudEventProcessed for event_id={}, but no response was expected", sender_id, routing_id);
+                    let retry_at = SystemTime::now().add(backoff);
+                    // every attempt gets the full ttl of the destination
+                    let ttl = retry_at.add(options.delivery_ttl_of(&sender_id));
+                    pending_deliveries.schedule_retry(key, delivery_id, retry_at, ttl);
+                }
+                None => {
+                    let missing = pending_deliveries.remove_copy(key, delivery_id);
+                    failed_copy = missing
+                        .and_then(|missing| missing.event.map(|event| (event, missing.attempts)));
+                    if let Some(delivery) = pending_deliveries.get_mut(key) {
+                        delivery.failed += 1;
+                        if delivery.can_be_acknowledged() {
+                            debug!("delivery_id={} for event_id={} was NOT successful ({}), the other copies can still satisfy the ack policy {:?}", delivery_id, routing_id, result, delivery.ack_policy);
+                        } else {
+                            debug!("delivery for event_id={} was NOT successful ({}) -> immediately notify the sender or send a dead letter", routing_id, result);
+                            resolved_missing_delivery = true
+                        }
+                    }
                 }
             }
         }
-    } else {
-        debug!("there was no pending delivery for event_id {}", routing_id);
+        None => {
+            warn!("{} sent OutgoingCloudEventProcessed for event_id={} delivery_id={}, but no response was expected", sender_id, routing_id, delivery_id);
+        }
     }
 
     if resolved_missing_delivery {
-        if pending_deliveries.remove_entry(&routing_id).is_none() {
-            warn!(
+        match key.and_then(|key| pending_deliveries.remove(&key)) {
+            Some(delivery) => {
+                if !dead_letter(
+                    outboxes,
+                    pending_deliveries,
+                    options,
+                    metrics,
+                    system_events,
+                    &routing_id,
+                    &delivery.sender,
+                    true,
+                    failed_copy.into_iter().collect(),
+                    &result,
+                ) {
+                    send_to_server(
+                        outboxes,
+                        &delivery.sender,
+                        BrokerEvent::IncomingCloudEventProcessed(routing_id, result),
+                    )?;
+                }
+            }
+            None => warn!(
                 "failed to delete pending_deliveries for event_id={}",
                 routing_id
-            );
+            ),
         }
+    } else if let (Some((sender, _)), Some(failed_copy)) = (key, failed_copy) {
+        // the delivery is still pending, only the failed copy is dead-lettered
+        dead_letter(
+            outboxes,
+            pending_deliveries,
+            options,
+            metrics,
+            system_events,
+            &routing_id,
+            &sender,
+            false,
+            vec![failed_copy],
+            &result,
+        );
     }
+    Ok(())
 }
 
-fn process_broker_event(
-    broker_event: BrokerEvent,
-    outboxes: &mut Outboxes,
-    number_of_servers: usize,
-    pending_deliveries: &mut PendingDeliveries,
-) {
+fn process_broker_event(broker_event: BrokerEvent, state: &mut KernelState) -> Result<()> {
+    if let Some(ShutdownPhase::StoppingComponents(_)) = state.shutdown {
+        process_broker_event_while_stopping(broker_event, state);
+        return Ok(());
+    }
+    match &broker_event {
+        BrokerEvent::IncomingCloudEvent(event) => {
+            state
+                .metrics
+                .increment(METRIC_INCOMING_EVENTS, &event.incoming_id);
+        }
+        BrokerEvent::RoutingResult(RoutingResult {
+            incoming_id,
+            routing_id,
+            ..
+        }) => observe_routing_latency(state, incoming_id, routing_id),
+        BrokerEvent::ProcessorResult(event) if ends_routing(event) => {
+            observe_routing_latency(state, &event.incoming_id, &event.routing_id)
+        }
+        _ => {}
+    }
     match broker_event {
         BrokerEvent::InternalServerScheduled(id, sender_to_server) => {
-            init_internal_server(outboxes, number_of_servers, id, sender_to_server);
+            init_internal_server(state, id, sender_to_server);
+            Ok(())
         }
-        broker_event @ BrokerEvent::IncomingCloudEvent(_) => {
-            outboxes
-                .get(&String::from(ROUTER_ID))
-                .unwrap()
-                .send(broker_event) // if the router is not present: panic! we cant work without it
+        BrokerEvent::IncomingCloudEvent(event) if state.shutdown.is_some() => {
+            drop_incoming_cloud_event(event);
+            Ok(())
+        }
+        BrokerEvent::IncomingCloudEvent(event) => {
+            state.inputs.insert(event.incoming_id.clone());
+            state.journal.accept(&event);
+            match state.held_back {
+                Some(ref mut held_back) => {
+                    debug!(
+                        "startup is not completed yet, hold back event_id={} from {}",
+                        event.routing_id, event.incoming_id
+                    );
+                    held_back.push(event);
+                    Ok(())
+                }
+                None => route_incoming_cloud_event(state, event),
+            }
         }
-        BrokerEvent::RoutingResult(event) => {
-            process_routing_result(event, outboxes, pending_deliveries)
+        BrokerEvent::ProcessorResult(event) => process_processor_result(state, event),
+        BrokerEvent::RoutingResult(mut event) => {
+            state.journal.skip_acknowledged(&mut event);
+            process_routing_result(
+                event,
+                &mut state.outboxes,
+                &mut state.pending_deliveries,
+                &state.options,
+                &mut state.metrics,
+                &mut state.system_events,
+                &mut state.circuit_breakers,
+                &mut state.spools,
+                &state.supervisions,
+                &state.idempotency,
+            )
         }
-        BrokerEvent::OutgoingCloudEventProcessed(event) => {
-            process_outgoing_cloud_event_processed(event, outboxes, pending_deliveries)
+        BrokerEvent::OutgoingCloudEventProcessed(event) => process_outgoing_cloud_event_processed(
+            event,
+            &mut state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+            &mut state.circuit_breakers,
+            &mut state.spools,
+            &mut state.idempotency,
+            &state.journal,
+        ),
+        BrokerEvent::ConfigUpdated(config, destination_server_id)
+            if is_restarting(&state.supervisions, &destination_server_id) =>
+        {
+            debug!(
+                "received ConfigUpdated for {}, it is sent after the restart",
+                destination_server_id
+            );
+            let replaced = state.configs.insert(destination_server_id.clone(), config);
+            state
+                .replaced_configs
+                .insert(destination_server_id, replaced);
+            Ok(())
         }
         BrokerEvent::ConfigUpdated(config, destination_server_id) => {
             debug!(
                 "received ConfigUpdated, forward to {}",
                 destination_server_id
             );
-            outboxes
-                .get(&destination_server_id)
-                .unwrap()
-                .send(BrokerEvent::ConfigUpdated(config, destination_server_id));
+            let outbox = state.outboxes.get(&destination_server_id).ok_or_else(|| {
+                anyhow!(
+                    "ConfigUpdated for unknown component {} rejected",
+                    destination_server_id
+                )
+            })?;
+            let replaced = state
+                .configs
+                .insert(destination_server_id.clone(), config.clone());
+            state
+                .replaced_configs
+                .insert(destination_server_id.clone(), replaced);
+            outbox.send(BrokerEvent::ConfigUpdated(config, destination_server_id));
+            Ok(())
+        }
+        BrokerEvent::ConfigApplied(id) => {
+            debug!("{} applied its config", id);
+            state.replaced_configs.remove(&id);
+            state
+                .system_events
+                .emit(SYSTEM_EVENT_CONFIG_APPLIED, &id, json!({ "component": id }));
+            send_to_server(
+                &state.outboxes,
+                CONFIG_LOADER_ID,
+                BrokerEvent::ConfigApplied(id),
+            )
+        }
+        BrokerEvent::ConfigRejected(id, reason) => {
+            warn!("{} rejected its config: {}", id, reason);
+            state.system_events.emit(
+                SYSTEM_EVENT_CONFIG_REJECTED,
+                &id,
+                json!({ "component": id, "reason": reason }),
+            );
+            match state.replaced_configs.remove(&id) {
+                Some(Some(config)) => {
+                    state.configs.insert(id.clone(), config);
+                }
+                Some(None) => {
+                    state.configs.remove(&id);
+                }
+                None => {}
+            }
+            send_to_server(
+                &state.outboxes,
+                CONFIG_LOADER_ID,
+                BrokerEvent::ConfigRejected(id, reason),
+            )
         }
         BrokerEvent::Batch(broker_events) => {
             for broker_event in broker_events.into_iter() {
-                process_broker_event(
-                    broker_event,
-                    outboxes,
-                    number_of_servers,
-                    pending_deliveries,
-                );
+                if let Err(e) = process_broker_event(broker_event, state) {
+                    error!("failed to process event of batch: {}", e);
+                }
+            }
+            Ok(())
+        }
+        BrokerEvent::HealthCheckRequest(event) if event.destination_id == KERNEL_ID => {
+            let status = match state.startup {
+                StartupPhase::Completed => HealthCheckStatus::Healthy,
+                _ => HealthCheckStatus::Unhealthy(String::from("kernel is starting")),
+            };
+            answer_health_check_request(&state.outboxes, event, status)
+        }
+        BrokerEvent::HealthCheckRequest(event)
+            if !state.outboxes.contains_key(&event.destination_id) =>
+        {
+            let status = match state
+                .supervisions
+                .get(&event.destination_id)
+                .map(|supervision| &supervision.status)
+            {
+                Some(ComponentStatus::Restarting(_)) | Some(ComponentStatus::Scheduling) => {
+                    HealthCheckStatus::Unhealthy(String::from("component is restarting"))
+                }
+                Some(ComponentStatus::Failed) => HealthCheckStatus::Unhealthy(String::from(
+                    "component exited too often and is not restarted anymore",
+                )),
+                _ => return reject_health_check_request(&state.outboxes, event),
+            };
+            state
+                .system_events
+                .observe_health(&event.destination_id, &status);
+            answer_health_check_request(&state.outboxes, event, status)
+        }
+        BrokerEvent::HealthCheckRequest(event) => send_to_server(
+            &state.outboxes,
+            &event.destination_id.clone(),
+            BrokerEvent::HealthCheckRequest(event),
+        ),
+        BrokerEvent::HealthCheckResponse(mut event) => {
+            event.status = state
+                .circuit_breakers
+                .health_of(&event.sender_id, event.status);
+            state
+                .system_events
+                .observe_health(&event.sender_id, &event.status);
+            send_to_server(
+                &state.outboxes,
+                &event.destination_id.clone(),
+                BrokerEvent::HealthCheckResponse(event),
+            )
+        }
+        BrokerEvent::Shutdown => {
+            start_shutdown(state);
+            Ok(())
+        }
+        BrokerEvent::InternalServerExited(id) => process_internal_server_exited(state, id),
+        BrokerEvent::MetricsUpdate(event) => {
+            for update in event.updates {
+                if let Err(e) = state.metrics.apply(&event.sender_id, update) {
+                    error!("failed to update metrics of {}: {}", event.sender_id, e);
+                }
             }
+            Ok(())
+        }
+        BrokerEvent::MetricsRequest(event) => answer_metrics_request(state, event),
+        BrokerEvent::ComponentsRequest(event) => answer_components_request(state, event),
+        broker_event => {
+            warn!("event {} not implemented", broker_event);
+            Ok(())
         }
-        BrokerEvent::HealthCheckRequest(event) => outboxes
-            .get(event.destination_id.as_str())
-            .unwrap()
-            .send(BrokerEvent::HealthCheckRequest(event)),
-        BrokerEvent::HealthCheckResponse(event) => outboxes
-            .get(event.destination_id.as_str())
-            .unwrap()
-            .send(BrokerEvent::HealthCheckResponse(event)),
-        broker_event => warn!("event {} not implemented", broker_event),
     }
 }
 
-fn init_internal_server(
-    outboxes: &mut Outboxes,
-    number_of_servers: usize,
-    id: InternalServerId,
-    sender_to_server: BoxedSender,
-) {
-    outboxes.insert(id, sender_to_server);
-    if outboxes.len() == number_of_servers {
-        for (_, outbox) in outboxes.iter() {
-            outbox.send(BrokerEvent::Init);
+/// Forwards the CloudEvent to the first stage, the processors or the router.
+fn route_incoming_cloud_event(state: &mut KernelState, event: IncomingCloudEvent) -> Result<()> {
+    let key = (event.incoming_id.clone(), event.routing_id.clone());
+    let stage_id = next_stage(&state.pipeline, None).to_string();
+    forward_to_stage(state, &stage_id, event)?;
+    state.routing_started.insert(key, SystemTime::now());
+    Ok(())
+}
+
+/// Routes the CloudEvents of the journal and the ones that were held back meanwhile,
+/// as soon as all components received `Init` and the router has its config.
+fn replay_journal(state: &mut KernelState) {
+    if state.held_back.is_none()
+        || !matches!(state.startup, StartupPhase::Completed)
+        || !state.configs.contains_key(ROUTER_ID)
+    {
+        return;
+    }
+    let events = state.held_back.take().unwrap_or_default();
+    info!(
+        "startup completed, route {} CloudEvents of the journal and the ones held back meanwhile",
+        events.len()
+    );
+    for event in events {
+        if let Err(e) = route_incoming_cloud_event(state, event) {
+            error!("failed to replay event: {}", e);
         }
     }
 }
 
-/// The function that gets started from the scheduler.
-/// It implements the Kernel.
-pub fn kernel_start(
-    start_options: StartOptions,
-    inbox: BoxedReceiver,
-    sender_to_scheduler: BoxedSender,
-) {
-    let mut outboxes = Outboxes::new();
-    // old entries are deleted with clean_pending_deliveries() before new are inserted.
-    // At the moment this is only done before a new event is created, if this should change with e.g. a job add a lock! as in 24bb886a37c187936d906a0df90a9e90a3cf4255
-    let mut pending_deliveries = PendingDeliveries::new();
-
-    sender_to_scheduler.send(BrokerEvent::ScheduleInternalServer(
-        ScheduleInternalServer {
-            id: String::from(ROUTER_ID),
-            function: start_options.router,
-        },
-    ));
-    sender_to_scheduler.send(BrokerEvent::ScheduleInternalServer(
-        ScheduleInternalServer {
-            id: String::from(CONFIG_LOADER_ID),
-            function: start_options.config_loader,
-        },
-    ));
-
-    for service in start_options.ports.iter() {
-        sender_to_scheduler.send(BrokerEvent::ScheduleInternalServer(service.clone()));
+/// Routes the CloudEvents the Kernel emitted about itself like incoming CloudEvents, see `KernelOptions.system_events`.
+/// They are held back until the startup is completed and dropped during the shutdown.
+fn route_system_events(state: &mut KernelState) {
+    for event in state.system_events.take() {
+        if state.shutdown.is_some() {
+            drop_incoming_cloud_event(event);
+            continue;
+        }
+        state
+            .metrics
+            .increment(METRIC_INCOMING_EVENTS, &event.incoming_id);
+        match state.held_back {
+            Some(ref mut held_back) => held_back.push(event),
+            None => {
+                if let Err(e) = route_incoming_cloud_event(state, event) {
+                    error!("failed to route system event: {}", e);
+                }
+            }
+        }
     }
-    let number_of_servers = 2 + start_options.ports.len(); // 2 = router + config_loader
+}
 
-    loop {
-        let broker_event = inbox.receive();
-        process_broker_event(
-            broker_event,
-            &mut outboxes,
-            number_of_servers,
-            &mut pending_deliveries,
-        );
+/// The stage is not scheduled (anymore), so the event can not be routed.
+/// The sender gets the given result if it expects an acknowledgment.
+fn reject_incoming_cloud_event(
+    outboxes: &Outboxes,
+    stage_id: &str,
+    event: IncomingCloudEvent,
+    result: ProcessingResult,
+) -> Result<()> {
+    let IncomingCloudEvent {
+        incoming_id,
+        routing_id,
+        args,
+        ..
+    } = event;
+    let error = anyhow!(
+        "{} is not scheduled, event_id={} from {} was rejected with {}",
+        stage_id,
+        routing_id,
+        incoming_id,
+        result
+    );
+    if args.delivery_guarantee.requires_acknowledgment() {
+        send_to_server(
+            outboxes,
+            &incoming_id,
+            BrokerEvent::IncomingCloudEventProcessed(routing_id, result),
+        )?;
     }
+    Err(error)
 }
 
-#[cfg(test)]
-mod test {
-    use super::*;
-    use crate::runtime::channel::Sender;
-    use std::ops::Sub;
+/// The kernel answers in the name of the requested component with the given status.
+fn answer_health_check_request(
+    outboxes: &Outboxes,
+    event: HealthCheckRequest,
+    status: HealthCheckStatus,
+) -> Result<()> {
+    let sender_id = event.sender_id.clone();
+    send_to_server(outboxes, &sender_id, event.respond(status))
+}
+
+/// The Kernel answers with the state of all components it scheduled.
+fn answer_components_request(state: &KernelState, event: ComponentsRequest) -> Result<()> {
+    let ComponentsRequest { id, sender_id } = event;
+    let mut components: Vec<ComponentInfo> = state
+        .functions
+        .keys()
+        .map(|component_id| {
+            let running = state.outboxes.contains_key(component_id);
+            ComponentInfo {
+                id: component_id.clone(),
+                // the config loader is the source of the configs, it does not get one itself
+                configured: component_id == CONFIG_LOADER_ID
+                    || state.configs.contains_key(component_id),
+                initialized: received_init(state, component_id) && running,
+            }
+        })
+        .collect();
+    components.sort_by(|a, b| a.id.cmp(&b.id));
+    send_to_server(
+        &state.outboxes,
+        &sender_id.clone(),
+        BrokerEvent::ComponentsResponse(ComponentsResponse {
+            id,
+            destination_id: sender_id,
+            components,
+        }),
+    )
+}
+
+/// The requested component does not exist, the kernel answers in its name with an unhealthy status.
+fn reject_health_check_request(outboxes: &Outboxes, event: HealthCheckRequest) -> Result<()> {
+    let error = anyhow!(
+        "HealthCheckRequest from {} for unknown component {} rejected",
+        event.sender_id,
+        event.destination_id
+    );
+    let status = HealthCheckStatus::Unhealthy(format!(
+        "component {} is not scheduled",
+        event.destination_id
+    ));
+    answer_health_check_request(outboxes, event, status)?;
+    Err(error)
+}
+
+/// The Kernel answers with a snapshot of all metrics.
+/// The pending deliveries and unacknowledged copies are the ones at the time of the request.
+fn answer_metrics_request(state: &mut KernelState, event: MetricsRequest) -> Result<()> {
+    let MetricsRequest { id, sender_id } = event;
+    let mut pending: HashMap<&str, usize> =
+        state.functions.keys().map(|id| (id.as_str(), 0)).collect();
+    let mut unacknowledged = pending.clone();
+    pending.extend(state.pending_deliveries.pending_counts());
+    unacknowledged.extend(state.pending_deliveries.unacknowledged_counts());
+    for (component_id, count) in pending {
+        state
+            .metrics
+            .set(METRIC_PENDING_DELIVERIES, component_id, count as f64);
+    }
+    for (component_id, count) in unacknowledged {
+        state
+            .metrics
+            .set(METRIC_UNACKNOWLEDGED_EVENTS, component_id, count as f64);
+    }
+    send_to_server(
+        &state.outboxes,
+        &sender_id.clone(),
+        BrokerEvent::MetricsResponse(MetricsResponse {
+            id,
+            destination_id: sender_id,
+            metrics: state.metrics.snapshot(),
+        }),
+    )
+}
+
+/// records the routing latency of an acknowledged routing
+fn observe_routing_latency(state: &mut KernelState, incoming_id: &str, routing_id: &str) {
+    let key = (incoming_id.to_string(), routing_id.to_string());
+    if let Some(started) = state.routing_started.remove(&key) {
+        let latency = started.elapsed().unwrap_or_default();
+        state
+            .metrics
+            .observe(METRIC_ROUTING_LATENCY, incoming_id, latency.as_secs_f64());
+    }
+}
+
+/// Forgets the start times of routings that took too long, their latency is not measured.
+fn clean_routing_started(state: &mut KernelState) {
+    let now = SystemTime::now();
+    let max_latency = Duration::from_millis(MAX_ROUTING_LATENCY_MS);
+    state
+        .routing_started
+        .retain(|_, started| now.duration_since(*started).unwrap_or_default() < max_latency);
+}
+
+/// Pauses the input ports while the Kernel is overloaded and resumes them once it recovered,
+/// see `KernelOptions.flow_control` and `SpoolOverflow::Block`.
+fn control_flow(state: &mut KernelState) {
+    let options = &state.options.flow_control;
+    let blocked = state.spools.is_blocked();
+    if options.outbox_depth.is_none()
+        && options.pending_deliveries.is_none()
+        && !blocked
+        && state.paused.is_empty()
+    {
+        return;
+    }
+    let outbox_depth = state
+        .outboxes
+        .values()
+        .filter_map(|outbox| outbox.queue_length())
+        .max()
+        .unwrap_or(0);
+    for input_id in state.inputs.iter() {
+        if !state.outboxes.contains_key(input_id) {
+            continue;
+        }
+        let values = [
+            (options.outbox_depth.as_ref(), outbox_depth),
+            (
+                options.pending_deliveries.as_ref(),
+                state.pending_deliveries.pending_of(input_id),
+            ),
+        ];
+        let overloaded = blocked
+            || values
+                .iter()
+                .any(|(watermarks, value)| watermarks.is_some_and(|w| *value >= w.high));
+        let recovered = !blocked
+            && values
+                .iter()
+                .all(|(watermarks, value)| !watermarks.is_some_and(|w| *value > w.low));
+        let event = if state.paused.contains(input_id) && recovered {
+            state.paused.remove(input_id);
+            info!("resume {} (outbox depth {})", input_id, outbox_depth);
+            BrokerEvent::Resume(input_id.clone())
+        } else if !state.paused.contains(input_id) && overloaded {
+            state.paused.insert(input_id.clone());
+            warn!("pause {} (outbox depth {})", input_id, outbox_depth);
+            BrokerEvent::Pause(input_id.clone())
+        } else {
+            continue;
+        };
+        if let Err(e) = send_to_server(&state.outboxes, input_id, event) {
+            error!("failed to control the flow of {}: {}", input_id, e);
+        }
+    }
+}
+
+/// A component exited outside of the shutdown.
+/// Its pending deliveries fail and it is scheduled again after the backoff of `KernelOptions.restart`.
+fn process_internal_server_exited(state: &mut KernelState, id: InternalServerId) -> Result<()> {
+    if !state.functions.contains_key(&id) {
+        bail!("unknown component {} exited", id);
+    }
+    state.outboxes.remove(&id);
+    state.batches.remove(&id);
+    state.circuit_breakers.remove(&id);
+    state.spools.reset(&id);
+    // a restarted port starts unpaused
+    state.paused.remove(&id);
+    fail_pending_deliveries_of(&state.outboxes, &mut state.pending_deliveries, &id);
+
+    let now = SystemTime::now();
+    let policy = &state.options.restart;
+    let supervision = state
+        .supervisions
+        .entry(id.clone())
+        .or_insert_with(|| Supervision {
+            status: ComponentStatus::Running,
+            restarts: 0,
+            restarted_at: now,
+        });
+    if now
+        .duration_since(supervision.restarted_at)
+        .unwrap_or_default()
+        > policy.max_backoff
+    {
+        supervision.restarts = 0;
+    }
+    if supervision.restarts >= policy.max_attempts {
+        supervision.status = ComponentStatus::Failed;
+        bail!(
+            "{} exited after {} restarts in a row, it is not restarted anymore",
+            id,
+            supervision.restarts
+        );
+    }
+    let backoff = policy.backoff(supervision.restarts + 1);
+    warn!("{} exited, restart it in {:?}", id, backoff);
+    supervision.status = ComponentStatus::Restarting(now.add(backoff));
+    Ok(())
+}
+
+/// The deliveries that wait for an acknowledgment of the exited component fail with `ProcessingResult::TransientError`,
+/// so their senders may redeliver them, unless their ack policy can still be satisfied by the other copies. The deliveries of CloudEvents that the component sent itself are dropped.
+fn fail_pending_deliveries_of(
+    outboxes: &Outboxes,
+    pending_deliveries: &mut PendingDeliveries,
+    id: &str,
+) {
+    for key in pending_deliveries.keys_involving(id) {
+        if key.0 != id {
+            let failed = pending_deliveries.remove_copies_to(&key, id);
+            if let Some(delivery) = pending_deliveries.get_mut(&key) {
+                delivery.failed += failed;
+                if delivery.can_be_acknowledged() {
+                    debug!(
+                        "{} exited, the other copies of event_id={} can still satisfy the ack policy",
+                        id, key.1
+                    );
+                    continue;
+                }
+            }
+        }
+        if let Some(delivery) = pending_deliveries.remove(&key) {
+            let (_, routing_id) = key;
+            if delivery.sender == id {
+                debug!("sender of event_id={} exited, delivery dropped", routing_id);
+            } else if let Err(e) = send_to_server(
+                outboxes,
+                &delivery.sender,
+                BrokerEvent::IncomingCloudEventProcessed(
+                    routing_id,
+                    ProcessingResult::TransientError,
+                ),
+            ) {
+                error!(
+                    "failed to notify the sender about the exit of {}: {}",
+                    id, e
+                );
+            }
+        }
+    }
+}
+
+/// Sends the spooled copies to the ports that are available again, see `PortOptions.spool`.
+/// A port with a half-open circuit gets a single copy to probe it.
+fn drain_spools(state: &mut KernelState) {
+    for id in state.spools.expire_in_flight() {
+        warn!(
+            "{} did not acknowledge the spooled copy in time, it is sent again",
+            id
+        );
+        state.metrics.increment(METRIC_TIMEOUTS, &id);
+        state.circuit_breakers.record_failure(&state.options, &id);
+    }
+    for id in state.spools.drainable() {
+        let max_copies = if state.circuit_breakers.is_closed(&id) {
+            MAX_DRAINED_COPIES
+        } else {
+            1
+        };
+        let in_flight = state.spools.in_flight(&id);
+        // the circuit is only asked if a copy can be sent, a half-open one lets it through as probe
+        if in_flight >= max_copies
+            || !state.outboxes.contains_key(&id)
+            || !state.circuit_breakers.allows(&state.options, &id)
+        {
+            continue;
+        }
+        let delivery_ttl = state.options.delivery_ttl_of(&id);
+        for _ in in_flight..max_copies {
+            let event = match state.spools.next_copy(&id, delivery_ttl) {
+                Some(event) => event,
+                None => break,
+            };
+            debug!("send spooled event_id={} to {}", event.routing_id, id);
+            match send_to_server(&state.outboxes, &id, BrokerEvent::OutgoingCloudEvent(event)) {
+                Ok(()) => state.metrics.increment(METRIC_OUTGOING_EVENTS, &id),
+                Err(e) => {
+                    error!("failed to send a spooled copy to {}: {}", id, e);
+                    break;
+                }
+            }
+        }
+    }
+}
+
+/// Asks the Scheduler to schedule the components whose restart is due.
+fn restart_due_components(state: &mut KernelState) {
+    let now = SystemTime::now();
+    for (id, supervision) in state.supervisions.iter_mut() {
+        match supervision.status {
+            ComponentStatus::Restarting(restart_at) if restart_at <= now => {
+                supervision.status = ComponentStatus::Scheduling;
+                supervision.restarts += 1;
+                supervision.restarted_at = now;
+                info!("restart {} (restart {} in a row)", id, supervision.restarts);
+                state
+                    .sender_to_scheduler
+                    .send(BrokerEvent::ScheduleInternalServer(
+                        ScheduleInternalServer {
+                            id: id.clone(),
+                            function: state.functions[id],
+                        },
+                    ));
+            }
+            _ => {}
+        }
+    }
+}
+
+/// After `BrokerEvent::Shutdown` was sent to the components, only their responses are processed.
+/// The components could already be gone, so nothing is forwarded anymore.
+fn process_broker_event_while_stopping(broker_event: BrokerEvent, state: &mut KernelState) {
+    match broker_event {
+        BrokerEvent::ShutdownComplete(id) => {
+            if state.outboxes.remove(&id).is_some() {
+                debug!("{} completed the shutdown", id);
+            } else {
+                warn!("received ShutdownComplete from unknown component {}", id);
+            }
+        }
+        BrokerEvent::InternalServerExited(id) => {
+            if state.outboxes.remove(&id).is_some() {
+                debug!("{} exited during the shutdown", id);
+            }
+        }
+        BrokerEvent::InternalServerScheduled(id, sender_to_server) => {
+            debug!("{} was scheduled during the shutdown -> stop it", id);
+            sender_to_server.send(BrokerEvent::Shutdown);
+            state.outboxes.insert(id, sender_to_server);
+        }
+        broker_event => debug!("kernel is shutting down, event {} dropped", broker_event),
+    }
+}
+
+/// The event is neither routed nor answered.
+/// The input port has to nack it when it receives `BrokerEvent::Shutdown`.
+fn drop_incoming_cloud_event(event: IncomingCloudEvent) {
+    debug!(
+        "kernel is shutting down, drop event_id={} from {}",
+        event.routing_id, event.incoming_id
+    );
+}
+
+fn start_shutdown(state: &mut KernelState) {
+    if state.shutdown.is_some() {
+        debug!("shutdown is already in progress");
+    } else {
+        info!(
+            "shutdown requested, stop accepting CloudEvents and wait for {} pending deliveries",
+            state.pending_deliveries.len()
+        );
+        state.shutdown = Some(ShutdownPhase::Draining);
+    }
+}
+
+/// Moves the shutdown forward, returns `true` as soon as the kernel can stop.
+fn progress_shutdown(state: &mut KernelState) -> bool {
+    match state.shutdown {
+        None => false,
+        Some(ShutdownPhase::Draining) => {
+            clean_pending_deliveries(
+                &state.outboxes,
+                &mut state.pending_deliveries,
+                &state.options,
+                &mut state.metrics,
+                &mut state.system_events,
+                &mut state.circuit_breakers,
+            );
+            if state.pending_deliveries.is_empty() {
+                info!("all pending deliveries are settled, stop the components");
+                for outbox in state.outboxes.values() {
+                    outbox.send(BrokerEvent::Shutdown);
+                }
+                state.shutdown = Some(ShutdownPhase::StoppingComponents(
+                    SystemTime::now().add(Duration::from_millis(SHUTDOWN_TIMEOUT_MS)),
+                ));
+            }
+            false
+        }
+        Some(ShutdownPhase::StoppingComponents(deadline)) => {
+            if state.outboxes.is_empty() {
+                info!("all components completed the shutdown");
+                true
+            } else if deadline < SystemTime::now() {
+                warn!(
+                    "components {:?} did not complete the shutdown within {}ms",
+                    state.outboxes.keys().collect::<Vec<_>>(),
+                    SHUTDOWN_TIMEOUT_MS
+                );
+                true
+            } else {
+                false
+            }
+        }
+    }
+}
+
+fn init_internal_server(
+    state: &mut KernelState,
+    id: InternalServerId,
+    sender_to_server: BoxedSender,
+) {
+    let sender_to_server = state.journal.wrap(&id, sender_to_server);
+    let sender_to_server = state.batches.wrap(
+        &id,
+        sender_to_server,
+        state
+            .options
+            .ports
+            .get(&id)
+            .and_then(|options| options.batch.as_ref()),
+    );
+    state.system_events.emit(
+        SYSTEM_EVENT_COMPONENT_SCHEDULED,
+        &id,
+        json!({ "component": id }),
+    );
+    if let Some(supervision) = state.supervisions.get_mut(&id) {
+        supervision.status = ComponentStatus::Running;
+        if !matches!(state.startup, StartupPhase::Scheduling(_)) {
+            info!("{} was restarted", id);
+            if let Some(config) = state.configs.get(&id) {
+                sender_to_server.send(BrokerEvent::ConfigUpdated(config.clone(), id.clone()));
+            }
+            if received_init(state, &id) {
+                sender_to_server.send(BrokerEvent::Init);
+            }
+        }
+    }
+    state.outboxes.insert(id, sender_to_server);
+}
+
+/// `BrokerEvent::Init` was sent to the component in the current startup phase
+fn received_init(state: &KernelState, id: &str) -> bool {
+    match state.startup {
+        StartupPhase::Scheduling(_) => false,
+        StartupPhase::Configuring(_) => id == CONFIG_LOADER_ID,
+        StartupPhase::Completed => true,
+    }
+}
+
+/// Moves the startup forward.
+/// Once all components are scheduled, the config loader gets `BrokerEvent::Init`.
+/// Once all other components got their config, they get `BrokerEvent::Init` and the Scheduler gets `BrokerEvent::Ready`.
+///
+/// Fails with the missing components if a phase is not completed until its deadline, the shutdown is started in that case.
+fn progress_startup(state: &mut KernelState) -> Result<()> {
+    let now = SystemTime::now();
+    match state.startup {
+        StartupPhase::Scheduling(deadline) => {
+            let mut missing: Vec<&InternalServerId> = state
+                .functions
+                .keys()
+                .filter(|id| !state.outboxes.contains_key(*id))
+                .collect();
+            missing.sort();
+            if missing.is_empty() {
+                info!("all components are scheduled, initialize the config loader");
+                if let Err(e) = send_to_server(&state.outboxes, CONFIG_LOADER_ID, BrokerEvent::Init)
+                {
+                    error!("failed to initialize the config loader: {}", e);
+                }
+                state.startup = StartupPhase::Configuring(deadline);
+            } else if deadline <= now {
+                let error = anyhow!(
+                    "components {:?} were not scheduled within {}ms, stop the router",
+                    missing,
+                    state.options.startup_timeout.as_millis()
+                );
+                start_shutdown(state);
+                return Err(error);
+            }
+        }
+        StartupPhase::Configuring(deadline) => {
+            let mut missing: Vec<&InternalServerId> = state
+                .functions
+                .keys()
+                .filter(|id| *id != CONFIG_LOADER_ID && !state.configs.contains_key(*id))
+                .collect();
+            missing.sort();
+            if !missing.is_empty() {
+                if deadline <= now {
+                    let error = anyhow!(
+                        "components {:?} got no config within {}ms, stop the router",
+                        missing,
+                        state.options.startup_timeout.as_millis()
+                    );
+                    start_shutdown(state);
+                    return Err(error);
+                }
+                return Ok(());
+            }
+            info!("all components are configured, initialize them");
+            for (id, outbox) in state.outboxes.iter() {
+                if id != CONFIG_LOADER_ID {
+                    outbox.send(BrokerEvent::Init);
+                }
+            }
+            state.startup = StartupPhase::Completed;
+            info!("startup completed, the router is ready");
+            state.sender_to_scheduler.send(BrokerEvent::Ready);
+        }
+        StartupPhase::Completed => {}
+    }
+    Ok(())
+}
+
+/// The function that gets started from the scheduler.
+/// It implements the Kernel.
+///
+/// The function returns after a `BrokerEvent::Shutdown` was processed and the Scheduler was notified about it.
+/// If the startup is not completed within `KernelOptions.startup_timeout`, the components are stopped
+/// and the function fails with the components that did not start.
+pub fn kernel_start(
+    start_options: StartOptions,
+    inbox: BoxedReceiver,
+    sender_to_scheduler: BoxedSender,
+) -> Result<()> {
+    // expired entries are deleted with clean_pending_deliveries() after every event and whenever the next ttl is reached.
+    // Everything runs in the kernel thread, if this should change with e.g. a job add a lock! as in 24bb886a37c187936d906a0df90a9e90a3cf4255
+    for id in start_options.kernel.ports.keys() {
+        if !start_options.ports.iter().any(|port| port.id.eq(id)) {
+            warn!("kernel options for unknown port {} are ignored", id);
+        }
+    }
+    if let Some(ref id) = start_options.kernel.dead_letter_port {
+        if !start_options.ports.iter().any(|port| port.id.eq(id)) {
+            warn!("dead-letter port {} is not a scheduled port", id);
+        }
+    }
+    let pipeline: Vec<InternalServerId> = start_options
+        .processors
+        .iter()
+        .map(|processor| processor.id.clone())
+        .collect();
+    let components: Vec<ScheduleInternalServer> = vec![
+        ScheduleInternalServer {
+            id: String::from(ROUTER_ID),
+            function: start_options.router,
+        },
+        ScheduleInternalServer {
+            id: String::from(CONFIG_LOADER_ID),
+            function: start_options.config_loader,
+        },
+    ]
+    .into_iter()
+    .chain(start_options.processors)
+    .chain(start_options.ports)
+    .collect();
+    let idempotency = match IdempotencyStore::open(start_options.kernel.idempotency.clone()) {
+        Ok(store) => store,
+        Err(e) => {
+            error!(
+                "failed to load the idempotency store, the deliveries are only kept in memory: {:?}",
+                e
+            );
+            IdempotencyStore::open(IdempotencyOptions {
+                path: None,
+                ..start_options.kernel.idempotency.clone()
+            })
+            .expect("a store without path can always be opened")
+        }
+    };
+    let journal = match Journal::open(&start_options.kernel.journal) {
+        Ok(journal) => journal,
+        Err(e) => {
+            error!(
+                "failed to open the journal, the CloudEvents are not written to disk: {:?}",
+                e
+            );
+            Journal::disabled()
+        }
+    };
+    let mut replayed_events = Vec::new();
+    for event in journal.replayed_events() {
+        if components
+            .iter()
+            .any(|component| component.id == event.incoming_id)
+        {
+            replayed_events.push(event);
+        } else {
+            warn!(
+                "{} is not scheduled anymore, event_id={} of the journal is dropped",
+                event.incoming_id, event.routing_id
+            );
+            journal.complete(&event.incoming_id, &event.routing_id);
+        }
+    }
+    let startup_deadline = SystemTime::now().add(start_options.kernel.startup_timeout);
+    let system_events = SystemEvents::new(start_options.kernel.system_events);
+    let spools = Spools::open(&start_options.kernel);
+    // the delivery ids of the spooled copies are not used again
+    let first_delivery_id = spools
+        .max_delivery_id()
+        .map_or(0, |delivery_id| delivery_id.wrapping_add(1));
+    let mut state = KernelState {
+        outboxes: Outboxes::new(),
+        pending_deliveries: PendingDeliveries::new(first_delivery_id),
+        shutdown: None,
+        options: start_options.kernel,
+        functions: components
+            .iter()
+            .map(|component| (component.id.clone(), component.function))
+            .collect(),
+        configs: HashMap::new(),
+        replaced_configs: HashMap::new(),
+        supervisions: Supervisions::new(),
+        startup: StartupPhase::Scheduling(startup_deadline),
+        sender_to_scheduler,
+        metrics: Metrics::default(),
+        routing_started: HashMap::new(),
+        pipeline,
+        idempotency,
+        journal,
+        batches: Batches::default(),
+        held_back: Some(replayed_events),
+        inputs: HashSet::new(),
+        paused: HashSet::new(),
+        system_events,
+        circuit_breakers: CircuitBreakers::default(),
+        spools,
+    };
+
+    for component in components {
+        state
+            .sender_to_scheduler
+            .send(BrokerEvent::ScheduleInternalServer(component));
+    }
+
+    let mut startup_error = None;
+    loop {
+        let broker_event = match get_receive_timeout(&state) {
+            Some(timeout) => inbox.receive_timeout(timeout),
+            None => Some(inbox.receive()),
+        };
+        if let Some(broker_event) = broker_event {
+            if let Err(e) = process_broker_event(broker_event, &mut state) {
+                error!("failed to process event: {}", e);
+            }
+        }
+        clean_pending_deliveries(
+            &state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+            &mut state.circuit_breakers,
+        );
+        send_due_retries(
+            &state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+        );
+        if !matches!(state.shutdown, Some(ShutdownPhase::StoppingComponents(_))) {
+            restart_due_components(&mut state);
+        }
+        clean_routing_started(&mut state);
+        state.batches.flush_due();
+        if state.shutdown.is_none() {
+            if let Err(e) = progress_startup(&mut state) {
+                error!("startup failed: {}", e);
+                startup_error = Some(e);
+            }
+        }
+        route_system_events(&mut state);
+        replay_journal(&mut state);
+        if state.shutdown.is_none() && state.held_back.is_none() {
+            drain_spools(&mut state);
+        }
+        if state.shutdown.is_none() {
+            control_flow(&mut state);
+        }
+        if progress_shutdown(&mut state) {
+            break;
+        }
+    }
+
+    info!("kernel stopped, notify scheduler");
+    state.sender_to_scheduler.send(BrokerEvent::Shutdown);
+    match startup_error {
+        Some(e) => Err(e),
+        None => Ok(()),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::*;
+    use crate::kernel::broker_event::OutgoingCloudEvent;
+    use crate::kernel::KernelFn;
+    use crate::kernel::{
+        CircuitBreakerOptions, CloudEventDeliveryId, CloudEventRoutingArgs, Config,
+        DeliveryGuarantee, Metric, MetricLabels, MetricUpdate, MetricValue, MetricsUpdate,
+        PortOptions, RetryPolicy, SpoolOptions, SpoolOverflow, Watermarks, DEFAULT_DELIVERY_TTL_MS,
+        METRIC_ACKS, METRIC_COMPONENT_LABEL,
+    };
+    use crate::runtime::channel::{Receiver, RecordingSender, Sender};
+    use crate::runtime::{InternalServerFn, ScheduleFn};
+    use cloudevents::{AttributesWriter, Event};
+    use std::fs;
+    use std::ops::Sub;
+    use std::path::PathBuf;
+    use std::sync::{mpsc, Arc};
 
     #[test]
     fn ttl_should_be_after_now() {
-        assert!(get_ttl() > SystemTime::now());
+        assert!(get_ttl(Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)) > SystemTime::now());
     }
 
     #[test]
     fn should_not_delete_anything_on_empty_list() {
         let outboxes = Outboxes::new();
-        let mut pending_deliveries = PendingDeliveries::new();
-        clean_pending_deliveries(&outboxes, &mut pending_deliveries);
+        let mut pending_deliveries = PendingDeliveries::default();
+        clean_pending_deliveries(
+            &outboxes,
+            &mut pending_deliveries,
+            &KernelOptions::default(),
+            &mut Metrics::default(),
+            &mut SystemEvents::new(false),
+            &mut CircuitBreakers::default(),
+        );
         assert_eq!(pending_deliveries.len(), 0);
     }
 
@@ -326,16 +2127,24 @@ mod test {
             .into_iter()
             .map(|n| {
                 (
-                    n.to_string(),
-                    PendingDelivery {
-                        sender: "a sender".to_string(),
-                        missing_receivers: vec![],
-                        ttl: get_ttl(),
-                    },
+                    ("a sender".to_string(), n.to_string()),
+                    PendingDelivery::new(
+                        "a sender".to_string(),
+                        vec![],
+                        get_ttl(Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)),
+                        AckPolicy::All,
+                    ),
                 )
             })
             .collect();
-        clean_pending_deliveries(&outboxes, &mut pending_deliveries);
+        clean_pending_deliveries(
+            &outboxes,
+            &mut pending_deliveries,
+            &KernelOptions::default(),
+            &mut Metrics::default(),
+            &mut SystemEvents::new(false),
+            &mut CircuitBreakers::default(),
+        );
         assert_eq!(pending_deliveries.len(), 10);
     }
 
@@ -356,24 +2165,1915 @@ mod test {
             .into_iter()
             .map(|n| {
                 (
-                    n.to_string(),
-                    PendingDelivery {
-                        sender: "a sender".to_string(),
-                        missing_receivers: vec![],
-                        ttl: get_ttl(),
-                    },
+                    ("a sender".to_string(), n.to_string()),
+                    PendingDelivery::new(
+                        "a sender".to_string(),
+                        vec![],
+                        get_ttl(Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)),
+                        AckPolicy::All,
+                    ),
                 )
             })
             .collect();
         pending_deliveries.insert(
-            "todelete".to_string(),
-            PendingDelivery {
-                sender: "real-sender".to_string(),
-                missing_receivers: vec![],
-                ttl: SystemTime::now().sub(Duration::from_millis(ROUTING_TTL_MS + 1)),
-            },
+            ("real-sender".to_string(), "todelete".to_string()),
+            PendingDelivery::new(
+                "real-sender".to_string(),
+                vec![],
+                SystemTime::now().sub(Duration::from_millis(DEFAULT_DELIVERY_TTL_MS + 1)),
+                AckPolicy::All,
+            ),
+        );
+        clean_pending_deliveries(
+            &outboxes,
+            &mut pending_deliveries,
+            &KernelOptions::default(),
+            &mut Metrics::default(),
+            &mut SystemEvents::new(false),
+            &mut CircuitBreakers::default(),
         );
-        clean_pending_deliveries(&outboxes, &mut pending_deliveries);
         assert_eq!(pending_deliveries.len(), 10);
     }
+
+    fn build_state(outboxes: &[(&str, &RecordingSender)]) -> KernelState {
+        KernelState {
+            outboxes: outboxes
+                .iter()
+                .map(|(id, sender)| (id.to_string(), sender.clone_boxed()))
+                .collect(),
+            pending_deliveries: PendingDeliveries::default(),
+            shutdown: None,
+            options: KernelOptions::default(),
+            functions: HashMap::new(),
+            configs: HashMap::new(),
+            replaced_configs: HashMap::new(),
+            supervisions: Supervisions::new(),
+            startup: StartupPhase::Completed,
+            sender_to_scheduler: RecordingSender::default().clone_boxed(),
+            metrics: Metrics::default(),
+            routing_started: HashMap::new(),
+            pipeline: vec![],
+            idempotency: IdempotencyStore::open(IdempotencyOptions::default()).unwrap(),
+            journal: Journal::disabled(),
+            batches: Batches::default(),
+            held_back: None,
+            inputs: HashSet::new(),
+            paused: HashSet::new(),
+            system_events: SystemEvents::new(false),
+            circuit_breakers: CircuitBreakers::default(),
+            spools: Spools::default(),
+        }
+    }
+
+    #[test]
+    fn shutdown_should_wait_for_all_components() {
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let mut state = build_state(&[(ROUTER_ID, &router), ("port", &port)]);
+
+        process_broker_event(BrokerEvent::Shutdown, &mut state).unwrap();
+        assert!(!progress_shutdown(&mut state));
+        assert_eq!(router.events(), vec!["Shutdown"]);
+        assert_eq!(port.events(), vec!["Shutdown"]);
+
+        process_broker_event(
+            BrokerEvent::ShutdownComplete(ROUTER_ID.to_string()),
+            &mut state,
+        )
+        .unwrap();
+        assert!(!progress_shutdown(&mut state));
+        process_broker_event(
+            BrokerEvent::ShutdownComplete("port".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        assert!(progress_shutdown(&mut state));
+    }
+
+    #[test]
+    fn shutdown_should_drain_pending_deliveries_first() {
+        let port = RecordingSender::default();
+        let mut state = build_state(&[("port", &port)]);
+        state.pending_deliveries.insert(
+            ("port".to_string(), "1".to_string()),
+            PendingDelivery::new(
+                "port".to_string(),
+                vec![missing_delivery("port", 0)],
+                get_ttl(Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)),
+                AckPolicy::All,
+            ),
+        );
+
+        process_broker_event(BrokerEvent::Shutdown, &mut state).unwrap();
+        assert!(!progress_shutdown(&mut state));
+        assert!(port.events().is_empty());
+
+        process_broker_event(
+            BrokerEvent::OutgoingCloudEventProcessed(OutgoingCloudEventProcessed {
+                sender_id: "port".to_string(),
+                routing_id: "1".to_string(),
+                delivery_id: 0,
+                result: ProcessingResult::Successful,
+            }),
+            &mut state,
+        )
+        .unwrap();
+        assert!(!progress_shutdown(&mut state));
+        assert_eq!(
+            port.events(),
+            vec!["IncomingCloudEventProcessed state=Successful", "Shutdown"]
+        );
+    }
+
+    #[test]
+    fn shutdown_should_drop_incoming_cloud_events() {
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let mut state = build_state(&[(ROUTER_ID, &router), ("port", &port)]);
+        state.shutdown = Some(ShutdownPhase::Draining);
+
+        process_broker_event(
+            BrokerEvent::IncomingCloudEvent(IncomingCloudEvent {
+                incoming_id: "port".to_string(),
+                routing_id: "1".to_string(),
+                cloud_event: Arc::new(Event::default()),
+                args: CloudEventRoutingArgs {
+                    delivery_guarantee: DeliveryGuarantee::AtLeastOnce,
+                    ..CloudEventRoutingArgs::default()
+                },
+            }),
+            &mut state,
+        )
+        .unwrap();
+        assert!(router.events().is_empty());
+        assert!(port.events().is_empty());
+    }
+
+    #[test]
+    fn input_ports_should_be_paused_while_deliveries_are_pending() {
+        let input = RecordingSender::default();
+        let mut state = build_state(&[("input", &input)]);
+        state.options.flow_control.pending_deliveries = Some(Watermarks { high: 2, low: 0 });
+        state.inputs.insert("input".to_string());
+        for routing_id in ["1", "2"] {
+            state.pending_deliveries.insert(
+                ("input".to_string(), routing_id.to_string()),
+                PendingDelivery::new(
+                    "input".to_string(),
+                    vec![missing_delivery("output", 0)],
+                    get_ttl(Duration::from_millis(DEFAULT_DELIVERY_TTL_MS)),
+                    AckPolicy::All,
+                ),
+            );
+        }
+
+        control_flow(&mut state);
+        control_flow(&mut state);
+        assert_eq!(input.events(), vec!["Pause server_id=input"]);
+
+        state
+            .pending_deliveries
+            .remove(&("input".to_string(), "1".to_string()));
+        control_flow(&mut state);
+        assert_eq!(input.events(), vec!["Pause server_id=input"]);
+
+        state
+            .pending_deliveries
+            .remove(&("input".to_string(), "2".to_string()));
+        control_flow(&mut state);
+        assert_eq!(
+            input.events(),
+            vec!["Pause server_id=input", "Resume server_id=input"]
+        );
+    }
+
+    fn missing_delivery(destination_id: &str, delivery_id: u32) -> MissingDelivery {
+        MissingDelivery {
+            destination_id: destination_id.to_string(),
+            delivery_id,
+            attempts: 1,
+            event: None,
+            retry_at: None,
+            idempotency_key: None,
+        }
+    }
+
+    fn at_least_once() -> CloudEventRoutingArgs {
+        CloudEventRoutingArgs {
+            delivery_guarantee: DeliveryGuarantee::AtLeastOnce,
+            ..CloudEventRoutingArgs::default()
+        }
+    }
+
+    #[test]
+    fn routing_to_unknown_destination_should_be_rejected() {
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let mut state = build_state(&[(ROUTER_ID, &router), ("port", &port)]);
+
+        let result = process_broker_event(
+            BrokerEvent::RoutingResult(RoutingResult {
+                incoming_id: "port".to_string(),
+                routing_id: "1".to_string(),
+                routing: vec![
+                    OutgoingCloudEvent {
+                        routing_id: "1".to_string(),
+                        cloud_event: Arc::new(Event::default()),
+                        destination_id: "port".to_string(),
+                        args: at_least_once(),
+                        delivery_id: 0,
+                    },
+                    OutgoingCloudEvent {
+                        routing_id: "1".to_string(),
+                        cloud_event: Arc::new(Event::default()),
+                        destination_id: "typo".to_string(),
+                        args: at_least_once(),
+                        delivery_id: 0,
+                    },
+                ],
+                args: at_least_once(),
+                result: ProcessingResult::Successful,
+            }),
+            &mut state,
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            port.events(),
+            vec!["IncomingCloudEventProcessed state=PermanentError"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    #[test]
+    fn incoming_cloud_events_should_wait_for_the_replay_of_the_journal() {
+        let router = RecordingSender::default();
+        let mut state = build_state(&[(ROUTER_ID, &router)]);
+        let event = |incoming_id: &str| IncomingCloudEvent {
+            incoming_id: incoming_id.to_string(),
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            args: at_least_once(),
+        };
+        state.held_back = Some(vec![event("replayed")]);
+
+        process_broker_event(BrokerEvent::IncomingCloudEvent(event("new")), &mut state).unwrap();
+        replay_journal(&mut state);
+        assert!(router.events().is_empty());
+
+        process_broker_event(
+            BrokerEvent::ConfigUpdated(Config::Null, ROUTER_ID.to_string()),
+            &mut state,
+        )
+        .unwrap();
+        replay_journal(&mut state);
+        assert_eq!(
+            router.events(),
+            vec![
+                "ConfigUpdated destination_id=router",
+                "IncomingCloudEvent receiver_id=replayed",
+                "IncomingCloudEvent receiver_id=new"
+            ]
+        );
+        assert!(state.held_back.is_none());
+    }
+
+    #[test]
+    fn incoming_cloud_event_without_router_should_be_rejected() {
+        let port = RecordingSender::default();
+        let mut state = build_state(&[("port", &port)]);
+
+        let result = process_broker_event(
+            BrokerEvent::IncomingCloudEvent(IncomingCloudEvent {
+                incoming_id: "port".to_string(),
+                routing_id: "1".to_string(),
+                cloud_event: Arc::new(Event::default()),
+                args: at_least_once(),
+            }),
+            &mut state,
+        );
+        assert!(result.is_err());
+        assert_eq!(
+            port.events(),
+            vec!["IncomingCloudEventProcessed state=PermanentError"]
+        );
+    }
+
+    #[test]
+    fn config_for_unknown_component_should_be_rejected() {
+        let port = RecordingSender::default();
+        let mut state = build_state(&[("port", &port)]);
+
+        let result = process_broker_event(
+            BrokerEvent::ConfigUpdated(Config::Null, "typo".to_string()),
+            &mut state,
+        );
+        assert!(result.is_err());
+        assert!(port.events().is_empty());
+    }
+
+    #[test]
+    fn rejected_config_should_be_reported_to_the_config_loader() {
+        let config_loader = RecordingSender::default();
+        let port = RecordingSender::default();
+        let mut state = build_state(&[(CONFIG_LOADER_ID, &config_loader), ("port", &port)]);
+
+        for (config, answer) in [
+            (
+                Config::U8(1),
+                BrokerEvent::ConfigApplied("port".to_string()),
+            ),
+            (
+                Config::U8(2),
+                BrokerEvent::ConfigRejected("port".to_string(), "invalid".to_string()),
+            ),
+        ] {
+            process_broker_event(
+                BrokerEvent::ConfigUpdated(config, "port".to_string()),
+                &mut state,
+            )
+            .unwrap();
+            process_broker_event(answer, &mut state).unwrap();
+        }
+        assert_eq!(
+            config_loader.events(),
+            vec![
+                "ConfigApplied sender_id=port",
+                "ConfigRejected sender_id=port reason=invalid"
+            ]
+        );
+        assert_eq!(state.configs.get("port"), Some(&Config::U8(1)));
+    }
+
+    #[test]
+    fn system_events_should_be_routed_once_the_startup_is_completed() {
+        let config_loader = RecordingSender::default();
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let mut state = build_state(&[
+            (CONFIG_LOADER_ID, &config_loader),
+            (ROUTER_ID, &router),
+            ("port", &port),
+        ]);
+        state.system_events = SystemEvents::new(true);
+        state.held_back = Some(vec![]);
+
+        process_broker_event(
+            BrokerEvent::ConfigUpdated(Config::U8(1), "port".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            BrokerEvent::ConfigRejected("port".to_string(), "invalid".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        route_system_events(&mut state);
+        assert!(router.events().is_empty());
+
+        state.configs.insert(ROUTER_ID.to_string(), Config::Null);
+        replay_journal(&mut state);
+        assert_eq!(
+            router.events(),
+            vec!["IncomingCloudEvent receiver_id=cerk.system"]
+        );
+    }
+
+    #[test]
+    fn health_check_for_unknown_component_should_be_unhealthy() {
+        let health_check = RecordingSender::default();
+        let mut state = build_state(&[("health_check", &health_check)]);
+
+        let result = process_broker_event(
+            BrokerEvent::HealthCheckRequest(HealthCheckRequest {
+                id: "1".to_string(),
+                sender_id: "health_check".to_string(),
+                destination_id: "typo".to_string(),
+            }),
+            &mut state,
+        );
+        assert!(result.is_err());
+        assert_eq!(health_check.events(), vec!["HealthCheckResponse"]);
+    }
+
+    #[test]
+    fn receive_timeout_should_block_without_pending_deliveries() {
+        let state = build_state(&[]);
+        assert_eq!(get_receive_timeout(&state), None);
+    }
+
+    #[test]
+    fn receive_timeout_should_wake_up_at_next_expiry() {
+        let mut state = build_state(&[]);
+        state.pending_deliveries.insert(
+            ("port".to_string(), "expired".to_string()),
+            PendingDelivery::new(
+                "port".to_string(),
+                vec![],
+                SystemTime::now().sub(Duration::from_millis(1)),
+                AckPolicy::All,
+            ),
+        );
+        state.pending_deliveries.insert(
+            ("port".to_string(), "pending".to_string()),
+            PendingDelivery::new(
+                "port".to_string(),
+                vec![],
+                get_ttl(Duration::from_secs(60)),
+                AckPolicy::All,
+            ),
+        );
+        assert_eq!(get_receive_timeout(&state), Some(Duration::from_millis(0)));
+    }
+
+    #[test]
+    fn pending_delivery_should_use_ttl_of_slowest_destination() {
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let slow_port = RecordingSender::default();
+        let mut state = build_state(&[
+            (ROUTER_ID, &router),
+            ("port", &port),
+            ("slow_port", &slow_port),
+        ]);
+        state.options.ports.insert(
+            "slow_port".to_string(),
+            PortOptions {
+                delivery_ttl: Some(Duration::from_secs(60)),
+                ..PortOptions::default()
+            },
+        );
+
+        process_broker_event(
+            BrokerEvent::RoutingResult(RoutingResult {
+                incoming_id: "port".to_string(),
+                routing_id: "1".to_string(),
+                routing: vec![
+                    OutgoingCloudEvent {
+                        routing_id: "1".to_string(),
+                        cloud_event: Arc::new(Event::default()),
+                        destination_id: "port".to_string(),
+                        args: at_least_once(),
+                        delivery_id: 0,
+                    },
+                    OutgoingCloudEvent {
+                        routing_id: "1".to_string(),
+                        cloud_event: Arc::new(Event::default()),
+                        destination_id: "slow_port".to_string(),
+                        args: at_least_once(),
+                        delivery_id: 0,
+                    },
+                ],
+                args: at_least_once(),
+                result: ProcessingResult::Successful,
+            }),
+            &mut state,
+        )
+        .unwrap();
+
+        let timeout = get_receive_timeout(&state).unwrap();
+        assert!(timeout > Duration::from_secs(59));
+        clean_pending_deliveries(
+            &state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+            &mut state.circuit_breakers,
+        );
+        assert_eq!(state.pending_deliveries.len(), 1);
+    }
+
+    #[test]
+    fn copies_to_the_same_destination_should_be_acknowledged_separately() {
+        let router = RecordingSender::default();
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let mut state =
+            build_state(&[(ROUTER_ID, &router), ("input", &input), ("output", &output)]);
+        let copy = OutgoingCloudEvent {
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            destination_id: "output".to_string(),
+            args: at_least_once(),
+            delivery_id: 0,
+        };
+
+        process_broker_event(
+            BrokerEvent::RoutingResult(RoutingResult {
+                incoming_id: "input".to_string(),
+                routing_id: "1".to_string(),
+                routing: vec![copy.clone(), copy],
+                args: at_least_once(),
+                result: ProcessingResult::Successful,
+            }),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            state
+                .pending_deliveries
+                .get(&("input".to_string(), "1".to_string()))
+                .unwrap()
+                .missing_deliveries()
+                .iter()
+                .map(|missing| (missing.destination_id.as_str(), missing.delivery_id))
+                .collect::<Vec<_>>(),
+            vec![("output", 0), ("output", 1)]
+        );
+
+        for delivery_id in 0..2 {
+            assert!(input.events().is_empty());
+            process_broker_event(
+                BrokerEvent::OutgoingCloudEventProcessed(OutgoingCloudEventProcessed {
+                    sender_id: "output".to_string(),
+                    routing_id: "1".to_string(),
+                    delivery_id,
+                    result: ProcessingResult::Successful,
+                }),
+                &mut state,
+            )
+            .unwrap();
+        }
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    fn build_routing(incoming_id: &str, destinations: &[&str]) -> BrokerEvent {
+        BrokerEvent::RoutingResult(RoutingResult {
+            incoming_id: incoming_id.to_string(),
+            routing_id: "1".to_string(),
+            routing: destinations
+                .iter()
+                .map(|destination_id| OutgoingCloudEvent {
+                    routing_id: "1".to_string(),
+                    cloud_event: Arc::new(Event::default()),
+                    destination_id: destination_id.to_string(),
+                    args: at_least_once(),
+                    delivery_id: 0,
+                })
+                .collect(),
+            args: at_least_once(),
+            result: ProcessingResult::Successful,
+        })
+    }
+
+    fn build_processed(
+        sender_id: &str,
+        delivery_id: CloudEventDeliveryId,
+        result: ProcessingResult,
+    ) -> BrokerEvent {
+        BrokerEvent::OutgoingCloudEventProcessed(OutgoingCloudEventProcessed {
+            sender_id: sender_id.to_string(),
+            routing_id: "1".to_string(),
+            delivery_id,
+            result,
+        })
+    }
+
+    fn retry_immediately(max_attempts: u32) -> PortOptions {
+        PortOptions {
+            retry: Some(RetryPolicy {
+                max_attempts,
+                initial_backoff: Duration::from_millis(0),
+                jitter: 0.0,
+                ..RetryPolicy::default()
+            }),
+            ..PortOptions::default()
+        }
+    }
+
+    #[test]
+    fn transient_error_should_be_retried_until_max_attempts() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let mut state = build_state(&[("input", &input), ("output", &output)]);
+        state
+            .options
+            .ports
+            .insert("output".to_string(), retry_immediately(2));
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::TransientError),
+            &mut state,
+        )
+        .unwrap();
+        assert!(input.events().is_empty());
+        assert_eq!(get_receive_timeout(&state), Some(Duration::from_millis(0)));
+
+        send_due_retries(
+            &state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+        );
+        assert_eq!(output.events().len(), 2);
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::TransientError),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=TransientError"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    #[test]
+    fn same_routing_id_from_different_inputs_should_not_collide() {
+        let first = RecordingSender::default();
+        let second = RecordingSender::default();
+        let output = RecordingSender::default();
+        let mut state = build_state(&[("first", &first), ("second", &second), ("output", &output)]);
+
+        process_broker_event(build_routing("first", &["output"]), &mut state).unwrap();
+        process_broker_event(build_routing("second", &["output"]), &mut state).unwrap();
+        let delivery_ids: Vec<_> = ["first", "second"]
+            .iter()
+            .flat_map(|input| {
+                state
+                    .pending_deliveries
+                    .get(&(input.to_string(), "1".to_string()))
+                    .unwrap()
+                    .missing_deliveries()
+                    .iter()
+                    .map(|missing| missing.delivery_id)
+            })
+            .collect();
+        assert_eq!(delivery_ids, vec![0, 1]);
+
+        process_broker_event(
+            build_processed("output", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert!(first.events().is_empty());
+        assert_eq!(
+            second.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            first.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    #[test]
+    fn acknowledgment_should_only_match_its_own_copy() {
+        let first = RecordingSender::default();
+        let second = RecordingSender::default();
+        let output = RecordingSender::default();
+        let other = RecordingSender::default();
+        let mut state = build_state(&[
+            ("first", &first),
+            ("second", &second),
+            ("output", &output),
+            ("other", &other),
+        ]);
+
+        process_broker_event(build_routing("first", &["output"]), &mut state).unwrap();
+        process_broker_event(build_routing("second", &["other"]), &mut state).unwrap();
+        // the delivery id of the copy for other, but sent by output
+        process_broker_event(
+            build_processed("output", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        // an unknown delivery id
+        process_broker_event(
+            build_processed("output", 7, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert!(first.events().is_empty());
+        assert!(second.events().is_empty());
+        assert_eq!(state.pending_deliveries.len(), 2);
+
+        process_broker_event(
+            build_processed("other", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert!(first.events().is_empty());
+        assert_eq!(
+            second.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+    }
+
+    fn build_routing_with_ack_policy(destinations: &[&str], ack_policy: AckPolicy) -> BrokerEvent {
+        match build_routing("input", destinations) {
+            BrokerEvent::RoutingResult(mut event) => {
+                event.args.ack_policy = ack_policy;
+                BrokerEvent::RoutingResult(event)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn any_ack_policy_should_acknowledge_the_first_successful_copy() {
+        let input = RecordingSender::default();
+        let a = RecordingSender::default();
+        let b = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("a", &a), ("b", &b), ("dlq", &dlq)],
+            "dlq",
+        );
+
+        process_broker_event(
+            build_routing_with_ack_policy(&["a", "b"], AckPolicy::Any),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_processed("a", 0, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert!(input.events().is_empty());
+        assert_eq!(dlq.events(), vec!["OutgoingCloudEvent destination_id=dlq"]);
+        assert_eq!(state.pending_deliveries.len(), 1);
+
+        process_broker_event(
+            build_processed("b", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    #[test]
+    fn at_least_ack_policy_should_fail_once_it_can_not_be_satisfied() {
+        let input = RecordingSender::default();
+        let a = RecordingSender::default();
+        let b = RecordingSender::default();
+        let c = RecordingSender::default();
+        let mut state = build_state(&[("input", &input), ("a", &a), ("b", &b), ("c", &c)]);
+
+        process_broker_event(
+            build_routing_with_ack_policy(&["a", "b", "c"], AckPolicy::AtLeast(2)),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_processed("a", 0, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_processed("b", 1, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert!(input.events().is_empty());
+
+        process_broker_event(
+            build_processed("c", 2, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=PermanentError"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    #[test]
+    fn exited_destination_should_not_fail_a_satisfiable_ack_policy() {
+        let input = RecordingSender::default();
+        let a = RecordingSender::default();
+        let b = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state =
+            build_supervised_state(&[("input", &input), ("a", &a), ("b", &b)], &scheduler);
+
+        process_broker_event(
+            build_routing_with_ack_policy(&["a", "b"], AckPolicy::Any),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            BrokerEvent::InternalServerExited("a".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        assert!(input.events().is_empty());
+
+        process_broker_event(
+            build_processed("b", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+    }
+
+    fn build_exactly_once_routing(incoming_id: &str, destinations: &[&str]) -> BrokerEvent {
+        match build_routing(incoming_id, destinations) {
+            BrokerEvent::RoutingResult(mut event) => {
+                event.args.delivery_guarantee = DeliveryGuarantee::ExactlyOnce;
+                for copy in event.routing.iter_mut() {
+                    copy.args.delivery_guarantee = DeliveryGuarantee::ExactlyOnce;
+                    Arc::make_mut(&mut copy.cloud_event).set_id("1");
+                }
+                BrokerEvent::RoutingResult(event)
+            }
+            _ => unreachable!(),
+        }
+    }
+
+    #[test]
+    fn exactly_once_should_not_forward_redelivered_cloud_events() {
+        let input = RecordingSender::default();
+        let a = RecordingSender::default();
+        let b = RecordingSender::default();
+        let mut state = build_state(&[("input", &input), ("a", &a), ("b", &b)]);
+
+        process_broker_event(build_exactly_once_routing("input", &["a", "b"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("a", 0, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_processed("b", 1, ProcessingResult::TransientError),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=TransientError"]
+        );
+
+        // the sender redelivers the event, only b did not receive it yet
+        process_broker_event(build_exactly_once_routing("input", &["a", "b"]), &mut state).unwrap();
+        assert_eq!(a.events().len(), 1);
+        assert_eq!(b.events().len(), 2);
+        process_broker_event(
+            build_processed("b", 2, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+
+        process_broker_event(build_exactly_once_routing("input", &["a", "b"]), &mut state).unwrap();
+        assert_eq!(a.events().len(), 1);
+        assert_eq!(b.events().len(), 2);
+        assert_eq!(
+            input.events()[1..],
+            [
+                "IncomingCloudEventProcessed state=Successful",
+                "IncomingCloudEventProcessed state=Successful"
+            ]
+        );
+        assert!(state.pending_deliveries.is_empty());
+        assert_eq!(
+            metric_value(&state.metrics.snapshot(), METRIC_DUPLICATES, "a"),
+            Some(MetricValue::Counter(2))
+        );
+    }
+
+    #[test]
+    fn retry_should_only_resend_to_failed_destinations() {
+        let input = RecordingSender::default();
+        let stable = RecordingSender::default();
+        let flaky = RecordingSender::default();
+        let mut state = build_state(&[("input", &input), ("stable", &stable), ("flaky", &flaky)]);
+        state.options.retry = retry_immediately(3).retry.unwrap();
+
+        process_broker_event(build_routing("input", &["stable", "flaky"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("stable", 0, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_processed("flaky", 1, ProcessingResult::TransientError),
+            &mut state,
+        )
+        .unwrap();
+        send_due_retries(
+            &state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+        );
+        process_broker_event(
+            build_processed("flaky", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+
+        assert_eq!(stable.events().len(), 1);
+        assert_eq!(flaky.events().len(), 2);
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+    }
+
+    #[test]
+    fn permanent_error_should_not_be_retried() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let mut state = build_state(&[("input", &input), ("output", &output)]);
+        state
+            .options
+            .ports
+            .insert("output".to_string(), retry_immediately(3));
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=PermanentError"]
+        );
+        assert_eq!(output.events().len(), 1);
+    }
+
+    fn build_dead_letter_state(
+        outboxes: &[(&str, &RecordingSender)],
+        dead_letter_port: &str,
+    ) -> KernelState {
+        let mut state = build_state(outboxes);
+        state.options.dead_letter_port = Some(dead_letter_port.to_string());
+        state
+    }
+
+    #[test]
+    fn permanent_error_should_be_dead_lettered() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert!(input.events().is_empty());
+        assert_eq!(dlq.events(), vec!["OutgoingCloudEvent destination_id=dlq"]);
+
+        process_broker_event(
+            build_processed("dlq", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=Successful"]
+        );
+    }
+
+    fn open_circuit_after_one_failure(
+        state: &mut KernelState,
+        destination_id: &str,
+        open_duration: Duration,
+    ) {
+        state.options.circuit_breaker = Some(CircuitBreakerOptions {
+            failure_threshold: 1,
+            open_duration,
+        });
+        process_broker_event(build_routing("input", &[destination_id]), state).unwrap();
+        process_broker_event(
+            build_processed(destination_id, 0, ProcessingResult::TransientError),
+            state,
+        )
+        .unwrap();
+    }
+
+    #[test]
+    fn open_circuit_should_dead_letter_the_copies_at_once() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+        open_circuit_after_one_failure(&mut state, "output", Duration::from_secs(60));
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=TransientError"]
+        );
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        assert_eq!(output.events().len(), 1);
+        assert_eq!(dlq.events(), vec!["OutgoingCloudEvent destination_id=dlq"]);
+        process_broker_event(
+            build_processed("dlq", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events()[1],
+            "IncomingCloudEventProcessed state=Successful"
+        );
+    }
+
+    #[test]
+    fn open_circuit_should_fail_the_copies_at_once_without_dead_letter_port() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let redundant = RecordingSender::default();
+        let mut state = build_state(&[
+            ("input", &input),
+            ("output", &output),
+            ("redundant", &redundant),
+        ]);
+        open_circuit_after_one_failure(&mut state, "output", Duration::from_secs(60));
+
+        let result = process_broker_event(build_routing("input", &["output"]), &mut state);
+        assert!(result.is_err());
+        assert_eq!(output.events().len(), 1);
+        assert_eq!(
+            input.events()[1],
+            "IncomingCloudEventProcessed state=TransientError"
+        );
+        assert!(state.pending_deliveries.is_empty());
+
+        // the other copy can still satisfy the ack policy
+        process_broker_event(
+            build_routing_with_ack_policy(&["output", "redundant"], AckPolicy::Any),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(output.events().len(), 1);
+        process_broker_event(
+            build_processed("redundant", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events()[2],
+            "IncomingCloudEventProcessed state=Successful"
+        );
+    }
+
+    #[test]
+    fn half_open_circuit_should_be_closed_by_a_successful_probe() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let mut state = build_state(&[("input", &input), ("output", &output)]);
+        open_circuit_after_one_failure(&mut state, "output", Duration::from_millis(0));
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        assert_eq!(output.events().len(), 2);
+        // only a single probe is sent until it is answered
+        let result = process_broker_event(build_routing("input", &["output"]), &mut state);
+        assert!(result.is_err());
+        assert_eq!(output.events().len(), 2);
+        process_broker_event(
+            build_processed("output", 1, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        assert_eq!(output.events().len(), 3);
+    }
+
+    #[test]
+    fn half_open_circuit_should_only_dead_letter_the_copies_beyond_the_probe() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+        open_circuit_after_one_failure(&mut state, "output", Duration::from_millis(0));
+
+        process_broker_event(build_routing("input", &["output", "output"]), &mut state).unwrap();
+        assert_eq!(output.events().len(), 2);
+        assert_eq!(dlq.events(), vec!["OutgoingCloudEvent destination_id=dlq"]);
+    }
+
+    fn build_spool_state(
+        outboxes: &[(&str, &RecordingSender)],
+        name: &str,
+        max_bytes: u64,
+        overflow: SpoolOverflow,
+    ) -> (KernelState, PathBuf) {
+        let mut state = build_state(outboxes);
+        let directory = std::env::temp_dir().join(format!(
+            "cerk_kernel_spool_test_{}_{}",
+            name,
+            std::process::id()
+        ));
+        state.options.ports.insert(
+            "output".to_string(),
+            PortOptions {
+                spool: Some(SpoolOptions {
+                    directory: directory.clone(),
+                    max_bytes,
+                    overflow,
+                    sync: false,
+                }),
+                ..PortOptions::default()
+            },
+        );
+        state.spools = Spools::open(&state.options);
+        (state, directory)
+    }
+
+    #[test]
+    fn copies_for_an_unavailable_port_should_be_spooled_and_drained() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let (mut state, directory) = build_spool_state(
+            &[("input", &input), ("output", &output)],
+            "drain",
+            1_000_000,
+            SpoolOverflow::DropNewest,
+        );
+        fs::remove_dir_all(directory).unwrap();
+        let outbox = state.outboxes.remove("output").unwrap();
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        assert_eq!(
+            input.events(),
+            vec![
+                "IncomingCloudEventProcessed state=Successful",
+                "IncomingCloudEventProcessed state=Successful"
+            ]
+        );
+        // the copies count as acknowledged as soon as they are spooled
+        assert!(state.pending_deliveries.is_empty());
+
+        // new copies are spooled behind the spooled ones, they are sent without waiting for each other
+        state.outboxes.insert("output".to_string(), outbox);
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        drain_spools(&mut state);
+        drain_spools(&mut state);
+        let delivery_ids: Vec<_> = output
+            .take_events()
+            .into_iter()
+            .map(|event| match event {
+                BrokerEvent::OutgoingCloudEvent(event) => event.delivery_id,
+                event => panic!("unexpected {}", event),
+            })
+            .collect();
+        assert_eq!(delivery_ids, vec![0, 1, 2]);
+        for delivery_id in [2, 0, 1] {
+            assert!(state.spools.is_draining());
+            process_broker_event(
+                build_processed("output", delivery_id, ProcessingResult::Successful),
+                &mut state,
+            )
+            .unwrap();
+        }
+        assert!(!state.spools.is_draining());
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        assert_eq!(output.events().len(), 1);
+        assert_eq!(state.pending_deliveries.len(), 1);
+    }
+
+    #[test]
+    fn full_spool_with_block_should_pause_the_inputs() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let (mut state, directory) = build_spool_state(
+            &[("input", &input), ("output", &output)],
+            "block",
+            // one spooled copy fits, a second one does not
+            300,
+            SpoolOverflow::Block,
+        );
+        fs::remove_dir_all(directory).unwrap();
+        let outbox = state.outboxes.remove("output").unwrap();
+        state.inputs.insert("input".to_string());
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        let result = process_broker_event(build_routing("input", &["output"]), &mut state);
+        assert!(result.is_err());
+        control_flow(&mut state);
+        assert_eq!(
+            input.events(),
+            vec![
+                "IncomingCloudEventProcessed state=Successful",
+                "IncomingCloudEventProcessed state=TransientError",
+                "Pause server_id=input"
+            ]
+        );
+
+        state.outboxes.insert("output".to_string(), outbox);
+        drain_spools(&mut state);
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+        control_flow(&mut state);
+        assert_eq!(input.events()[3], "Resume server_id=input");
+    }
+
+    #[test]
+    fn dead_lettered_system_event_should_not_emit_another_one() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+        state.system_events = SystemEvents::new(true);
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        let emitted = state.system_events.take();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].cloud_event.ty(), SYSTEM_EVENT_DEAD_LETTERED);
+        assert_eq!(emitted[0].cloud_event.subject(), Some("output"));
+
+        let mut failed_routing = match build_routing(SYSTEM_EVENTS_ID, &["output"]) {
+            BrokerEvent::RoutingResult(event) => event,
+            _ => unreachable!(),
+        };
+        failed_routing.args = CloudEventRoutingArgs::default();
+        failed_routing.result = ProcessingResult::PermanentError;
+        process_broker_event(BrokerEvent::RoutingResult(failed_routing), &mut state).unwrap();
+        assert_eq!(dlq.events().len(), 2);
+        assert!(state.system_events.take().is_empty());
+    }
+
+    #[test]
+    fn timeout_should_be_dead_lettered() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+
+        state.options.ports.insert(
+            "output".to_string(),
+            PortOptions {
+                delivery_ttl: Some(Duration::from_millis(0)),
+                ..PortOptions::default()
+            },
+        );
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        std::thread::sleep(Duration::from_millis(1));
+        clean_pending_deliveries(
+            &state.outboxes,
+            &mut state.pending_deliveries,
+            &state.options,
+            &mut state.metrics,
+            &mut state.system_events,
+            &mut state.circuit_breakers,
+        );
+        assert!(input.events().is_empty());
+        assert_eq!(dlq.events(), vec!["OutgoingCloudEvent destination_id=dlq"]);
+        assert_eq!(state.pending_deliveries.len(), 1);
+    }
+
+    #[test]
+    fn unknown_destination_should_be_dead_lettered() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+
+        process_broker_event(build_routing("input", &["output", "typo"]), &mut state).unwrap();
+        assert_eq!(
+            output.events(),
+            vec!["OutgoingCloudEvent destination_id=output"]
+        );
+        assert_eq!(dlq.events(), vec!["OutgoingCloudEvent destination_id=dlq"]);
+        assert!(input.events().is_empty());
+    }
+
+    #[test]
+    fn failed_dead_letter_should_be_reported_to_the_sender() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let dlq = RecordingSender::default();
+        let mut state = build_dead_letter_state(
+            &[("input", &input), ("output", &output), ("dlq", &dlq)],
+            "dlq",
+        );
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_processed("dlq", 1, ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(dlq.events().len(), 1);
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=PermanentError"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+    }
+
+    fn dummy_component(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
+
+    fn dummy_scheduler(_: StartOptions, _: KernelFn) -> Result<()> {
+        Ok(())
+    }
+
+    /// the inbox of the Kernel
+    struct ChannelReceiver(mpsc::Receiver<BrokerEvent>);
+
+    impl Receiver for ChannelReceiver {
+        fn receive(&self) -> BrokerEvent {
+            self.0.recv().unwrap()
+        }
+
+        fn receive_timeout(&self, timeout: Duration) -> Option<BrokerEvent> {
+            self.0.recv_timeout(timeout).ok()
+        }
+    }
+
+    #[test]
+    fn missed_startup_deadline_should_fail_the_kernel() {
+        let scheduler = RecordingSender::default();
+        // the components are never scheduled
+        let (_sender_to_kernel, inbox) = mpsc::channel();
+        let start_options = StartOptions {
+            scheduler: &(dummy_scheduler as ScheduleFn),
+            router: &(dummy_component as InternalServerFn),
+            processors: vec![],
+            config_loader: &(dummy_component as InternalServerFn),
+            ports: vec![],
+            kernel: KernelOptions {
+                startup_timeout: Duration::from_millis(10),
+                ..KernelOptions::default()
+            },
+        };
+
+        let result = kernel_start(
+            start_options,
+            Box::new(ChannelReceiver(inbox)),
+            scheduler.clone_boxed(),
+        );
+        let error = result.unwrap_err().to_string();
+        assert!(error.contains("were not scheduled"), "{}", error);
+        assert_eq!(scheduler.events().last().unwrap(), "Shutdown");
+    }
+
+    fn build_supervised_state(
+        outboxes: &[(&str, &RecordingSender)],
+        scheduler: &RecordingSender,
+    ) -> KernelState {
+        let mut state = build_state(outboxes);
+        state.sender_to_scheduler = scheduler.clone_boxed();
+        for (id, _) in outboxes {
+            state
+                .functions
+                .insert(id.to_string(), &(dummy_component as InternalServerFn));
+        }
+        state.options.restart = RetryPolicy {
+            max_attempts: 3,
+            initial_backoff: Duration::from_millis(0),
+            max_backoff: Duration::from_secs(60),
+            multiplier: 1.0,
+            jitter: 0.0,
+        };
+        state
+    }
+
+    fn build_health_check_request(destination_id: &str) -> BrokerEvent {
+        BrokerEvent::HealthCheckRequest(HealthCheckRequest {
+            id: "1".to_string(),
+            sender_id: "health_check".to_string(),
+            destination_id: destination_id.to_string(),
+        })
+    }
+
+    #[test]
+    fn exited_component_should_fail_its_pending_deliveries() {
+        let input = RecordingSender::default();
+        let output = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state =
+            build_supervised_state(&[("input", &input), ("output", &output)], &scheduler);
+
+        process_broker_event(build_routing("input", &["output"]), &mut state).unwrap();
+        process_broker_event(
+            BrokerEvent::InternalServerExited("output".to_string()),
+            &mut state,
+        )
+        .unwrap();
+
+        assert_eq!(
+            input.events(),
+            vec!["IncomingCloudEventProcessed state=TransientError"]
+        );
+        assert!(state.pending_deliveries.is_empty());
+        assert!(!state.outboxes.contains_key("output"));
+
+        // routing to the exited component is rejected until it is back
+        let result = process_broker_event(build_routing("input", &["output"]), &mut state);
+        assert!(result.is_err());
+        assert_eq!(
+            input.events()[1],
+            "IncomingCloudEventProcessed state=TransientError"
+        );
+    }
+
+    #[test]
+    fn exited_component_should_be_restarted_with_its_last_config() {
+        let output = RecordingSender::default();
+        let health_check = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(
+            &[("output", &output), ("health_check", &health_check)],
+            &scheduler,
+        );
+        process_broker_event(
+            BrokerEvent::ConfigUpdated(Config::Null, "output".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            BrokerEvent::InternalServerExited("output".to_string()),
+            &mut state,
+        )
+        .unwrap();
+
+        process_broker_event(build_health_check_request("output"), &mut state).unwrap();
+        assert_eq!(health_check.events(), vec!["HealthCheckResponse"]);
+        process_broker_event(
+            BrokerEvent::ConfigUpdated(Config::Bool(true), "output".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(state.configs.get("output"), Some(&Config::Bool(true)));
+
+        assert_eq!(get_receive_timeout(&state), Some(Duration::from_millis(0)));
+        restart_due_components(&mut state);
+        assert_eq!(
+            scheduler.events(),
+            vec!["ScheduleInternalServer server_id=output"]
+        );
+
+        let restarted = RecordingSender::default();
+        process_broker_event(
+            BrokerEvent::InternalServerScheduled("output".to_string(), restarted.clone_boxed()),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            restarted.events(),
+            vec!["ConfigUpdated destination_id=output", "Init"]
+        );
+        assert!(state.outboxes.contains_key("output"));
+        assert_eq!(output.events(), vec!["ConfigUpdated destination_id=output"]);
+    }
+
+    #[test]
+    fn component_should_stay_down_after_max_restarts() {
+        let output = RecordingSender::default();
+        let health_check = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(
+            &[("output", &output), ("health_check", &health_check)],
+            &scheduler,
+        );
+        state.options.restart.max_attempts = 1;
+
+        process_broker_event(
+            BrokerEvent::InternalServerExited("output".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        restart_due_components(&mut state);
+        process_broker_event(
+            BrokerEvent::InternalServerScheduled("output".to_string(), output.clone_boxed()),
+            &mut state,
+        )
+        .unwrap();
+        let result = process_broker_event(
+            BrokerEvent::InternalServerExited("output".to_string()),
+            &mut state,
+        );
+        assert!(result.is_err());
+
+        restart_due_components(&mut state);
+        assert_eq!(scheduler.events().len(), 1);
+        assert_eq!(get_receive_timeout(&state), None);
+        process_broker_event(build_health_check_request("output"), &mut state).unwrap();
+        assert_eq!(health_check.events(), vec!["HealthCheckResponse"]);
+    }
+
+    #[test]
+    fn exited_component_should_not_be_restarted_during_shutdown() {
+        let port = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(&[("port", &port)], &scheduler);
+
+        process_broker_event(BrokerEvent::Shutdown, &mut state).unwrap();
+        assert!(!progress_shutdown(&mut state));
+        process_broker_event(
+            BrokerEvent::InternalServerExited("port".to_string()),
+            &mut state,
+        )
+        .unwrap();
+
+        assert!(progress_shutdown(&mut state));
+        assert!(state.supervisions.is_empty());
+        assert!(scheduler.events().is_empty());
+    }
+
+    #[test]
+    fn components_should_get_their_config_before_init() {
+        let config_loader = RecordingSender::default();
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(
+            &[(CONFIG_LOADER_ID, &config_loader), (ROUTER_ID, &router)],
+            &scheduler,
+        );
+        state
+            .functions
+            .insert("port".to_string(), &(dummy_component as InternalServerFn));
+        state.startup = StartupPhase::Scheduling(get_ttl(Duration::from_secs(60)));
+
+        progress_startup(&mut state).unwrap();
+        assert!(config_loader.events().is_empty());
+
+        process_broker_event(
+            BrokerEvent::InternalServerScheduled("port".to_string(), port.clone_boxed()),
+            &mut state,
+        )
+        .unwrap();
+        progress_startup(&mut state).unwrap();
+        assert_eq!(config_loader.events(), vec!["Init"]);
+        assert!(port.events().is_empty());
+
+        for id in [ROUTER_ID, "port"] {
+            process_broker_event(
+                BrokerEvent::ConfigUpdated(Config::Null, id.to_string()),
+                &mut state,
+            )
+            .unwrap();
+            progress_startup(&mut state).unwrap();
+        }
+        assert_eq!(
+            port.events(),
+            vec!["ConfigUpdated destination_id=port", "Init"]
+        );
+        assert_eq!(
+            router.events(),
+            vec!["ConfigUpdated destination_id=router", "Init"]
+        );
+        assert_eq!(config_loader.events(), vec!["Init"]);
+        assert_eq!(scheduler.events(), vec!["Ready"]);
+    }
+
+    #[test]
+    fn component_that_is_not_scheduled_should_stop_the_router() {
+        let config_loader = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(&[(CONFIG_LOADER_ID, &config_loader)], &scheduler);
+        state
+            .functions
+            .insert("port".to_string(), &(dummy_component as InternalServerFn));
+        state.startup = StartupPhase::Scheduling(SystemTime::now().sub(Duration::from_secs(1)));
+
+        let error = progress_startup(&mut state).unwrap_err();
+        assert!(error.to_string().contains("[\"port\"] were not scheduled"));
+        assert!(config_loader.events().is_empty());
+        assert!(matches!(state.shutdown, Some(ShutdownPhase::Draining)));
+    }
+
+    #[test]
+    fn component_without_config_should_stop_the_router() {
+        let config_loader = RecordingSender::default();
+        let router = RecordingSender::default();
+        let port = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(
+            &[
+                (CONFIG_LOADER_ID, &config_loader),
+                (ROUTER_ID, &router),
+                ("port", &port),
+            ],
+            &scheduler,
+        );
+        state.configs.insert(ROUTER_ID.to_string(), Config::Null);
+        state.startup = StartupPhase::Configuring(SystemTime::now().sub(Duration::from_secs(1)));
+
+        let error = progress_startup(&mut state).unwrap_err();
+        assert!(error.to_string().contains("[\"port\"] got no config"));
+        assert!(router.events().is_empty());
+        assert!(port.events().is_empty());
+        assert!(scheduler.events().is_empty());
+        assert!(matches!(state.shutdown, Some(ShutdownPhase::Draining)));
+    }
+
+    #[derive(Clone)]
+    struct ChannelSender(std::sync::mpsc::Sender<BrokerEvent>);
+
+    impl Sender for ChannelSender {
+        fn send(&self, event: BrokerEvent) {
+            self.0.send(event).unwrap();
+        }
+        fn clone_boxed(&self) -> Box<dyn Sender + Send> {
+            Box::new(self.clone())
+        }
+    }
+
+    fn request_metrics(state: &mut KernelState) -> Vec<Metric> {
+        let (sender, receiver) = std::sync::mpsc::channel();
+        state
+            .outboxes
+            .insert("exporter".to_string(), Box::new(ChannelSender(sender)));
+        process_broker_event(
+            BrokerEvent::MetricsRequest(MetricsRequest {
+                id: "1".to_string(),
+                sender_id: "exporter".to_string(),
+            }),
+            state,
+        )
+        .unwrap();
+        match receiver.try_recv() {
+            Ok(BrokerEvent::MetricsResponse(response)) => response.metrics,
+            _ => panic!("no MetricsResponse received"),
+        }
+    }
+
+    fn metric_value(metrics: &[Metric], name: &str, component_id: &str) -> Option<MetricValue> {
+        metrics
+            .iter()
+            .find(|metric| {
+                metric.name == name && metric.labels[METRIC_COMPONENT_LABEL] == component_id
+            })
+            .map(|metric| metric.value.clone())
+    }
+
+    #[test]
+    fn kernel_should_count_routed_and_acknowledged_events() {
+        let input = RecordingSender::default();
+        let router = RecordingSender::default();
+        let output = RecordingSender::default();
+        let mut state =
+            build_state(&[("input", &input), (ROUTER_ID, &router), ("output", &output)]);
+
+        process_broker_event(
+            BrokerEvent::IncomingCloudEvent(IncomingCloudEvent {
+                incoming_id: "input".to_string(),
+                routing_id: "1".to_string(),
+                cloud_event: Arc::new(Event::default()),
+                args: at_least_once(),
+            }),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(build_routing("input", &["output", "output"]), &mut state).unwrap();
+        process_broker_event(
+            build_processed("output", 0, ProcessingResult::Successful),
+            &mut state,
+        )
+        .unwrap();
+
+        let metrics = request_metrics(&mut state);
+        assert_eq!(
+            metric_value(&metrics, METRIC_INCOMING_EVENTS, "input"),
+            Some(MetricValue::Counter(1))
+        );
+        assert_eq!(
+            metric_value(&metrics, METRIC_ROUTED_EVENTS, "input"),
+            Some(MetricValue::Counter(1))
+        );
+        assert_eq!(
+            metric_value(&metrics, METRIC_OUTGOING_EVENTS, "output"),
+            Some(MetricValue::Counter(2))
+        );
+        assert_eq!(
+            metric_value(&metrics, METRIC_ACKS, "output"),
+            Some(MetricValue::Counter(1))
+        );
+        assert_eq!(
+            metric_value(&metrics, METRIC_PENDING_DELIVERIES, "input"),
+            Some(MetricValue::Gauge(1.0))
+        );
+        assert_eq!(
+            metric_value(&metrics, METRIC_UNACKNOWLEDGED_EVENTS, "output"),
+            Some(MetricValue::Gauge(1.0))
+        );
+        match metric_value(&metrics, METRIC_ROUTING_LATENCY, "input") {
+            Some(MetricValue::Histogram(histogram)) => assert_eq!(histogram.count, 1),
+            value => panic!("unexpected routing latency {:?}", value),
+        }
+    }
+
+    #[test]
+    fn ports_should_add_their_own_metrics() {
+        let mut state = build_state(&[]);
+        process_broker_event(
+            BrokerEvent::MetricsUpdate(MetricsUpdate {
+                sender_id: "port".to_string(),
+                updates: vec![MetricUpdate::Set {
+                    name: "connections".to_string(),
+                    labels: MetricLabels::new(),
+                    value: 2.0,
+                }],
+            }),
+            &mut state,
+        )
+        .unwrap();
+
+        let metrics = request_metrics(&mut state);
+        assert_eq!(
+            metric_value(&metrics, "connections", "port"),
+            Some(MetricValue::Gauge(2.0))
+        );
+    }
+
+    #[test]
+    fn kernel_should_answer_health_checks_for_itself() {
+        let health_check = RecordingSender::default();
+        let mut state = build_state(&[("health_check", &health_check)]);
+        process_broker_event(build_health_check_request(KERNEL_ID), &mut state).unwrap();
+        assert_eq!(health_check.events(), vec!["HealthCheckResponse"]);
+    }
+
+    #[test]
+    fn kernel_should_list_the_state_of_all_components() {
+        let output = RecordingSender::default();
+        let scheduler = RecordingSender::default();
+        let mut state = build_supervised_state(&[("output", &output)], &scheduler);
+        state
+            .functions
+            .insert("router".to_string(), &(dummy_component as InternalServerFn));
+        process_broker_event(
+            BrokerEvent::ConfigUpdated(Config::Null, "output".to_string()),
+            &mut state,
+        )
+        .unwrap();
+
+        let (sender, receiver) = std::sync::mpsc::channel();
+        state
+            .outboxes
+            .insert("health_check".to_string(), Box::new(ChannelSender(sender)));
+        process_broker_event(
+            BrokerEvent::ComponentsRequest(ComponentsRequest {
+                id: "1".to_string(),
+                sender_id: "health_check".to_string(),
+            }),
+            &mut state,
+        )
+        .unwrap();
+        let components = match receiver.try_recv() {
+            Ok(BrokerEvent::ComponentsResponse(response)) => response.components,
+            _ => panic!("no ComponentsResponse received"),
+        };
+        assert_eq!(
+            components,
+            vec![
+                ComponentInfo {
+                    id: "output".to_string(),
+                    configured: true,
+                    initialized: true,
+                },
+                ComponentInfo {
+                    id: "router".to_string(),
+                    configured: false,
+                    initialized: false,
+                },
+            ]
+        );
+    }
+
+    fn build_incoming(incoming_id: &str) -> IncomingCloudEvent {
+        IncomingCloudEvent {
+            incoming_id: incoming_id.to_string(),
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            args: at_least_once(),
+        }
+    }
+
+    #[test]
+    fn incoming_cloud_event_should_pass_all_processors_before_the_router() {
+        let input = RecordingSender::default();
+        let filter = RecordingSender::default();
+        let transform = RecordingSender::default();
+        let router = RecordingSender::default();
+        let mut state = build_state(&[
+            ("input", &input),
+            ("filter", &filter),
+            ("transform", &transform),
+            (ROUTER_ID, &router),
+        ]);
+        state.pipeline = vec!["filter".to_string(), "transform".to_string()];
+
+        process_broker_event(
+            BrokerEvent::IncomingCloudEvent(build_incoming("input")),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            filter.events(),
+            vec!["IncomingCloudEvent receiver_id=input"]
+        );
+
+        process_broker_event(
+            build_incoming("input").pass_on("filter".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            transform.events(),
+            vec!["IncomingCloudEvent receiver_id=input"]
+        );
+        assert!(router.events().is_empty());
+
+        process_broker_event(
+            build_incoming("input").pass_on("transform".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            router.events(),
+            vec!["IncomingCloudEvent receiver_id=input"]
+        );
+        assert!(input.events().is_empty());
+    }
+
+    #[test]
+    fn dropped_or_failed_cloud_event_should_be_answered() {
+        let input = RecordingSender::default();
+        let filter = RecordingSender::default();
+        let router = RecordingSender::default();
+        let mut state =
+            build_state(&[("input", &input), ("filter", &filter), (ROUTER_ID, &router)]);
+        state.pipeline = vec!["filter".to_string()];
+
+        process_broker_event(
+            build_incoming("input").drop_event("filter".to_string()),
+            &mut state,
+        )
+        .unwrap();
+        process_broker_event(
+            build_incoming("input").fail("filter".to_string(), ProcessingResult::PermanentError),
+            &mut state,
+        )
+        .unwrap();
+        assert_eq!(
+            input.events(),
+            vec![
+                "IncomingCloudEventProcessed state=Successful",
+                "IncomingCloudEventProcessed state=PermanentError"
+            ]
+        );
+        assert!(router.events().is_empty());
+
+        // only processors can pass events on
+        let result = process_broker_event(
+            build_incoming("input").pass_on("input".to_string()),
+            &mut state,
+        );
+        assert!(result.is_err());
+        assert!(router.events().is_empty());
+    }
 }
@@ -0,0 +1,319 @@
+use crate::kernel::ProcessingResult;
+use anyhow::Result;
+use std::collections::BTreeMap;
+
+/// number of CloudEvents a component sent to the Kernel; label: `component`
+pub const METRIC_INCOMING_EVENTS: &str = "cerk_incoming_events_total";
+/// number of CloudEvents of a component the router routed successfully; label: `component`
+pub const METRIC_ROUTED_EVENTS: &str = "cerk_routed_events_total";
+/// number of CloudEvents the Kernel sent to a port, including retries and dead letters; label: `component`
+pub const METRIC_OUTGOING_EVENTS: &str = "cerk_outgoing_events_total";
+/// number of acknowledgments of a port; labels: `component`, `result`
+pub const METRIC_ACKS: &str = "cerk_acks_total";
+/// number of copies with `DeliveryGuarantee::ExactlyOnce` that were not sent because the port already received them; label: `component`
+pub const METRIC_DUPLICATES: &str = "cerk_duplicates_total";
+/// number of copies that a port did not acknowledge within the delivery ttl; label: `component`
+pub const METRIC_TIMEOUTS: &str = "cerk_timeouts_total";
+/// number of deliveries that wait for acknowledgments, by sender; label: `component`
+pub const METRIC_PENDING_DELIVERIES: &str = "cerk_pending_deliveries";
+/// number of copies that were sent to a port but not acknowledged yet; label: `component`
+pub const METRIC_UNACKNOWLEDGED_EVENTS: &str = "cerk_unacknowledged_events";
+/// time between an `IncomingCloudEvent` and its `RoutingResult` in seconds; label: `component`
+pub const METRIC_ROUTING_LATENCY: &str = "cerk_routing_latency_seconds";
+
+/// label with the id of the component a metric belongs to, the Kernel sets it for all metrics
+pub const METRIC_COMPONENT_LABEL: &str = "component";
+/// label with the `ProcessingResult` of `METRIC_ACKS`
+pub const METRIC_RESULT_LABEL: &str = "result";
+
+/// upper bounds (in seconds) of the histogram buckets
+pub const DEFAULT_HISTOGRAM_BUCKETS: [f64; 10] =
+    [0.0005, 0.001, 0.005, 0.01, 0.025, 0.05, 0.1, 0.5, 1.0, 5.0];
+
+/// labels of a metric, key: label name
+pub type MetricLabels = BTreeMap<String, String>;
+
+/// A single metric of a `MetricsResponse`
+#[derive(Clone, Debug, PartialEq)]
+pub struct Metric {
+    /// name of the metric, e.g. `METRIC_INCOMING_EVENTS`
+    pub name: String,
+    /// labels that identify the metric, always contains `METRIC_COMPONENT_LABEL`
+    pub labels: MetricLabels,
+    /// current value
+    pub value: MetricValue,
+}
+
+/// value of a metric
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricValue {
+    /// a value that only grows
+    Counter(u64),
+    /// a value that could go up and down
+    Gauge(f64),
+    /// distribution of observed values
+    Histogram(Histogram),
+}
+
+/// Distribution of observed values in buckets with fixed upper bounds.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Histogram {
+    /// ascending upper bounds of the buckets
+    pub bounds: Vec<f64>,
+    /// number of observations per bucket (not cumulative), the last entry counts the observations above the highest bound
+    pub counts: Vec<u64>,
+    /// sum of all observations
+    pub sum: f64,
+    /// number of all observations
+    pub count: u64,
+}
+
+/// A change of a metric, sent by a component with `BrokerEvent::MetricsUpdate`
+#[derive(Clone, Debug, PartialEq)]
+pub enum MetricUpdate {
+    /// increments a counter by the value
+    Increment {
+        /// name of the counter
+        name: String,
+        /// labels of the counter, `METRIC_COMPONENT_LABEL` is set by the Kernel
+        labels: MetricLabels,
+        /// the increment
+        value: u64,
+    },
+    /// sets a gauge to the value
+    Set {
+        /// name of the gauge
+        name: String,
+        /// labels of the gauge, `METRIC_COMPONENT_LABEL` is set by the Kernel
+        labels: MetricLabels,
+        /// the new value
+        value: f64,
+    },
+    /// adds an observation to a histogram with the `DEFAULT_HISTOGRAM_BUCKETS`
+    Observe {
+        /// name of the histogram
+        name: String,
+        /// labels of the histogram, `METRIC_COMPONENT_LABEL` is set by the Kernel
+        labels: MetricLabels,
+        /// the observed value
+        value: f64,
+    },
+}
+
+impl Histogram {
+    /// creates an empty histogram with the given ascending upper bounds
+    pub fn new(bounds: &[f64]) -> Self {
+        Histogram {
+            bounds: bounds.to_vec(),
+            counts: vec![0; bounds.len() + 1],
+            sum: 0.0,
+            count: 0,
+        }
+    }
+
+    /// adds the value to the first bucket whose upper bound is not lower than it
+    pub fn observe(&mut self, value: f64) {
+        let bucket = self
+            .bounds
+            .iter()
+            .position(|bound| value <= *bound)
+            .unwrap_or(self.bounds.len());
+        self.counts[bucket] += 1;
+        self.sum += value;
+        self.count += 1;
+    }
+}
+
+/// The metrics of all components, kept by the Kernel.
+#[derive(Clone, Debug, Default)]
+pub(crate) struct Metrics {
+    values: BTreeMap<(String, MetricLabels), MetricValue>,
+}
+
+fn component_labels(component_id: &str, labels: &[(&str, &str)]) -> MetricLabels {
+    labels
+        .iter()
+        .map(|(k, v)| (k.to_string(), v.to_string()))
+        .chain(std::iter::once((
+            METRIC_COMPONENT_LABEL.to_string(),
+            component_id.to_string(),
+        )))
+        .collect()
+}
+
+impl Metrics {
+    /// increments the counter of the component by one
+    pub fn increment(&mut self, name: &str, component_id: &str) {
+        self.increment_labeled(name, component_id, &[]);
+    }
+
+    /// increments the counter of the component with the additional labels by one
+    pub fn increment_labeled(&mut self, name: &str, component_id: &str, labels: &[(&str, &str)]) {
+        let key = (name.to_string(), component_labels(component_id, labels));
+        if let Err(e) = self.add(key, 1) {
+            error!("{}", e);
+        }
+    }
+
+    /// counts the acknowledgment of a port by its result
+    pub fn count_ack(&mut self, component_id: &str, result: &ProcessingResult) {
+        let result = result.to_string();
+        self.increment_labeled(METRIC_ACKS, component_id, &[(METRIC_RESULT_LABEL, &result)]);
+    }
+
+    /// adds an observation to the histogram of the component
+    pub fn observe(&mut self, name: &str, component_id: &str, value: f64) {
+        let key = (name.to_string(), component_labels(component_id, &[]));
+        if let Err(e) = self.add_observation(key, value) {
+            error!("{}", e);
+        }
+    }
+
+    /// sets the gauge of the component
+    pub fn set(&mut self, name: &str, component_id: &str, value: f64) {
+        let key = (name.to_string(), component_labels(component_id, &[]));
+        if let Err(e) = self.set_gauge(key, value) {
+            error!("{}", e);
+        }
+    }
+
+    /// Applies an update sent by a component.
+    /// Fails if a metric with the same name and labels exists with another type.
+    pub fn apply(&mut self, component_id: &str, update: MetricUpdate) -> Result<()> {
+        let with_component = |mut labels: MetricLabels| {
+            labels.insert(METRIC_COMPONENT_LABEL.to_string(), component_id.to_string());
+            labels
+        };
+        match update {
+            MetricUpdate::Increment {
+                name,
+                labels,
+                value,
+            } => self.add((name, with_component(labels)), value),
+            MetricUpdate::Set {
+                name,
+                labels,
+                value,
+            } => self.set_gauge((name, with_component(labels)), value),
+            MetricUpdate::Observe {
+                name,
+                labels,
+                value,
+            } => self.add_observation((name, with_component(labels)), value),
+        }
+    }
+
+    /// all metrics, sorted by name and labels
+    pub fn snapshot(&self) -> Vec<Metric> {
+        self.values
+            .iter()
+            .map(|((name, labels), value)| Metric {
+                name: name.clone(),
+                labels: labels.clone(),
+                value: value.clone(),
+            })
+            .collect()
+    }
+
+    fn add(&mut self, key: (String, MetricLabels), value: u64) -> Result<()> {
+        match self
+            .values
+            .entry(key.clone())
+            .or_insert(MetricValue::Counter(0))
+        {
+            MetricValue::Counter(counter) => {
+                *counter += value;
+                Ok(())
+            }
+            _ => bail!("metric {} {:?} is not a counter", key.0, key.1),
+        }
+    }
+
+    fn set_gauge(&mut self, key: (String, MetricLabels), value: f64) -> Result<()> {
+        match self
+            .values
+            .entry(key.clone())
+            .or_insert(MetricValue::Gauge(0.0))
+        {
+            MetricValue::Gauge(gauge) => {
+                *gauge = value;
+                Ok(())
+            }
+            _ => bail!("metric {} {:?} is not a gauge", key.0, key.1),
+        }
+    }
+
+    fn add_observation(&mut self, key: (String, MetricLabels), value: f64) -> Result<()> {
+        match self
+            .values
+            .entry(key.clone())
+            .or_insert_with(|| MetricValue::Histogram(Histogram::new(&DEFAULT_HISTOGRAM_BUCKETS)))
+        {
+            MetricValue::Histogram(histogram) => {
+                histogram.observe(value);
+                Ok(())
+            }
+            _ => bail!("metric {} {:?} is not a histogram", key.0, key.1),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn histogram_should_count_per_bucket() {
+        let mut histogram = Histogram::new(&[1.0, 2.0]);
+        histogram.observe(0.5);
+        histogram.observe(1.0);
+        histogram.observe(1.5);
+        histogram.observe(10.0);
+        assert_eq!(histogram.counts, vec![2, 1, 1]);
+        assert_eq!(histogram.count, 4);
+        assert_eq!(histogram.sum, 13.0);
+    }
+
+    #[test]
+    fn counters_should_be_kept_per_component_and_labels() {
+        let mut metrics = Metrics::default();
+        metrics.increment(METRIC_INCOMING_EVENTS, "a");
+        metrics.increment(METRIC_INCOMING_EVENTS, "a");
+        metrics.increment(METRIC_INCOMING_EVENTS, "b");
+        metrics.count_ack("a", &ProcessingResult::Successful);
+        metrics.count_ack("a", &ProcessingResult::Timeout);
+
+        let snapshot = metrics.snapshot();
+        assert_eq!(snapshot.len(), 4);
+        let incoming_a = snapshot
+            .iter()
+            .find(|metric| {
+                metric.name == METRIC_INCOMING_EVENTS
+                    && metric.labels[METRIC_COMPONENT_LABEL] == "a"
+            })
+            .unwrap();
+        assert_eq!(incoming_a.value, MetricValue::Counter(2));
+    }
+
+    #[test]
+    fn update_should_not_change_the_type_of_a_metric() {
+        let mut metrics = Metrics::default();
+        let increment = MetricUpdate::Increment {
+            name: "connections".to_string(),
+            labels: MetricLabels::new(),
+            value: 3,
+        };
+        let set = MetricUpdate::Set {
+            name: "connections".to_string(),
+            labels: MetricLabels::new(),
+            value: 1.0,
+        };
+        assert!(metrics.apply("port", increment).is_ok());
+        assert!(metrics.apply("port", set).is_err());
+        assert_eq!(
+            metrics.snapshot()[0].value,
+            MetricValue::Counter(3),
+            "the counter should be unchanged"
+        );
+    }
+}
@@ -1,22 +1,60 @@
 //! Implementation of the core components of CERK
 
+mod ack_policy;
+mod append_log;
+mod batches;
 mod bootstrap;
 mod broker_event;
+mod circuit_breakers;
 mod cloud_event_routing_args;
 mod config;
+mod dead_letter;
 mod delivery_guarantees;
+mod idempotency_store;
+mod journal;
+mod kernel_options;
 mod kernel_start;
+mod metrics;
 mod outgoing_processing_result;
+mod pending_deliveries;
+mod routing_id_generator;
+mod spool;
 mod start_options;
+mod system_events;
 
+pub use self::ack_policy::AckPolicy;
 pub use self::bootstrap::{bootstrap, KernelFn};
 pub use self::broker_event::{
-    BrokerEvent, CloudEventMessageRoutingId, HealthCheckRequest, HealthCheckResponse,
-    HealthCheckStatus, IncomingCloudEvent, OutgoingCloudEvent, OutgoingCloudEventProcessed,
-    RoutingResult, ScheduleInternalServer, ScheduleInternalServerStatic,
+    BrokerEvent, CloudEventDeliveryId, CloudEventMessageRoutingId, ComponentInfo,
+    ComponentsRequest, ComponentsResponse, HealthCheckRequest, HealthCheckResponse,
+    HealthCheckStatus, IncomingCloudEvent, MetricsRequest, MetricsResponse, MetricsUpdate,
+    OutgoingCloudEvent, OutgoingCloudEventProcessed, ProcessorResult, RoutingResult,
+    ScheduleInternalServer, ScheduleInternalServerStatic, SharedCloudEvent,
 };
 pub use self::cloud_event_routing_args::CloudEventRoutingArgs;
 pub use self::config::{Config, ConfigHelpers};
+pub use self::dead_letter::{
+    DEAD_LETTER_ATTEMPTS_EXTENSION, DEAD_LETTER_DESTINATION_EXTENSION,
+    DEAD_LETTER_REASON_EXTENSION, DEAD_LETTER_ROUTING_ID_EXTENSION,
+};
 pub use self::delivery_guarantees::DeliveryGuarantee;
+pub use self::kernel_options::{
+    BatchOptions, CircuitBreakerOptions, FlowControlOptions, IdempotencyOptions, JournalOptions,
+    KernelOptions, PortOptions, RetryPolicy, SpoolOptions, SpoolOverflow, Watermarks,
+    DEFAULT_DELIVERY_TTL_MS,
+};
+pub use self::kernel_start::{CONFIG_LOADER_ID, KERNEL_ID, ROUTER_ID, STARTUP_FAILED_EXIT_CODE};
+pub use self::metrics::{
+    Histogram, Metric, MetricLabels, MetricUpdate, MetricValue, DEFAULT_HISTOGRAM_BUCKETS,
+    METRIC_ACKS, METRIC_COMPONENT_LABEL, METRIC_DUPLICATES, METRIC_INCOMING_EVENTS,
+    METRIC_OUTGOING_EVENTS, METRIC_PENDING_DELIVERIES, METRIC_RESULT_LABEL, METRIC_ROUTED_EVENTS,
+    METRIC_ROUTING_LATENCY, METRIC_TIMEOUTS, METRIC_UNACKNOWLEDGED_EVENTS,
+};
 pub use self::outgoing_processing_result::ProcessingResult;
+pub use self::routing_id_generator::RoutingIdGenerator;
 pub use self::start_options::StartOptions;
+pub use self::system_events::{
+    SYSTEM_EVENTS_ID, SYSTEM_EVENT_COMPONENT_SCHEDULED, SYSTEM_EVENT_COMPONENT_UNHEALTHY,
+    SYSTEM_EVENT_CONFIG_APPLIED, SYSTEM_EVENT_CONFIG_REJECTED, SYSTEM_EVENT_DEAD_LETTERED,
+    SYSTEM_EVENT_DELIVERY_TIMEOUT, SYSTEM_EVENT_SOURCE,
+};
@@ -0,0 +1,506 @@
+use super::idempotency_store::IdempotencyKey;
+use crate::kernel::{
+    AckPolicy, CloudEventDeliveryId, CloudEventMessageRoutingId, OutgoingCloudEvent,
+};
+use crate::runtime::InternalServerId;
+use std::cmp::Reverse;
+use std::collections::{BinaryHeap, HashMap};
+use std::time::SystemTime;
+
+/// the deadlines are rebuilt once they contain this many outdated entries
+const MIN_OUTDATED_DEADLINES: usize = 1_000;
+
+/// The routing ids are only unique per input port, so the pending deliveries are scoped by it, key: (incoming_id, routing_id)
+pub(crate) type PendingKey = (InternalServerId, CloudEventMessageRoutingId);
+
+/// the copies of a CloudEvent that wait for their acknowledgments
+pub(crate) struct PendingDelivery {
+    pub sender: InternalServerId,
+    /// the copies that were not acknowledged yet, only changed by `PendingDeliveries` to keep its indexes
+    missing_deliveries: Vec<MissingDelivery>,
+    ttl: SystemTime,
+    /// how many copies have to be acknowledged, see `CloudEventRoutingArgs.ack_policy`
+    pub ack_policy: AckPolicy,
+    /// number of copies that were acknowledged
+    pub acknowledged: usize,
+    /// number of copies that failed
+    pub failed: usize,
+}
+
+impl PendingDelivery {
+    pub fn new(
+        sender: InternalServerId,
+        missing_deliveries: Vec<MissingDelivery>,
+        ttl: SystemTime,
+        ack_policy: AckPolicy,
+    ) -> Self {
+        PendingDelivery {
+            sender,
+            missing_deliveries,
+            ttl,
+            ack_policy,
+            acknowledged: 0,
+            failed: 0,
+        }
+    }
+
+    pub fn missing_deliveries(&self) -> &[MissingDelivery] {
+        &self.missing_deliveries
+    }
+
+    /// the copies that were not acknowledged yet, once the delivery was removed
+    pub fn into_missing_deliveries(self) -> Vec<MissingDelivery> {
+        self.missing_deliveries
+    }
+
+    pub fn ttl(&self) -> SystemTime {
+        self.ttl
+    }
+
+    fn required_acknowledgments(&self) -> usize {
+        let copies = self.acknowledged + self.failed + self.missing_deliveries.len();
+        self.ack_policy.required(copies)
+    }
+
+    /// enough copies were acknowledged to satisfy the ack policy
+    pub fn is_acknowledged(&self) -> bool {
+        self.acknowledged >= self.required_acknowledgments()
+    }
+
+    /// the copies that were not acknowledged yet can still satisfy the ack policy
+    pub fn can_be_acknowledged(&self) -> bool {
+        self.acknowledged + self.missing_deliveries.len() >= self.required_acknowledgments()
+    }
+}
+
+/// a copy of a CloudEvent that was not acknowledged yet
+pub(crate) struct MissingDelivery {
+    pub destination_id: InternalServerId,
+    pub delivery_id: CloudEventDeliveryId,
+    /// number of times the copy was sent to the destination
+    pub attempts: u32,
+    /// the copy itself, only kept if the retry policy of the destination allows retries
+    pub event: Option<OutgoingCloudEvent>,
+    /// time of the next attempt, if a retry is scheduled
+    pub retry_at: Option<SystemTime>,
+    /// remembered in the idempotency store once the destination acknowledged the copy
+    pub idempotency_key: Option<IdempotencyKey>,
+}
+
+impl MissingDelivery {
+    pub fn is(&self, destination_id: &str, delivery_id: CloudEventDeliveryId) -> bool {
+        self.destination_id == destination_id && self.delivery_id == delivery_id
+    }
+}
+
+fn increment(counts: &mut HashMap<InternalServerId, usize>, id: &str) {
+    *counts.entry(id.to_string()).or_default() += 1;
+}
+
+fn decrement(counts: &mut HashMap<InternalServerId, usize>, id: &str) {
+    if let Some(count) = counts.get_mut(id) {
+        *count -= 1;
+        if *count == 0 {
+            counts.remove(id);
+        }
+    }
+}
+
+/// The deliveries that wait for acknowledgments, kept by the Kernel.
+///
+/// Every copy gets a delivery id that is unique among all pending copies, so an acknowledgment is found by it.
+/// The deadlines of the deliveries and their retries are kept in the order they are due,
+/// entries that are outdated because a delivery was completed or extended are skipped when they are due.
+#[derive(Default)]
+pub(crate) struct PendingDeliveries {
+    deliveries: HashMap<PendingKey, PendingDelivery>,
+    /// the delivery of every missing copy by its delivery id
+    copies: HashMap<CloudEventDeliveryId, PendingKey>,
+    /// the ttls of the deliveries, the earliest first
+    expirations: BinaryHeap<Reverse<(SystemTime, PendingKey)>>,
+    /// the scheduled retries of the copies, the earliest first
+    retries: BinaryHeap<Reverse<(SystemTime, CloudEventDeliveryId)>>,
+    /// number of pending deliveries by sender
+    pending: HashMap<InternalServerId, usize>,
+    /// number of missing copies by destination
+    unacknowledged: HashMap<InternalServerId, usize>,
+    next_delivery_id: CloudEventDeliveryId,
+}
+
+impl PendingDeliveries {
+    /// the delivery ids start with `first_delivery_id`
+    pub fn new(first_delivery_id: CloudEventDeliveryId) -> Self {
+        PendingDeliveries {
+            next_delivery_id: first_delivery_id,
+            ..PendingDeliveries::default()
+        }
+    }
+
+    /// a delivery id that is not used by any pending copy
+    pub fn next_delivery_id(&mut self) -> CloudEventDeliveryId {
+        while self.copies.contains_key(&self.next_delivery_id) {
+            self.next_delivery_id = self.next_delivery_id.wrapping_add(1);
+        }
+        let delivery_id = self.next_delivery_id;
+        self.next_delivery_id = self.next_delivery_id.wrapping_add(1);
+        delivery_id
+    }
+
+    pub fn len(&self) -> usize {
+        self.deliveries.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.deliveries.is_empty()
+    }
+
+    pub fn get(&self, key: &PendingKey) -> Option<&PendingDelivery> {
+        self.deliveries.get(key)
+    }
+
+    pub fn get_mut(&mut self, key: &PendingKey) -> Option<&mut PendingDelivery> {
+        self.deliveries.get_mut(key)
+    }
+
+    /// Adds the delivery, an existing one with the same key is replaced and returned.
+    pub fn insert(
+        &mut self,
+        key: PendingKey,
+        delivery: PendingDelivery,
+    ) -> Option<PendingDelivery> {
+        let replaced = self.remove(&key);
+        increment(&mut self.pending, &delivery.sender);
+        for missing in delivery.missing_deliveries.iter() {
+            self.copies.insert(missing.delivery_id, key.clone());
+            increment(&mut self.unacknowledged, &missing.destination_id);
+            if let Some(retry_at) = missing.retry_at {
+                self.retries.push(Reverse((retry_at, missing.delivery_id)));
+            }
+        }
+        self.expirations.push(Reverse((delivery.ttl, key.clone())));
+        self.deliveries.insert(key, delivery);
+        self.rebuild_outdated_deadlines();
+        replaced
+    }
+
+    pub fn remove(&mut self, key: &PendingKey) -> Option<PendingDelivery> {
+        let delivery = self.deliveries.remove(key)?;
+        decrement(&mut self.pending, &delivery.sender);
+        for missing in delivery.missing_deliveries.iter() {
+            self.copies.remove(&missing.delivery_id);
+            decrement(&mut self.unacknowledged, &missing.destination_id);
+        }
+        Some(delivery)
+    }
+
+    /// The key of the pending delivery the copy belongs to, `None` if the copy is not missing.
+    pub fn find(
+        &self,
+        routing_id: &str,
+        destination_id: &str,
+        delivery_id: CloudEventDeliveryId,
+    ) -> Option<PendingKey> {
+        let key = self.copies.get(&delivery_id)?;
+        let delivery = self.deliveries.get(key)?;
+        if key.1 == routing_id
+            && delivery
+                .missing_deliveries
+                .iter()
+                .any(|missing| missing.is(destination_id, delivery_id))
+        {
+            Some(key.clone())
+        } else {
+            None
+        }
+    }
+
+    /// Removes the missing copy from the delivery, e.g. because it was acknowledged.
+    pub fn remove_copy(
+        &mut self,
+        key: &PendingKey,
+        delivery_id: CloudEventDeliveryId,
+    ) -> Option<MissingDelivery> {
+        let delivery = self.deliveries.get_mut(key)?;
+        let index = delivery
+            .missing_deliveries
+            .iter()
+            .position(|missing| missing.delivery_id == delivery_id)?;
+        let missing = delivery.missing_deliveries.remove(index);
+        self.copies.remove(&delivery_id);
+        decrement(&mut self.unacknowledged, &missing.destination_id);
+        Some(missing)
+    }
+
+    /// Removes the missing copies of the destination from the delivery, returns their number.
+    pub fn remove_copies_to(&mut self, key: &PendingKey, destination_id: &str) -> usize {
+        let delivery_ids: Vec<_> = match self.deliveries.get(key) {
+            Some(delivery) => delivery
+                .missing_deliveries
+                .iter()
+                .filter(|missing| missing.destination_id == destination_id)
+                .map(|missing| missing.delivery_id)
+                .collect(),
+            None => return 0,
+        };
+        for delivery_id in delivery_ids.iter() {
+            self.remove_copy(key, *delivery_id);
+        }
+        delivery_ids.len()
+    }
+
+    /// Schedules a retry of the copy and extends the ttl of the delivery until at least `ttl`.
+    pub fn schedule_retry(
+        &mut self,
+        key: &PendingKey,
+        delivery_id: CloudEventDeliveryId,
+        retry_at: SystemTime,
+        ttl: SystemTime,
+    ) {
+        let delivery = match self.deliveries.get_mut(key) {
+            Some(delivery) => delivery,
+            None => return,
+        };
+        if let Some(missing) = delivery
+            .missing_deliveries
+            .iter_mut()
+            .find(|missing| missing.delivery_id == delivery_id)
+        {
+            missing.retry_at = Some(retry_at);
+            self.retries.push(Reverse((retry_at, delivery_id)));
+        }
+        if ttl > delivery.ttl {
+            delivery.ttl = ttl;
+            self.expirations.push(Reverse((ttl, key.clone())));
+        }
+        self.rebuild_outdated_deadlines();
+    }
+
+    /// The deliveries whose ttl passed.
+    pub fn expired(&mut self, now: SystemTime) -> Vec<PendingKey> {
+        let mut expired = Vec::new();
+        while let Some(Reverse((ttl, _))) = self.expirations.peek() {
+            if *ttl >= now {
+                break;
+            }
+            if let Some(Reverse((ttl, key))) = self.expirations.pop() {
+                if self
+                    .deliveries
+                    .get(&key)
+                    .is_some_and(|delivery| delivery.ttl == ttl)
+                {
+                    expired.push(key);
+                }
+            }
+        }
+        expired
+    }
+
+    /// The copies whose retry is due, with the key of their delivery.
+    pub fn due_retries(&mut self, now: SystemTime) -> Vec<(PendingKey, CloudEventDeliveryId)> {
+        let mut due = Vec::new();
+        while let Some(Reverse((retry_at, _))) = self.retries.peek() {
+            if *retry_at > now {
+                break;
+            }
+            if let Some(Reverse((retry_at, delivery_id))) = self.retries.pop() {
+                if let Some(key) = self.scheduled_retry(delivery_id, retry_at) {
+                    due.push((key, delivery_id));
+                }
+            }
+        }
+        due
+    }
+
+    /// the key of the delivery, if the retry of the copy is still scheduled at the given time
+    fn scheduled_retry(
+        &self,
+        delivery_id: CloudEventDeliveryId,
+        retry_at: SystemTime,
+    ) -> Option<PendingKey> {
+        let key = self.copies.get(&delivery_id)?;
+        self.deliveries
+            .get(key)?
+            .missing_deliveries
+            .iter()
+            .any(|missing| missing.delivery_id == delivery_id && missing.retry_at == Some(retry_at))
+            .then(|| key.clone())
+    }
+
+    /// Starts the next attempt of the copy and returns it.
+    pub fn start_retry(
+        &mut self,
+        key: &PendingKey,
+        delivery_id: CloudEventDeliveryId,
+    ) -> Option<&MissingDelivery> {
+        let missing = self
+            .deliveries
+            .get_mut(key)?
+            .missing_deliveries
+            .iter_mut()
+            .find(|missing| missing.delivery_id == delivery_id)?;
+        missing.retry_at = None;
+        missing.attempts += 1;
+        Some(missing)
+    }
+
+    /// the earliest time a delivery expires or a retry is due, it could be outdated
+    pub fn next_deadline(&self) -> Option<SystemTime> {
+        let expiration = self.expirations.peek().map(|Reverse((ttl, _))| *ttl);
+        let retry = self.retries.peek().map(|Reverse((retry_at, _))| *retry_at);
+        expiration.into_iter().chain(retry).min()
+    }
+
+    /// number of pending deliveries of the sender
+    pub fn pending_of(&self, sender: &str) -> usize {
+        self.pending.get(sender).copied().unwrap_or(0)
+    }
+
+    /// the senders with pending deliveries and their number
+    pub fn pending_counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.pending.iter().map(|(id, count)| (id.as_str(), *count))
+    }
+
+    /// the destinations with missing copies and their number
+    pub fn unacknowledged_counts(&self) -> impl Iterator<Item = (&str, usize)> {
+        self.unacknowledged
+            .iter()
+            .map(|(id, count)| (id.as_str(), *count))
+    }
+
+    /// The deliveries that were sent by the component or wait for one of its copies.
+    /// It scans all deliveries, so it is only used when a component exits.
+    pub fn keys_involving(&self, id: &str) -> Vec<PendingKey> {
+        self.deliveries
+            .iter()
+            .filter(|(_, delivery)| {
+                delivery.sender == id
+                    || delivery
+                        .missing_deliveries
+                        .iter()
+                        .any(|missing| missing.destination_id == id)
+            })
+            .map(|(key, _)| key.clone())
+            .collect()
+    }
+
+    /// Rebuilds the deadlines if most of them are outdated, e.g. because the deliveries were acknowledged long before their ttl.
+    fn rebuild_outdated_deadlines(&mut self) {
+        if self.expirations.len() + self.retries.len()
+            < MIN_OUTDATED_DEADLINES + 2 * (self.deliveries.len() + self.copies.len())
+        {
+            return;
+        }
+        self.expirations = self
+            .deliveries
+            .iter()
+            .map(|(key, delivery)| Reverse((delivery.ttl, key.clone())))
+            .collect();
+        self.retries = self
+            .deliveries
+            .values()
+            .flat_map(|delivery| delivery.missing_deliveries.iter())
+            .filter_map(|missing| {
+                missing
+                    .retry_at
+                    .map(|retry_at| Reverse((retry_at, missing.delivery_id)))
+            })
+            .collect();
+    }
+}
+
+impl FromIterator<(PendingKey, PendingDelivery)> for PendingDeliveries {
+    fn from_iter<T: IntoIterator<Item = (PendingKey, PendingDelivery)>>(iter: T) -> Self {
+        let mut pending_deliveries = PendingDeliveries::default();
+        for (key, delivery) in iter {
+            pending_deliveries.insert(key, delivery);
+        }
+        pending_deliveries
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::ops::{Add, Sub};
+    use std::time::Duration;
+
+    fn key(routing_id: &str) -> PendingKey {
+        ("input".to_string(), routing_id.to_string())
+    }
+
+    fn delivery(
+        pending_deliveries: &mut PendingDeliveries,
+        destinations: &[&str],
+        ttl: SystemTime,
+    ) -> PendingDelivery {
+        let missing_deliveries = destinations
+            .iter()
+            .map(|destination_id| MissingDelivery {
+                destination_id: destination_id.to_string(),
+                delivery_id: pending_deliveries.next_delivery_id(),
+                attempts: 1,
+                event: None,
+                retry_at: None,
+                idempotency_key: None,
+            })
+            .collect();
+        PendingDelivery::new("input".to_string(), missing_deliveries, ttl, AckPolicy::All)
+    }
+
+    #[test]
+    fn counts_should_follow_the_copies() {
+        let mut pending_deliveries = PendingDeliveries::default();
+        let ttl = SystemTime::now().add(Duration::from_secs(60));
+        let first = delivery(&mut pending_deliveries, &["a", "b"], ttl);
+        pending_deliveries.insert(key("1"), first);
+        let second = delivery(&mut pending_deliveries, &["a"], ttl);
+        pending_deliveries.insert(key("2"), second);
+        assert_eq!(pending_deliveries.pending_of("input"), 2);
+        let mut unacknowledged: Vec<_> = pending_deliveries.unacknowledged_counts().collect();
+        unacknowledged.sort_unstable();
+        assert_eq!(unacknowledged, vec![("a", 2), ("b", 1)]);
+
+        assert_eq!(pending_deliveries.find("1", "b", 1), Some(key("1")));
+        assert_eq!(pending_deliveries.find("2", "b", 1), None);
+        assert_eq!(pending_deliveries.find("1", "a", 1), None);
+        assert!(pending_deliveries.remove_copy(&key("1"), 1).is_some());
+        assert_eq!(pending_deliveries.find("1", "b", 1), None);
+        assert_eq!(pending_deliveries.remove_copies_to(&key("1"), "a"), 1);
+        assert_eq!(pending_deliveries.unacknowledged_counts().count(), 1);
+
+        pending_deliveries.remove(&key("1"));
+        pending_deliveries.remove(&key("2"));
+        assert_eq!(pending_deliveries.pending_of("input"), 0);
+        assert_eq!(pending_deliveries.unacknowledged_counts().count(), 0);
+    }
+
+    #[test]
+    fn outdated_deadlines_should_be_skipped() {
+        let mut pending_deliveries = PendingDeliveries::default();
+        let now = SystemTime::now();
+        let past = now.sub(Duration::from_millis(1));
+        let expired = delivery(&mut pending_deliveries, &["a"], past);
+        pending_deliveries.insert(key("expired"), expired);
+        let extended = delivery(&mut pending_deliveries, &["a"], past);
+        pending_deliveries.insert(key("extended"), extended);
+        let acknowledged = delivery(&mut pending_deliveries, &["a"], past);
+        pending_deliveries.insert(key("acknowledged"), acknowledged);
+        assert_eq!(pending_deliveries.next_deadline(), Some(past));
+
+        pending_deliveries.schedule_retry(
+            &key("extended"),
+            1,
+            past,
+            now.add(Duration::from_secs(60)),
+        );
+        pending_deliveries.remove(&key("acknowledged"));
+        assert_eq!(pending_deliveries.expired(now), vec![key("expired")]);
+        assert_eq!(
+            pending_deliveries.due_retries(now),
+            vec![(key("extended"), 1)]
+        );
+        assert!(pending_deliveries.due_retries(now).is_empty());
+        let retry = pending_deliveries.start_retry(&key("extended"), 1).unwrap();
+        assert_eq!((retry.attempts, retry.retry_at), (2, None));
+    }
+}
@@ -0,0 +1,48 @@
+use crate::kernel::CloudEventMessageRoutingId;
+use std::sync::atomic::{AtomicU64, Ordering};
+
+/// Generates unique `CloudEventMessageRoutingId`s for a port.
+///
+/// The Kernel scopes the routing ids by the id of the input port, so the ids only have to be unique per port.
+/// Every generator starts with a random prefix, so the ids of a restarted port or router do not collide
+/// with the ones that are still pending or replayed from the journal.
+pub struct RoutingIdGenerator {
+    prefix: String,
+    counter: AtomicU64,
+}
+
+impl RoutingIdGenerator {
+    /// creates a generator with a new random prefix
+    pub fn new() -> Self {
+        RoutingIdGenerator {
+            prefix: format!("{:016x}", fastrand::u64(..)),
+            counter: AtomicU64::new(0),
+        }
+    }
+
+    /// returns a routing id that was not returned by this generator before
+    pub fn next_id(&self) -> CloudEventMessageRoutingId {
+        let number = self.counter.fetch_add(1, Ordering::Relaxed);
+        format!("{}-{}", self.prefix, number)
+    }
+}
+
+impl Default for RoutingIdGenerator {
+    fn default() -> Self {
+        RoutingIdGenerator::new()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn ids_should_be_unique() {
+        let generator = RoutingIdGenerator::new();
+        let other = RoutingIdGenerator::new();
+        let first = generator.next_id();
+        assert_ne!(first, generator.next_id());
+        assert_ne!(first, other.next_id());
+    }
+}
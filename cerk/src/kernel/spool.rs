@@ -0,0 +1,608 @@
+use super::append_log::{to_line, AppendLog};
+use crate::kernel::broker_event::OutgoingCloudEvent;
+use crate::kernel::{
+    CloudEventDeliveryId, CloudEventMessageRoutingId, CloudEventRoutingArgs, Config,
+    DeliveryGuarantee, KernelOptions, ProcessingResult, SpoolOptions, SpoolOverflow,
+};
+use crate::runtime::InternalServerId;
+use anyhow::{Context, Result};
+use std::collections::{HashMap, VecDeque};
+use std::convert::TryFrom;
+use std::fs;
+use std::ops::Add;
+use std::sync::Arc;
+use std::time::{Duration, SystemTime};
+
+/// the spool file is not compacted before it has at least this many lines
+const MIN_COMPACT_LINES: usize = 1_000;
+
+/// a copy in the spool
+struct SpoolEntry {
+    /// the input port of the CloudEvent, e.g. for dead letters
+    incoming_id: InternalServerId,
+    event: OutgoingCloudEvent,
+    /// size of the entry in the spool file
+    bytes: u64,
+    /// number of times the copy was sent
+    attempts: u32,
+}
+
+enum Record {
+    Spooled(Box<SpoolEntry>),
+    /// the copy was drained or dropped
+    Removed(CloudEventMessageRoutingId, CloudEventDeliveryId),
+}
+
+fn to_fields(incoming_id: &str, event: &OutgoingCloudEvent) -> Result<Vec<String>> {
+    Ok(vec![
+        "spooled".to_string(),
+        incoming_id.to_string(),
+        event.routing_id.clone(),
+        event.delivery_id.to_string(),
+        (event.args.delivery_guarantee as u8).to_string(),
+        serde_json::to_string(event.cloud_event.as_ref())?,
+    ])
+}
+
+fn from_fields(fields: &[String], destination_id: &str) -> Result<Record> {
+    match (fields[0].as_str(), fields.len()) {
+        ("spooled", 6) => {
+            let delivery_guarantee = fields[4].parse().context("invalid delivery guarantee")?;
+            Ok(Record::Spooled(Box::new(SpoolEntry {
+                incoming_id: fields[1].clone(),
+                event: OutgoingCloudEvent {
+                    routing_id: fields[2].clone(),
+                    delivery_id: fields[3].parse().context("invalid delivery id")?,
+                    destination_id: destination_id.to_string(),
+                    cloud_event: Arc::new(
+                        serde_json::from_str(&fields[5]).context("invalid CloudEvent")?,
+                    ),
+                    args: CloudEventRoutingArgs {
+                        delivery_guarantee: DeliveryGuarantee::try_from(Config::U8(
+                            delivery_guarantee,
+                        ))?,
+                        ..CloudEventRoutingArgs::default()
+                    },
+                },
+                bytes: to_line(fields).len() as u64,
+                attempts: 0,
+            })))
+        }
+        ("removed", 3) => Ok(Record::Removed(
+            fields[1].clone(),
+            fields[2].parse().context("invalid delivery id")?,
+        )),
+        (kind, count) => bail!("unknown record {} with {} fields", kind, count),
+    }
+}
+
+/// a copy that was sent to the port and waits for its acknowledgment
+struct InFlight {
+    routing_id: CloudEventMessageRoutingId,
+    /// the copy is sent again if it is not acknowledged until then
+    deadline: SystemTime,
+}
+
+/// the spool of a single port
+struct Spool {
+    options: SpoolOptions,
+    /// the log is compacted if it grows too large
+    log: AppendLog,
+    /// the spooled copies, the oldest first
+    entries: VecDeque<SpoolEntry>,
+    /// size of the entries
+    bytes: u64,
+    /// the copies sent to the port by their delivery id
+    in_flight: HashMap<CloudEventDeliveryId, InFlight>,
+    /// a copy did not fit into the spool with `SpoolOverflow::Block`
+    blocked: bool,
+}
+
+impl Spool {
+    fn open(destination_id: &str, options: &SpoolOptions) -> Result<Self> {
+        fs::create_dir_all(&options.directory)
+            .with_context(|| format!("failed to create {}", options.directory.display()))?;
+        let name: String = destination_id
+            .chars()
+            .map(|c| {
+                if c.is_ascii_alphanumeric() || c == '-' || c == '_' {
+                    c
+                } else {
+                    '_'
+                }
+            })
+            .collect();
+        let path = options.directory.join(format!("{}.spool", name));
+        let mut records = Vec::new();
+        let log = AppendLog::open(path.clone(), options.sync, |fields| {
+            records.push(from_fields(fields, destination_id)?);
+            Ok(())
+        })?;
+        let mut spool = Spool {
+            options: options.clone(),
+            log,
+            entries: VecDeque::new(),
+            bytes: 0,
+            in_flight: HashMap::new(),
+            blocked: false,
+        };
+        for record in records {
+            spool.apply(record);
+        }
+        spool.compact()?;
+        info!(
+            "spool {} loaded with {} copies for {}",
+            path.display(),
+            spool.entries.len(),
+            destination_id
+        );
+        Ok(spool)
+    }
+
+    fn apply(&mut self, record: Record) -> Option<SpoolEntry> {
+        match record {
+            Record::Spooled(entry) => {
+                self.bytes += entry.bytes;
+                self.entries.push_back(*entry);
+                None
+            }
+            Record::Removed(routing_id, delivery_id) => {
+                let index = self.entries.iter().position(|entry| {
+                    entry.event.routing_id == routing_id && entry.event.delivery_id == delivery_id
+                })?;
+                let entry = self.entries.remove(index)?;
+                self.bytes -= entry.bytes;
+                if self.is_in_flight(&entry) {
+                    self.in_flight.remove(&delivery_id);
+                }
+                if self.bytes <= self.options.max_bytes / 2 {
+                    self.blocked = false;
+                }
+                Some(entry)
+            }
+        }
+    }
+
+    fn is_in_flight(&self, entry: &SpoolEntry) -> bool {
+        self.in_flight
+            .get(&entry.event.delivery_id)
+            .is_some_and(|in_flight| in_flight.routing_id == entry.event.routing_id)
+    }
+
+    /// index of the oldest copy that is not in flight
+    fn next_unsent(&self) -> Option<usize> {
+        self.entries
+            .iter()
+            .position(|entry| !self.is_in_flight(entry))
+    }
+
+    /// Removes a copy.
+    fn remove(
+        &mut self,
+        routing_id: &str,
+        delivery_id: CloudEventDeliveryId,
+    ) -> Option<SpoolEntry> {
+        let entry = self.apply(Record::Removed(routing_id.to_string(), delivery_id))?;
+        let result = if self.entries.is_empty()
+            || self.log.lines() >= MIN_COMPACT_LINES.max(4 * self.entries.len())
+        {
+            self.compact()
+        } else {
+            self.log.append(&[
+                "removed".to_string(),
+                routing_id.to_string(),
+                delivery_id.to_string(),
+            ])
+        };
+        if let Err(e) = result {
+            error!(
+                "failed to write to the spool {}: {:?}",
+                self.log.path().display(),
+                e
+            );
+        }
+        Some(entry)
+    }
+
+    /// Rewrites the spool file with the spooled copies only.
+    fn compact(&mut self) -> Result<()> {
+        let records = self
+            .entries
+            .iter()
+            .map(|entry| to_fields(&entry.incoming_id, &entry.event))
+            .collect::<Result<Vec<_>>>()?;
+        self.log.compact(records)
+    }
+
+    fn push(&mut self, incoming_id: &str, event: OutgoingCloudEvent) -> Result<bool> {
+        let fields = to_fields(incoming_id, &event)?;
+        let bytes = to_line(&fields).len() as u64;
+        if bytes > self.options.max_bytes {
+            return Ok(false);
+        }
+        while self.bytes + bytes > self.options.max_bytes {
+            match self.options.overflow {
+                // the copies in flight could be acknowledged right away
+                SpoolOverflow::DropOldest => {
+                    let (routing_id, delivery_id) = match self.next_unsent() {
+                        Some(index) => {
+                            let oldest = &self.entries[index].event;
+                            (oldest.routing_id.clone(), oldest.delivery_id)
+                        }
+                        None => return Ok(false),
+                    };
+                    warn!(
+                        "spool {} is full, the oldest copy event_id={} is dropped",
+                        self.log.path().display(),
+                        routing_id
+                    );
+                    self.remove(&routing_id, delivery_id);
+                }
+                SpoolOverflow::Block => {
+                    self.blocked = true;
+                    return Ok(false);
+                }
+                _ => return Ok(false),
+            }
+        }
+        self.log.append(&fields)?;
+        self.apply(Record::Spooled(Box::new(SpoolEntry {
+            incoming_id: incoming_id.to_string(),
+            event,
+            bytes,
+            attempts: 0,
+        })));
+        Ok(true)
+    }
+}
+
+/// result of an acknowledgment of a spooled copy
+pub(crate) enum SpoolAcknowledgment {
+    /// the copy was delivered and removed from the spool
+    Drained,
+    /// the copy stays in the spool and is sent again
+    Failed,
+    /// The port rejected the copy with `ProcessingResult::PermanentError`, it was removed from the spool.
+    /// Contains the input port, the copy and the number of attempts.
+    Rejected(InternalServerId, OutgoingCloudEvent, u32),
+}
+
+/// The spools of the ports that have `PortOptions.spool`, see `SpoolOptions`.
+#[derive(Default)]
+pub(crate) struct Spools {
+    spools: HashMap<InternalServerId, Spool>,
+}
+
+impl Spools {
+    /// Opens the spools of all ports with `PortOptions.spool` and loads the copies that were not drained yet.
+    /// A port whose spool can not be opened has no spool.
+    pub(crate) fn open(options: &KernelOptions) -> Self {
+        let mut spools = HashMap::new();
+        for (id, port_options) in options.ports.iter() {
+            if let Some(ref spool_options) = port_options.spool {
+                match Spool::open(id, spool_options) {
+                    Ok(spool) => {
+                        spools.insert(id.clone(), spool);
+                    }
+                    Err(e) => error!(
+                        "failed to open the spool of {}, its CloudEvents are not spooled: {:?}",
+                        id, e
+                    ),
+                }
+            }
+        }
+        Spools { spools }
+    }
+
+    /// the port has a spool
+    pub(crate) fn contains(&self, destination_id: &str) -> bool {
+        self.spools.contains_key(destination_id)
+    }
+
+    /// the port has a spool with copies that were not drained yet
+    pub(crate) fn has_copies(&self, destination_id: &str) -> bool {
+        self.spools
+            .get(destination_id)
+            .is_some_and(|spool| !spool.entries.is_empty())
+    }
+
+    /// Appends the copy to the spool of its destination.
+    /// Returns `false` if the destination has no spool or the copy does not fit into it, see `SpoolOverflow`.
+    pub(crate) fn push(&mut self, incoming_id: &str, event: &OutgoingCloudEvent) -> bool {
+        let spool = match self.spools.get_mut(&event.destination_id) {
+            Some(spool) => spool,
+            None => return false,
+        };
+        let routing_id = event.routing_id.clone();
+        match spool.push(incoming_id, event.clone()) {
+            Ok(true) => true,
+            Ok(false) => {
+                warn!(
+                    "spool {} is full, event_id={} is not spooled",
+                    spool.log.path().display(),
+                    routing_id
+                );
+                false
+            }
+            Err(e) => {
+                error!(
+                    "failed to write event_id={} to the spool {}: {:?}",
+                    routing_id,
+                    spool.log.path().display(),
+                    e
+                );
+                false
+            }
+        }
+    }
+
+    /// the ports whose spool has copies that are not in flight
+    pub(crate) fn drainable(&self) -> Vec<InternalServerId> {
+        self.spools
+            .iter()
+            .filter(|(_, spool)| spool.next_unsent().is_some())
+            .map(|(id, _)| id.clone())
+            .collect()
+    }
+
+    /// number of spooled copies of the port that wait for their acknowledgment
+    pub(crate) fn in_flight(&self, destination_id: &str) -> usize {
+        self.spools
+            .get(destination_id)
+            .map_or(0, |spool| spool.in_flight.len())
+    }
+
+    /// the highest delivery id of the spooled copies, the Kernel does not use it again for other copies
+    pub(crate) fn max_delivery_id(&self) -> Option<CloudEventDeliveryId> {
+        self.spools
+            .values()
+            .flat_map(|spool| spool.entries.iter().map(|entry| entry.event.delivery_id))
+            .max()
+    }
+
+    /// Returns the oldest copy of the port that is not in flight to send it, `None` if there is none.
+    /// A copy that requires an acknowledgment stays in the spool until the port acknowledged it,
+    /// it is sent again if it is not acknowledged within the `delivery_ttl`.
+    pub(crate) fn next_copy(
+        &mut self,
+        destination_id: &str,
+        delivery_ttl: Duration,
+    ) -> Option<OutgoingCloudEvent> {
+        let spool = self.spools.get_mut(destination_id)?;
+        let index = spool.next_unsent()?;
+        let entry = &mut spool.entries[index];
+        let event = entry.event.clone();
+        if event.args.delivery_guarantee.requires_acknowledgment() {
+            entry.attempts += 1;
+            spool.in_flight.insert(
+                event.delivery_id,
+                InFlight {
+                    routing_id: event.routing_id.clone(),
+                    deadline: SystemTime::now().add(delivery_ttl),
+                },
+            );
+        } else {
+            spool.remove(&event.routing_id, event.delivery_id);
+        }
+        Some(event)
+    }
+
+    /// Applies the acknowledgment of the port, `None` if it is not for a copy in flight.
+    pub(crate) fn acknowledge(
+        &mut self,
+        destination_id: &str,
+        routing_id: &str,
+        delivery_id: CloudEventDeliveryId,
+        result: &ProcessingResult,
+    ) -> Option<SpoolAcknowledgment> {
+        let spool = self.spools.get_mut(destination_id)?;
+        if spool
+            .in_flight
+            .get(&delivery_id)
+            .is_none_or(|in_flight| in_flight.routing_id != routing_id)
+        {
+            return None;
+        }
+        match result {
+            ProcessingResult::Successful => {
+                spool.remove(routing_id, delivery_id);
+                Some(SpoolAcknowledgment::Drained)
+            }
+            ProcessingResult::PermanentError => {
+                let entry = spool.remove(routing_id, delivery_id)?;
+                Some(SpoolAcknowledgment::Rejected(
+                    entry.incoming_id,
+                    entry.event,
+                    entry.attempts,
+                ))
+            }
+            _ => {
+                spool.in_flight.remove(&delivery_id);
+                Some(SpoolAcknowledgment::Failed)
+            }
+        }
+    }
+
+    /// Forgets the copies in flight that were not acknowledged in time, they are sent again.
+    /// Returns the port of every copy.
+    pub(crate) fn expire_in_flight(&mut self) -> Vec<InternalServerId> {
+        let now = SystemTime::now();
+        let mut expired = Vec::new();
+        for (id, spool) in self.spools.iter_mut() {
+            spool.in_flight.retain(|_, in_flight| {
+                if in_flight.deadline > now {
+                    return true;
+                }
+                expired.push(id.clone());
+                false
+            });
+        }
+        expired
+    }
+
+    /// the copies in flight of an exited port are sent again after its restart
+    pub(crate) fn reset(&mut self, destination_id: &str) {
+        if let Some(spool) = self.spools.get_mut(destination_id) {
+            spool.in_flight.clear();
+        }
+    }
+
+    /// the earliest time a copy in flight has to be acknowledged
+    pub(crate) fn next_deadline(&self) -> Option<SystemTime> {
+        self.spools
+            .values()
+            .flat_map(|spool| spool.in_flight.values().map(|in_flight| in_flight.deadline))
+            .min()
+    }
+
+    /// any spool has copies that were not drained yet
+    pub(crate) fn is_draining(&self) -> bool {
+        self.spools.values().any(|spool| !spool.entries.is_empty())
+    }
+
+    /// any spool with `SpoolOverflow::Block` is full, the input ports are paused until it is drained to half of its size
+    pub(crate) fn is_blocked(&self) -> bool {
+        self.spools.values().any(|spool| spool.blocked)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudevents::{AttributesReader, AttributesWriter, Event};
+
+    fn spool_options(name: &str, max_bytes: u64, overflow: SpoolOverflow) -> SpoolOptions {
+        SpoolOptions {
+            directory: std::env::temp_dir().join(format!(
+                "cerk_spool_test_{}_{}",
+                name,
+                std::process::id()
+            )),
+            max_bytes,
+            overflow,
+            sync: false,
+        }
+    }
+
+    fn copy(routing_id: &str) -> OutgoingCloudEvent {
+        let mut cloud_event = Event::default();
+        cloud_event.set_id(format!("id\t{}", routing_id));
+        OutgoingCloudEvent {
+            routing_id: routing_id.to_string(),
+            cloud_event: Arc::new(cloud_event),
+            destination_id: "output".to_string(),
+            args: CloudEventRoutingArgs {
+                delivery_guarantee: DeliveryGuarantee::AtLeastOnce,
+                ..CloudEventRoutingArgs::default()
+            },
+            delivery_id: routing_id.parse().unwrap(),
+        }
+    }
+
+    fn open_spools(options: &SpoolOptions) -> Spools {
+        let mut kernel_options = KernelOptions::default();
+        kernel_options.ports.insert(
+            "output".to_string(),
+            crate::kernel::PortOptions {
+                spool: Some(options.clone()),
+                ..crate::kernel::PortOptions::default()
+            },
+        );
+        Spools::open(&kernel_options)
+    }
+
+    fn drain(spools: &mut Spools) -> Vec<String> {
+        let mut drained = Vec::new();
+        while let Some(event) = spools.next_copy("output", Duration::from_secs(60)) {
+            spools.acknowledge(
+                "output",
+                &event.routing_id,
+                event.delivery_id,
+                &ProcessingResult::Successful,
+            );
+            drained.push(event.routing_id);
+        }
+        drained
+    }
+
+    #[test]
+    fn spooled_copies_should_be_drained_in_order_after_a_restart() -> Result<()> {
+        let options = spool_options("restart", 10_000, SpoolOverflow::DropNewest);
+        {
+            let mut spools = open_spools(&options);
+            assert!(spools.push("input", &copy("1")));
+            assert!(spools.push("input", &copy("2")));
+            assert!(spools.push("input", &copy("3")));
+            let first = spools.next_copy("output", Duration::from_secs(60)).unwrap();
+            let second = spools.next_copy("output", Duration::from_secs(60)).unwrap();
+            assert_eq!(spools.in_flight("output"), 2);
+            // the copies in flight are acknowledged in any order
+            assert!(matches!(
+                spools.acknowledge("output", "2", 2, &ProcessingResult::Successful),
+                Some(SpoolAcknowledgment::Drained)
+            ));
+            assert_eq!(first.cloud_event.id(), "id\t1");
+            assert_eq!(second.cloud_event.id(), "id\t2");
+        }
+        let mut spools = open_spools(&options);
+        fs::remove_dir_all(&options.directory)?;
+        assert!(spools.has_copies("output"));
+        assert_eq!(spools.max_delivery_id(), Some(3));
+        assert_eq!(drain(&mut spools), vec!["1", "3"]);
+        assert!(!spools.is_draining());
+        Ok(())
+    }
+
+    #[test]
+    fn failed_copy_should_stay_in_the_spool() -> Result<()> {
+        let options = spool_options("failed", 10_000, SpoolOverflow::DropNewest);
+        let mut spools = open_spools(&options);
+        fs::remove_dir_all(&options.directory)?;
+        spools.push("input", &copy("1"));
+        spools.next_copy("output", Duration::from_secs(60));
+        assert!(spools
+            .acknowledge("output", "1", 2, &ProcessingResult::Successful)
+            .is_none());
+        assert!(matches!(
+            spools.acknowledge("output", "1", 1, &ProcessingResult::TransientError),
+            Some(SpoolAcknowledgment::Failed)
+        ));
+        spools.next_copy("output", Duration::from_secs(0));
+        assert_eq!(spools.expire_in_flight(), vec!["output".to_string()]);
+        assert_eq!(drain(&mut spools), vec!["1"]);
+        Ok(())
+    }
+
+    #[test]
+    fn full_spool_should_apply_the_overflow_policy() -> Result<()> {
+        let size = to_line(&to_fields("input", &copy("1"))?).len() as u64;
+
+        let options = spool_options("drop_oldest", 2 * size, SpoolOverflow::DropOldest);
+        let mut spools = open_spools(&options);
+        fs::remove_dir_all(&options.directory)?;
+        for routing_id in ["1", "2", "3"] {
+            assert!(spools.push("input", &copy(routing_id)));
+        }
+        assert_eq!(drain(&mut spools), vec!["2", "3"]);
+
+        let options = spool_options("drop_newest", 2 * size, SpoolOverflow::DropNewest);
+        let mut spools = open_spools(&options);
+        fs::remove_dir_all(&options.directory)?;
+        assert!(spools.push("input", &copy("1")));
+        assert!(spools.push("input", &copy("2")));
+        assert!(!spools.push("input", &copy("3")));
+        assert!(!spools.is_blocked());
+        assert_eq!(drain(&mut spools), vec!["1", "2"]);
+
+        let options = spool_options("block", 2 * size, SpoolOverflow::Block);
+        let mut spools = open_spools(&options);
+        fs::remove_dir_all(&options.directory)?;
+        assert!(spools.push("input", &copy("1")));
+        assert!(spools.push("input", &copy("2")));
+        assert!(!spools.push("input", &copy("3")));
+        assert!(spools.is_blocked());
+        assert_eq!(drain(&mut spools), vec!["1", "2"]);
+        assert!(!spools.is_blocked());
+        Ok(())
+    }
+}
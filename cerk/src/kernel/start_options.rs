@@ -1,4 +1,4 @@
-use crate::kernel::ScheduleInternalServerStatic;
+use crate::kernel::{KernelOptions, ScheduleInternalServerStatic};
 use crate::runtime::{InternalServerFnRefStatic, ScheduleFnRefStatic};
 
 /// The start option for the Kernel.
@@ -10,6 +10,11 @@ pub struct StartOptions {
     /// the function to start the router
     pub router: InternalServerFnRefStatic,
 
+    /// A vector of processor ids and functions to start the processors.
+    /// Every `IncomingCloudEvent` passes the processors in the given order before it is routed,
+    /// each of them could pass it on, change or drop it (e.g., filters, transformations or validations).
+    pub processors: Vec<ScheduleInternalServerStatic>,
+
     /// the function to start the config loader
     pub config_loader: InternalServerFnRefStatic,
 
@@ -17,4 +22,7 @@ pub struct StartOptions {
     /// That could handle input, output or both.
     /// The type of port depends on the messages the components send and receive.
     pub ports: Vec<ScheduleInternalServerStatic>,
+
+    /// options for the Kernel itself, e.g., how long it waits for acknowledgments
+    pub kernel: KernelOptions,
 }
@@ -0,0 +1,134 @@
+use crate::kernel::{
+    CloudEventRoutingArgs, HealthCheckStatus, IncomingCloudEvent, RoutingIdGenerator,
+};
+use crate::runtime::InternalServerId;
+use chrono::Utc;
+use cloudevents::{EventBuilder, EventBuilderV10};
+use serde_json::{json, Value};
+use std::collections::HashSet;
+use std::sync::Arc;
+
+/// The pseudo `incoming_id` of the CloudEvents the Kernel emits about itself, see `KernelOptions.system_events`.
+/// No component can use it.
+pub const SYSTEM_EVENTS_ID: &str = "cerk.system";
+/// `source` of the CloudEvents the Kernel emits about itself
+pub const SYSTEM_EVENT_SOURCE: &str = "urn:cerk:system";
+
+/// A component was scheduled (again after a restart), the subject is the component id.
+pub const SYSTEM_EVENT_COMPONENT_SCHEDULED: &str = "cerk.system.component.scheduled";
+/// A component applied its config, the subject is the component id.
+pub const SYSTEM_EVENT_CONFIG_APPLIED: &str = "cerk.system.config.applied";
+/// A component rejected its config, the subject is the component id and the data contains the `reason`.
+pub const SYSTEM_EVENT_CONFIG_REJECTED: &str = "cerk.system.config.rejected";
+/// A port did not acknowledge a copy within the delivery ttl, the subject is the port id.
+/// The data contains the `incoming_id`, `routing_id` and `attempts` of the copy.
+pub const SYSTEM_EVENT_DELIVERY_TIMEOUT: &str = "cerk.system.delivery.timeout";
+/// A copy was sent to the dead-letter port, the subject is the id of the port it could not be delivered to.
+/// The data contains the `incoming_id`, `routing_id`, `reason`, `attempts` and `dead_letter_port`.
+pub const SYSTEM_EVENT_DEAD_LETTERED: &str = "cerk.system.delivery.dead_lettered";
+/// A component reported that it is unhealthy, the subject is the component id and the data contains the `reason`.
+/// It is only emitted again after the component was healthy in between.
+pub const SYSTEM_EVENT_COMPONENT_UNHEALTHY: &str = "cerk.system.component.unhealthy";
+
+/// The CloudEvents the Kernel emitted about itself, until they are routed.
+pub(crate) struct SystemEvents {
+    enabled: bool,
+    routing_ids: RoutingIdGenerator,
+    emitted: Vec<IncomingCloudEvent>,
+    /// components that were reported as unhealthy
+    unhealthy: HashSet<InternalServerId>,
+}
+
+impl SystemEvents {
+    pub fn new(enabled: bool) -> Self {
+        SystemEvents {
+            enabled,
+            routing_ids: RoutingIdGenerator::new(),
+            emitted: Vec::new(),
+            unhealthy: HashSet::new(),
+        }
+    }
+
+    /// Creates a CloudEvent with the given type, subject and JSON data, nothing happens if the system events are disabled.
+    /// The CloudEvent is routed with `DeliveryGuarantee::BestEffort`, so it never causes further system events itself.
+    pub fn emit(&mut self, ty: &str, subject: &str, data: Value) {
+        if !self.enabled {
+            return;
+        }
+        let routing_id = self.routing_ids.next_id();
+        let cloud_event = EventBuilderV10::new()
+            .id(routing_id.clone())
+            .ty(ty)
+            .source(SYSTEM_EVENT_SOURCE)
+            .subject(subject)
+            .time(Utc::now())
+            .data("application/json", data)
+            .build();
+        match cloud_event {
+            Ok(cloud_event) => self.emitted.push(IncomingCloudEvent {
+                incoming_id: SYSTEM_EVENTS_ID.to_string(),
+                routing_id,
+                cloud_event: Arc::new(cloud_event),
+                args: CloudEventRoutingArgs::default(),
+            }),
+            Err(e) => error!("failed to create system event {}: {}", ty, e),
+        }
+    }
+
+    /// Emits `SYSTEM_EVENT_COMPONENT_UNHEALTHY` if the component became unhealthy.
+    pub fn observe_health(&mut self, component_id: &str, status: &HealthCheckStatus) {
+        match status {
+            HealthCheckStatus::Unhealthy(reason) => {
+                if self.unhealthy.insert(component_id.to_string()) {
+                    self.emit(
+                        SYSTEM_EVENT_COMPONENT_UNHEALTHY,
+                        component_id,
+                        json!({ "component": component_id, "reason": reason }),
+                    );
+                }
+            }
+            HealthCheckStatus::Healthy => {
+                self.unhealthy.remove(component_id);
+            }
+            HealthCheckStatus::Unsupported => {}
+        }
+    }
+
+    /// the emitted CloudEvents in the order they were emitted
+    pub fn take(&mut self) -> Vec<IncomingCloudEvent> {
+        std::mem::take(&mut self.emitted)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cloudevents::AttributesReader;
+
+    #[test]
+    fn unhealthy_component_should_be_reported_once() {
+        let mut system_events = SystemEvents::new(true);
+        let unhealthy = HealthCheckStatus::Unhealthy("broken".to_string());
+        system_events.observe_health("port", &unhealthy);
+        system_events.observe_health("port", &unhealthy);
+        let emitted = system_events.take();
+        assert_eq!(emitted.len(), 1);
+        assert_eq!(emitted[0].incoming_id, SYSTEM_EVENTS_ID);
+        assert_eq!(
+            emitted[0].cloud_event.ty(),
+            SYSTEM_EVENT_COMPONENT_UNHEALTHY
+        );
+        assert_eq!(emitted[0].cloud_event.subject(), Some("port"));
+
+        system_events.observe_health("port", &HealthCheckStatus::Healthy);
+        system_events.observe_health("port", &unhealthy);
+        assert_eq!(system_events.take().len(), 1);
+    }
+
+    #[test]
+    fn disabled_system_events_should_not_be_emitted() {
+        let mut system_events = SystemEvents::new(false);
+        system_events.emit(SYSTEM_EVENT_CONFIG_APPLIED, "port", Value::Null);
+        assert!(system_events.take().is_empty());
+    }
+}
@@ -1,7 +1,11 @@
 //! Wrappers for platform-specific channel implementation used in CERK.
 
 mod receiver;
+#[cfg(any(test, feature = "test-util"))]
+mod recording_sender;
 mod sender;
 
 pub use self::receiver::{BoxedReceiver, Receiver};
+#[cfg(any(test, feature = "test-util"))]
+pub use self::recording_sender::RecordingSender;
 pub use self::sender::{BoxedSender, Sender};
@@ -0,0 +1,39 @@
+use crate::kernel::BrokerEvent;
+use crate::runtime::channel::Sender;
+use std::sync::{Arc, Mutex};
+
+/// A Sender that records the events it gets, to check them in tests.
+///
+/// The clones record into the same list.
+/// Only available in tests and with the feature `test-util`.
+#[derive(Clone, Default)]
+pub struct RecordingSender {
+    events: Arc<Mutex<Vec<BrokerEvent>>>,
+}
+
+impl RecordingSender {
+    /// the recorded events in the order they were sent, formatted with `Display`
+    pub fn events(&self) -> Vec<String> {
+        self.events
+            .lock()
+            .unwrap()
+            .iter()
+            .map(|event| event.to_string())
+            .collect()
+    }
+
+    /// removes the recorded events and returns them in the order they were sent
+    pub fn take_events(&self) -> Vec<BrokerEvent> {
+        std::mem::take(&mut *self.events.lock().unwrap())
+    }
+}
+
+impl Sender for RecordingSender {
+    fn send(&self, event: BrokerEvent) {
+        self.events.lock().unwrap().push(event);
+    }
+
+    fn clone_boxed(&self) -> Box<dyn Sender + Send> {
+        Box::new(self.clone())
+    }
+}
@@ -11,6 +11,12 @@ pub trait Sender: Send {
     ///
     /// * https://github.com/ce-rust/cerk/issues/21
     fn clone_boxed(&self) -> Box<dyn Sender + Send>;
+
+    /// Number of events that were sent but not received yet.
+    /// `None` if the channel does not count them.
+    fn queue_length(&self) -> Option<usize> {
+        None
+    }
 }
 
 /// Boxed wrapper for a platform-specific channel sender.
@@ -1,6 +1,7 @@
 use crate::kernel::{KernelFn, StartOptions};
+use anyhow::Result;
 
-/// Function signature for the Scheduler.
-pub type ScheduleFn = fn(StartOptions, KernelFn);
+/// Function signature for the Scheduler, it returns the result of the Kernel.
+pub type ScheduleFn = fn(StartOptions, KernelFn) -> Result<()>;
 /// Function signature for the Scheduler as reference
-pub type ScheduleFnRefStatic = &'static fn(StartOptions, KernelFn);
+pub type ScheduleFnRefStatic = &'static fn(StartOptions, KernelFn) -> Result<()>;
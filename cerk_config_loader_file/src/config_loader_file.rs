@@ -1,9 +1,10 @@
 use super::file_reader::read_file;
 use crate::config_parser::parse_json_to_events;
 use anyhow::Result;
-use cerk::kernel::BrokerEvent;
+use cerk::kernel::{BrokerEvent, HealthCheckStatus};
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
+use std::collections::{HashMap, HashSet};
 use std::env;
 
 pub fn read_configs_from_file(config_path: &str) -> Result<Vec<BrokerEvent>> {
@@ -11,7 +12,47 @@ pub fn read_configs_from_file(config_path: &str) -> Result<Vec<BrokerEvent>> {
     parse_json_to_events(content)
 }
 
+/// the answers of the components to the configs the loader sent
+#[derive(Default)]
+struct ConfigStatus {
+    /// components that applied at least one config
+    applied: HashSet<InternalServerId>,
+    /// the reason of the last rejected config per component
+    rejected: HashMap<InternalServerId, String>,
+}
+
+impl ConfigStatus {
+    fn applied(&mut self, component_id: InternalServerId) {
+        self.rejected.remove(&component_id);
+        self.applied.insert(component_id);
+    }
+
+    /// Remembers the rejection.
+    /// Returns `true` if the component has no previous config to keep, so the router can not start.
+    fn rejected(&mut self, component_id: InternalServerId, reason: String) -> bool {
+        let fatal = !self.applied.contains(&component_id);
+        self.rejected.insert(component_id, reason);
+        fatal
+    }
+
+    fn health(&self) -> HealthCheckStatus {
+        if self.rejected.is_empty() {
+            return HealthCheckStatus::Healthy;
+        }
+        let mut rejected: Vec<String> = self
+            .rejected
+            .iter()
+            .map(|(id, reason)| format!("{} ({})", id, reason))
+            .collect();
+        rejected.sort();
+        HealthCheckStatus::Unhealthy(format!("rejected configs: {}", rejected.join(", ")))
+    }
+}
+
 /// This is the main function to start the config loader.
+///
+/// If a component rejects its first config, the config loader stops the router.
+/// If it rejects a later one, the component keeps its previous config and the config loader reports it as unhealthy.
 pub fn config_loader_file_start(
     id: InternalServerId,
     inbox: BoxedReceiver,
@@ -22,6 +63,7 @@ pub fn config_loader_file_start(
         "start file based config loader with id {}, will consume config from {}",
         id, config_path
     );
+    let mut status = ConfigStatus::default();
     loop {
         match inbox.receive() {
             BrokerEvent::Init => {
@@ -35,6 +77,32 @@ pub fn config_loader_file_start(
                     Err(e) => error!("failed to read config {:?}", e),
                 }
             }
+            BrokerEvent::ConfigApplied(component_id) => {
+                debug!("{} applied its config", component_id);
+                status.applied(component_id);
+            }
+            BrokerEvent::ConfigRejected(component_id, reason) => {
+                if status.rejected(component_id.clone(), reason.clone()) {
+                    error!(
+                        "{} rejected its config, stop the router: {}",
+                        component_id, reason
+                    );
+                    sender_to_kernel.send(BrokerEvent::Shutdown);
+                } else {
+                    error!(
+                        "{} rejected the new config and keeps the previous one: {}",
+                        component_id, reason
+                    );
+                }
+            }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(status.health()))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -50,6 +118,20 @@ mod tests {
     use cerk::kernel::Config;
     use std::collections::HashMap;
 
+    #[test]
+    fn rejected_config_should_only_be_fatal_without_previous_config() {
+        let mut status = ConfigStatus::default();
+        assert!(status.rejected("port".to_string(), "invalid".to_string()));
+        status.applied("port".to_string());
+        assert_eq!(status.health(), HealthCheckStatus::Healthy);
+
+        assert!(!status.rejected("port".to_string(), "invalid".to_string()));
+        assert_eq!(
+            status.health(),
+            HealthCheckStatus::Unhealthy("rejected configs: port (invalid)".to_string())
+        );
+    }
+
     #[test]
     fn read_configs_from_file_sample() -> Result<()> {
         let config = read_configs_from_file("./src/test_data/amqp_to_printer.json")?;
@@ -21,6 +21,10 @@ This port loads configurations from a json file.
 
 The file path could be set with the env variable `CONFIG_PATH`, default is `./config.json`.
 
+The components answer their config with `ConfigApplied` or `ConfigRejected`.
+If a component rejects its first config, the config loader stops the router.
+If it rejects a later one, the component keeps its previous config and the config loader answers health checks as unhealthy.
+
 ## Example Config
 
 ```json
@@ -33,7 +33,9 @@ mod tests {
     use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
     use cerk::runtime::{InternalServerFn, InternalServerId, ScheduleFn};
 
-    fn dummy_scheduler(_: StartOptions, _: KernelFn) {}
+    fn dummy_scheduler(_: StartOptions, _: KernelFn) -> anyhow::Result<()> {
+        Ok(())
+    }
 
     fn dummy_router(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
@@ -58,6 +60,7 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
+            processors: std::collections::HashMap::new(),
             config_loaders: [(
                 "myconfig_loadertype".to_string(),
                 &(dummy_config_loader as InternalServerFn),
@@ -1,18 +1,208 @@
 use crate::start_links::ComponentStartLinks;
 use anyhow::{Context, Result};
-use cerk::kernel::{ScheduleInternalServer, ScheduleInternalServerStatic, StartOptions};
+use cerk::kernel::{
+    BatchOptions, CircuitBreakerOptions, FlowControlOptions, IdempotencyOptions, JournalOptions,
+    KernelOptions, PortOptions, RetryPolicy, ScheduleInternalServer, ScheduleInternalServerStatic,
+    SpoolOptions, SpoolOverflow, StartOptions, Watermarks, CONFIG_LOADER_ID, KERNEL_ID, ROUTER_ID,
+    SYSTEM_EVENTS_ID,
+};
 use serde::Deserialize;
 use std::collections::HashMap;
+use std::path::PathBuf;
+use std::time::Duration;
 
 #[derive(Deserialize, Debug, PartialEq)]
 struct Configuration {
     scheduler: String,
     router: String,
+    /// in the order the CloudEvents pass them
+    #[serde(default)]
+    processors: Vec<ProcessorConfiguration>,
     config_loader: String,
     /// key: port name; value: port id
     ports: HashMap<String, String>,
+    #[serde(default)]
+    kernel: KernelConfiguration,
 }
 
+#[derive(Deserialize, Debug, PartialEq)]
+struct ProcessorConfiguration {
+    id: String,
+    /// name of the start function in the `ComponentStartLinks`
+    processor: String,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct KernelConfiguration {
+    delivery_ttl_ms: Option<u64>,
+    retry: Option<RetryConfiguration>,
+    /// port name
+    dead_letter_port: Option<String>,
+    restart: Option<RetryConfiguration>,
+    /// key: port name
+    #[serde(default)]
+    ports: HashMap<String, PortConfiguration>,
+    idempotency: Option<IdempotencyConfiguration>,
+    journal: Option<JournalConfiguration>,
+    flow_control: Option<FlowControlConfiguration>,
+    startup_timeout_ms: Option<u64>,
+    #[serde(default)]
+    system_events: bool,
+    circuit_breaker: Option<CircuitBreakerConfiguration>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct IdempotencyConfiguration {
+    max_entries: Option<usize>,
+    retention_ms: Option<u64>,
+    path: Option<PathBuf>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct JournalConfiguration {
+    directory: PathBuf,
+    #[serde(default)]
+    sync: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct FlowControlConfiguration {
+    outbox_depth: Option<WatermarksConfiguration>,
+    pending_deliveries: Option<WatermarksConfiguration>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct WatermarksConfiguration {
+    high: usize,
+    low: usize,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct PortConfiguration {
+    delivery_ttl_ms: Option<u64>,
+    /// missing fields are taken from the global retry policy
+    retry: Option<RetryConfiguration>,
+    batch: Option<BatchConfiguration>,
+    /// missing fields are taken from the global circuit breaker
+    circuit_breaker: Option<CircuitBreakerConfiguration>,
+    spool: Option<SpoolConfiguration>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct SpoolConfiguration {
+    directory: PathBuf,
+    max_bytes: u64,
+    /// `drop_oldest` (default), `drop_newest` or `block`
+    overflow: Option<String>,
+    #[serde(default)]
+    sync: bool,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct CircuitBreakerConfiguration {
+    failure_threshold: Option<u32>,
+    open_duration_ms: Option<u64>,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct BatchConfiguration {
+    max_size: usize,
+    max_delay_ms: u64,
+}
+
+#[derive(Deserialize, Debug, PartialEq, Default)]
+struct RetryConfiguration {
+    max_attempts: Option<u32>,
+    initial_backoff_ms: Option<u64>,
+    max_backoff_ms: Option<u64>,
+    multiplier: Option<f64>,
+    jitter: Option<f64>,
+}
+
+fn parse_retry_policy(config: &RetryConfiguration, base: &RetryPolicy) -> Result<RetryPolicy> {
+    let policy = RetryPolicy {
+        max_attempts: config.max_attempts.unwrap_or(base.max_attempts),
+        initial_backoff: config
+            .initial_backoff_ms
+            .map_or(base.initial_backoff, Duration::from_millis),
+        max_backoff: config
+            .max_backoff_ms
+            .map_or(base.max_backoff, Duration::from_millis),
+        multiplier: config.multiplier.unwrap_or(base.multiplier),
+        jitter: config.jitter.unwrap_or(base.jitter),
+    };
+    if policy.max_attempts == 0 {
+        bail!("retry max_attempts must be at least 1");
+    }
+    if policy.multiplier < 1.0 {
+        bail!("retry multiplier must be at least 1.0");
+    }
+    if !(0.0..=1.0).contains(&policy.jitter) {
+        bail!("retry jitter must be between 0.0 and 1.0");
+    }
+    Ok(policy)
+}
+
+fn parse_circuit_breaker_options(
+    config: &CircuitBreakerConfiguration,
+    base: &CircuitBreakerOptions,
+) -> Result<CircuitBreakerOptions> {
+    let options = CircuitBreakerOptions {
+        failure_threshold: config.failure_threshold.unwrap_or(base.failure_threshold),
+        open_duration: config
+            .open_duration_ms
+            .map_or(base.open_duration, Duration::from_millis),
+    };
+    if options.failure_threshold == 0 {
+        bail!("circuit breaker failure_threshold must be at least 1");
+    }
+    Ok(options)
+}
+
+fn parse_spool_options(config: &SpoolConfiguration) -> Result<SpoolOptions> {
+    if config.max_bytes == 0 {
+        bail!("spool max_bytes must be at least 1");
+    }
+    let overflow = match config.overflow.as_deref() {
+        None | Some("drop_oldest") => SpoolOverflow::DropOldest,
+        Some("drop_newest") => SpoolOverflow::DropNewest,
+        Some("block") => SpoolOverflow::Block,
+        Some(overflow) => bail!("unknown spool overflow {}", overflow),
+    };
+    Ok(SpoolOptions {
+        directory: config.directory.clone(),
+        max_bytes: config.max_bytes,
+        overflow,
+        sync: config.sync,
+    })
+}
+
+fn parse_watermarks(config: &Option<WatermarksConfiguration>) -> Result<Option<Watermarks>> {
+    match config {
+        Some(config) if config.low >= config.high => {
+            bail!("the low watermark must be lower than the high watermark")
+        }
+        Some(config) => Ok(Some(Watermarks {
+            high: config.high,
+            low: config.low,
+        })),
+        None => Ok(None),
+    }
+}
+
+fn parse_batch_options(config: &BatchConfiguration) -> Result<BatchOptions> {
+    if config.max_size == 0 {
+        bail!("batch max_size must be at least 1");
+    }
+    Ok(BatchOptions {
+        max_size: config.max_size,
+        max_delay: Duration::from_millis(config.max_delay_ms),
+    })
+}
+
+/// ids of the components the Kernel starts itself and of the Kernel, a processor can not use them
+const RESERVED_IDS: [&str; 4] = [ROUTER_ID, CONFIG_LOADER_ID, KERNEL_ID, SYSTEM_EVENTS_ID];
+
 fn parse_json_to_config(content: String) -> Result<Configuration> {
     let config = serde_json::from_str(content.as_str())?;
     Ok(config)
@@ -24,6 +214,109 @@ fn get_link<'a, T>(name: &String, links: &'a HashMap<String, T>) -> Result<&'a T
         .with_context(|| format!("was not able to find {}", name))
 }
 
+fn parse_config_to_kernel_options(config: &Configuration) -> Result<KernelOptions> {
+    let mut options = KernelOptions::default();
+    if let Some(ttl) = config.kernel.delivery_ttl_ms {
+        options.delivery_ttl = Duration::from_millis(ttl);
+    }
+    if let Some(ref retry) = config.kernel.retry {
+        options.retry = parse_retry_policy(retry, &options.retry)?;
+    }
+    if let Some(ref id) = config.kernel.dead_letter_port {
+        if !config.ports.contains_key(id) {
+            bail!("dead-letter port {} is not configured in ports", id);
+        }
+        options.dead_letter_port = Some(id.to_string());
+    }
+    if let Some(ref restart) = config.kernel.restart {
+        options.restart =
+            parse_retry_policy(restart, &options.restart).context("invalid restart policy")?;
+    }
+    if let Some(ref idempotency) = config.kernel.idempotency {
+        let defaults = &options.idempotency;
+        options.idempotency = IdempotencyOptions {
+            max_entries: idempotency.max_entries.unwrap_or(defaults.max_entries),
+            retention: idempotency
+                .retention_ms
+                .map_or(defaults.retention, Duration::from_millis),
+            path: idempotency.path.clone(),
+        };
+        if options.idempotency.max_entries == 0 {
+            bail!("idempotency max_entries must be at least 1");
+        }
+    }
+    if let Some(ref journal) = config.kernel.journal {
+        options.journal = JournalOptions {
+            directory: Some(journal.directory.clone()),
+            sync: journal.sync,
+        };
+    }
+    if let Some(ref flow_control) = config.kernel.flow_control {
+        options.flow_control = FlowControlOptions {
+            outbox_depth: parse_watermarks(&flow_control.outbox_depth)
+                .context("invalid flow_control outbox_depth")?,
+            pending_deliveries: parse_watermarks(&flow_control.pending_deliveries)
+                .context("invalid flow_control pending_deliveries")?,
+        };
+    }
+    if let Some(timeout) = config.kernel.startup_timeout_ms {
+        options.startup_timeout = Duration::from_millis(timeout);
+    }
+    options.system_events = config.kernel.system_events;
+    if let Some(ref circuit_breaker) = config.kernel.circuit_breaker {
+        options.circuit_breaker = Some(
+            parse_circuit_breaker_options(circuit_breaker, &CircuitBreakerOptions::default())
+                .context("invalid circuit breaker")?,
+        );
+    }
+    for (id, port) in config.kernel.ports.iter() {
+        if !config.ports.contains_key(id) {
+            bail!("kernel options for unknown port {}", id);
+        }
+        options.ports.insert(
+            id.to_string(),
+            PortOptions {
+                delivery_ttl: port.delivery_ttl_ms.map(Duration::from_millis),
+                retry: match port.retry {
+                    Some(ref retry) => Some(
+                        parse_retry_policy(retry, &options.retry)
+                            .with_context(|| format!("invalid retry policy for port {}", id))?,
+                    ),
+                    None => None,
+                },
+                batch: match port.batch {
+                    Some(ref batch) => Some(
+                        parse_batch_options(batch)
+                            .with_context(|| format!("invalid batch options for port {}", id))?,
+                    ),
+                    None => None,
+                },
+                circuit_breaker: match port.circuit_breaker {
+                    Some(ref circuit_breaker) => Some(
+                        parse_circuit_breaker_options(
+                            circuit_breaker,
+                            options
+                                .circuit_breaker
+                                .as_ref()
+                                .unwrap_or(&Default::default()),
+                        )
+                        .with_context(|| format!("invalid circuit breaker for port {}", id))?,
+                    ),
+                    None => None,
+                },
+                spool: match port.spool {
+                    Some(ref spool) => Some(
+                        parse_spool_options(spool)
+                            .with_context(|| format!("invalid spool for port {}", id))?,
+                    ),
+                    None => None,
+                },
+            },
+        );
+    }
+    Ok(options)
+}
+
 fn parse_config_to_start_options(
     links: &ComponentStartLinks<'static>,
     config: &Configuration,
@@ -46,15 +339,37 @@ fn parse_config_to_start_options(
         );
     }
 
+    let mut processors: Vec<ScheduleInternalServerStatic> = Vec::new();
+    for processor in config.processors.iter() {
+        if RESERVED_IDS.contains(&processor.id.as_str()) {
+            bail!(
+                "processor id {} is reserved for the router itself",
+                processor.id
+            );
+        }
+        if config.ports.contains_key(&processor.id)
+            || processors.iter().any(|p| p.id == processor.id)
+        {
+            bail!("processor id {} is used more than once", processor.id);
+        }
+        processors.push(ScheduleInternalServer {
+            id: processor.id.to_string(),
+            function: get_link(&processor.processor, &links.processors)
+                .context("error while building start options for processors")?,
+        });
+    }
+
     let config = StartOptions {
         scheduler: get_link(&config.scheduler, &links.schedulers)?,
         config_loader: get_link(&config.config_loader, &links.config_loaders)?,
         router: get_link(&config.router, &links.routers)?,
+        processors,
         ports: ports
             .iter()
             .map(|ref r| r.as_ref().unwrap())
             .cloned()
             .collect(),
+        kernel: parse_config_to_kernel_options(config)?,
     };
 
     Ok(config)
@@ -93,11 +408,211 @@ mod tests {
         assert_eq!(config.config_loader, "myconfig_loadertype");
         assert_eq!(config.ports.len(), 1);
         assert_eq!(config.ports.get("myport"), Some(&"myporttype".to_string()));
+        assert!(config.processors.is_empty());
+        assert_eq!(config.kernel, KernelConfiguration::default());
 
         Ok(())
     }
 
-    fn dummy_scheduler(_: StartOptions, _: KernelFn) {}
+    #[test]
+    fn parse_json_to_kernel_options_test() -> Result<()> {
+        let json = r#"
+        {
+            "scheduler": "myschedulertype",
+            "router": "myroutertype",
+            "config_loader": "myconfig_loadertype",
+            "ports": {
+                "myport": "myporttype",
+                "myslowport": "myporttype"
+            },
+            "kernel": {
+                "delivery_ttl_ms": 500,
+                "startup_timeout_ms": 5000,
+                "system_events": true,
+                "dead_letter_port": "myport",
+                "circuit_breaker": {
+                    "failure_threshold": 3
+                },
+                "retry": {
+                    "max_attempts": 3,
+                    "initial_backoff_ms": 50
+                },
+                "restart": {
+                    "max_attempts": 10
+                },
+                "idempotency": {
+                    "retention_ms": 60000,
+                    "path": "/var/lib/cerk/idempotency.log"
+                },
+                "journal": {
+                    "directory": "/var/lib/cerk/journal"
+                },
+                "flow_control": {
+                    "pending_deliveries": {
+                        "high": 100,
+                        "low": 50
+                    }
+                },
+                "ports": {
+                    "myslowport": {
+                        "delivery_ttl_ms": 10000,
+                        "retry": {
+                            "max_attempts": 5
+                        },
+                        "batch": {
+                            "max_size": 100,
+                            "max_delay_ms": 20
+                        },
+                        "circuit_breaker": {
+                            "open_duration_ms": 1000
+                        },
+                        "spool": {
+                            "directory": "/var/lib/cerk/spool",
+                            "max_bytes": 1048576,
+                            "overflow": "block"
+                        }
+                    }
+                }
+            }
+        }
+        "#;
+        let config = parse_json_to_config(json.to_string())?;
+        let options = parse_config_to_kernel_options(&config)?;
+        assert_eq!(
+            options.delivery_ttl_of("myport"),
+            Duration::from_millis(500)
+        );
+        assert_eq!(
+            options.delivery_ttl_of("myslowport"),
+            Duration::from_millis(10000)
+        );
+        assert_eq!(options.startup_timeout, Duration::from_secs(5));
+        assert!(options.system_events);
+        assert_eq!(options.retry_policy_of("myport").max_attempts, 3);
+        assert_eq!(options.dead_letter_port, Some("myport".to_string()));
+        let port_retry = options.retry_policy_of("myslowport");
+        assert_eq!(port_retry.max_attempts, 5);
+        assert_eq!(port_retry.initial_backoff, Duration::from_millis(50));
+        assert_eq!(
+            options.ports["myslowport"].batch,
+            Some(BatchOptions {
+                max_size: 100,
+                max_delay: Duration::from_millis(20),
+            })
+        );
+        assert_eq!(
+            options.circuit_breaker_of("myport"),
+            Some(&CircuitBreakerOptions {
+                failure_threshold: 3,
+                ..CircuitBreakerOptions::default()
+            })
+        );
+        assert_eq!(
+            options.circuit_breaker_of("myslowport"),
+            Some(&CircuitBreakerOptions {
+                failure_threshold: 3,
+                open_duration: Duration::from_secs(1),
+            })
+        );
+        assert_eq!(
+            options.ports["myslowport"].spool,
+            Some(SpoolOptions {
+                directory: PathBuf::from("/var/lib/cerk/spool"),
+                max_bytes: 1048576,
+                overflow: SpoolOverflow::Block,
+                sync: false,
+            })
+        );
+        assert_eq!(options.restart.max_attempts, 10);
+        assert_eq!(
+            options.restart.max_backoff,
+            KernelOptions::default().restart.max_backoff
+        );
+        assert_eq!(
+            options.idempotency,
+            IdempotencyOptions {
+                retention: Duration::from_secs(60),
+                path: Some(PathBuf::from("/var/lib/cerk/idempotency.log")),
+                ..IdempotencyOptions::default()
+            }
+        );
+        assert_eq!(
+            options.journal,
+            JournalOptions {
+                directory: Some(PathBuf::from("/var/lib/cerk/journal")),
+                sync: false,
+            }
+        );
+        assert_eq!(
+            options.flow_control,
+            FlowControlOptions {
+                outbox_depth: None,
+                pending_deliveries: Some(Watermarks { high: 100, low: 50 }),
+            }
+        );
+
+        Ok(())
+    }
+
+    #[test]
+    fn kernel_options_for_unknown_port_test() -> Result<()> {
+        let json = r#"
+        {
+            "scheduler": "myschedulertype",
+            "router": "myroutertype",
+            "config_loader": "myconfig_loadertype",
+            "ports": {},
+            "kernel": {
+                "ports": {
+                    "typo": {
+                        "delivery_ttl_ms": 10000
+                    }
+                }
+            }
+        }
+        "#;
+        let config = parse_json_to_config(json.to_string())?;
+        assert!(parse_config_to_kernel_options(&config).is_err());
+
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_retry_policy_test() {
+        let base = RetryPolicy::default();
+        let zero_attempts = RetryConfiguration {
+            max_attempts: Some(0),
+            ..RetryConfiguration::default()
+        };
+        assert!(parse_retry_policy(&zero_attempts, &base).is_err());
+        let too_much_jitter = RetryConfiguration {
+            jitter: Some(1.5),
+            ..RetryConfiguration::default()
+        };
+        assert!(parse_retry_policy(&too_much_jitter, &base).is_err());
+    }
+
+    #[test]
+    fn invalid_watermarks_test() {
+        let inverted = Some(WatermarksConfiguration { high: 10, low: 20 });
+        assert!(parse_watermarks(&inverted).is_err());
+    }
+
+    #[test]
+    fn invalid_spool_test() {
+        let unknown_overflow = SpoolConfiguration {
+            max_bytes: 1024,
+            overflow: Some("drop_all".to_string()),
+            ..SpoolConfiguration::default()
+        };
+        assert!(parse_spool_options(&unknown_overflow).is_err());
+        let empty = SpoolConfiguration::default();
+        assert!(parse_spool_options(&empty).is_err());
+    }
+
+    fn dummy_scheduler(_: StartOptions, _: KernelFn) -> anyhow::Result<()> {
+        Ok(())
+    }
 
     fn dummy_router(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
@@ -107,19 +622,10 @@ mod tests {
 
     fn dummy_port_other(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
-    #[test]
-    fn parse_config_to_start_options_test() -> Result<()> {
-        let config = Configuration {
-            scheduler: "myschedulertype".to_string(),
-            router: "myroutertype".to_string(),
-            config_loader: "myconfig_loadertype".to_string(),
-            ports: [("myport".to_string(), "myporttype".to_string())]
-                .iter()
-                .cloned()
-                .collect(),
-        };
+    fn dummy_processor(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
-        let link = ComponentStartLinks {
+    fn build_links() -> ComponentStartLinks<'static> {
+        ComponentStartLinks {
             schedulers: [(
                 "myschedulertype".to_string(),
                 &(dummy_scheduler as ScheduleFn),
@@ -151,9 +657,31 @@ mod tests {
             .iter()
             .cloned()
             .collect(),
+            processors: [(
+                "myprocessortype".to_string(),
+                &(dummy_processor as InternalServerFn),
+            )]
+            .iter()
+            .cloned()
+            .collect(),
+        }
+    }
+
+    #[test]
+    fn parse_config_to_start_options_test() -> Result<()> {
+        let config = Configuration {
+            scheduler: "myschedulertype".to_string(),
+            router: "myroutertype".to_string(),
+            processors: vec![],
+            config_loader: "myconfig_loadertype".to_string(),
+            ports: [("myport".to_string(), "myporttype".to_string())]
+                .iter()
+                .cloned()
+                .collect(),
+            kernel: KernelConfiguration::default(),
         };
 
-        let start_options = parse_config_to_start_options(&link, &config)?;
+        let start_options = parse_config_to_start_options(&build_links(), &config)?;
         assert_eq!(start_options.scheduler, &(dummy_scheduler as ScheduleFn));
         assert_eq!(start_options.router, &(dummy_router as InternalServerFn));
         assert_eq!(
@@ -169,4 +697,40 @@ mod tests {
 
         Ok(())
     }
+
+    #[test]
+    fn parse_processors_test() -> Result<()> {
+        let json = r#"
+        {
+            "scheduler": "myschedulertype",
+            "router": "myroutertype",
+            "processors": [
+                { "id": "validate", "processor": "myprocessortype" },
+                { "id": "enrich", "processor": "myprocessortype" }
+            ],
+            "config_loader": "myconfig_loadertype",
+            "ports": {
+                "myport": "myporttype"
+            }
+        }
+        "#;
+        let mut config = parse_json_to_config(json.to_string())?;
+        let start_options = parse_config_to_start_options(&build_links(), &config)?;
+        let ids: Vec<&str> = start_options
+            .processors
+            .iter()
+            .map(|processor| processor.id.as_str())
+            .collect();
+        assert_eq!(ids, vec!["validate", "enrich"]);
+
+        config.processors[1].id = "myport".to_string();
+        assert!(parse_config_to_start_options(&build_links(), &config).is_err());
+
+        for reserved_id in RESERVED_IDS {
+            config.processors[1].id = reserved_id.to_string();
+            assert!(parse_config_to_start_options(&build_links(), &config).is_err());
+        }
+
+        Ok(())
+    }
 }
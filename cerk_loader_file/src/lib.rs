@@ -29,13 +29,95 @@ The configuration file could be passed by the env variable `$INIT_PATH` or just
 {
   "scheduler": "SCHEDULER",
   "router": "ROUTER",
+  "processors": [
+    { "id": "myvalidator", "processor": "PROCESSOR" }
+  ],
   "config_loader": "CONFIG_LOADER",
   "ports": {
-    "myport": "PORT"
+    "myport": "PORT",
+    "mydeadletters": "PORT"
+  },
+  "kernel": {
+    "delivery_ttl_ms": 100,
+    "startup_timeout_ms": 30000,
+    "system_events": true,
+    "dead_letter_port": "mydeadletters",
+    "circuit_breaker": {
+      "failure_threshold": 5,
+      "open_duration_ms": 30000
+    },
+    "retry": {
+      "max_attempts": 3,
+      "initial_backoff_ms": 100,
+      "max_backoff_ms": 10000,
+      "multiplier": 2.0,
+      "jitter": 0.2
+    },
+    "restart": {
+      "initial_backoff_ms": 100,
+      "max_backoff_ms": 30000
+    },
+    "idempotency": {
+      "max_entries": 100000,
+      "retention_ms": 86400000,
+      "path": "/var/lib/cerk/idempotency.log"
+    },
+    "journal": {
+      "directory": "/var/lib/cerk/journal",
+      "sync": true
+    },
+    "flow_control": {
+      "outbox_depth": {
+        "high": 1000,
+        "low": 100
+      },
+      "pending_deliveries": {
+        "high": 200,
+        "low": 50
+      }
+    },
+    "ports": {
+      "myport": {
+        "delivery_ttl_ms": 5000,
+        "retry": {
+          "max_attempts": 5
+        },
+        "batch": {
+          "max_size": 100,
+          "max_delay_ms": 20
+        },
+        "circuit_breaker": {
+          "failure_threshold": 2
+        },
+        "spool": {
+          "directory": "/var/lib/cerk/spool",
+          "max_bytes": 10485760,
+          "overflow": "drop_oldest"
+        }
+      }
+    }
   }
 }
 ```
 
+The `processors` are optional, every CloudEvent passes them in the given order before it is routed.
+Their ids must differ from the port ids and from the ids `router`, `config_loader`, `kernel` and `cerk.system`, which the router uses itself.
+
+The `kernel` section is optional.
+`delivery_ttl_ms` defines how long the Kernel waits for the acknowledgments of a delivery, it can be overridden per port.
+`startup_timeout_ms` defines how long the components have to start: the router stops and the process exits with code 1 if a component is not scheduled or did not get its config by then.
+With `system_events`, the Kernel emits CloudEvents about itself with the incoming id `cerk.system` and routes them like any other CloudEvent, their types are defined by the `SYSTEM_EVENT_*` constants of the `cerk` crate (e.g. `cerk.system.config.rejected`).
+`retry` defines how often the Kernel resends a CloudEvent to a port that answered with a transient error (by default it does not retry).
+The retry policy of a port only overrides the given fields of the global one.
+With `batch`, the Kernel sends the CloudEvents for a port in batches of up to `max_size` CloudEvents (`BrokerEvent::OutgoingCloudEventBatch`), a CloudEvent waits at most `max_delay_ms` for the batch to be filled; only use it for ports that support batches (AMQP, MQTT and the UNIX socket output port).
+`dead_letter_port` receives the CloudEvents that failed permanently or timed out, extended with the failure reason, the failed destination, the number of attempts and the original routing id.
+`circuit_breaker` opens the circuit of a port after `failure_threshold` failed or timed out copies in a row: for `open_duration_ms`, its copies are sent to the dead-letter port at once (without a dead-letter port, the sender gets a transient error), afterwards a single copy probes the port; health checks of the port report the open circuit. The circuit breaker of a port only overrides the given fields of the global one.
+`spool` writes the CloudEvents for a port to a file in `directory` while the port is unavailable (its circuit is open or it restarts), they count as acknowledged and are sent in order once the port is available again; if the spool holds `max_bytes`, `overflow` either drops the oldest CloudEvents (`drop_oldest`, the default), fails the new ones (`drop_newest`) or also pauses the input ports until the spool is drained (`block`), with `sync` every CloudEvent is flushed to the disk.
+`restart` defines how the Kernel restarts components that exited unexpectedly, `max_attempts` limits the restarts in a row (by default components are always restarted).
+`idempotency` defines how long and how many deliveries with the `ExactlyOnce` delivery guarantee the Kernel remembers, with a `path` they are also written to this file and survive a restart.
+`journal` enables the write-ahead journal in the given `directory`: the Kernel writes every CloudEvent that requires an acknowledgment to it and routes the ones that were not completed again after a restart, with `sync` every entry is flushed to the disk.
+`flow_control` defines when the Kernel pauses the input ports (`BrokerEvent::Pause`) until it recovered: if any outbox holds `outbox_depth.high` events or an input port has `pending_deliveries.high` unacknowledged CloudEvents, the port is resumed once the values fell to `low`.
+
 #### Example ComponentStartLinks
 
 ```no_run
@@ -47,10 +129,14 @@ use cerk::runtime::{InternalServerId, InternalServerFn, InternalServerFnRefStati
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::kernel::{StartOptions, KernelFn};
 
-fn dummy_scheduler(_: StartOptions, _: KernelFn) {}
+fn dummy_scheduler(_: StartOptions, _: KernelFn) -> anyhow::Result<()> {
+    Ok(())
+}
 
 fn dummy_router(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
+fn dummy_processor(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
+
 fn dummy_config_loader(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
 fn dummy_port(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
@@ -59,6 +145,7 @@ fn dummy_port_other(_: InternalServerId, _: BoxedReceiver, _: BoxedSender) {}
 
 const SCHEDULER: ScheduleFnRefStatic = &(dummy_scheduler as ScheduleFn);
 const ROUTER: InternalServerFnRefStatic = &(dummy_router as InternalServerFn);
+const PROCESSOR: InternalServerFnRefStatic = &(dummy_processor as InternalServerFn);
 const CONFIG_LOADER: InternalServerFnRefStatic = &(dummy_config_loader as InternalServerFn);
 const PORT: InternalServerFnRefStatic = &(dummy_port as InternalServerFn);
 
@@ -66,6 +153,7 @@ fn main() {
     let link = ComponentStartLinks {
             schedulers: fn_to_links![SCHEDULER],
             routers: fn_to_links![ROUTER],
+            processors: fn_to_links![PROCESSOR],
             config_loaders: fn_to_links![CONFIG_LOADER],
             ports: fn_to_links![PORT],
         };
@@ -4,6 +4,7 @@ use std::collections::HashMap;
 pub struct ComponentStartLinks<'a> {
     pub schedulers: HashMap<String, ScheduleFnRefStatic>,
     pub routers: HashMap<String, InternalServerFnRef<'a>>,
+    pub processors: HashMap<String, InternalServerFnRef<'a>>,
     pub config_loaders: HashMap<String, InternalServerFnRef<'a>>,
     pub ports: HashMap<String, InternalServerFnRef<'a>>,
 }
@@ -36,7 +37,9 @@ pub mod helpers {
 
         #[test]
         fn fn_to_link_test() {
-            fn dummy_scheduler(_: StartOptions, _: KernelFn) {}
+            fn dummy_scheduler(_: StartOptions, _: KernelFn) -> anyhow::Result<()> {
+                Ok(())
+            }
             const DUMMY: ScheduleFnRefStatic = &(dummy_scheduler as ScheduleFn);
             let schedulers: HashMap<String, ScheduleFnRefStatic> =
                 [("DUMMY".to_string(), DUMMY)].iter().cloned().collect();
@@ -47,7 +50,9 @@ pub mod helpers {
 
         #[test]
         fn fn_to_links_test() {
-            fn dummy_scheduler(_: StartOptions, _: KernelFn) {}
+            fn dummy_scheduler(_: StartOptions, _: KernelFn) -> anyhow::Result<()> {
+                Ok(())
+            }
             const DUMMY: ScheduleFnRefStatic = &(dummy_scheduler as ScheduleFn);
             let schedulers: HashMap<String, ScheduleFnRefStatic> =
                 [("DUMMY".to_string(), DUMMY)].iter().cloned().collect();
@@ -57,7 +62,9 @@ pub mod helpers {
 
         #[test]
         fn fn_to_links_multiple_test() {
-            fn dummy_scheduler(_: StartOptions, _: KernelFn) {}
+            fn dummy_scheduler(_: StartOptions, _: KernelFn) -> anyhow::Result<()> {
+                Ok(())
+            }
             const DUMMY: ScheduleFnRefStatic = &(dummy_scheduler as ScheduleFn);
             const DUMMY2: ScheduleFnRefStatic = &(dummy_scheduler as ScheduleFn);
             let schedulers: HashMap<String, ScheduleFnRefStatic> =
@@ -28,6 +28,10 @@ However, it does not support the binary content mode.
 
 <https://github.com/cloudevents/spec/blob/master/amqp-protocol-binding.md#2-use-of-cloudevents-attributes>
 
+## Batches
+
+The port supports `BrokerEvent::OutgoingCloudEventBatch` (`PortOptions.batch`), it publishes all CloudEvents of a batch before it waits for the confirmations of the broker.
+
 ## Examples
 
  * [Sequence to AMQP to Printer](https://github.com/ce-rust/cerk/tree/master/examples/examples/src/sequence_to_amqp_to_printer/)
@@ -8,6 +8,7 @@ use cerk::kernel::{
     BrokerEvent, CloudEventMessageRoutingId, CloudEventRoutingArgs, Config, ConfigHelpers,
     DeliveryGuarantee, HealthCheckRequest, HealthCheckResponse, HealthCheckStatus,
     IncomingCloudEvent, OutgoingCloudEvent, OutgoingCloudEventProcessed, ProcessingResult,
+    RoutingIdGenerator,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
@@ -16,7 +17,7 @@ use futures_lite::future;
 use futures_lite::stream::StreamExt;
 use lapin::message::Delivery;
 use lapin::{
-    options::*, publisher_confirm::Confirmation, types::FieldTable, BasicProperties, Channel,
+    options::*, publisher_confirm::PublisherConfirm, types::FieldTable, BasicProperties, Channel,
     Connection, ConnectionProperties, ExchangeKind,
 };
 use std::collections::HashMap;
@@ -149,10 +150,7 @@ fn setup_connection(
     config: Config,
     pending_deliveries: Arc<Mutex<HashMap<String, PendingDelivery>>>,
 ) -> Result<(Connection, AmqpOptions)> {
-    let mut config = match build_config(&id, &config) {
-        Ok(c) => c,
-        Err(e) => std::panic::panic_any(e),
-    };
+    let mut config = build_config(&id, &config)?;
 
     async_global_executor::block_on(async {
         let setup =
@@ -262,20 +260,48 @@ async fn setup_consume_channel(
         }
     }
 
+    start_consuming(
+        id,
+        sender_to_kernel,
+        pending_deliveries,
+        name,
+        &channel,
+        &channel_options.delivery_guarantee,
+    )
+    .await?;
+
+    Ok(channel)
+}
+
+/// starts a consumer on the queue `name` that forwards the messages to the Kernel
+async fn start_consuming(
+    id: &String,
+    sender_to_kernel: &BoxedSender,
+    pending_deliveries: &Arc<Mutex<HashMap<String, PendingDelivery>>>,
+    name: &String,
+    channel: &Channel,
+    delivery_guarantee: &DeliveryGuarantee,
+) -> Result<()> {
+    // with AtMostOnce the broker considers a message as acknowledged as soon as it was delivered, it is never requeued
+    let auto_ack = *delivery_guarantee == DeliveryGuarantee::AtMostOnce;
     let mut consumer = channel
         .basic_consume(
             name.as_str(),
-            format!("cerk-{}", id.clone()).as_str(),
-            BasicConsumeOptions::default(),
+            get_consumer_tag(id).as_str(),
+            BasicConsumeOptions {
+                no_ack: auto_ack,
+                ..BasicConsumeOptions::default()
+            },
             FieldTable::default(),
         )
         .await?;
 
     let cloned_sender = sender_to_kernel.clone_boxed();
     let cloned_id = id.clone();
-    let cloned_delivery_guarantee = channel_options.delivery_guarantee.clone();
+    let cloned_delivery_guarantee = delivery_guarantee.clone();
     let cloned_name = name.clone();
     let weak_clone = pending_deliveries.clone();
+    let routing_ids = RoutingIdGenerator::new();
     async_global_executor::spawn(async move {
         info!("will consume");
         while let Some(delivery_result) = consumer.next().await {
@@ -290,9 +316,10 @@ async fn setup_consume_channel(
                         &cloned_id,
                         weak_clone.clone(),
                         &cloned_delivery_guarantee,
+                        &routing_ids,
                     ) {
                         Ok(send_immediate_ack) => {
-                            if send_immediate_ack {
+                            if send_immediate_ack && !auto_ack {
                                 if let Err(e) = ack_message(channel, delivery.delivery_tag)
                                     .await
                                     .context("failed to ack message")
@@ -301,6 +328,12 @@ async fn setup_consume_channel(
                                 }
                             }
                         }
+                        Err(e) if auto_ack => {
+                            error!(
+                                "{} error while receive_message: {:?} -> message dropped",
+                                &cloned_id, e
+                            );
+                        }
                         Err(e) => {
                             error!(
                                 "{} error while receive_message: {:?} -> reject message",
@@ -320,7 +353,7 @@ async fn setup_consume_channel(
     })
     .detach();
 
-    Ok(channel)
+    Ok(())
 }
 
 async fn setup_dlx(
@@ -398,6 +431,7 @@ async fn setup_publish_channel(
 /// * If the consumption was successful it the bool indicates if an ack should be sent or it should wait for it.
 /// * If it failed an error is provided.
 ///
+#[allow(clippy::too_many_arguments)]
 fn receive_message(
     name: &String,
     channel: &Channel,
@@ -406,6 +440,7 @@ fn receive_message(
     id: &String,
     pending_deliveries: Arc<Mutex<HashMap<String, PendingDelivery>>>,
     delivery_guarantee: &DeliveryGuarantee,
+    routing_ids: &RoutingIdGenerator,
 ) -> Result<bool> {
     debug!("{} received CloudEvent on queue {}", id, channel.id());
     let payload_str = std::str::from_utf8(&delivery.data).unwrap();
@@ -413,7 +448,14 @@ fn receive_message(
     match serde_json::from_str::<Event>(&payload_str) {
         Ok(cloud_event) => {
             debug!("{} deserialized event successfully", id);
-            let routing_id = get_event_id(&cloud_event, &delivery.delivery_tag);
+            // the delivery tags start again with every channel, so they could collide with the routings of the journal
+            let routing_id = routing_ids.next_id();
+            debug!(
+                "{} routes event_id={} as {}",
+                id,
+                cloud_event.id(),
+                routing_id
+            );
             if delivery_guarantee.requires_acknowledgment() {
                 info!(
                     "pending_deliveries size: {}",
@@ -444,9 +486,10 @@ fn receive_message(
             sender.send(BrokerEvent::IncomingCloudEvent(IncomingCloudEvent {
                 incoming_id: id.clone(),
                 routing_id,
-                cloud_event,
+                cloud_event: Arc::new(cloud_event),
                 args: CloudEventRoutingArgs {
                     delivery_guarantee: delivery_guarantee.clone(),
+                    ..CloudEventRoutingArgs::default()
                 },
             }));
         }
@@ -458,38 +501,74 @@ fn receive_message(
     Ok(send_immediate_ack)
 }
 
-fn get_event_id(cloud_event: &Event, delivery_tag: &LongLongUInt) -> String {
-    format!("{}--{}", cloud_event.id(), delivery_tag)
+fn get_consumer_tag(id: &str) -> String {
+    format!("cerk-{}", id)
 }
 
-async fn send_cloud_event(cloud_event: &Event, configurations: &AmqpOptions) -> Result<()> {
+/// publishes the CloudEvent on all publish channels, the confirmations are awaited with `await_confirmations`
+async fn publish_to_channels(
+    cloud_event: &Event,
+    configurations: &AmqpOptions,
+) -> Result<Vec<(DeliveryGuarantee, PublisherConfirm)>> {
     let payload = serde_json::to_string(cloud_event).unwrap();
+    let mut confirmations = Vec::with_capacity(configurations.publish_channels.len());
     for (name, options) in configurations.publish_channels.iter() {
-        let result = match options.channel {
-            Some(ref channel) => {
-                let result = publish_cloud_event(&payload, &name, channel).await;
-                if let Ok(result) = result {
-                    if !options.delivery_guarantee.requires_acknowledgment() || result.is_ack() {
-                        Ok(())
-                    } else {
-                        bail!("Message was not acknowledged, but channel delivery_guarantee requires it: {:?}", result)
-                    }
-                } else {
-                    Err(anyhow!("message was not sent successful"))
-                }
-            }
-            None => Err(anyhow!("channel to exchange is closed")),
-        };
-        result?
+        let channel = options
+            .channel
+            .as_ref()
+            .ok_or(anyhow!("channel to exchange is closed"))?;
+        let confirmation = publish_cloud_event(&payload, name, channel)
+            .await
+            .context("message was not sent successful")?;
+        confirmations.push((options.delivery_guarantee, confirmation));
+    }
+    Ok(confirmations)
+}
+
+async fn await_confirmations(
+    confirmations: Vec<(DeliveryGuarantee, PublisherConfirm)>,
+) -> Result<()> {
+    for (delivery_guarantee, confirmation) in confirmations {
+        let result = confirmation
+            .await
+            .context("message was not sent successful")?;
+        if delivery_guarantee.requires_acknowledgment() && !result.is_ack() {
+            bail!(
+                "Message was not acknowledged, but channel delivery_guarantee requires it: {:?}",
+                result
+            )
+        }
     }
     Ok(())
 }
 
+/// Publishes all CloudEvents before it waits for the confirmations of the broker.
+/// The confirmations of CloudEvents that do not require an acknowledgment are not awaited.
+/// The results are in the same order as the CloudEvents.
+async fn send_cloud_events(
+    events: &[OutgoingCloudEvent],
+    configurations: &AmqpOptions,
+) -> Vec<Result<()>> {
+    let mut published = Vec::with_capacity(events.len());
+    for event in events {
+        published.push(publish_to_channels(event.cloud_event.as_ref(), configurations).await);
+    }
+    let mut results = Vec::with_capacity(published.len());
+    for (event, confirmations) in events.iter().zip(published) {
+        results.push(match confirmations {
+            Ok(_) if !event.args.delivery_guarantee.requires_acknowledgment() => Ok(()),
+            Ok(confirmations) => await_confirmations(confirmations).await,
+            Err(e) => Err(e),
+        });
+    }
+    results
+}
+
 async fn publish_cloud_event(
     payload: &String,
     name: &String,
     channel: &Channel,
-) -> Result<Confirmation> {
+) -> Result<PublisherConfirm> {
     let confirmation = channel
         .basic_publish(
             name.as_str(),
@@ -505,7 +584,6 @@ async fn publish_cloud_event(
                     "application/cloudevents+json; charset=UTF-8",
                 )),
         )
-        .await?
         .await?;
     Ok(confirmation)
 }
@@ -565,6 +643,126 @@ async fn ack_nack_pending_event(
     Ok(())
 }
 
+/// Sends the CloudEvents and returns the `OutgoingCloudEventProcessed` for the ones that require an acknowledgment.
+fn send_outgoing_cloud_events(
+    id: &InternalServerId,
+    events: Vec<OutgoingCloudEvent>,
+    configuration_option: &Option<AmqpOptions>,
+) -> Vec<BrokerEvent> {
+    let configuration = match configuration_option.as_ref() {
+        Some(configuration) => configuration,
+        None => {
+            error!(
+                "received CloudEvent before connection was  set up - message will not be delivered"
+            );
+            return vec![];
+        }
+    };
+    let results = future::block_on(send_cloud_events(&events, configuration));
+    events
+        .into_iter()
+        .zip(results)
+        .filter_map(|(event, result)| {
+            let result = match result {
+                Ok(_) => {
+                    info!("sent cloud event to queue");
+                    ProcessingResult::Successful
+                }
+                Err(e) => {
+                    error!("{} was not able to send CloudEvent {}", id, e);
+                    // todo transient or permanent?
+                    ProcessingResult::PermanentError
+                }
+            };
+            if !event.args.delivery_guarantee.requires_acknowledgment() {
+                return None;
+            }
+            Some(BrokerEvent::OutgoingCloudEventProcessed(
+                OutgoingCloudEventProcessed {
+                    sender_id: id.clone(),
+                    routing_id: event.routing_id,
+                    delivery_id: event.delivery_id,
+                    result,
+                },
+            ))
+        })
+        .collect()
+}
+
+/// stops consuming on all consume channels, the deliveries that are in progress are still completed
+async fn cancel_consumers(id: &InternalServerId, configuration_option: &Option<AmqpOptions>) {
+    if let Some(configuration) = configuration_option.as_ref() {
+        for (name, channel_options) in configuration.consume_channels.iter() {
+            if let Some(channel) = channel_options.channel.as_ref() {
+                if let Err(e) = channel
+                    .basic_cancel(get_consumer_tag(id).as_str(), BasicCancelOptions::default())
+                    .await
+                {
+                    warn!("{} failed to cancel consumer on {}: {:?}", id, name, e);
+                }
+            }
+        }
+    }
+}
+
+/// starts consuming again on all consume channels after `cancel_consumers`
+async fn resume_consumers(
+    id: &InternalServerId,
+    sender_to_kernel: &BoxedSender,
+    pending_deliveries: &Arc<Mutex<HashMap<String, PendingDelivery>>>,
+    configuration_option: &Option<AmqpOptions>,
+) {
+    if let Some(configuration) = configuration_option.as_ref() {
+        for (name, channel_options) in configuration.consume_channels.iter() {
+            if let Some(channel) = channel_options.channel.as_ref() {
+                if let Err(e) = start_consuming(
+                    id,
+                    sender_to_kernel,
+                    pending_deliveries,
+                    name,
+                    channel,
+                    &channel_options.delivery_guarantee,
+                )
+                .await
+                {
+                    error!("{} failed to resume consumer on {}: {:?}", id, name, e);
+                }
+            }
+        }
+    }
+}
+
+/// stops consuming, nacks (with requeue) all deliveries that were not completed and closes the connection
+async fn shutdown(
+    id: &InternalServerId,
+    connection_option: &Option<Connection>,
+    configuration_option: &Option<AmqpOptions>,
+    pending_deliveries: &mut HashMap<String, PendingDelivery>,
+) {
+    cancel_consumers(id, configuration_option).await;
+
+    let event_ids: Vec<String> = pending_deliveries.keys().cloned().collect();
+    for event_id in event_ids {
+        if let Err(e) = ack_nack_pending_event(
+            configuration_option,
+            pending_deliveries,
+            &event_id,
+            ProcessingResult::TransientError,
+        )
+        .await
+        {
+            warn!("{} failed to nack event_id={}: {:?}", id, event_id, e);
+        }
+        pending_deliveries.remove_entry(&event_id);
+    }
+
+    if let Some(connection) = connection_option.as_ref() {
+        if let Err(e) = connection.close(200, "shutdown").await {
+            warn!("{} failed to close connection: {:?}", id, e);
+        }
+    }
+}
+
 fn check_health(
     event: HealthCheckRequest,
     send_to_kernel: &BoxedSender,
@@ -587,10 +785,34 @@ fn check_health(
     }))
 }
 
+/// Replaces the connection and the configuration with the ones for the new config.
+/// The previous ones are kept if the config is rejected.
+fn update_connection(
+    id: &InternalServerId,
+    sender_to_kernel: &BoxedSender,
+    connection_option: &mut Option<Connection>,
+    configuration_option: &mut Option<AmqpOptions>,
+    config: Config,
+    pending_deliveries: Arc<Mutex<HashMap<String, PendingDelivery>>>,
+) -> Result<()> {
+    let (connection, configuration) = setup_connection(
+        id.clone(),
+        sender_to_kernel.clone_boxed(),
+        connection_option,
+        config,
+        pending_deliveries,
+    )?;
+    *connection_option = Some(connection);
+    *configuration_option = Some(configuration);
+    Ok(())
+}
+
 /// This is the main function to start the port.
 pub fn port_amqp_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_kernel: BoxedSender) {
     let mut connection_option: Option<Connection> = None;
     let mut configuration_option: Option<AmqpOptions> = None;
+    // the Kernel sent `BrokerEvent::Pause`
+    let mut paused = false;
     let pending_deliveries: PendingDeliveries = HashMap::new();
     let arc_pending_deliveries: Arc<Mutex<HashMap<String, PendingDelivery>>> =
         Arc::new(Mutex::new(pending_deliveries));
@@ -604,57 +826,38 @@ pub fn port_amqp_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_ker
             }
             BrokerEvent::ConfigUpdated(config, _) => {
                 info!("{} received ConfigUpdated", &id);
-                let result = setup_connection(
-                    id.clone(),
-                    sender_to_kernel.clone_boxed(),
-                    &connection_option,
+                let result = update_connection(
+                    &id,
+                    &sender_to_kernel,
+                    &mut connection_option,
+                    &mut configuration_option,
                     config,
                     arc_pending_deliveries.clone(),
                 );
                 match result {
-                    Ok(as_ok) => {
-                        connection_option = Some(as_ok.0);
-                        configuration_option = Some(as_ok.1);
-                    }
-                    Err(e) => {
-                        warn!("{} was not able to establish a connection: {:?}", &id, e);
-                        connection_option = None;
-                        configuration_option = None;
+                    Ok(()) if paused => {
+                        future::block_on(cancel_consumers(&id, &configuration_option))
                     }
+                    Ok(()) => {}
+                    Err(ref e) => warn!(
+                        "{} was not able to establish a connection, keeps the previous one: {:?}",
+                        &id, e
+                    ),
                 }
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
             }
             BrokerEvent::OutgoingCloudEvent(event) => {
-                let OutgoingCloudEvent {
-                    routing_id,
-                    cloud_event,
-                    destination_id: _,
-                    args,
-                } = event;
                 debug!("{} CloudEvent received", &id);
-                if let Some(configuration) = configuration_option.as_ref() {
-                    let result = future::block_on(send_cloud_event(&cloud_event, configuration));
-                    let result = match result {
-                        Ok(_) => {
-                            info!("sent cloud event to queue");
-                            ProcessingResult::Successful
-                        }
-                        Err(e) => {
-                            error!("{} was not able to send CloudEvent {}", &id, e);
-                            // todo transient or permanent?
-                            ProcessingResult::PermanentError
-                        }
-                    };
-                    if args.delivery_guarantee.requires_acknowledgment() {
-                        sender_to_kernel.send(BrokerEvent::OutgoingCloudEventProcessed(
-                            OutgoingCloudEventProcessed {
-                                sender_id: id.clone(),
-                                routing_id,
-                                result,
-                            },
-                        ));
-                    }
-                } else {
-                    error!("received CloudEvent before connection was  set up - message will not be delivered")
+                for processed in send_outgoing_cloud_events(&id, vec![event], &configuration_option)
+                {
+                    sender_to_kernel.send(processed);
+                }
+            }
+            BrokerEvent::OutgoingCloudEventBatch(events) => {
+                debug!("{} batch of {} CloudEvents received", &id, events.len());
+                let processed = send_outgoing_cloud_events(&id, events, &configuration_option);
+                if !processed.is_empty() {
+                    sender_to_kernel.send(BrokerEvent::Batch(processed));
                 }
             }
             BrokerEvent::IncomingCloudEventProcessed(event_id, result) => {
@@ -674,6 +877,34 @@ pub fn port_amqp_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_ker
             BrokerEvent::HealthCheckRequest(event) => {
                 check_health(event, &sender_to_kernel, &connection_option)
             }
+            BrokerEvent::Pause(_) if !paused => {
+                info!("{} paused, stop consuming", id);
+                paused = true;
+                future::block_on(cancel_consumers(&id, &configuration_option));
+            }
+            BrokerEvent::Resume(_) if paused => {
+                info!("{} resumed, start consuming", id);
+                paused = false;
+                future::block_on(resume_consumers(
+                    &id,
+                    &sender_to_kernel,
+                    &arc_pending_deliveries,
+                    &configuration_option,
+                ));
+            }
+            BrokerEvent::Pause(_) | BrokerEvent::Resume(_) => {}
+            BrokerEvent::Shutdown => {
+                let mut pending_deliveries = arc_pending_deliveries.lock().unwrap();
+                future::block_on(shutdown(
+                    &id,
+                    &connection_option,
+                    &configuration_option,
+                    &mut pending_deliveries,
+                ));
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -685,6 +916,7 @@ pub static PORT_AMQP: InternalServerFnRefStatic = &(port_amqp_start as InternalS
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cerk::runtime::channel::{RecordingSender, Sender};
 
     #[test]
     fn minimal_config() -> Result<()> {
@@ -697,4 +929,70 @@ mod tests {
         assert_eq!(config.uri, uri);
         Ok(())
     }
+
+    fn build_event(
+        id: &InternalServerId,
+        delivery_guarantee: DeliveryGuarantee,
+    ) -> OutgoingCloudEvent {
+        OutgoingCloudEvent {
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            destination_id: id.clone(),
+            args: CloudEventRoutingArgs {
+                delivery_guarantee,
+                ..CloudEventRoutingArgs::default()
+            },
+            delivery_id: 0,
+        }
+    }
+
+    #[test]
+    fn rejected_config_should_keep_the_previous_configuration() -> Result<()> {
+        let id = "amqp-output".to_string();
+        let map = [(
+            "uri".to_string(),
+            Config::String("amqp://127.0.0.1:5672/%2f".to_string()),
+        )];
+        let mut connection_option = None;
+        let mut configuration_option = Some(build_config(
+            &id,
+            &Config::HashMap(map.iter().cloned().collect()),
+        )?);
+
+        let result = update_connection(
+            &id,
+            &RecordingSender::default().clone_boxed(),
+            &mut connection_option,
+            &mut configuration_option,
+            Config::String("invalid".to_string()),
+            Arc::new(Mutex::new(HashMap::new())),
+        );
+        assert!(result.is_err());
+
+        let event = build_event(&id, DeliveryGuarantee::AtLeastOnce);
+        match send_outgoing_cloud_events(&id, vec![event], &configuration_option).as_slice() {
+            [BrokerEvent::OutgoingCloudEventProcessed(processed)] => {
+                assert_eq!(processed.result, ProcessingResult::Successful)
+            }
+            _ => panic!("expected one OutgoingCloudEventProcessed"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn at_most_once_copies_should_not_be_answered() -> Result<()> {
+        let id = "amqp-output".to_string();
+        let map = [(
+            "uri".to_string(),
+            Config::String("amqp://127.0.0.1:5672/%2f".to_string()),
+        )];
+        let configuration_option = Some(build_config(
+            &id,
+            &Config::HashMap(map.iter().cloned().collect()),
+        )?);
+
+        let events = vec![build_event(&id, DeliveryGuarantee::AtMostOnce)];
+        assert!(send_outgoing_cloud_events(&id, events, &configuration_option).is_empty());
+        Ok(())
+    }
 }
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use cerk::kernel::{
-    BrokerEvent, OutgoingCloudEvent, OutgoingCloudEventProcessed, ProcessingResult,
+    BrokerEvent, HealthCheckStatus, OutgoingCloudEvent, OutgoingCloudEventProcessed,
+    ProcessingResult,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
@@ -23,12 +24,23 @@ pub fn port_printer_start(
     loop {
         match inbox.receive() {
             BrokerEvent::Init => info!("{} initiated", id),
-            BrokerEvent::ConfigUpdated(_, _) => info!("{} received ConfigUpdated", id),
+            BrokerEvent::ConfigUpdated(_, _) => {
+                info!("{} received ConfigUpdated", id);
+                sender_to_kernel.send(BrokerEvent::ConfigApplied(id.clone()));
+            }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
             BrokerEvent::OutgoingCloudEvent(event) => {
                 if let Err(e) = print_event(&id, &sender_to_kernel, &event) {
                     error!("{} was not able to print event {:?}", id, e)
                 }
             }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -42,13 +54,14 @@ fn print_event(
     info!(
         "{} received cloud event: {}!",
         id,
-        serde_json::to_string(&event.cloud_event)?,
+        serde_json::to_string(event.cloud_event.as_ref())?,
     );
     if event.args.delivery_guarantee.requires_acknowledgment() {
         sender_to_kernel.send(BrokerEvent::OutgoingCloudEventProcessed(
             OutgoingCloudEventProcessed {
                 result: ProcessingResult::Successful,
                 routing_id: event.routing_id.to_string(),
+                delivery_id: event.delivery_id,
                 sender_id: id.clone(),
             },
         ))
@@ -68,19 +81,32 @@ mod tests {
         BrokerEvent, CloudEventRoutingArgs, DeliveryGuarantee, OutgoingCloudEvent,
         OutgoingCloudEventProcessed, ProcessingResult,
     };
+    use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
     use cerk_runtime_threading::channel::new_channel_with_size;
     use cloudevents::Event;
+    use std::sync::Arc;
     use std::thread;
+    use std::thread::JoinHandle;
     use std::time::Duration;
 
     const ID: &'static str = "port-id";
 
+    fn shutdown(send_to_port: BoxedSender, recv_from_port: BoxedReceiver, port: JoinHandle<()>) {
+        send_to_port.send(BrokerEvent::Shutdown);
+        match recv_from_port.receive_timeout(Duration::from_millis(100)) {
+            Some(BrokerEvent::ShutdownComplete(id)) => assert_eq!(id, ID),
+            _ => panic!("port did not complete the shutdown"),
+        }
+        assert!(port.join().is_ok());
+    }
+
     fn build_event() -> OutgoingCloudEvent {
         OutgoingCloudEvent {
             routing_id: String::from("abc"),
-            cloud_event: Event::default(),
+            cloud_event: Arc::new(Event::default()),
             destination_id: ID.to_string(),
             args: CloudEventRoutingArgs::default(),
+            delivery_id: 7,
         }
     }
 
@@ -91,30 +117,25 @@ mod tests {
     }
 
     /// We send a CloudEvent to the port with `DeliveryGuarantee::default()`, it does not need to be acked.
-    ///
-    /// `thread '<unnamed>' panicked at 'called Result::unwrap() on an Err value: RecvError'`
-    /// this is okay -> we don't do a real shutdown of the port but just kill the communication channel
     #[test]
     fn print_unack_message() {
         let (send_to_port, recv) = new_channel_with_size(1);
         let (send, recv_from_port) = new_channel_with_size(1);
-        thread::spawn(move || {
+        let port = thread::spawn(move || {
             PORT_PRINTER(ID.to_string(), recv, send);
         });
         send_to_port.send(BrokerEvent::OutgoingCloudEvent(build_event()));
         let response = recv_from_port.receive_timeout(Duration::from_millis(10));
         assert!(response.is_none());
+        shutdown(send_to_port, recv_from_port, port);
     }
 
     /// We send a CloudEvent to the port with `DeliveryGuarantee::AtLeastOnce`, it needs to be acked.
-    ///
-    /// `thread '<unnamed>' panicked at 'called Result::unwrap() on an Err value: RecvError'`
-    /// this is okay -> we don't do a real shutdown of the port but just kill the communication channel
     #[test]
     fn print_ack_message() {
         let (send_to_port, recv) = new_channel_with_size(1);
         let (send, recv_from_port) = new_channel_with_size(1);
-        thread::spawn(move || {
+        let port = thread::spawn(move || {
             PORT_PRINTER(ID.to_string(), recv, send);
         });
         let mut event = build_event();
@@ -128,6 +149,7 @@ mod tests {
                 e,
                 OutgoingCloudEventProcessed {
                     routing_id: event.routing_id.to_string(),
+                    delivery_id: event.delivery_id,
                     sender_id: ID.to_string(),
                     result: ProcessingResult::Successful
                 }
@@ -135,5 +157,6 @@ mod tests {
         } else {
             assert!(false, "response has wrong type");
         }
+        shutdown(send_to_port, recv_from_port, port);
     }
 }
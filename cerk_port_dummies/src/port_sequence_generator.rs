@@ -1,7 +1,7 @@
 use anyhow::{Context, Result};
 use cerk::kernel::{
-    BrokerEvent, CloudEventRoutingArgs, Config, ConfigHelpers, DeliveryGuarantee,
-    IncomingCloudEvent, ProcessingResult,
+    BrokerEvent, CloudEventMessageRoutingId, CloudEventRoutingArgs, Config, ConfigHelpers,
+    DeliveryGuarantee, HealthCheckStatus, IncomingCloudEvent, ProcessingResult, RoutingIdGenerator,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
@@ -26,7 +26,13 @@ const DEFAULT_UNACK_MAX_COUNT: usize = 30;
 
 struct SequenceGeneratorData {
     config: Option<SequenceGeneratorConfig>,
-    missing_deliveries: Vec<String>,
+    /// routing ids and sequence numbers of the events that were not acknowledged yet
+    missing_deliveries: Vec<(CloudEventMessageRoutingId, u32)>,
+    /// the sequence numbers start again after a restart, the routing ids must not
+    routing_ids: RoutingIdGenerator,
+    shutdown: bool,
+    /// the Kernel sent `BrokerEvent::Pause`
+    paused: bool,
 }
 
 struct SequenceGeneratorConfig {
@@ -36,17 +42,6 @@ struct SequenceGeneratorConfig {
     unack_max_count: usize,
 }
 
-impl Default for SequenceGeneratorConfig {
-    fn default() -> Self {
-        SequenceGeneratorConfig {
-            sleep_between_messages: Duration::from_secs(1),
-            amount: None,
-            delivery_guarantee: DeliveryGuarantee::BestEffort,
-            unack_max_count: DEFAULT_UNACK_MAX_COUNT,
-        }
-    }
-}
-
 macro_rules! get_config {
     ($data:expr, $field:tt) => {
         $data
@@ -92,7 +87,9 @@ fn build_config(_id: &InternalServerId, config: &Config) -> Result<SequenceGener
 }
 
 fn get_delivery_guarantee(config: &Config) -> Result<DeliveryGuarantee> {
-    if let Some(c) = config.get_op_val_config("delivery_guarantee")? {
+    if let Config::Null = config {
+        Ok(DeliveryGuarantee::default())
+    } else if let Some(c) = config.get_op_val_config("delivery_guarantee")? {
         DeliveryGuarantee::try_from(c)
     } else {
         Ok(DeliveryGuarantee::default())
@@ -129,12 +126,20 @@ fn send_event_and_track(
     let delivery_guarantee = get_config!(data, delivery_guarantee);
     let sleep_between_messages = get_config!(data, sleep_between_messages);
     wait_until_delivered(id, data, unack_max_count)?;
-    data.lock()
-        .as_mut()
-        .unwrap()
-        .missing_deliveries
-        .push(format!("{}", i));
-    send_event(id, sender_to_kernel, i, delivery_guarantee);
+    wait_until_resumed(id, data);
+    if data.lock().unwrap().shutdown {
+        bail!("{} is shutting down, stop generating events", id);
+    }
+    let routing_id = data.lock().unwrap().routing_ids.next_id();
+    // without acknowledgments the event is never resent, so there is nothing to track
+    if delivery_guarantee.requires_acknowledgment() {
+        data.lock()
+            .as_mut()
+            .unwrap()
+            .missing_deliveries
+            .push((routing_id.clone(), i));
+    }
+    send_event(id, sender_to_kernel, routing_id, i, delivery_guarantee);
     thread::sleep(sleep_between_messages.clone());
     Ok(())
 }
@@ -146,6 +151,7 @@ fn wait_until_delivered(
 ) -> Result<()> {
     let delivery_guarantee = get_config!(data, delivery_guarantee);
     while delivery_guarantee.requires_acknowledgment()
+        && !data.lock().unwrap().shutdown
         && data.lock().unwrap().missing_deliveries.len() >= unack_max_count
     {
         warn!("{} received unack_max_count - wait with resending", id);
@@ -154,19 +160,32 @@ fn wait_until_delivered(
     Ok(())
 }
 
+fn wait_until_resumed(id: &String, data: &ArcSequenceGenData) {
+    if data.lock().unwrap().paused {
+        info!("{} is paused - wait with generating events", id);
+    }
+    while data.lock().unwrap().paused && !data.lock().unwrap().shutdown {
+        thread::sleep(Duration::from_millis(10));
+    }
+}
+
 fn send_event(
     id: &String,
     sender_to_kernel: &BoxedSender,
+    routing_id: CloudEventMessageRoutingId,
     i: u32,
     delivery_guarantee: DeliveryGuarantee,
 ) {
     debug!("send dummy event with sequence number {} to kernel", i);
 
     sender_to_kernel.send(BrokerEvent::IncomingCloudEvent(IncomingCloudEvent {
-        routing_id: i.clone().to_string(),
+        routing_id,
         incoming_id: id.clone(),
-        cloud_event: generate_sequence_event(i),
-        args: CloudEventRoutingArgs { delivery_guarantee },
+        cloud_event: Arc::new(generate_sequence_event(i)),
+        args: CloudEventRoutingArgs {
+            delivery_guarantee,
+            ..CloudEventRoutingArgs::default()
+        },
     }));
 }
 
@@ -189,6 +208,15 @@ pub fn generate_sequence_event(i: u32) -> Event {
 /// * `GENERATOR_SLEEP_MS` define the sleep time between 2 events
 /// * `GENERATOR_AMOUNT` define the total amount of events that should be generated
 ///
+/// # Config Options
+///
+/// * `delivery_guarantee` the `DeliveryGuarantee` of the generated events as `Config::U8` (default: `BestEffort`),
+///   events that were not acknowledged successfully are resent if it requires acknowledgments
+///
+/// `Config::Null` uses the defaults, an invalid config is rejected with `BrokerEvent::ConfigRejected`.
+///
+/// The generation stops between `BrokerEvent::Pause` and `BrokerEvent::Resume`.
+///
 /// # Examples
 ///
 /// * [Hello World](https://github.com/ce-rust/cerk/tree/master/examples/examples/src/hello_world)
@@ -205,22 +233,25 @@ pub fn port_sequence_generator_start(
     let data = SequenceGeneratorData {
         config: None,
         missing_deliveries: vec![],
+        routing_ids: RoutingIdGenerator::new(),
+        shutdown: false,
+        paused: false,
     };
     let data: ArcSequenceGenData = Arc::new(Mutex::new(data));
     loop {
         match inbox.receive() {
             BrokerEvent::Init => (),
             BrokerEvent::ConfigUpdated(config, _) => {
-                data.lock().as_mut().unwrap().config = Some(match build_config(&id, &config) {
+                let settings = match build_config(&id, &config) {
+                    Ok(settings) => settings,
                     Err(e) => {
-                        info!(
-                            "was not able to read config -> will fallback to default; read error: {:?}",
-                            e
-                        );
-                        SequenceGeneratorConfig::default()
+                        error!("{} was not able to read config: {:?}", id, e);
+                        sender_to_kernel.send(BrokerEvent::config_result(id.clone(), Err(e)));
+                        continue;
                     }
-                    Ok(settings) => settings,
-                });
+                };
+                data.lock().as_mut().unwrap().config = Some(settings);
+                sender_to_kernel.send(BrokerEvent::ConfigApplied(id.clone()));
                 info!("{} start generating events", &id);
                 let data = data.clone();
                 let id = id.clone();
@@ -242,6 +273,17 @@ pub fn port_sequence_generator_start(
                     error!("failed to process IncomingCloudEventProcessed: {:?}", e);
                 }
             }
+            BrokerEvent::Pause(_) => data.lock().as_mut().unwrap().paused = true,
+            BrokerEvent::Resume(_) => data.lock().as_mut().unwrap().paused = false,
+            BrokerEvent::Shutdown => {
+                data.lock().as_mut().unwrap().shutdown = true;
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -261,7 +303,7 @@ fn process_incoming_event_result(
             .unwrap()
             .missing_deliveries
             .iter()
-            .position(|e| *e == routing_id);
+            .position(|(e, _)| *e == routing_id);
         if let Some(idx) = idx {
             match result {
                 ProcessingResult::Successful => {
@@ -270,15 +312,15 @@ fn process_incoming_event_result(
                 ProcessingResult::PermanentError
                 | ProcessingResult::TransientError
                 | ProcessingResult::Timeout => {
-                    // just resend it with a delay
+                    // just resend it with a delay, as a new routing
                     thread::sleep(Duration::from_millis(10));
-                    // the routing id is just the sequence id
-                    send_event(
-                        &id,
-                        &sender_to_kernel,
-                        routing_id.parse().unwrap(),
-                        delivery_guarantee,
-                    );
+                    let (routing_id, i) = {
+                        let mut data = data.lock().unwrap();
+                        let routing_id = data.routing_ids.next_id();
+                        data.missing_deliveries[idx].0 = routing_id.clone();
+                        (routing_id, data.missing_deliveries[idx].1)
+                    };
+                    send_event(id, sender_to_kernel, routing_id, i, delivery_guarantee);
                 }
             }
         }
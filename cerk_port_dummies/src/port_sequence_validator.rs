@@ -1,6 +1,6 @@
 use crate::port_sequence_generator::CLOUD_EVENT_TYPE;
 use anyhow::{Context, Result};
-use cerk::kernel::{BrokerEvent, OutgoingCloudEventProcessed, ProcessingResult};
+use cerk::kernel::{BrokerEvent, HealthCheckStatus, OutgoingCloudEventProcessed, ProcessingResult};
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
 use chrono::Utc;
@@ -92,13 +92,20 @@ pub fn port_sequence_validator_start(
                 match configure(data) {
                     Ok(new_data) => {
                         data = Some(new_data);
+                        sender_to_kernel.send(BrokerEvent::ConfigApplied(id.clone()));
                     }
                     Err(e) => {
                         data = None;
-                        error!("{} failed to load config {:?}", &id, e)
+                        error!("{} failed to load config {:?}", &id, e);
+                        sender_to_kernel.send(BrokerEvent::config_result(id.clone(), Err(e)));
                     }
                 }
             }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
             BrokerEvent::OutgoingCloudEvent(event) => {
                 let result = match process_event(&mut data, &event.cloud_event) {
                     Err(e) => {
@@ -111,12 +118,16 @@ pub fn port_sequence_validator_start(
                     sender_to_kernel.send(BrokerEvent::OutgoingCloudEventProcessed(
                         OutgoingCloudEventProcessed {
                             routing_id: event.routing_id,
+                            delivery_id: event.delivery_id,
                             result,
                             sender_id: id.clone(),
                         },
                     ))
                 }
             }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -21,6 +21,26 @@ This component adds health check functionality to CERK via http.
 
 It is registered as a port, but neither sends nor receives CloudEvents.
 
+# Routes
+
+* `metrics_path` (default `/metrics`): the metrics of the Kernel and the ports in the [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/),
+  including the gauge `cerk_port_healthy` with the health of the `ports_to_check`.
+* `/livez`: `200` as long as the Kernel loop answers within the `timeout`, `503` otherwise.
+* `/readyz`: the state of the `ports_to_check` as JSON, or of all scheduled components if none are configured.
+  The status code is `503` unless every component received its config, reached `Init` and is healthy.
+  Components that do not check their health are reported as `Unsupported` and count as healthy.
+* every other path: the health of the `ports_to_check` as JSON, the status code is `503` if one port is not healthy.
+
+# Configurations
+
+All fields are optional.
+
+* `ip_addr`: `Config::String` with the IP address the server binds to, default: `0.0.0.0`
+* `http_port`: `Config::U32` with the port the server binds to, default: `3000`
+* `ports_to_check`: `Config::Vec` of `Config::String` with the ids of the ports to check, default: none
+* `timeout`: `Config::U32` with the time in milliseconds to wait for the responses of the ports and the Kernel, default: `10`
+* `metrics_path`: `Config::String` with the path of the metrics route, default: `/metrics`
+
 ## Examples
 
 * [AMQP to Printer](https://github.com/ce-rust/cerk/tree/master/examples/examples/src/amqp_to_printer/)
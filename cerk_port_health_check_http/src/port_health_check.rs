@@ -1,15 +1,18 @@
 use anyhow::Result;
 use cerk::kernel::{
-    BrokerEvent, Config, ConfigHelpers, HealthCheckRequest, HealthCheckResponse, HealthCheckStatus,
+    BrokerEvent, ComponentInfo, ComponentsRequest, ComponentsResponse, Config, ConfigHelpers,
+    HealthCheckRequest, HealthCheckResponse, HealthCheckStatus, Metric, MetricLabels, MetricValue,
+    MetricsRequest, MetricsResponse, KERNEL_ID, METRIC_COMPONENT_LABEL,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
 use hyper::service::{make_service_fn, service_fn};
-use hyper::{Body, Error, Response, Server, StatusCode};
+use hyper::{Body, Error, Request, Response, Server, StatusCode};
 use serde::Serialize;
 use std::borrow::BorrowMut;
-use std::collections::HashMap;
+use std::collections::{BTreeMap, HashMap};
 use std::convert::TryFrom;
+use std::fmt::Write;
 use std::net::{IpAddr, SocketAddr};
 use std::sync::{Arc, Mutex};
 use std::time::Duration;
@@ -19,16 +22,26 @@ use tokio::time::timeout;
 use uuid::Uuid;
 
 type ArcHealthCheckData = Arc<Mutex<HealthCheckData>>;
+type HealthResponses = HashMap<InternalServerId, Option<HealthCheckStatus>>;
+
+const DEFAULT_METRICS_PATH: &str = "/metrics";
+const LIVENESS_PATH: &str = "/livez";
+const READINESS_PATH: &str = "/readyz";
+const DEFAULT_TIMEOUT_MS: u32 = 10;
+/// gauge with the health of the `ports_to_check`, `1` if the port is healthy or does not check its health; label: `component`
+const METRIC_PORT_HEALTHY: &str = "cerk_port_healthy";
+const PROMETHEUS_CONTENT_TYPE: &str = "text/plain; version=0.0.4";
 
 struct PendingRequest {
     receiver: Option<Sender<()>>,
-    responses: HashMap<InternalServerId, Option<HealthCheckStatus>>,
+    responses: HealthResponses,
 }
 
 struct HealthCheckConfig {
     address: SocketAddr,
     ports_to_check: Vec<InternalServerId>,
     timeout: Duration,
+    metrics_path: String,
 }
 
 struct HealthCheckData {
@@ -38,12 +51,42 @@ struct HealthCheckData {
     sender_to_kernel: BoxedSender,
     id: InternalServerId,
     pending_requests: HashMap<String, PendingRequest>,
+    pending_metrics_requests: HashMap<String, Sender<Vec<Metric>>>,
+    pending_components_requests: HashMap<String, Sender<Vec<ComponentInfo>>>,
 }
 
 #[derive(Serialize, Debug, PartialEq)]
 struct HealthHttpResponse {
     message: String,
-    requests: HashMap<InternalServerId, Option<HealthCheckStatus>>,
+    requests: HealthResponses,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct ComponentReadiness {
+    configured: bool,
+    initialized: bool,
+    status: Option<HealthCheckStatus>,
+}
+
+#[derive(Serialize, Debug, PartialEq)]
+struct ReadinessHttpResponse {
+    message: String,
+    components: BTreeMap<InternalServerId, ComponentReadiness>,
+}
+
+enum Route {
+    Health,
+    Liveness,
+    Readiness,
+    Metrics,
+}
+
+/// a component that does not check its health is not considered unhealthy
+fn is_ok(status: &Option<HealthCheckStatus>) -> bool {
+    matches!(
+        status,
+        Some(HealthCheckStatus::Healthy) | Some(HealthCheckStatus::Unsupported)
+    )
 }
 
 fn build_config(id: &InternalServerId, config: Config) -> Result<HealthCheckConfig> {
@@ -68,8 +111,18 @@ fn build_config(id: &InternalServerId, config: Config) -> Result<HealthCheckConf
             .iter()
             .filter_map(|c| c.as_ref().ok().map(|v| v.to_string()))
             .collect(),
-        timeout: Duration::from_millis(config.get_op_val_u8("timeout")?.unwrap_or(10 as u8) as u64),
+        timeout: Duration::from_millis(
+            config
+                .get_op_val_u32("timeout")?
+                .unwrap_or(DEFAULT_TIMEOUT_MS) as u64,
+        ),
+        metrics_path: config
+            .get_op_val_string("metrics_path")?
+            .unwrap_or(DEFAULT_METRICS_PATH.to_string()),
     };
+    if !port_config.metrics_path.starts_with('/') {
+        bail!("{} metrics_path has to start with /", id)
+    }
     Ok(port_config)
 }
 
@@ -111,12 +164,35 @@ fn start_server(data: ArcHealthCheckData) -> Result<()> {
         .address
         .clone();
 
+    let metrics_path = data
+        .lock()
+        .unwrap()
+        .config
+        .as_ref()
+        .unwrap()
+        .metrics_path
+        .clone();
+
     let make_svc = make_service_fn(move |_| {
         let data = data.clone();
+        let metrics_path = metrics_path.clone();
         async move {
-            Ok::<_, Error>(service_fn(move |_req| {
+            Ok::<_, Error>(service_fn(move |req: Request<Body>| {
                 let data = data.clone();
-                async move { handle_health_request(data).await }
+                let route = match req.uri().path() {
+                    path if path == metrics_path => Route::Metrics,
+                    LIVENESS_PATH => Route::Liveness,
+                    READINESS_PATH => Route::Readiness,
+                    _ => Route::Health,
+                };
+                async move {
+                    match route {
+                        Route::Metrics => handle_metrics_request(data).await,
+                        Route::Liveness => handle_liveness_request(data).await,
+                        Route::Readiness => handle_readiness_request(data).await,
+                        Route::Health => handle_health_request(data).await,
+                    }
+                }
             }))
         }
     });
@@ -134,7 +210,12 @@ fn start_server(data: ArcHealthCheckData) -> Result<()> {
     Ok(())
 }
 
-async fn handle_health_request(data: Arc<Mutex<HealthCheckData>>) -> Result<Response<Body>, Error> {
+/// Sends a `HealthCheckRequest` to all `ports` and waits for their responses until the timeout.
+/// Returns `false` if not all ports responded in time, their status is `None` in that case.
+async fn request_health_status(
+    data: ArcHealthCheckData,
+    ports: Vec<InternalServerId>,
+) -> (bool, HealthResponses) {
     let uuid = Uuid::new_v4();
     let sender_id = data.clone().lock().unwrap().id.clone();
     let sender = data.lock().unwrap().sender_to_kernel.clone_boxed();
@@ -146,75 +227,369 @@ async fn handle_health_request(data: Arc<Mutex<HealthCheckData>>) -> Result<Resp
         .unwrap()
         .timeout
         .clone();
-    let ports = data
-        .clone()
+    if ports.is_empty() {
+        return (true, HashMap::new());
+    }
+
+    let (tx, rx) = tokio::sync::oneshot::channel::<()>();
+    let pending_request = PendingRequest {
+        receiver: Some(tx),
+        responses: ports.iter().map(|id| (id.clone(), None)).collect(),
+    };
+    data.lock()
+        .unwrap()
+        .pending_requests
+        .insert(uuid.to_string(), pending_request);
+
+    for port in ports {
+        sender.send(BrokerEvent::HealthCheckRequest(HealthCheckRequest {
+            sender_id: sender_id.clone(),
+            destination_id: port.clone(),
+            id: uuid.to_string(),
+        }));
+    }
+
+    let result = timeout(timeout_duration, rx).await;
+    if let Err(e) = &result {
+        warn!(
+            "did not receive a value in {}ms: {:?}",
+            timeout_duration.as_millis(),
+            e
+        );
+    }
+    let responses = data
         .lock()
+        .unwrap()
+        .pending_requests
+        .remove(uuid.to_string().as_str())
+        .unwrap();
+    (result.is_ok(), responses.responses)
+}
+
+fn ports_to_check(data: &ArcHealthCheckData) -> Vec<InternalServerId> {
+    data.lock()
         .unwrap()
         .config
         .as_ref()
         .unwrap()
         .ports_to_check
-        .clone();
+        .clone()
+}
 
-    if ports.len() > 0 {
-        let (tx, rx) = tokio::sync::oneshot::channel::<()>();
-        let pending_request = PendingRequest {
-            receiver: Some(tx),
-            responses: ports.iter().map(|id| (id.clone(), None)).collect(),
-        };
-        data.lock()
+async fn handle_health_request(data: Arc<Mutex<HealthCheckData>>) -> Result<Response<Body>, Error> {
+    let ports = ports_to_check(&data);
+    if ports.is_empty() {
+        return Ok::<_, Error>(Response::new(Body::from("OK")));
+    }
+
+    let (all_responded, responses) = request_health_status(data, ports).await;
+    let mut status_code = StatusCode::SERVICE_UNAVAILABLE;
+    let body = HealthHttpResponse {
+        message: if !all_responded {
+            "Timeout, ports did not respond; current result: {}".to_string()
+        } else if responses.iter().any(|(_, status)| !is_ok(status)) {
+            "not all responses were successful".to_string()
+        } else {
+            status_code = StatusCode::OK;
+            "successful".to_string()
+        },
+        requests: responses,
+    };
+
+    let body = serde_json::to_vec(&body).unwrap();
+
+    let response = Response::builder()
+        .status(status_code)
+        .body(Body::from(body))
+        .unwrap();
+    Ok::<_, Error>(response)
+}
+
+/// The router is alive as long as the Kernel answers a `HealthCheckRequest` in time.
+async fn handle_liveness_request(data: ArcHealthCheckData) -> Result<Response<Body>, Error> {
+    let (_, responses) = request_health_status(data, vec![KERNEL_ID.to_string()]).await;
+    let response = if responses.get(KERNEL_ID) == Some(&Some(HealthCheckStatus::Healthy)) {
+        Response::new(Body::from("OK"))
+    } else {
+        Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from("Timeout, kernel did not respond"))
             .unwrap()
-            .pending_requests
-            .insert(uuid.to_string(), pending_request);
-
-        for port in ports {
-            sender.send(BrokerEvent::HealthCheckRequest(HealthCheckRequest {
-                sender_id: sender_id.clone(),
-                destination_id: port.clone(),
-                id: uuid.to_string(),
-            }));
+    };
+    Ok::<_, Error>(response)
+}
+
+/// Sends a `ComponentsRequest` to the Kernel and waits for the `ComponentsResponse` until the timeout.
+async fn request_components(data: ArcHealthCheckData) -> Option<Vec<ComponentInfo>> {
+    let uuid = Uuid::new_v4().to_string();
+    let (tx, rx) = tokio::sync::oneshot::channel::<Vec<ComponentInfo>>();
+    let (sender, sender_id, timeout_duration) = {
+        let mut data = data.lock().unwrap();
+        data.pending_components_requests.insert(uuid.clone(), tx);
+        (
+            data.sender_to_kernel.clone_boxed(),
+            data.id.clone(),
+            data.config.as_ref().unwrap().timeout,
+        )
+    };
+    sender.send(BrokerEvent::ComponentsRequest(ComponentsRequest {
+        id: uuid.clone(),
+        sender_id,
+    }));
+    match timeout(timeout_duration, rx).await {
+        Ok(Ok(components)) => Some(components),
+        _ => {
+            warn!(
+                "kernel did not respond with the components in {}ms",
+                timeout_duration.as_millis()
+            );
+            data.lock()
+                .unwrap()
+                .pending_components_requests
+                .remove(&uuid);
+            None
+        }
+    }
+}
+
+/// The router is ready if every component is configured, initialized and not unhealthy.
+/// Without `ports_to_check` all components the Kernel knows are checked.
+async fn handle_readiness_request(data: ArcHealthCheckData) -> Result<Response<Body>, Error> {
+    let components = match request_components(data.clone()).await {
+        Some(components) => components,
+        None => {
+            return Ok::<_, Error>(
+                Response::builder()
+                    .status(StatusCode::SERVICE_UNAVAILABLE)
+                    .body(Body::from(
+                        "Timeout, kernel did not respond with the components",
+                    ))
+                    .unwrap(),
+            )
         }
+    };
 
-        let result = timeout(timeout_duration, rx).await;
-        let responses = data
-            .lock()
-            .unwrap()
-            .pending_requests
-            .remove(uuid.to_string().as_str())
-            .unwrap();
-        let mut status_code = StatusCode::SERVICE_UNAVAILABLE;
-        let body = HealthHttpResponse {
-            message: if let Err(e) = result {
-                warn!(
-                    "did not receive a value in {}ms: {:?}",
-                    timeout_duration.as_millis(),
-                    e
+    let ports = ports_to_check(&data);
+    let ports = if ports.is_empty() {
+        components.iter().map(|c| c.id.clone()).collect()
+    } else {
+        ports
+    };
+    let (all_responded, mut responses) = request_health_status(data, ports.clone()).await;
+
+    let components: BTreeMap<InternalServerId, ComponentReadiness> = ports
+        .into_iter()
+        .map(|port| {
+            let info = components.iter().find(|c| c.id == port);
+            let readiness = ComponentReadiness {
+                configured: info.is_some_and(|c| c.configured),
+                initialized: info.is_some_and(|c| c.initialized),
+                status: responses.remove(&port).flatten(),
+            };
+            (port, readiness)
+        })
+        .collect();
+
+    let mut status_code = StatusCode::SERVICE_UNAVAILABLE;
+    let message = if components.values().any(|c| !c.configured || !c.initialized) {
+        "not all components are configured and initialized".to_string()
+    } else if !all_responded {
+        "Timeout, components did not respond".to_string()
+    } else if components.values().any(|c| !is_ok(&c.status)) {
+        "not all components are healthy".to_string()
+    } else {
+        status_code = StatusCode::OK;
+        "ready".to_string()
+    };
+    let body = serde_json::to_vec(&ReadinessHttpResponse {
+        message,
+        components,
+    })
+    .unwrap();
+
+    let response = Response::builder()
+        .status(status_code)
+        .body(Body::from(body))
+        .unwrap();
+    Ok::<_, Error>(response)
+}
+
+/// Sends a `MetricsRequest` to the Kernel and waits for the `MetricsResponse` until the timeout.
+async fn request_metrics(data: ArcHealthCheckData) -> Option<Vec<Metric>> {
+    let uuid = Uuid::new_v4().to_string();
+    let (tx, rx) = tokio::sync::oneshot::channel::<Vec<Metric>>();
+    let (sender, sender_id, timeout_duration) = {
+        let mut data = data.lock().unwrap();
+        data.pending_metrics_requests.insert(uuid.clone(), tx);
+        (
+            data.sender_to_kernel.clone_boxed(),
+            data.id.clone(),
+            data.config.as_ref().unwrap().timeout,
+        )
+    };
+    sender.send(BrokerEvent::MetricsRequest(MetricsRequest {
+        id: uuid.clone(),
+        sender_id,
+    }));
+    match timeout(timeout_duration, rx).await {
+        Ok(Ok(metrics)) => Some(metrics),
+        _ => {
+            warn!(
+                "kernel did not respond with metrics in {}ms",
+                timeout_duration.as_millis()
+            );
+            data.lock().unwrap().pending_metrics_requests.remove(&uuid);
+            None
+        }
+    }
+}
+
+async fn handle_metrics_request(data: ArcHealthCheckData) -> Result<Response<Body>, Error> {
+    let ports = ports_to_check(&data);
+    let (metrics, (_, health)) = futures_lite::future::zip(
+        request_metrics(data.clone()),
+        request_health_status(data, ports),
+    )
+    .await;
+    let response = match metrics {
+        Some(metrics) => Response::builder()
+            .status(StatusCode::OK)
+            .header("Content-Type", PROMETHEUS_CONTENT_TYPE)
+            .body(Body::from(render_prometheus(&metrics, &health)))
+            .unwrap(),
+        None => Response::builder()
+            .status(StatusCode::SERVICE_UNAVAILABLE)
+            .body(Body::from("Timeout, kernel did not respond with metrics"))
+            .unwrap(),
+    };
+    Ok::<_, Error>(response)
+}
+
+fn escape_label_value(value: &str) -> String {
+    value
+        .replace('\\', "\\\\")
+        .replace('"', "\\\"")
+        .replace('\n', "\\n")
+}
+
+fn format_labels(labels: &MetricLabels, extra: Option<(&str, String)>) -> String {
+    let labels: Vec<String> = labels
+        .iter()
+        .map(|(k, v)| (k.as_str(), v.clone()))
+        .chain(extra)
+        .map(|(k, v)| format!("{}=\"{}\"", k, escape_label_value(&v)))
+        .collect();
+    if labels.is_empty() {
+        String::new()
+    } else {
+        format!("{{{}}}", labels.join(","))
+    }
+}
+
+/// Renders the metrics in the Prometheus text format.
+/// The health of the ports is added as `METRIC_PORT_HEALTHY` gauge, ports that did not respond are unhealthy.
+fn render_prometheus(metrics: &[Metric], health: &HealthResponses) -> String {
+    let mut text = String::new();
+    let mut previous_name = None;
+    for metric in metrics {
+        let name = metric.name.as_str();
+        if previous_name != Some(name) {
+            let metric_type = match metric.value {
+                MetricValue::Counter(_) => "counter",
+                MetricValue::Gauge(_) => "gauge",
+                MetricValue::Histogram(_) => "histogram",
+            };
+            let _ = writeln!(text, "# TYPE {} {}", name, metric_type);
+            previous_name = Some(name);
+        }
+        match &metric.value {
+            MetricValue::Counter(value) => {
+                let _ = writeln!(
+                    text,
+                    "{}{} {}",
+                    name,
+                    format_labels(&metric.labels, None),
+                    value
                 );
-                "Timeout, ports did not respond; current result: {}".to_string()
-            } else {
-                if responses
-                    .responses
+            }
+            MetricValue::Gauge(value) => {
+                let _ = writeln!(
+                    text,
+                    "{}{} {}",
+                    name,
+                    format_labels(&metric.labels, None),
+                    value
+                );
+            }
+            MetricValue::Histogram(histogram) => {
+                let mut cumulative = 0;
+                let bounds = histogram
+                    .bounds
                     .iter()
-                    .any(|(_, status)| !status.eq(&Some(HealthCheckStatus::Healthy)))
-                {
-                    "not all responses were successful".to_string()
-                } else {
-                    status_code = StatusCode::OK;
-                    "successful".to_string()
+                    .map(|bound| bound.to_string())
+                    .chain(std::iter::once("+Inf".to_string()));
+                for (bound, count) in bounds.zip(histogram.counts.iter()) {
+                    cumulative += count;
+                    let labels = format_labels(&metric.labels, Some(("le", bound)));
+                    let _ = writeln!(text, "{}_bucket{} {}", name, labels, cumulative);
                 }
-            },
-            requests: responses.responses.clone(),
-        };
+                let labels = format_labels(&metric.labels, None);
+                let _ = writeln!(text, "{}_sum{} {}", name, labels, histogram.sum);
+                let _ = writeln!(text, "{}_count{} {}", name, labels, histogram.count);
+            }
+        }
+    }
 
-        let body = serde_json::to_vec(&body).unwrap();
+    if !health.is_empty() {
+        let _ = writeln!(text, "# TYPE {} gauge", METRIC_PORT_HEALTHY);
+        let mut ports: Vec<_> = health.iter().collect();
+        ports.sort_by(|a, b| a.0.cmp(b.0));
+        for (port, status) in ports {
+            let labels = format_labels(
+                &MetricLabels::new(),
+                Some((METRIC_COMPONENT_LABEL, port.clone())),
+            );
+            let healthy = is_ok(status) as u8;
+            let _ = writeln!(text, "{}{} {}", METRIC_PORT_HEALTHY, labels, healthy);
+        }
+    }
+    text
+}
 
-        let response = Response::builder()
-            .status(status_code)
-            .body(Body::from(body))
-            .unwrap();
-        Ok::<_, Error>(response)
-    } else {
-        Ok::<_, Error>(Response::new(Body::from("OK")))
+fn received_metrics_from_kernel(event: MetricsResponse, data: ArcHealthCheckData) -> Result<()> {
+    let receiver = data
+        .lock()
+        .unwrap()
+        .pending_metrics_requests
+        .remove(event.id.as_str());
+    match receiver {
+        Some(receiver) => {
+            if receiver.send(event.metrics).is_err() {
+                bail!("failed to notify web server")
+            }
+            Ok(())
+        }
+        None => bail!("no pending metrics request with id {}", event.id),
+    }
+}
+
+fn received_components_from_kernel(
+    event: ComponentsResponse,
+    data: ArcHealthCheckData,
+) -> Result<()> {
+    let receiver = data
+        .lock()
+        .unwrap()
+        .pending_components_requests
+        .remove(event.id.as_str());
+    match receiver {
+        Some(receiver) => {
+            if receiver.send(event.components).is_err() {
+                bail!("failed to notify web server")
+            }
+            Ok(())
+        }
+        None => bail!("no pending components request with id {}", event.id),
     }
 }
 
@@ -258,6 +633,8 @@ pub fn port_health_check_http(
         sender_to_kernel,
         id: id.clone(),
         pending_requests: HashMap::new(),
+        pending_metrics_requests: HashMap::new(),
+        pending_components_requests: HashMap::new(),
     };
     let data: ArcHealthCheckData = Arc::new(Mutex::new(data));
 
@@ -265,15 +642,46 @@ pub fn port_health_check_http(
         match inbox.receive() {
             BrokerEvent::Init => info!("{} initiated", &id),
             BrokerEvent::ConfigUpdated(config, _) => {
-                if let Err(e) = update(config, data.clone()) {
+                let result = update(config, data.clone());
+                if let Err(ref e) = result {
                     error!("failed to build config {:?}", e)
                 }
+                data.lock()
+                    .unwrap()
+                    .sender_to_kernel
+                    .send(BrokerEvent::config_result(id.clone(), result));
             }
             BrokerEvent::HealthCheckResponse(event) => {
                 if let Err(e) = received_health_check_from_port(event, data.clone()) {
                     error!("failed to register HealthCheckResponse {:?}", e)
                 }
             }
+            BrokerEvent::MetricsResponse(event) => {
+                if let Err(e) = received_metrics_from_kernel(event, data.clone()) {
+                    error!("failed to register MetricsResponse {:?}", e)
+                }
+            }
+            BrokerEvent::ComponentsResponse(event) => {
+                if let Err(e) = received_components_from_kernel(event, data.clone()) {
+                    error!("failed to register ComponentsResponse {:?}", e)
+                }
+            }
+            BrokerEvent::Shutdown => {
+                let mut data = data.lock().unwrap();
+                if let Some(tx) = data.shutdown.take() {
+                    if tx.send(()).is_err() {
+                        error!("failed to shutdown the http server");
+                    }
+                }
+                info!("{} shut down", id);
+                data.sender_to_kernel
+                    .send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                let sender_to_kernel = data.lock().unwrap().sender_to_kernel.clone_boxed();
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Healthy));
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -286,6 +694,7 @@ pub static PORT_HEALTH_CHECK_HTTP: InternalServerFnRefStatic =
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cerk::kernel::Histogram;
     use cerk_runtime_threading::channel::new_channel_with_size;
     use env_logger::Env;
     use hyper::Client;
@@ -321,11 +730,14 @@ mod tests {
             sender_to_kernel: send,
             id: "the-id".to_string(),
             pending_requests: HashMap::new(),
+            pending_metrics_requests: HashMap::new(),
+            pending_components_requests: HashMap::new(),
         };
         config.config = Some(HealthCheckConfig {
             address: SocketAddr::new("127.0.0.1".parse()?, server_port),
             timeout: Duration::from_millis(10),
             ports_to_check: ports,
+            metrics_path: DEFAULT_METRICS_PATH.to_string(),
         });
         let data: ArcHealthCheckData = Arc::new(Mutex::new(config));
         let e = start_server(data.clone());
@@ -349,4 +761,241 @@ mod tests {
         assert!(e.is_ok());
         Ok(())
     }
+
+    #[test]
+    fn test_metrics_route() -> Result<()> {
+        let server_port = 3003;
+        let (send, receive) = new_channel_with_size(1);
+        let tokio = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let data: ArcHealthCheckData = Arc::new(Mutex::new(HealthCheckData {
+            shutdown: None,
+            config: Some(HealthCheckConfig {
+                address: SocketAddr::new("127.0.0.1".parse()?, server_port),
+                timeout: Duration::from_millis(1000),
+                ports_to_check: vec![],
+                metrics_path: "/custom-metrics".to_string(),
+            }),
+            tokio: tokio.handle().clone(),
+            sender_to_kernel: send,
+            id: "the-id".to_string(),
+            pending_requests: HashMap::new(),
+            pending_metrics_requests: HashMap::new(),
+            pending_components_requests: HashMap::new(),
+        }));
+        start_server(data.clone())?;
+
+        // answers the MetricsRequest in the name of the kernel
+        let kernel_data = data.clone();
+        std::thread::spawn(move || {
+            if let BrokerEvent::MetricsRequest(request) = receive.receive() {
+                let response = MetricsResponse {
+                    id: request.id,
+                    destination_id: request.sender_id,
+                    metrics: vec![Metric {
+                        name: "cerk_incoming_events_total".to_string(),
+                        labels: [(METRIC_COMPONENT_LABEL.to_string(), "input".to_string())]
+                            .iter()
+                            .cloned()
+                            .collect(),
+                        value: MetricValue::Counter(3),
+                    }],
+                };
+                received_metrics_from_kernel(response, kernel_data).unwrap();
+            }
+        });
+
+        tokio.handle().block_on(async {
+            delay_for(Duration::from_millis(10)).await;
+            let client = Client::new();
+            let uri = format!("http://localhost:{}/custom-metrics", server_port);
+            let response = client.get(uri.parse().unwrap()).await.unwrap();
+            assert_eq!(response.status(), 200);
+            let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+            assert_eq!(
+                String::from_utf8(body.to_vec()).unwrap(),
+                "# TYPE cerk_incoming_events_total counter\ncerk_incoming_events_total{component=\"input\"} 3\n"
+            );
+        });
+
+        let e = data.lock().unwrap().shutdown.take().unwrap().send(());
+        assert!(e.is_ok());
+        Ok(())
+    }
+
+    fn build_test_data(
+        server_port: u16,
+        sender_to_kernel: BoxedSender,
+        tokio: &tokio::runtime::Runtime,
+    ) -> Result<ArcHealthCheckData> {
+        Ok(Arc::new(Mutex::new(HealthCheckData {
+            shutdown: None,
+            config: Some(HealthCheckConfig {
+                address: SocketAddr::new("127.0.0.1".parse()?, server_port),
+                timeout: Duration::from_millis(1000),
+                ports_to_check: vec![],
+                metrics_path: DEFAULT_METRICS_PATH.to_string(),
+            }),
+            tokio: tokio.handle().clone(),
+            sender_to_kernel,
+            id: "the-id".to_string(),
+            pending_requests: HashMap::new(),
+            pending_metrics_requests: HashMap::new(),
+            pending_components_requests: HashMap::new(),
+        })))
+    }
+
+    /// answers the requests of the port in the name of the kernel and the components
+    fn answer_as_kernel(
+        receive: BoxedReceiver,
+        data: ArcHealthCheckData,
+        components: Vec<ComponentInfo>,
+        status: HealthCheckStatus,
+    ) {
+        std::thread::spawn(move || loop {
+            match receive.receive() {
+                BrokerEvent::ComponentsRequest(request) => {
+                    let response = ComponentsResponse {
+                        id: request.id,
+                        destination_id: request.sender_id,
+                        components: components.clone(),
+                    };
+                    received_components_from_kernel(response, data.clone()).unwrap();
+                }
+                BrokerEvent::HealthCheckRequest(request) => {
+                    let status = if request.destination_id == KERNEL_ID {
+                        HealthCheckStatus::Healthy
+                    } else {
+                        status.clone()
+                    };
+                    if let BrokerEvent::HealthCheckResponse(response) = request.respond(status) {
+                        let _ = received_health_check_from_port(response, data.clone());
+                    }
+                }
+                _ => {}
+            }
+        });
+    }
+
+    async fn get(server_port: u16, path: &str) -> (u16, String) {
+        delay_for(Duration::from_millis(10)).await;
+        let uri = format!("http://localhost:{}{}", server_port, path);
+        let response = Client::new().get(uri.parse().unwrap()).await.unwrap();
+        let status = response.status().as_u16();
+        let body = hyper::body::to_bytes(response.into_body()).await.unwrap();
+        (status, String::from_utf8(body.to_vec()).unwrap())
+    }
+
+    #[test]
+    fn test_liveness_route() -> Result<()> {
+        let server_port = 3004;
+        let (send, receive) = new_channel_with_size(1);
+        let tokio = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let data = build_test_data(server_port, send, &tokio)?;
+        start_server(data.clone())?;
+        answer_as_kernel(receive, data.clone(), vec![], HealthCheckStatus::Healthy);
+
+        let (status, _) = tokio.handle().block_on(get(server_port, LIVENESS_PATH));
+        assert_eq!(status, 200);
+
+        let e = data.lock().unwrap().shutdown.take().unwrap().send(());
+        assert!(e.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_readiness_route() -> Result<()> {
+        let server_port = 3005;
+        let (send, receive) = new_channel_with_size(1);
+        let tokio = tokio::runtime::Builder::new()
+            .threaded_scheduler()
+            .enable_all()
+            .build()
+            .unwrap();
+        let data = build_test_data(server_port, send, &tokio)?;
+        start_server(data.clone())?;
+        let components = vec![
+            ComponentInfo {
+                id: "input".to_string(),
+                configured: true,
+                initialized: true,
+            },
+            ComponentInfo {
+                id: "output".to_string(),
+                configured: false,
+                initialized: true,
+            },
+        ];
+        answer_as_kernel(
+            receive,
+            data.clone(),
+            components,
+            HealthCheckStatus::Unsupported,
+        );
+
+        let (status, body) = tokio.handle().block_on(get(server_port, READINESS_PATH));
+        assert_eq!(status, 503);
+        let body: serde_json::Value = serde_json::from_str(&body)?;
+        assert_eq!(body["components"]["input"]["status"], "Unsupported");
+        assert_eq!(body["components"]["output"]["configured"], false);
+
+        data.lock().unwrap().config.as_mut().unwrap().ports_to_check = vec!["input".to_string()];
+        let (status, _) = tokio.handle().block_on(get(server_port, READINESS_PATH));
+        assert_eq!(status, 200, "only the ports to check should be considered");
+
+        let e = data.lock().unwrap().shutdown.take().unwrap().send(());
+        assert!(e.is_ok());
+        Ok(())
+    }
+
+    #[test]
+    fn test_render_prometheus() {
+        let mut histogram = Histogram::new(&[0.1, 1.0]);
+        histogram.observe(0.05);
+        histogram.observe(0.5);
+        histogram.observe(2.0);
+        let labels: MetricLabels = [(METRIC_COMPONENT_LABEL.to_string(), "in\"put".to_string())]
+            .iter()
+            .cloned()
+            .collect();
+        let metrics = vec![
+            Metric {
+                name: "latency".to_string(),
+                labels: labels.clone(),
+                value: MetricValue::Histogram(histogram),
+            },
+            Metric {
+                name: "pending".to_string(),
+                labels,
+                value: MetricValue::Gauge(1.5),
+            },
+        ];
+        let health: HealthResponses = vec![
+            ("a".to_string(), Some(HealthCheckStatus::Healthy)),
+            ("b".to_string(), None),
+        ]
+        .into_iter()
+        .collect();
+
+        let expected = r#"# TYPE latency histogram
+latency_bucket{component="in\"put",le="0.1"} 1
+latency_bucket{component="in\"put",le="1"} 2
+latency_bucket{component="in\"put",le="+Inf"} 3
+latency_sum{component="in\"put"} 2.55
+latency_count{component="in\"put"} 3
+# TYPE pending gauge
+pending{component="in\"put"} 1.5
+# TYPE cerk_port_healthy gauge
+cerk_port_healthy{component="a"} 1
+cerk_port_healthy{component="b"} 0
+"#;
+        assert_eq!(render_prometheus(&metrics, &health), expected);
+    }
 }
@@ -1,16 +1,20 @@
-use anyhow::{bail, Result};
+use anyhow::{bail, Context, Result};
 use async_std::task::block_on;
 use cerk::kernel::{
-    BrokerEvent, CloudEventRoutingArgs, Config, DeliveryGuarantee, IncomingCloudEvent,
-    OutgoingCloudEvent, OutgoingCloudEventProcessed, ProcessingResult,
+    BrokerEvent, CloudEventRoutingArgs, Config, DeliveryGuarantee, HealthCheckStatus,
+    IncomingCloudEvent, OutgoingCloudEvent, OutgoingCloudEventProcessed, ProcessingResult,
+    RoutingIdGenerator,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
-use cloudevents::{AttributesReader, Event};
+use cloudevents::Event;
 use paho_mqtt::{
     AsyncClient, ConnectOptionsBuilder, CreateOptionsBuilder, Message, PersistenceType,
 };
 use serde_json;
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
+use std::thread;
 use std::time::Duration;
 
 struct MqttConnection {
@@ -19,23 +23,23 @@ struct MqttConnection {
     subscribe_topic: Option<String>,
 }
 
-fn build_connection(id: &InternalServerId, config: Config) -> MqttConnection {
+fn build_connection(id: &InternalServerId, config: Config) -> Result<MqttConnection> {
     match config {
         Config::HashMap(ref config_map) => {
             let host = match config_map.get("host") {
                 Some(Config::String(host)) => host,
-                _ => panic!("{} invalid value for host", id),
+                _ => bail!("{} invalid value for host", id),
             };
 
             let send_topic = match config_map.get("send_topic") {
                 Some(Config::String(topic)) => Some(topic.clone()),
-                Some(_) => panic!("{} invalid value for send_topic", id),
+                Some(_) => bail!("{} invalid value for send_topic", id),
                 _ => None,
             };
 
             let subscribe_topic = match config_map.get("subscribe_topic") {
                 Some(Config::String(topic)) => Some(topic.clone()),
-                Some(_) => panic!("{} invalid value for subscribe_topic", id),
+                Some(_) => bail!("{} invalid value for subscribe_topic", id),
                 _ => None,
             };
 
@@ -46,17 +50,16 @@ fn build_connection(id: &InternalServerId, config: Config) -> MqttConnection {
                 .mqtt_version(5)
                 .finalize();
 
-            let client = AsyncClient::new(mqtt_config).unwrap_or_else(|err| {
-                panic!("Error creating the client: {}", err);
-            });
+            let client = AsyncClient::new(mqtt_config)
+                .with_context(|| format!("{} failed to create the client", id))?;
 
-            return MqttConnection {
+            Ok(MqttConnection {
                 client,
                 send_topic,
                 subscribe_topic,
-            };
+            })
         }
-        _ => panic!("{} received invalide config", id),
+        _ => bail!("{} received invalide config", id),
     }
 }
 
@@ -64,22 +67,27 @@ fn message_handler(
     id: InternalServerId,
     sender_to_kernel: BoxedSender,
     routing_args: CloudEventRoutingArgs,
+    paused: Arc<AtomicBool>,
 ) -> Box<dyn Fn(&AsyncClient, Option<paho_mqtt::Message>)> {
+    let routing_ids = RoutingIdGenerator::new();
     Box::new(
         move |_client: &AsyncClient, msg: Option<paho_mqtt::Message>| {
             debug!("{} received message callback", id);
+            // blocking the callback stops the client from processing further messages
+            while paused.load(Ordering::Relaxed) {
+                thread::sleep(Duration::from_millis(10));
+            }
             if let Some(msg) = msg {
                 debug!("{} received cloudevent on topic {}", id, msg.topic());
                 let payload_str = msg.payload_str();
                 match serde_json::from_str::<Event>(&payload_str) {
                     Ok(cloud_event) => {
                         debug!("{} deserialized event successfully", id);
-                        let routing_id = cloud_event.id().to_string();
                         sender_to_kernel.send(BrokerEvent::IncomingCloudEvent(
                             IncomingCloudEvent {
-                                routing_id,
+                                routing_id: routing_ids.next_id(),
                                 incoming_id: id.clone(),
-                                cloud_event,
+                                cloud_event: Arc::new(cloud_event),
                                 args: routing_args.clone(),
                             },
                         ));
@@ -96,12 +104,11 @@ fn message_handler(
 async fn setup_connection(
     id: &InternalServerId,
     sender_to_kernel: BoxedSender,
-    config: Config,
-) -> Result<MqttConnection> {
+    connection: &MqttConnection,
+    paused: Arc<AtomicBool>,
+) -> Result<()> {
     debug!("{} start connection to mqtt broker", id);
 
-    let connection = build_connection(id, config);
-
     let connection_options = ConnectOptionsBuilder::new()
         .clean_session(false)
         .clean_start(false)
@@ -112,12 +119,14 @@ async fn setup_connection(
 
     let routing_args = CloudEventRoutingArgs {
         delivery_guarantee: DeliveryGuarantee::BestEffort,
+        ..CloudEventRoutingArgs::default()
     };
 
     connection.client.set_message_callback(message_handler(
         id.clone(),
         sender_to_kernel,
         routing_args,
+        paused,
     ));
 
     if let Some(ref subscribe_topic) = connection.subscribe_topic {
@@ -126,31 +135,59 @@ async fn setup_connection(
         connection.client.subscribe(subscribe_topic, 0).await?;
     }
 
-    Ok(connection)
+    Ok(())
 }
 
-async fn send_cloud_event(
+/// Replaces the connection with one for the new config.
+/// The previous connection is kept if the config is rejected.
+fn update_connection(
+    id: &InternalServerId,
+    connection: &mut Option<MqttConnection>,
+    config: Config,
+    sender_to_kernel: &BoxedSender,
+    paused: &Arc<AtomicBool>,
+) -> Result<()> {
+    let new_connection = build_connection(id, config)?;
+    // the new client has the same client id, so the previous one has to disconnect first
+    if let Some(ref previous) = connection {
+        block_on(previous.client.disconnect(None))
+            .with_context(|| format!("{} failed to disconnect", id))?;
+        debug!("{} disconnected succesfully", id);
+    }
+    if let Err(err) = block_on(setup_connection(
+        id,
+        sender_to_kernel.clone_boxed(),
+        &new_connection,
+        paused.clone(),
+    )) {
+        if let Some(ref previous) = connection {
+            if let Err(err) = block_on(previous.client.reconnect()) {
+                error!(
+                    "{} failed to reconnect the previous connection {:?}",
+                    id, err
+                );
+            }
+        }
+        return Err(err);
+    }
+    *connection = Some(new_connection);
+    Ok(())
+}
+
+fn build_message(
     id: &InternalServerId,
     event: &OutgoingCloudEvent,
     connection: &MqttConnection,
-) -> Result<ProcessingResult> {
+) -> Result<Message> {
     if let Some(ref send_topic) = connection.send_topic {
-        let serialized = serde_json::to_string(&event.cloud_event).unwrap();
+        let serialized = serde_json::to_string(event.cloud_event.as_ref())?;
         debug!("{} message serialized", id);
         let send_qos = match event.args.delivery_guarantee {
-            DeliveryGuarantee::BestEffort => 0,
+            DeliveryGuarantee::BestEffort | DeliveryGuarantee::AtMostOnce => 0,
             DeliveryGuarantee::AtLeastOnce => 1,
+            DeliveryGuarantee::ExactlyOnce => 2,
         };
-        let msg = Message::new(send_topic, serialized, send_qos);
-        debug!("start publishing on {}", send_topic);
-
-        match connection.client.publish(msg).await {
-            Ok(_) => Ok(ProcessingResult::Successful),
-            Err(e) => {
-                error!("{} error while publishing {:?}", id, e);
-                Ok(ProcessingResult::PermanentError)
-            }
-        }
+        Ok(Message::new(send_topic, serialized, send_qos))
     } else {
         bail!(
             "{} received event before the mqtt port was configured as output port -> message will be dropped",
@@ -159,9 +196,80 @@ async fn send_cloud_event(
     }
 }
 
+/// Publishes all CloudEvents before it waits for the first delivery.
+/// The results are in the same order as the CloudEvents, a CloudEvent that can not be published only fails itself.
+async fn send_cloud_events(
+    id: &InternalServerId,
+    events: &[OutgoingCloudEvent],
+    connection: &MqttConnection,
+) -> Vec<ProcessingResult> {
+    let mut tokens = Vec::with_capacity(events.len());
+    for event in events {
+        tokens.push(match build_message(id, event, connection) {
+            Ok(msg) => Some(connection.client.publish(msg)),
+            Err(e) => {
+                error!(
+                    "{} can not publish event_id={} {:?}",
+                    id, event.routing_id, e
+                );
+                None
+            }
+        });
+    }
+    let mut results = Vec::with_capacity(tokens.len());
+    for token in tokens {
+        results.push(match token {
+            Some(token) => match token.await {
+                Ok(_) => ProcessingResult::Successful,
+                Err(e) => {
+                    error!("{} error while publishing {:?}", id, e);
+                    ProcessingResult::PermanentError
+                }
+            },
+            None => ProcessingResult::PermanentError,
+        });
+    }
+    results
+}
+
+/// Sends the CloudEvents and returns the `OutgoingCloudEventProcessed` for the ones that require an acknowledgment.
+fn send_outgoing_cloud_events(
+    id: &InternalServerId,
+    events: Vec<OutgoingCloudEvent>,
+    connection: &Option<MqttConnection>,
+) -> Vec<BrokerEvent> {
+    let results = match connection {
+        Some(ref connection) => block_on(send_cloud_events(id, &events, connection)),
+        None => {
+            // the connection could still be configured, the Kernel may retry the CloudEvents
+            error!(
+                "{} can not send {} cloudevents, no connection configured",
+                id,
+                events.len()
+            );
+            vec![ProcessingResult::TransientError; events.len()]
+        }
+    };
+    events
+        .into_iter()
+        .zip(results)
+        .filter(|(event, _)| event.args.delivery_guarantee.requires_acknowledgment())
+        .map(|(event, result)| {
+            BrokerEvent::OutgoingCloudEventProcessed(OutgoingCloudEventProcessed {
+                sender_id: id.clone(),
+                routing_id: event.routing_id,
+                delivery_id: event.delivery_id,
+                result,
+            })
+        })
+        .collect()
+}
+
 /// This is the main function to start the port.
 pub fn port_mqtt_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_kernel: BoxedSender) {
     let mut connection: Option<MqttConnection> = None;
+    // the Kernel sent `BrokerEvent::Pause`
+    let paused = Arc::new(AtomicBool::new(false));
 
     info!("start mqtt port with id {}", id);
 
@@ -172,44 +280,44 @@ pub fn port_mqtt_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_ker
             }
             BrokerEvent::ConfigUpdated(config, _) => {
                 info!("{} received ConfigUpdated", &id);
-                if let Some(ref connection) = connection {
-                    match block_on(connection.client.disconnect(None)) {
-                        Ok(_) => debug!("disconnected succesfully"),
-                        Err(err) => panic!("{} disconnects failed {:?}", id, err),
-                    }
-                }
-
-                match block_on(setup_connection(
-                    &id,
-                    sender_to_kernel.clone_boxed(),
-                    config,
-                )) {
-                    Ok(new_connection) => {
-                        connection = Some(new_connection);
-                    }
-                    Err(err) => panic!("{} connection setup failed {:?}", id, err),
+                let result =
+                    update_connection(&id, &mut connection, config, &sender_to_kernel, &paused);
+                if let Err(ref err) = result {
+                    error!(
+                        "{} connection setup failed, keeps the previous one {:?}",
+                        id, err
+                    );
                 }
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
             }
             BrokerEvent::OutgoingCloudEvent(event) => {
                 debug!("{} cloudevent received", &id);
+                for processed in send_outgoing_cloud_events(&id, vec![event], &connection) {
+                    sender_to_kernel.send(processed);
+                }
+            }
+            BrokerEvent::OutgoingCloudEventBatch(events) => {
+                debug!("{} batch of {} cloudevents received", &id, events.len());
+                let processed = send_outgoing_cloud_events(&id, events, &connection);
+                if !processed.is_empty() {
+                    sender_to_kernel.send(BrokerEvent::Batch(processed));
+                }
+            }
+            BrokerEvent::Pause(_) => paused.store(true, Ordering::Relaxed),
+            BrokerEvent::Resume(_) => paused.store(false, Ordering::Relaxed),
+            BrokerEvent::Shutdown => {
+                paused.store(false, Ordering::Relaxed);
                 if let Some(ref connection) = connection {
-                    match block_on(send_cloud_event(&id, &event, &connection)) {
-                        Ok(result) => {
-                            debug!("{} cloudevent sent -> {:?}", &id, &result);
-
-                            sender_to_kernel.send(BrokerEvent::OutgoingCloudEventProcessed(
-                                OutgoingCloudEventProcessed {
-                                    sender_id: id.clone(),
-                                    routing_id: event.routing_id,
-                                    result: result,
-                                },
-                            ));
-                        }
-                        Err(err) => panic!("{} connection setup failed {:?}", id, err),
+                    if let Err(err) = block_on(connection.client.disconnect(None)) {
+                        warn!("{} disconnect failed {:?}", id, err);
                     }
-                } else {
-                    panic!("{} can not send message, no connection configured", id)
                 }
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
             }
             broker_event => warn!("event {} not implemented", broker_event),
         }
@@ -218,3 +326,86 @@ pub fn port_mqtt_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_ker
 
 /// This is the pointer for the main function to start the port.
 pub static PORT_MQTT: InternalServerFnRefStatic = &(port_mqtt_start as InternalServerFn);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use cerk::runtime::channel::{RecordingSender, Sender};
+    use std::collections::HashMap;
+
+    fn output_config(send_topic: &str) -> Config {
+        let map: HashMap<String, Config> = [
+            (
+                "host".to_string(),
+                Config::String("tcp://mqtt-broker:1883".to_string()),
+            ),
+            (
+                "send_topic".to_string(),
+                Config::String(send_topic.to_string()),
+            ),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        Config::HashMap(map)
+    }
+
+    fn build_event(
+        id: &InternalServerId,
+        delivery_guarantee: DeliveryGuarantee,
+    ) -> OutgoingCloudEvent {
+        OutgoingCloudEvent {
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            destination_id: id.clone(),
+            args: CloudEventRoutingArgs {
+                delivery_guarantee,
+                ..CloudEventRoutingArgs::default()
+            },
+            delivery_id: 0,
+        }
+    }
+
+    #[test]
+    fn rejected_config_should_keep_the_previous_connection() -> Result<()> {
+        let id = "mqtt-output".to_string();
+        let mut connection = Some(build_connection(&id, output_config("outbox"))?);
+        let sender = RecordingSender::default();
+
+        let result = update_connection(
+            &id,
+            &mut connection,
+            Config::String("invalid".to_string()),
+            &sender.clone_boxed(),
+            &Arc::new(AtomicBool::new(false)),
+        );
+        assert!(result.is_err());
+
+        let event = build_event(&id, DeliveryGuarantee::BestEffort);
+        let message = build_message(&id, &event, connection.as_ref().unwrap())?;
+        assert_eq!(message.topic(), "outbox");
+        Ok(())
+    }
+
+    #[test]
+    fn only_copies_that_require_an_acknowledgment_should_be_answered() {
+        let id = "mqtt-output".to_string();
+        let events = vec![
+            build_event(&id, DeliveryGuarantee::AtMostOnce),
+            build_event(&id, DeliveryGuarantee::AtLeastOnce),
+        ];
+
+        match send_outgoing_cloud_events(&id, events, &None).as_slice() {
+            [BrokerEvent::OutgoingCloudEventProcessed(processed)] => {
+                assert_eq!(processed.result, ProcessingResult::TransientError)
+            }
+            _ => panic!("expected one OutgoingCloudEventProcessed"),
+        }
+        assert!(send_outgoing_cloud_events(
+            &id,
+            vec![build_event(&id, DeliveryGuarantee::BestEffort)],
+            &None
+        )
+        .is_empty());
+    }
+}
@@ -1,24 +1,28 @@
 use anyhow::{Context, Result};
 use cerk::kernel::{
-    BrokerEvent, CloudEventMessageRoutingId, CloudEventRoutingArgs, Config, ConfigHelpers,
-    DeliveryGuarantee, IncomingCloudEvent, OutgoingCloudEvent, OutgoingCloudEventProcessed,
-    ProcessingResult,
+    BrokerEvent, CloudEventDeliveryId, CloudEventMessageRoutingId, CloudEventRoutingArgs, Config,
+    ConfigHelpers, DeliveryGuarantee, HealthCheckStatus, IncomingCloudEvent, OutgoingCloudEvent,
+    OutgoingCloudEventProcessed, ProcessingResult, RoutingIdGenerator,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
-use cloudevents::{AttributesReader, Event};
+use cloudevents::Event;
 use mosquitto_client_wrapper::Mosquitto;
 use serde_json;
 use std::collections::HashMap;
 use std::sync::mpsc::{channel, Sender};
 use std::sync::{Arc, Mutex};
 use std::thread;
+use std::time::Duration;
 use url::Url;
 
 const MOSQ_OPT_DELAYED_ACK: u32 = 14;
 
 struct Data {
-    unacked: HashMap<i32, String>,
+    /// key: mqtt message id; value: routing id and delivery id of the published copy
+    unacked: HashMap<i32, (CloudEventMessageRoutingId, CloudEventDeliveryId)>,
+    /// the Kernel sent `BrokerEvent::Pause`
+    paused: bool,
 }
 
 #[derive(Clone)]
@@ -39,7 +43,9 @@ struct Connection {
 type ArcData = Arc<Mutex<Data>>;
 
 fn build_configurations(config: Config) -> Result<Configurations> {
-    let host = config.get_op_val_string("host")?.unwrap();
+    let host = config
+        .get_op_val_string("host")?
+        .ok_or(anyhow!("no host was configured"))?;
     let send_topic = config.get_op_val_string("send_topic")?;
     let subscribe_topic = config.get_op_val_string("subscribe_topic")?;
     let subscribe_qos = config.get_op_val_u8("subscribe_qos")?.unwrap_or(0);
@@ -102,6 +108,7 @@ fn connect(
     data: ArcData,
 ) -> Result<Sender<(CloudEventMessageRoutingId, ProcessingResult)>> {
     let (sender, receiver) = channel();
+    let routing_ids = RoutingIdGenerator::new();
     let sub_delivery_guarantee = match connection.configs.subscribe_qos {
         1 => DeliveryGuarantee::AtLeastOnce,
         _ => DeliveryGuarantee::BestEffort,
@@ -109,16 +116,21 @@ fn connect(
     thread::spawn(move || {
         let mut callbacks = connection.client.callbacks(Vec::<()>::new());
         callbacks.on_message(|_, msg| {
+            // blocking on_message delays the PUBACK, so the broker stops sending new messages
+            while data.lock().unwrap().paused {
+                thread::sleep(Duration::from_millis(10));
+            }
             let text = msg.text();
             debug!("received cloud event (on_message), text={}", text);
             let cloudevent: Event = serde_json::from_str(text).with_context(|| format!("{} failed to deserialize cloudevent {}", id, text)).unwrap();
-            let routing_id = cloudevent.id().to_string();
+            let routing_id = routing_ids.next_id();
             sender_to_kernel.send(BrokerEvent::IncomingCloudEvent(IncomingCloudEvent {
                 incoming_id: id.clone(),
                 routing_id: routing_id.clone(),
-                cloud_event: cloudevent,
+                cloud_event: Arc::new(cloudevent),
                 args: CloudEventRoutingArgs {
                     delivery_guarantee: sub_delivery_guarantee,
+                    ..CloudEventRoutingArgs::default()
                 },
             }));
             if sub_delivery_guarantee.requires_acknowledgment() {
@@ -147,11 +159,12 @@ fn connect(
                 "{} published message with id {} successfully",
                 id, message_id
             );
-            if let Some(routing_id) = data_lock.unacked.remove(&message_id) {
+            if let Some((routing_id, delivery_id)) = data_lock.unacked.remove(&message_id) {
                 send_processed_event(
                     id.clone(),
                     &sender_to_kernel,
                     routing_id,
+                    delivery_id,
                     ProcessingResult::Successful,
                 );
             } else {
@@ -186,7 +199,7 @@ fn send_cloud_event(
     connection: &Connection,
     data: ArcData,
 ) -> Result<()> {
-    let serialized = serde_json::to_string(&event.cloud_event)?;
+    let serialized = serde_json::to_string(event.cloud_event.as_ref())?;
 
     if let Some(ref send_topic) = connection.configs.send_topic {
         let mut data_lock = data.lock().unwrap();
@@ -202,7 +215,7 @@ fn send_cloud_event(
         )?;
         data_lock
             .unacked
-            .insert(message_id, event.routing_id.clone());
+            .insert(message_id, (event.routing_id.clone(), event.delivery_id));
         debug!("{} sent publish with id {}", id, message_id);
     } else {
         error!("{} not send_topic configured", id);
@@ -214,6 +227,7 @@ fn send_processed_event(
     sender_id: InternalServerId,
     sender_to_kernel: &BoxedSender,
     routing_id: String,
+    delivery_id: CloudEventDeliveryId,
     result: ProcessingResult,
 ) {
     sender_to_kernel.send(BrokerEvent::OutgoingCloudEventProcessed(
@@ -221,10 +235,66 @@ fn send_processed_event(
             result,
             sender_id,
             routing_id,
+            delivery_id,
         },
     ));
 }
 
+fn process_outgoing_cloud_event(
+    id: &InternalServerId,
+    event: &OutgoingCloudEvent,
+    connection: &Option<Connection>,
+    sender_to_kernel: &BoxedSender,
+    data: &ArcData,
+) {
+    if let Some(ref connection) = connection {
+        debug!("{} will send event out", id);
+        if let Err(e) = send_cloud_event(id, event, connection, data.clone()) {
+            error!("failed to send event {:?}", e);
+            send_processed_event(
+                id.clone(),
+                sender_to_kernel,
+                event.routing_id.clone(),
+                event.delivery_id,
+                ProcessingResult::TransientError,
+            );
+        }
+    } else {
+        error!("no active connection - can't send event");
+        send_processed_event(
+            id.clone(),
+            sender_to_kernel,
+            event.routing_id.clone(),
+            event.delivery_id,
+            ProcessingResult::TransientError,
+        );
+    }
+}
+
+/// Replaces the connection with one for the new config.
+/// The previous connection is kept if the config is rejected.
+fn update_connection(
+    id: &InternalServerId,
+    config: Config,
+    connection: &mut Option<Connection>,
+    sender: &mut Option<Sender<(CloudEventMessageRoutingId, ProcessingResult)>>,
+    sender_to_kernel: &BoxedSender,
+    data: &ArcData,
+) -> Result<()> {
+    let new_connection =
+        build_connection(id, config).context("failed to parse connection config")?;
+    let new_sender = connect(
+        id.clone(),
+        new_connection.clone(),
+        sender_to_kernel.clone_boxed(),
+        data.clone(),
+    )
+    .context("failed to connect")?;
+    *connection = Some(new_connection);
+    *sender = Some(new_sender);
+    Ok(())
+}
+
 /// This is the main function to start the port.
 pub fn port_mqtt_mosquitto_start(
     id: InternalServerId,
@@ -236,6 +306,7 @@ pub fn port_mqtt_mosquitto_start(
     let mut sender: Option<Sender<(CloudEventMessageRoutingId, ProcessingResult)>> = None;
     let data: ArcData = Arc::new(Mutex::new(Data {
         unacked: HashMap::new(),
+        paused: false,
     }));
 
     loop {
@@ -245,49 +316,28 @@ pub fn port_mqtt_mosquitto_start(
             }
             BrokerEvent::ConfigUpdated(config, _) => {
                 info!("{} received ConfigUpdated", &id);
-                connection = match build_connection(&id, config) {
-                    Ok(new_connection) => Some(new_connection),
-                    Err(e) => {
-                        error!("failed to parse connection config {:?}", e);
-                        None
-                    }
-                };
-                if let Some(ref connection) = connection {
-                    sender = match connect(
-                        id.clone(),
-                        connection.clone(),
-                        sender_to_kernel.clone_boxed(),
-                        data.clone(),
-                    ) {
-                        Ok(connection) => Some(connection),
-                        Err(e) => {
-                            error!("failed to connect {:?}", e);
-                            None
-                        }
-                    };
+                let result = update_connection(
+                    &id,
+                    config,
+                    &mut connection,
+                    &mut sender,
+                    &sender_to_kernel,
+                    &data,
+                );
+                if let Err(ref e) = result {
+                    error!("{} keeps the previous connection {:?}", id, e);
                 }
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
             }
             BrokerEvent::OutgoingCloudEvent(event) => {
                 debug!("{} cloudevent received", &id);
-                if let Some(ref connection) = connection {
-                    debug!("{} will send event out", &id);
-                    if let Err(e) = send_cloud_event(&id, &event, &connection, data.clone()) {
-                        error!("failed to send event {:?}", e);
-                        send_processed_event(
-                            id.clone(),
-                            &sender_to_kernel,
-                            event.routing_id.clone(),
-                            ProcessingResult::TransientError,
-                        );
-                    }
-                } else {
-                    error!("no active connection - can't send event");
-                    send_processed_event(
-                        id.clone(),
-                        &sender_to_kernel,
-                        event.routing_id.clone(),
-                        ProcessingResult::TransientError,
-                    );
+                process_outgoing_cloud_event(&id, &event, &connection, &sender_to_kernel, &data);
+            }
+            BrokerEvent::OutgoingCloudEventBatch(events) => {
+                // the messages are published asynchronously, on_publish reports every result on its own
+                debug!("{} batch of {} cloudevents received", &id, events.len());
+                for event in events.iter() {
+                    process_outgoing_cloud_event(&id, event, &connection, &sender_to_kernel, &data);
                 }
             }
             BrokerEvent::IncomingCloudEventProcessed(routing_id, result) => {
@@ -300,6 +350,22 @@ pub fn port_mqtt_mosquitto_start(
                     error!("no active connection - can't send result");
                 }
             }
+            BrokerEvent::Pause(_) => data.lock().unwrap().paused = true,
+            BrokerEvent::Resume(_) => data.lock().unwrap().paused = false,
+            BrokerEvent::Shutdown => {
+                data.lock().unwrap().paused = false;
+                if let Some(ref connection) = connection {
+                    if let Err(e) = connection.client.disconnect() {
+                        warn!("{} disconnect failed {:?}", id, e);
+                    }
+                }
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -312,6 +378,7 @@ pub static PORT_MQTT_MOSQUITTO: InternalServerFnRefStatic =
 #[cfg(test)]
 mod tests {
     use super::*;
+    use cerk::runtime::channel::{RecordingSender, Sender as _};
 
     #[test]
     fn build_subscribe_config() {
@@ -335,4 +402,51 @@ mod tests {
         .collect();
         assert!(check_configurations(Config::HashMap(map)).is_err());
     }
+
+    #[test]
+    fn rejected_config_should_keep_the_previous_connection() -> Result<()> {
+        let id = "mqtt-output".to_string();
+        let map: HashMap<String, Config> = [
+            (
+                "host".to_string(),
+                Config::String("tcp://mqtt-broker:1883".to_string()),
+            ),
+            (
+                "send_topic".to_string(),
+                Config::String("outbox".to_string()),
+            ),
+        ]
+        .iter()
+        .cloned()
+        .collect();
+        let mut connection = Some(Connection {
+            client: Mosquitto::new_session(&id, false)?,
+            configs: build_configurations(Config::HashMap(map))?,
+        });
+        let (previous_sender, previous_receiver) = channel();
+        let mut sender = Some(previous_sender);
+        let data: ArcData = Arc::new(Mutex::new(Data {
+            unacked: HashMap::new(),
+            paused: false,
+        }));
+
+        let result = update_connection(
+            &id,
+            Config::HashMap(HashMap::new()),
+            &mut connection,
+            &mut sender,
+            &RecordingSender::default().clone_boxed(),
+            &data,
+        );
+        assert!(result.is_err());
+
+        let connection = connection.unwrap();
+        assert_eq!(connection.configs.send_topic.as_deref(), Some("outbox"));
+        // the results for the incoming CloudEvents still reach the on_message handler of the previous connection
+        sender
+            .unwrap()
+            .send(("1".to_string(), ProcessingResult::Successful))?;
+        assert_eq!(previous_receiver.recv()?.0, "1");
+        Ok(())
+    }
 }
@@ -1,10 +1,14 @@
-use cerk::kernel::{BrokerEvent, CloudEventRoutingArgs, Config, IncomingCloudEvent};
+use cerk::kernel::{
+    BrokerEvent, CloudEventRoutingArgs, Config, HealthCheckStatus, IncomingCloudEvent,
+    RoutingIdGenerator,
+};
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
 use cloudevents::Event;
 use serde_json;
 use std::io::{BufRead, BufReader};
 use std::os::unix::net::{UnixListener, UnixStream};
+use std::sync::Arc;
 use std::time::Duration;
 
 fn liten_to_stream(
@@ -12,6 +16,7 @@ fn liten_to_stream(
     listener: &UnixListener,
     mut stream: Option<BufReader<UnixStream>>,
     sender_to_kernel: &BoxedSender,
+    routing_ids: &RoutingIdGenerator,
     max_tries: usize,
 ) -> Option<BufReader<UnixStream>> {
     if max_tries == 0 {
@@ -22,7 +27,14 @@ fn liten_to_stream(
         None => match listener.accept() {
             Ok((socket, _)) => {
                 let stream = BufReader::new(socket);
-                liten_to_stream(id, listener, Some(stream), sender_to_kernel, max_tries - 1)
+                liten_to_stream(
+                    id,
+                    listener,
+                    Some(stream),
+                    sender_to_kernel,
+                    routing_ids,
+                    max_tries - 1,
+                )
             }
             Err(err) => std::panic::panic_any(err),
         },
@@ -43,9 +55,9 @@ fn liten_to_stream(
                                 debug!("{} deserialized event successfully", id);
                                 sender_to_kernel.send(BrokerEvent::IncomingCloudEvent(
                                     IncomingCloudEvent {
-                                        routing_id: id.clone(),
+                                        routing_id: routing_ids.next_id(),
                                         incoming_id: id.clone(),
-                                        cloud_event,
+                                        cloud_event: Arc::new(cloud_event),
                                         args: CloudEventRoutingArgs::default(),
                                     },
                                 ))
@@ -81,6 +93,7 @@ fn liten_to_stream(
 /// # Limitations
 ///
 /// * **reliability** this port does not support any `DeliveryGuarantee` other then `BestEffort` and so does never send a `IncomingCloudEventProcessed` message
+/// * **shutdown** the blocking socket operations are not interrupted, a `Shutdown` is only processed after a client closed its stream
 ///
 /// # open issues
 ///
@@ -94,6 +107,7 @@ pub fn port_input_unix_socket_json_start(
     info!("start input JSON over unix socket port with id {}", id);
     let mut listener: Option<UnixListener> = None;
     let mut stream: Option<BufReader<UnixStream>> = None;
+    let routing_ids = RoutingIdGenerator::new();
 
     loop {
         if let Some(broker_event) = inbox.receive_timeout(Duration::from_millis(100)) {
@@ -103,19 +117,35 @@ pub fn port_input_unix_socket_json_start(
                 }
                 BrokerEvent::ConfigUpdated(config, _) => {
                     info!("{} received ConfigUpdated", id);
-                    match config {
-                        Config::String(socket_path) => {
-                            listener = Some(UnixListener::bind(socket_path).unwrap());
-                        }
-                        _ => error!("{} received invalid config", id),
+                    let result = match config {
+                        Config::String(socket_path) => match UnixListener::bind(&socket_path) {
+                            Ok(new_listener) => {
+                                listener = Some(new_listener);
+                                Ok(())
+                            }
+                            Err(e) => Err(anyhow!("failed to bind to {}: {}", socket_path, e)),
+                        },
+                        _ => Err(anyhow!("expected the socket path as Config::String")),
                     };
+                    if let Err(ref e) = result {
+                        error!("{} received invalid config: {:?}", id, e);
+                    }
+                    sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
+                }
+                BrokerEvent::Shutdown => {
+                    info!("{} shut down", id);
+                    sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                    return;
+                }
+                BrokerEvent::HealthCheckRequest(event) => {
+                    sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
                 }
                 broker_event => warn!("event {} not implemented", broker_event),
             }
         }
 
         if let Some(listener) = listener.as_ref() {
-            stream = liten_to_stream(&id, listener, stream, &sender_to_kernel, 10);
+            stream = liten_to_stream(&id, listener, stream, &sender_to_kernel, &routing_ids, 10);
         }
     }
 }
@@ -1,6 +1,7 @@
 use anyhow::{Context, Result};
 use cerk::kernel::{
-    BrokerEvent, Config, OutgoingCloudEvent, OutgoingCloudEventProcessed, ProcessingResult,
+    BrokerEvent, Config, HealthCheckStatus, OutgoingCloudEvent, OutgoingCloudEventProcessed,
+    ProcessingResult,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
@@ -38,22 +39,54 @@ fn write_to_stream(connection: &mut Connection, event: &Event, max_tries: usize)
     }
 }
 
-fn send_event_out(
+/// Writes the CloudEvent to the stream and returns the `OutgoingCloudEventProcessed` if it requires an acknowledgment.
+fn write_event_out(
     id: &InternalServerId,
     connection: &mut Connection,
     event: &OutgoingCloudEvent,
-    sender_to_kernel: &BoxedSender,
-) -> Result<()> {
-    debug!("{} cloudevent received", id);
+) -> Option<BrokerEvent> {
     let send_result = write_to_stream(connection, &event.cloud_event, 10);
     if event.args.delivery_guarantee.requires_acknowledgment() {
-        sender_to_kernel.send(BrokerEvent::OutgoingCloudEventProcessed(
+        Some(BrokerEvent::OutgoingCloudEventProcessed(
             OutgoingCloudEventProcessed {
                 result: ProcessingResult::from(send_result),
                 routing_id: event.routing_id.to_string(),
+                delivery_id: event.delivery_id,
                 sender_id: id.clone(),
             },
-        ));
+        ))
+    } else {
+        None
+    }
+}
+
+fn send_event_out(
+    id: &InternalServerId,
+    connection: &mut Connection,
+    event: &OutgoingCloudEvent,
+    sender_to_kernel: &BoxedSender,
+) -> Result<()> {
+    debug!("{} cloudevent received", id);
+    if let Some(processed) = write_event_out(id, connection, event) {
+        sender_to_kernel.send(processed);
+    }
+    Ok(())
+}
+
+/// writes all CloudEvents and answers with one `BrokerEvent::Batch` for the whole batch
+fn send_events_out(
+    id: &InternalServerId,
+    connection: &mut Connection,
+    events: &[OutgoingCloudEvent],
+    sender_to_kernel: &BoxedSender,
+) -> Result<()> {
+    debug!("{} batch of {} cloudevents received", id, events.len());
+    let processed: Vec<_> = events
+        .iter()
+        .filter_map(|event| write_event_out(id, connection, event))
+        .collect();
+    if !processed.is_empty() {
+        sender_to_kernel.send(BrokerEvent::Batch(processed));
     }
     Ok(())
 }
@@ -84,6 +117,8 @@ struct Connection {
 ///
 /// e.g. `Config::String(String::from("path/to/the/socket"))`
 ///
+/// The port supports `BrokerEvent::OutgoingCloudEventBatch`, it answers with one `BrokerEvent::Batch` per batch.
+///
 /// # Examples
 ///
 /// * [UNIX Socket Example](https://github.com/ce-rust/cerk/tree/master/examples/examples/src/unix_socket)
@@ -102,16 +137,38 @@ pub fn port_output_unix_socket_json_start(
                 info!("{} initiated", id);
             }
             BrokerEvent::ConfigUpdated(config, _) => {
-                match update_config(&id, &config).context("create connection on config update") {
-                    Ok(l) => connection.listener = Some(l),
-                    Err(e) => error!("{} ConfigUpdated failed {:?}", id, e),
-                }
+                let result = match update_config(&id, &config)
+                    .context("create connection on config update")
+                {
+                    Ok(l) => {
+                        connection.listener = Some(l);
+                        Ok(())
+                    }
+                    Err(e) => {
+                        error!("{} ConfigUpdated failed {:?}", id, e);
+                        Err(e)
+                    }
+                };
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
             }
             BrokerEvent::OutgoingCloudEvent(event) => {
                 if let Err(e) = send_event_out(&id, &mut connection, &event, &sender_to_kernel) {
                     error!("{} was not able to send event out {:?}", id, e)
                 }
             }
+            BrokerEvent::OutgoingCloudEventBatch(events) => {
+                if let Err(e) = send_events_out(&id, &mut connection, &events, &sender_to_kernel) {
+                    error!("{} was not able to send events out {:?}", id, e)
+                }
+            }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -128,48 +185,56 @@ mod tests {
         BrokerEvent, CloudEventRoutingArgs, DeliveryGuarantee, OutgoingCloudEvent,
         OutgoingCloudEventProcessed, ProcessingResult,
     };
+    use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
     use cerk_runtime_threading::channel::new_channel_with_size;
     use cloudevents::Event;
+    use std::sync::Arc;
     use std::thread;
+    use std::thread::JoinHandle;
     use std::time::Duration;
 
     const ID: &'static str = "port-id";
 
+    fn shutdown(send_to_port: BoxedSender, recv_from_port: BoxedReceiver, port: JoinHandle<()>) {
+        send_to_port.send(BrokerEvent::Shutdown);
+        match recv_from_port.receive_timeout(Duration::from_millis(100)) {
+            Some(BrokerEvent::ShutdownComplete(id)) => assert_eq!(id, ID),
+            _ => panic!("port did not complete the shutdown"),
+        }
+        assert!(port.join().is_ok());
+    }
+
     fn build_event() -> OutgoingCloudEvent {
         OutgoingCloudEvent {
             routing_id: String::from("abc"),
-            cloud_event: Event::default(),
+            cloud_event: Arc::new(Event::default()),
             destination_id: ID.to_string(),
             args: CloudEventRoutingArgs::default(),
+            delivery_id: 7,
         }
     }
 
     /// We send a CloudEvent to the port with `DeliveryGuarantee::default()`, it does not need to be acked.
-    ///
-    /// test prints `thread '<unnamed>' panicked at 'called Result::unwrap() on an Err value: RecvError'`
-    /// this is okay -> we don't do a real shutdown of the port but just kill the communication channel
     #[test]
     fn send_unack_message() {
         let (send_to_port, recv) = new_channel_with_size(1);
         let (send, recv_from_port) = new_channel_with_size(1);
-        thread::spawn(move || {
+        let port = thread::spawn(move || {
             PORT_OUTPUT_UNIX_SOCKET(ID.to_string(), recv, send);
         });
         send_to_port.send(BrokerEvent::OutgoingCloudEvent(build_event()));
         let response = recv_from_port.receive_timeout(Duration::from_millis(10));
         assert!(response.is_none());
+        shutdown(send_to_port, recv_from_port, port);
     }
 
     /// We send a CloudEvent to the port with `DeliveryGuarantee::AtLeastOnce`, it needs to be acked.
     /// However, we haven't provide any config -> send nack
-    ///
-    /// test prints `thread '<unnamed>' panicked at 'called Result::unwrap() on an Err value: RecvError'`
-    /// this is okay -> we don't do a real shutdown of the port but just kill the communication channel
     #[test]
     fn send_ack_message_receive_nack() {
         let (send_to_port, recv) = new_channel_with_size(1);
         let (send, recv_from_port) = new_channel_with_size(1);
-        thread::spawn(move || {
+        let port = thread::spawn(move || {
             PORT_OUTPUT_UNIX_SOCKET(ID.to_string(), recv, send);
         });
         let mut event = build_event();
@@ -183,6 +248,7 @@ mod tests {
                 e,
                 OutgoingCloudEventProcessed {
                     routing_id: event.routing_id.to_string(),
+                    delivery_id: event.delivery_id,
                     sender_id: ID.to_string(),
                     result: ProcessingResult::PermanentError
                 }
@@ -190,5 +256,42 @@ mod tests {
         } else {
             assert!(false, "response has wrong type");
         }
+        shutdown(send_to_port, recv_from_port, port);
+    }
+
+    /// We send a batch with two CloudEvents that need to be acked, without config both fail in one answer.
+    #[test]
+    fn send_batch_receive_nacks() {
+        let (send_to_port, recv) = new_channel_with_size(1);
+        let (send, recv_from_port) = new_channel_with_size(1);
+        let port = thread::spawn(move || {
+            PORT_OUTPUT_UNIX_SOCKET(ID.to_string(), recv, send);
+        });
+        let mut event = build_event();
+        event.args.delivery_guarantee = DeliveryGuarantee::AtLeastOnce;
+        let mut second_event = event.clone();
+        second_event.delivery_id = 8;
+        send_to_port.send(BrokerEvent::OutgoingCloudEventBatch(vec![
+            event,
+            second_event,
+        ]));
+        let response = recv_from_port.receive_timeout(Duration::from_millis(10));
+
+        if let Some(BrokerEvent::Batch(events)) = response {
+            let delivery_ids: Vec<_> = events
+                .iter()
+                .map(|event| match event {
+                    BrokerEvent::OutgoingCloudEventProcessed(e) => {
+                        assert_eq!(e.result, ProcessingResult::PermanentError);
+                        e.delivery_id
+                    }
+                    _ => panic!("response has wrong type"),
+                })
+                .collect();
+            assert_eq!(delivery_ids, vec![7, 8]);
+        } else {
+            assert!(false, "response has wrong type");
+        }
+        shutdown(send_to_port, recv_from_port, port);
     }
 }
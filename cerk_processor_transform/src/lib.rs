@@ -0,0 +1,76 @@
+/*!
+
+This is a package for [CERK](https://github.com/ce-rust/cerk).
+CERK is an open source [CloudEvents](https://github.com/cloudevents/spec) Router written in Rust with a MicroKernel architecture.
+
+# Introduction
+
+CERK lets you route your [CloudEvents](https://github.com/cloudevents/spec) between different different ports.
+Ports are transport layer bindings over which CloudEvents can be exchanged.
+It is built with modularity and portability in mind.
+
+# Components
+
+CERK comes with a couple of prefabricated components, but implementing custom components is easy.
+
+A good overview is provided on [GitHub](https://github.com/ce-rust/cerk/).
+
+# This Component: Transform Processor
+
+The transform processor changes the CloudEvents before they are routed.
+
+It applies a list of transformations in order, each with mutations and optional rules.
+The rules are the `RoutingRules` of the rule-based router, the mutations of a transformation only apply to matching CloudEvents.
+The mutations `Set`, `Replace`, `SetExtension`, `RemoveExtension` and `MoveDataToExtension` are supported.
+
+A CloudEvent that could not be transformed (e.g., an invalid URL as source) is not passed on, the sender gets a `PermanentError`.
+Until the processor is configured, it rejects all CloudEvents with a `TransientError`.
+
+# Configurations
+
+The processor expects a `Config::String` as configuration.
+The string should be a json deserialized `transformation_rules::TransformationTable`.
+
+## Configuration Examples
+
+### Minimal
+
+`Config::String("[]".to_string())`
+
+### Extended
+
+```
+use serde_json;
+use cerk_processor_transform::{Mutation, Transformation, TransformationTable};
+use cerk_router_rule_based::{CloudEventFields, RoutingRules};
+
+let transformations: TransformationTable = vec![Transformation {
+  rules: Some(RoutingRules::StartsWith(
+    CloudEventFields::Type,
+    "com.example.order".to_string(),
+  )),
+  mutations: vec![
+    Mutation::Set(CloudEventFields::Type, Some("order".to_string())),
+    Mutation::MoveDataToExtension("/customer/id".to_string(), "customerid".to_string()),
+    Mutation::RemoveExtension("internal".to_string()),
+  ],
+}];
+
+let transformation_configs = serde_json::to_string(&transformations).unwrap();
+```
+
+*/
+
+#![deny(missing_docs)]
+
+#[macro_use]
+extern crate log;
+
+#[macro_use]
+extern crate anyhow;
+
+mod processor;
+mod transformation_rules;
+
+pub use self::processor::{processor_start, PROCESSOR_TRANSFORM};
+pub use self::transformation_rules::{Mutation, Transformation, TransformationTable};
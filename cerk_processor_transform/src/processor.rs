@@ -0,0 +1,294 @@
+use crate::transformation_rules::{Mutation, TransformationTable};
+use anyhow::{Context, Result};
+use cerk::kernel::{BrokerEvent, Config, HealthCheckStatus, ProcessingResult, SharedCloudEvent};
+use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
+use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
+use cerk_router_rule_based::CloudEventFields;
+use cloudevents::event::ExtensionValue;
+use cloudevents::{AttributesReader, AttributesWriter, Data, Event};
+use serde_json::error::Error as SerdeError;
+use serde_json::Value;
+use std::sync::Arc;
+use url::Url;
+
+fn parse_url(value: &str) -> Result<Url> {
+    Url::parse(value).with_context(|| format!("{} is not a valid URL", value))
+}
+
+fn get_field(field: &CloudEventFields, cloud_event: &Event) -> Option<String> {
+    match field {
+        CloudEventFields::Id => Some(cloud_event.id().to_string()),
+        CloudEventFields::Type => Some(cloud_event.ty().to_string()),
+        CloudEventFields::Source => Some(cloud_event.source().to_string()),
+        CloudEventFields::Subject => cloud_event.subject().map(String::from),
+        CloudEventFields::Dataschema => cloud_event.dataschema().map(|s| s.to_string()),
+    }
+}
+
+fn set_field(
+    field: &CloudEventFields,
+    cloud_event: &mut Event,
+    value: Option<String>,
+) -> Result<()> {
+    match (field, value) {
+        (CloudEventFields::Id, Some(value)) => {
+            cloud_event.set_id(value);
+        }
+        (CloudEventFields::Type, Some(value)) => {
+            cloud_event.set_type(value);
+        }
+        (CloudEventFields::Source, Some(value)) => {
+            cloud_event.set_source(parse_url(&value)?);
+        }
+        (CloudEventFields::Subject, value) => {
+            cloud_event.set_subject(value);
+        }
+        (CloudEventFields::Dataschema, Some(value)) => {
+            cloud_event.set_dataschema(Some(parse_url(&value)?));
+        }
+        (CloudEventFields::Dataschema, None) => {
+            cloud_event.set_dataschema(None as Option<Url>);
+        }
+        (field, None) => bail!("{:?} is required and can not be removed", field),
+    }
+    Ok(())
+}
+
+/// Removes the value the JSON pointer points to and returns it.
+fn take_json_value(data: &mut Value, pointer: &str) -> Option<Value> {
+    let (parent, key) = pointer.rsplit_once('/')?;
+    let key = key.replace("~1", "/").replace("~0", "~");
+    match data.pointer_mut(parent)? {
+        Value::Object(map) => map.remove(&key),
+        Value::Array(list) => key
+            .parse::<usize>()
+            .ok()
+            .filter(|index| *index < list.len())
+            .map(|index| list.remove(index)),
+        _ => None,
+    }
+}
+
+fn to_extension_value(value: Value) -> ExtensionValue {
+    match value {
+        Value::String(value) => ExtensionValue::String(value),
+        Value::Bool(value) => ExtensionValue::Boolean(value),
+        Value::Number(ref number) if number.is_i64() => {
+            ExtensionValue::Integer(number.as_i64().unwrap())
+        }
+        value => ExtensionValue::String(value.to_string()),
+    }
+}
+
+fn apply_mutation(mutation: &Mutation, cloud_event: &mut Event) -> Result<()> {
+    match mutation {
+        Mutation::Set(field, value) => set_field(field, cloud_event, value.clone())?,
+        Mutation::Replace(field, from, to) => {
+            if let Some(value) = get_field(field, cloud_event) {
+                set_field(field, cloud_event, Some(value.replace(from.as_str(), to)))?;
+            }
+        }
+        Mutation::SetExtension(name, value) => cloud_event.set_extension(name, value.as_str()),
+        Mutation::RemoveExtension(name) => {
+            cloud_event.remove_extension(name);
+        }
+        Mutation::MoveDataToExtension(pointer, name) => {
+            if let Some(Data::Json(data)) = cloud_event.data() {
+                let mut data = data.clone();
+                match take_json_value(&mut data, pointer) {
+                    Some(value) => {
+                        cloud_event.set_data_unchecked(data);
+                        cloud_event.set_extension(name, to_extension_value(value));
+                    }
+                    None => debug!("data of event_id={} has no {}", cloud_event.id(), pointer),
+                }
+            }
+        }
+    }
+    Ok(())
+}
+
+/// Applies the mutations of all matching transformations to the CloudEvent.
+/// The CloudEvent is only copied if a transformation matches and it is still shared.
+fn transform(table: &TransformationTable, cloud_event: &mut SharedCloudEvent) -> Result<()> {
+    for transformation in table.iter() {
+        let matches = transformation
+            .rules
+            .as_ref()
+            .is_none_or(|rules| rules.matches(cloud_event));
+        if matches {
+            for mutation in transformation.mutations.iter() {
+                apply_mutation(mutation, Arc::make_mut(cloud_event))
+                    .with_context(|| format!("failed to apply {:?}", mutation))?;
+            }
+        }
+    }
+    Ok(())
+}
+
+fn parse_config(config_update: String) -> Result<TransformationTable, SerdeError> {
+    serde_json::from_str::<TransformationTable>(&config_update)
+}
+
+fn update_config(config: &mut Option<TransformationTable>, updated_config: Config) -> Result<()> {
+    match updated_config {
+        Config::String(string_config) => {
+            *config = Some(parse_config(string_config).context("failed to parse transformations")?);
+            Ok(())
+        }
+        _ => bail!("expected the transformations as Config::String"),
+    }
+}
+
+/// This is the main function to start the processor.
+pub fn processor_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_kernel: BoxedSender) {
+    info!("start transform processor with id {}", id);
+    let mut config: Option<TransformationTable> = None;
+    loop {
+        match inbox.receive() {
+            BrokerEvent::Init => info!("{} initiated", id),
+            BrokerEvent::IncomingCloudEvent(mut event) => match config {
+                Some(ref table) => match transform(table, &mut event.cloud_event) {
+                    Ok(()) => sender_to_kernel.send(event.pass_on(id.clone())),
+                    Err(e) => {
+                        error!(
+                            "transformation of event_id={} failed: {:?}",
+                            event.routing_id, e
+                        );
+                        sender_to_kernel
+                            .send(event.fail(id.clone(), ProcessingResult::PermanentError));
+                    }
+                },
+                None => {
+                    // the config is probably on its way, the sender may redeliver the event later
+                    warn!(
+                        "{} is not configured yet, event_id={} rejected",
+                        id, event.routing_id
+                    );
+                    sender_to_kernel.send(event.fail(id.clone(), ProcessingResult::TransientError));
+                }
+            },
+            BrokerEvent::ConfigUpdated(updated_config, _) => {
+                let result = update_config(&mut config, updated_config);
+                if let Err(ref e) = result {
+                    error!("{} keeps its previous config: {:?}", id, e);
+                }
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
+            }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
+            broker_event => warn!("event {} not implemented", broker_event),
+        }
+    }
+}
+
+/// This is the pointer for the main function to start the processor.
+pub static PROCESSOR_TRANSFORM: InternalServerFnRefStatic = &(processor_start as InternalServerFn);
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::transformation_rules::Transformation;
+    use cerk_router_rule_based::RoutingRules;
+    use cloudevents::{EventBuilder, EventBuilderV10};
+    use serde_json::json;
+
+    fn build_event(ty: &str) -> SharedCloudEvent {
+        Arc::new(
+            EventBuilderV10::new()
+                .id("1")
+                .ty(ty)
+                .source("http://example.com/source")
+                .data(
+                    "application/json",
+                    json!({"customer": {"id": 42, "name": "a"}}),
+                )
+                .build()
+                .unwrap(),
+        )
+    }
+
+    #[test]
+    fn transform_only_matching_events() -> Result<()> {
+        let table = vec![Transformation {
+            rules: Some(RoutingRules::Exact(
+                CloudEventFields::Type,
+                Some("order.created".to_string()),
+            )),
+            mutations: vec![
+                Mutation::Set(CloudEventFields::Type, Some("order.received".to_string())),
+                Mutation::Replace(
+                    CloudEventFields::Source,
+                    "example.com".to_string(),
+                    "example.org".to_string(),
+                ),
+                Mutation::SetExtension("tenant".to_string(), "a".to_string()),
+            ],
+        }];
+
+        let original = build_event("order.created");
+        let mut matching = original.clone();
+        transform(&table, &mut matching)?;
+        assert_eq!(original.ty(), "order.created");
+        assert_eq!(matching.ty(), "order.received");
+        assert_eq!(matching.source().as_str(), "http://example.org/source");
+        assert_eq!(
+            matching.extension("tenant"),
+            Some(&ExtensionValue::String("a".to_string()))
+        );
+
+        let original = build_event("order.deleted");
+        let mut other = original.clone();
+        transform(&table, &mut other)?;
+        assert!(Arc::ptr_eq(&original, &other));
+        Ok(())
+    }
+
+    #[test]
+    fn move_data_to_extension() -> Result<()> {
+        let table = vec![Transformation {
+            rules: None,
+            mutations: vec![
+                Mutation::MoveDataToExtension("/customer/id".to_string(), "customerid".to_string()),
+                Mutation::MoveDataToExtension("/missing".to_string(), "missing".to_string()),
+            ],
+        }];
+
+        let mut event = build_event("order.created");
+        transform(&table, &mut event)?;
+        assert_eq!(
+            event.extension("customerid"),
+            Some(&ExtensionValue::Integer(42))
+        );
+        assert_eq!(event.extension("missing"), None);
+        assert_eq!(
+            event.data(),
+            Some(&Data::Json(json!({"customer": {"name": "a"}})))
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_mutation_should_fail() {
+        let table = vec![Transformation {
+            rules: None,
+            mutations: vec![Mutation::Set(
+                CloudEventFields::Source,
+                Some("not a url".to_string()),
+            )],
+        }];
+        assert!(transform(&table, &mut build_event("order.created")).is_err());
+
+        let table = vec![Transformation {
+            rules: None,
+            mutations: vec![Mutation::Set(CloudEventFields::Type, None)],
+        }];
+        assert!(transform(&table, &mut build_event("order.created")).is_err());
+    }
+}
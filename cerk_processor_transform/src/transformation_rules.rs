@@ -0,0 +1,93 @@
+use cerk_router_rule_based::{CloudEventFields, RoutingRules};
+use serde_derive::{Deserialize, Serialize};
+
+/// A change of a CloudEvent.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub enum Mutation {
+    /// Sets the field, `None` removes it (only possible for `Subject` and `Dataschema`)
+    ///
+    /// # Arguments
+    ///
+    /// * FieldName
+    /// * new value, `Source` and `Dataschema` have to be valid URLs
+    Set(CloudEventFields, Option<String>),
+
+    /// Replaces all occurrences of a string in the field, a missing field stays missing
+    ///
+    /// # Arguments
+    ///
+    /// * FieldName
+    /// * String to search for
+    /// * replacement
+    Replace(CloudEventFields, String, String),
+
+    /// Sets the extension attribute to a string
+    ///
+    /// # Arguments
+    ///
+    /// * name of the extension attribute
+    /// * new value
+    SetExtension(String, String),
+
+    /// Removes the extension attribute
+    ///
+    /// # Arguments
+    ///
+    /// * name of the extension attribute
+    RemoveExtension(String),
+
+    /// Moves a field of the JSON data into an extension attribute.
+    /// Strings, booleans and integers keep their type, other values are serialized as JSON string.
+    /// Nothing happens if the data is not JSON or the field is missing.
+    ///
+    /// # Arguments
+    ///
+    /// * [JSON pointer](https://tools.ietf.org/html/rfc6901) to the field, e.g. `/customer/id`
+    /// * name of the extension attribute
+    MoveDataToExtension(String, String),
+}
+
+/// Mutations that are applied to all CloudEvents that match the rules.
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct Transformation {
+    /// the mutations only apply to matching CloudEvents, `None` matches all CloudEvents
+    #[serde(default)]
+    pub rules: Option<RoutingRules>,
+    /// the mutations, applied in the given order
+    pub mutations: Vec<Mutation>,
+}
+
+/// transformation table
+///
+/// Transformations in the order they are applied, a transformation sees the changes of the previous ones.
+pub type TransformationTable = Vec<Transformation>;
+
+#[test]
+fn serialize() {
+    let transformation = Transformation {
+        rules: None,
+        mutations: vec![Mutation::Set(
+            CloudEventFields::Type,
+            Some("new".to_string()),
+        )],
+    };
+
+    let json = serde_json::to_string(&transformation).unwrap();
+    assert_eq!(
+        json,
+        "{\"rules\":null,\"mutations\":[{\"Set\":[\"Type\",\"new\"]}]}"
+    );
+}
+
+#[test]
+fn deserialize_without_rules() {
+    let json = "{\"mutations\":[{\"RemoveExtension\":\"tenant\"}]}";
+    let transformation: Transformation = serde_json::from_str(json).unwrap();
+    assert_eq!(
+        transformation,
+        Transformation {
+            rules: None,
+            mutations: vec![Mutation::RemoveExtension("tenant".to_string())],
+        }
+    );
+}
@@ -1,6 +1,7 @@
 use anyhow::Result;
 use cerk::kernel::{
-    BrokerEvent, Config, IncomingCloudEvent, OutgoingCloudEvent, ProcessingResult, RoutingResult,
+    BrokerEvent, Config, HealthCheckStatus, IncomingCloudEvent, OutgoingCloudEvent,
+    ProcessingResult, RoutingResult,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
@@ -19,6 +20,7 @@ fn route_event(
                 cloud_event: event.cloud_event.clone(),
                 destination_id: port_id.clone(),
                 args: event.args.clone(),
+                delivery_id: 0,
             }),
             _ => {
                 error!("No valid routing config found, message could not be routed!");
@@ -56,7 +58,27 @@ pub fn router_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_kernel
                     }));
                 }
             }
-            BrokerEvent::ConfigUpdated(updated_config, _) => config = updated_config,
+            BrokerEvent::ConfigUpdated(updated_config, _) => {
+                let result = match Vec::<Config>::try_from(&updated_config) {
+                    Ok(_) => {
+                        config = updated_config;
+                        Ok(())
+                    }
+                    Err(e) => {
+                        error!("{} keeps its previous config: {:?}", id, e);
+                        Err(e)
+                    }
+                };
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
+            }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
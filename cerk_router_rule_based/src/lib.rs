@@ -26,7 +26,10 @@ The operations `And`, `Or`, `Contains`, `StartsWith` and more are supported.
 # Configurations
 
 The Socket expects a `Config::String` as configuration.
-The string should be a json deserialized `routing_rules::RoutingTable`.
+The string should be a json deserialized `routing_rules::RoutingTable`
+or a `routing_rules::RoutingConfig`, which adds the ack policies of the routings to the table.
+The ack policy defines how many copies of a routing have to be acknowledged before the input port gets the acknowledgment
+(e.g. `"Any"` for redundant outputs or `{"AtLeast": 2}` for a quorum), by default all of them.
 
 ## Configuration Examples
 
@@ -57,6 +60,30 @@ let routing_rules: RoutingTable = [(
 let routing_configs = serde_json::to_string(&routing_rules).unwrap();
 ```
 
+### With Ack Policies
+
+```
+use serde_json;
+use cerk::kernel::AckPolicy;
+use cerk_router_rule_based::{AckPolicyRule, CloudEventFields, RoutingConfig, RoutingRules};
+
+let routing_config = RoutingConfig {
+  routes: [
+    ("primary-output".to_string(), RoutingRules::And(vec![])),
+    ("backup-output".to_string(), RoutingRules::And(vec![])),
+  ]
+  .iter()
+  .cloned()
+  .collect(),
+  ack_policies: vec![AckPolicyRule {
+    rules: RoutingRules::StartsWith(CloudEventFields::Type, "metrics.".to_string()),
+    ack_policy: AckPolicy::Any,
+  }],
+};
+
+let routing_configs = serde_json::to_string(&routing_config).unwrap();
+```
+
 # Examples
 
 * [Rule Based Routing Example](https://github.com/ce-rust/cerk/tree/master/examples/examples/src/rule_based_routing)
@@ -76,4 +103,6 @@ mod router;
 mod routing_rules;
 
 pub use self::router::{router_start, ROUTER_RULE_BASED};
-pub use self::routing_rules::{CloudEventFields, RoutingRules, RoutingTable};
+pub use self::routing_rules::{
+    AckPolicyRule, CloudEventFields, RoutingConfig, RoutingRules, RoutingTable,
+};
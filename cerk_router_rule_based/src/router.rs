@@ -1,53 +1,19 @@
-use crate::routing_rules::{CloudEventFields, RoutingRules, RoutingTable};
-use anyhow::Result;
+use crate::routing_rules::{RoutingConfig, RoutingTable};
+use anyhow::{Context, Result};
 use cerk::kernel::{
-    BrokerEvent, Config, IncomingCloudEvent, OutgoingCloudEvent, ProcessingResult, RoutingResult,
+    BrokerEvent, CloudEventRoutingArgs, Config, HealthCheckStatus, IncomingCloudEvent,
+    OutgoingCloudEvent, ProcessingResult, RoutingResult,
 };
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerFnRefStatic, InternalServerId};
-use cloudevents::{AttributesReader, Event};
+use serde_derive::Deserialize;
 use serde_json;
 use serde_json::error::Error as SerdeError;
 
-fn compare_field<F>(field: &CloudEventFields, cloud_event: &Event, compare: F) -> bool
-where
-    F: for<'a> Fn(Option<&'a str>) -> bool,
-{
-    match field {
-        CloudEventFields::Id => compare(Some(cloud_event.id())),
-        CloudEventFields::Source => compare(Some(cloud_event.source().as_str())),
-        CloudEventFields::Subject => cloud_event
-            .subject()
-            .and_then(|s| Some(compare(Some(s))))
-            .unwrap_or_else(|| false),
-        CloudEventFields::Dataschema => compare(cloud_event.dataschema().map(|s| s.as_str())),
-        CloudEventFields::Type => compare(Some(cloud_event.ty())),
-    }
-}
-
-fn route_to_port(rules: &RoutingRules, cloud_event: &Event) -> bool {
-    match rules {
-        RoutingRules::And(rules) => rules.iter().all(|rule| route_to_port(rule, cloud_event)),
-        RoutingRules::Or(rules) => rules.iter().any(|rule| route_to_port(rule, cloud_event)),
-        RoutingRules::Exact(field, value) => compare_field(field, cloud_event, |field| {
-            field == value.as_ref().map(|s| &**s)
-        }),
-        RoutingRules::Contains(field, value) => compare_field(field, cloud_event, |field| {
-            field.map_or(false, |f| f.contains(value.as_str()))
-        }),
-        RoutingRules::StartsWith(field, value) => compare_field(field, cloud_event, |field| {
-            field.map_or(false, |f| f.starts_with(value.as_str()))
-        }),
-        RoutingRules::EndsWith(field, value) => compare_field(field, cloud_event, |field| {
-            field.map_or(false, |f| f.ends_with(value.as_str()))
-        }),
-    }
-}
-
 fn route_event(
     event: &IncomingCloudEvent,
     sender_to_kernel: &BoxedSender,
-    port_config: &Option<RoutingTable>,
+    port_config: &Option<RoutingConfig>,
 ) -> Result<()> {
     let IncomingCloudEvent {
         cloud_event,
@@ -55,36 +21,63 @@ fn route_event(
         incoming_id,
         args,
     } = event;
-    let routing: Vec<_> = port_config
-        .as_ref()
-        .ok_or(anyhow!("no config"))?
+    let config = port_config.as_ref().ok_or(anyhow!("no config"))?;
+    let routing: Vec<_> = config
+        .routes
         .iter()
-        .filter(|(_, rules)| route_to_port(rules, &cloud_event))
+        .filter(|(_, rules)| rules.matches(&cloud_event))
         .map(|(port_id, _)| OutgoingCloudEvent {
             routing_id: routing_id.clone(),
             cloud_event: cloud_event.clone(),
             destination_id: port_id.clone(),
             args: args.clone(),
+            delivery_id: 0,
         })
         .collect();
     sender_to_kernel.send(BrokerEvent::RoutingResult(RoutingResult {
         routing_id: routing_id.clone(),
         incoming_id: incoming_id.clone(),
         routing,
-        args: args.clone(),
+        args: CloudEventRoutingArgs {
+            ack_policy: config.ack_policy_of(cloud_event),
+            ..args.clone()
+        },
         result: ProcessingResult::Successful,
     }));
     Ok(())
 }
 
-fn parse_config(config_update: String) -> Result<RoutingTable, SerdeError> {
-    serde_json::from_str::<RoutingTable>(&config_update)
+/// The formats of the config, a `RoutingConfig` is tried first.
+/// It does not accept unknown fields and its `routes` are a table of rules,
+/// so a plain `RoutingTable` is never mistaken for it, even if it has a port named `routes`.
+#[derive(Deserialize)]
+#[serde(untagged)]
+enum ConfigFormat {
+    Config(RoutingConfig),
+    Table(RoutingTable),
+}
+
+fn parse_config(config_update: String) -> Result<RoutingConfig, SerdeError> {
+    match serde_json::from_str(&config_update)? {
+        ConfigFormat::Config(config) => Ok(config),
+        ConfigFormat::Table(table) => Ok(RoutingConfig::from(table)),
+    }
+}
+
+fn update_config(config: &mut Option<RoutingConfig>, updated_config: Config) -> Result<()> {
+    match updated_config {
+        Config::String(string_config) => {
+            *config = Some(parse_config(string_config).context("failed to parse routing rules")?);
+            Ok(())
+        }
+        _ => bail!("expected the routing rules as Config::String"),
+    }
 }
 
 /// This is the main function to start the router.
 pub fn router_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_kernel: BoxedSender) {
     info!("start broadcast router with id {}", id);
-    let mut config: Option<RoutingTable> = None;
+    let mut config: Option<RoutingConfig> = None;
     loop {
         match inbox.receive() {
             BrokerEvent::Init => info!("{} initiated", id),
@@ -101,12 +94,19 @@ pub fn router_start(id: InternalServerId, inbox: BoxedReceiver, sender_to_kernel
                 }
             }
             BrokerEvent::ConfigUpdated(updated_config, _) => {
-                if let Config::String(string_config) = updated_config {
-                    match parse_config(string_config) {
-                        Ok(parsed_config) => config = Some(parsed_config),
-                        Err(err) => panic!("was not able to parse configs {:?}", err),
-                    }
+                let result = update_config(&mut config, updated_config);
+                if let Err(ref e) = result {
+                    error!("{} keeps its previous config: {:?}", id, e);
                 }
+                sender_to_kernel.send(BrokerEvent::config_result(id.clone(), result));
+            }
+            BrokerEvent::Shutdown => {
+                info!("{} shut down", id);
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
             }
             broker_event => warn!("event {} not implemented", broker_event),
         }
@@ -120,14 +120,107 @@ pub static ROUTER_RULE_BASED: InternalServerFnRefStatic = &(router_start as Inte
 mod tests {
     use super::*;
     use crate::routing_rules::{CloudEventFields, RoutingRules};
-    use cloudevents::{EventBuilder, EventBuilderV10};
+    use cerk::kernel::AckPolicy;
+    use cerk::runtime::channel::{RecordingSender, Sender};
+    use cloudevents::{AttributesWriter, Event, EventBuilder, EventBuilderV10};
+    use std::sync::Arc;
+
+    #[test]
+    fn copies_should_share_the_cloud_event() -> Result<()> {
+        let recording = RecordingSender::default();
+        let table: RoutingTable = vec!["a", "b"]
+            .into_iter()
+            .map(|port| (port.to_string(), RoutingRules::And(vec![])))
+            .collect();
+        let event = IncomingCloudEvent {
+            incoming_id: "input".to_string(),
+            routing_id: "1".to_string(),
+            cloud_event: Arc::new(Event::default()),
+            args: CloudEventRoutingArgs::default(),
+        };
+        route_event(&event, &recording.clone_boxed(), &Some(table.into()))?;
+
+        match recording.take_events().as_slice() {
+            [BrokerEvent::RoutingResult(result)] => {
+                assert_eq!(result.routing.len(), 2);
+                for copy in result.routing.iter() {
+                    assert!(Arc::ptr_eq(&copy.cloud_event, &event.cloud_event));
+                }
+            }
+            _ => panic!("expected one RoutingResult"),
+        }
+        Ok(())
+    }
+
+    #[test]
+    fn invalid_config_should_keep_the_previous_one() -> Result<()> {
+        let mut config = None;
+        update_config(
+            &mut config,
+            Config::String(r#"{"a": {"And": []}}"#.to_string()),
+        )?;
+        assert!(update_config(&mut config, Config::String("{".to_string())).is_err());
+        assert!(update_config(&mut config, Config::Null).is_err());
+        assert_eq!(config.map(|config| config.routes.len()), Some(1));
+        Ok(())
+    }
+
+    #[test]
+    fn table_with_a_port_named_routes_should_stay_a_table() -> Result<()> {
+        let mut config = None;
+        update_config(
+            &mut config,
+            Config::String(r#"{"routes": {"And": []}, "ack_policies": {"Or": []}}"#.to_string()),
+        )?;
+        let config = config.unwrap();
+        assert_eq!(config.routes.len(), 2);
+        assert_eq!(config.routes["routes"], RoutingRules::And(vec![]));
+        assert!(config.ack_policies.is_empty());
+        Ok(())
+    }
+
+    #[test]
+    fn routing_should_carry_the_ack_policy_of_the_cloud_event() -> Result<()> {
+        let mut config = None;
+        update_config(
+            &mut config,
+            Config::String(
+                r#"{
+                    "routes": {"a": {"And": []}, "b": {"And": []}},
+                    "ack_policies": [
+                        {"rules": {"Exact": ["Id", "redundant"]}, "ack_policy": "Any"}
+                    ]
+                }"#
+                .to_string(),
+            ),
+        )?;
+        let recording = RecordingSender::default();
+        for (id, ack_policy) in [("redundant", AckPolicy::Any), ("other", AckPolicy::All)] {
+            let mut cloud_event = Event::default();
+            cloud_event.set_id(id);
+            let event = IncomingCloudEvent {
+                incoming_id: "input".to_string(),
+                routing_id: id.to_string(),
+                cloud_event: Arc::new(cloud_event),
+                args: CloudEventRoutingArgs::default(),
+            };
+            route_event(&event, &recording.clone_boxed(), &config)?;
+            match recording.take_events().pop() {
+                Some(BrokerEvent::RoutingResult(result)) => {
+                    assert_eq!(result.routing.len(), 2);
+                    assert_eq!(result.args.ack_policy, ack_policy);
+                }
+                _ => panic!("expected a RoutingResult"),
+            }
+        }
+        Ok(())
+    }
 
     #[test]
     fn rout_to_port_by_id() {
         let rule = RoutingRules::Exact(CloudEventFields::Id, Some("1234".to_string()));
         // positive
-        assert!(route_to_port(
-            &rule,
+        assert!(rule.matches(
             &EventBuilderV10::new()
                 .id("1234")
                 .ty("test type")
@@ -136,8 +229,7 @@ mod tests {
                 .unwrap(),
         ));
         // negative
-        assert!(!route_to_port(
-            &rule,
+        assert!(!rule.matches(
             &EventBuilderV10::new()
                 .id("12345")
                 .ty("test type")
@@ -154,8 +246,7 @@ mod tests {
             RoutingRules::Contains(CloudEventFields::Source, "testsource".to_string()),
         ]);
         // positive
-        assert!(route_to_port(
-            &rule,
+        assert!(rule.matches(
             &EventBuilderV10::new()
                 .id("1")
                 .ty("testtype1")
@@ -165,8 +256,7 @@ mod tests {
         ));
         // negative
         // positive
-        assert!(!route_to_port(
-            &rule,
+        assert!(!rule.matches(
             &EventBuilderV10::new()
                 .id("1")
                 .ty("1testtype")
@@ -1,3 +1,5 @@
+use cerk::kernel::AckPolicy;
+use cloudevents::{AttributesReader, Event};
 use serde_derive::{Deserialize, Serialize};
 use std::collections::HashMap;
 
@@ -59,11 +61,89 @@ pub enum RoutingRules {
     EndsWith(CloudEventFields, String),
 }
 
+fn compare_field<F>(field: &CloudEventFields, cloud_event: &Event, compare: F) -> bool
+where
+    F: for<'a> Fn(Option<&'a str>) -> bool,
+{
+    match field {
+        CloudEventFields::Id => compare(Some(cloud_event.id())),
+        CloudEventFields::Source => compare(Some(cloud_event.source().as_str())),
+        CloudEventFields::Subject => cloud_event
+            .subject()
+            .and_then(|s| Some(compare(Some(s))))
+            .unwrap_or_else(|| false),
+        CloudEventFields::Dataschema => compare(cloud_event.dataschema().map(|s| s.as_str())),
+        CloudEventFields::Type => compare(Some(cloud_event.ty())),
+    }
+}
+
+impl RoutingRules {
+    /// Checks if the CloudEvent matches the rules.
+    pub fn matches(&self, cloud_event: &Event) -> bool {
+        match self {
+            RoutingRules::And(rules) => rules.iter().all(|rule| rule.matches(cloud_event)),
+            RoutingRules::Or(rules) => rules.iter().any(|rule| rule.matches(cloud_event)),
+            RoutingRules::Exact(field, value) => compare_field(field, cloud_event, |field| {
+                field == value.as_ref().map(|s| &**s)
+            }),
+            RoutingRules::Contains(field, value) => compare_field(field, cloud_event, |field| {
+                field.map_or(false, |f| f.contains(value.as_str()))
+            }),
+            RoutingRules::StartsWith(field, value) => compare_field(field, cloud_event, |field| {
+                field.map_or(false, |f| f.starts_with(value.as_str()))
+            }),
+            RoutingRules::EndsWith(field, value) => compare_field(field, cloud_event, |field| {
+                field.map_or(false, |f| f.ends_with(value.as_str()))
+            }),
+        }
+    }
+}
+
 /// routing rules table
 ///
 /// Routing rules indexed by the adapter that should receive the event
 pub type RoutingTable = HashMap<String, RoutingRules>;
 
+/// ack policy for the routings of the CloudEvents that match the rules
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq)]
+pub struct AckPolicyRule {
+    /// the CloudEvents the policy applies to
+    pub rules: RoutingRules,
+    /// how many copies of the routing have to be acknowledged
+    pub ack_policy: AckPolicy,
+}
+
+/// routing table with the ack policies of the routings
+#[derive(Debug, Serialize, Deserialize, Clone, PartialEq, Default)]
+#[serde(deny_unknown_fields)]
+pub struct RoutingConfig {
+    /// routing rules indexed by the adapter that should receive the event
+    pub routes: RoutingTable,
+    /// The first policy whose rules match the CloudEvent applies to its routing.
+    /// Without a match, all copies have to be acknowledged (`AckPolicy::All`).
+    #[serde(default)]
+    pub ack_policies: Vec<AckPolicyRule>,
+}
+
+impl RoutingConfig {
+    /// The ack policy for the routing of the CloudEvent.
+    pub fn ack_policy_of(&self, cloud_event: &Event) -> AckPolicy {
+        self.ack_policies
+            .iter()
+            .find(|policy| policy.rules.matches(cloud_event))
+            .map_or(AckPolicy::default(), |policy| policy.ack_policy)
+    }
+}
+
+impl From<RoutingTable> for RoutingConfig {
+    fn from(routes: RoutingTable) -> Self {
+        RoutingConfig {
+            routes,
+            ack_policies: vec![],
+        }
+    }
+}
+
 #[test]
 fn serialize() {
     let rules = RoutingRules::Contains(CloudEventFields::Id, "1".to_string());
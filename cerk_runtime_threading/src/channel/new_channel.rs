@@ -1,7 +1,9 @@
 use super::{ThreadingReceiver, ThreadingSender};
 use crate::channel::sender::ThreadingKernelSender;
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
+use std::sync::atomic::AtomicUsize;
 use std::sync::mpsc::{channel, sync_channel};
+use std::sync::Arc;
 
 /// Create a new channel with a `ThreadingSender` and a `ThreadingReceiver`.
 /// The implementation is based on `std::sync::mpsc` sync_channel model.
@@ -22,11 +24,13 @@ pub fn new_channel_with_size(bound: usize) -> (BoxedSender, BoxedReceiver) {
 /// The implementation is based on `std::sync::mpsc` channel model.
 ///
 /// This channel has an "infinite buffer" and should only be used to send message from the kernel to other components, so that the kernel is never blocked.
+/// The sender reports the number of queued events, the kernel uses it for flow control.
 ///
 pub fn new_channel_kernel_to_component() -> (BoxedSender, BoxedReceiver) {
     let (tx, rx) = channel();
+    let queued = Arc::new(AtomicUsize::new(0));
     return (
-        Box::new(ThreadingKernelSender::new(tx)),
-        Box::new(ThreadingReceiver::new(rx)),
+        Box::new(ThreadingKernelSender::new(tx, queued.clone())),
+        Box::new(ThreadingReceiver::new_counted(rx, queued)),
     );
 }
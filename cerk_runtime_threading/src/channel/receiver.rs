@@ -1,27 +1,48 @@
 use cerk::kernel::BrokerEvent;
 use cerk::runtime::channel::Receiver as CerkReceiver;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::Receiver;
+use std::sync::Arc;
 use std::time::Duration;
 
 /// Implementation for `cerk::runtime::channel::Receiver` that uses `std::sync::mpsc::SyncSender` channel sender.
 pub struct ThreadingReceiver {
     receiver: Receiver<BrokerEvent>,
+    queued: Option<Arc<AtomicUsize>>,
 }
 
 impl ThreadingReceiver {
     #[allow(missing_docs)]
     pub fn new(receiver: Receiver<BrokerEvent>) -> Self {
-        ThreadingReceiver { receiver }
+        ThreadingReceiver {
+            receiver,
+            queued: None,
+        }
+    }
+
+    /// Create a receiver that counts the queued events together with `ThreadingKernelSender`.
+    pub fn new_counted(receiver: Receiver<BrokerEvent>, queued: Arc<AtomicUsize>) -> Self {
+        ThreadingReceiver {
+            receiver,
+            queued: Some(queued),
+        }
+    }
+
+    fn received(&self, event: BrokerEvent) -> BrokerEvent {
+        if let Some(queued) = &self.queued {
+            queued.fetch_sub(1, Ordering::Relaxed);
+        }
+        event
     }
 }
 
 impl CerkReceiver for ThreadingReceiver {
     fn receive(&self) -> BrokerEvent {
-        self.receiver.recv().unwrap()
+        self.received(self.receiver.recv().unwrap())
     }
     fn receive_timeout(&self, timeout: Duration) -> Option<BrokerEvent> {
         match self.receiver.recv_timeout(timeout) {
-            Ok(msg) => Some(msg),
+            Ok(msg) => Some(self.received(msg)),
             Err(_) => None,
         }
     }
@@ -1,6 +1,8 @@
 use cerk::kernel::BrokerEvent;
 use cerk::runtime::channel::Sender as CerkSender;
+use std::sync::atomic::{AtomicUsize, Ordering};
 use std::sync::mpsc::{Sender, SyncSender};
+use std::sync::Arc;
 
 /// Implementation for `cerk::runtime::channel::Sender` that uses `std::sync::mpsc::SyncSender` channel sender.
 pub struct ThreadingSender {
@@ -26,24 +28,35 @@ impl CerkSender for ThreadingSender {
 }
 
 /// Implementation for `cerk::runtime::channel::Sender` that uses `std::sync::mpsc::Sender` channel sender.
+/// It counts the queued events together with `ThreadingReceiver::new_counted`.
 pub struct ThreadingKernelSender {
     sender: Sender<BrokerEvent>,
+    queued: Arc<AtomicUsize>,
 }
 
 impl ThreadingKernelSender {
     #[allow(missing_docs)]
-    pub fn new(sender: Sender<BrokerEvent>) -> Self {
-        return ThreadingKernelSender { sender };
+    pub fn new(sender: Sender<BrokerEvent>, queued: Arc<AtomicUsize>) -> Self {
+        return ThreadingKernelSender { sender, queued };
     }
 }
 
 impl CerkSender for ThreadingKernelSender {
     fn send(&self, event: BrokerEvent) {
-        self.sender.send(event).unwrap();
+        self.queued.fetch_add(1, Ordering::Relaxed);
+        // the component could have exited, the Kernel learns about it through `BrokerEvent::InternalServerExited`
+        if let Err(e) = self.sender.send(event) {
+            self.queued.fetch_sub(1, Ordering::Relaxed);
+            warn!("receiver is gone, {} dropped", e.0);
+        }
     }
     fn clone_boxed(&self) -> Box<dyn CerkSender + Send> {
         Box::new(ThreadingKernelSender {
             sender: self.sender.clone(),
+            queued: self.queued.clone(),
         })
     }
+    fn queue_length(&self) -> Option<usize> {
+        Some(self.queued.load(Ordering::Relaxed))
+    }
 }
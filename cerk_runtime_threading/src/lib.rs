@@ -21,6 +21,12 @@ A Scheduler implementation for CERK based on the `std::thread` model.
 
 `std::sync::mpsc` is used for the channels.
 
+## Shutdown
+
+The scheduler listens to SIGTERM and SIGINT and asks the Kernel to shut down the router gracefully.
+A second signal terminates the process immediately.
+`bootstrap` returns after the Kernel stopped and the threads of all components were joined.
+
 ## Examples
 
 * [Generator to MQTT](https://github.com/ce-rust/cerk/tree/master/examples/examples/src/mqtt/)
@@ -1,51 +1,145 @@
 use super::channel::{new_channel_kernel_to_component, new_channel_with_size};
+use anyhow::Result;
 use cerk::kernel::{BrokerEvent, KernelFn, StartOptions};
 use cerk::runtime::channel::BoxedSender;
 use cerk::runtime::{InternalServerFnRefStatic, InternalServerId, ScheduleFn, ScheduleFnRefStatic};
+use signal_hook::consts::{SIGINT, SIGTERM};
+use signal_hook::iterator::Signals;
+use std::panic::{self, AssertUnwindSafe};
 use std::thread;
+use std::thread::JoinHandle;
+use std::time::{Duration, SystemTime};
 
 const CHANNEL_TO_KERNEL_SIZE: usize = 50;
+const JOIN_TIMEOUT_MS: u64 = 1000;
 
+/// Starts the component in a new thread.
+/// When the start function returns or panics, `BrokerEvent::InternalServerExited` is sent to the Scheduler,
+/// which forwards it to the Kernel as long as the router is running.
 fn schedule(
     id: InternalServerId,
     internal_server_fn: InternalServerFnRefStatic,
     sender_to_kernel: &BoxedSender,
-) {
+    sender_to_scheduler: &BoxedSender,
+) -> JoinHandle<()> {
     debug!("schedule {} thread", id);
     let (sender_to_server, receiver_from_kernel) = new_channel_kernel_to_component();
     let server_sender_to_kernel = sender_to_kernel.clone_boxed();
+    let exit_sender = sender_to_scheduler.clone_boxed();
     let new_server_id = id.clone();
-    thread::spawn(move || {
-        internal_server_fn(new_server_id, receiver_from_kernel, server_sender_to_kernel);
+    let handle = thread::spawn(move || {
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            internal_server_fn(
+                new_server_id.clone(),
+                receiver_from_kernel,
+                server_sender_to_kernel,
+            )
+        }));
+        match result {
+            Ok(()) => debug!("{} thread returned", new_server_id),
+            Err(_) => error!("{} thread panicked", new_server_id),
+        }
+        exit_sender.send(BrokerEvent::InternalServerExited(new_server_id));
     });
     sender_to_kernel.send(BrokerEvent::InternalServerScheduled(
         id.clone(),
         sender_to_server,
     ));
+    handle
+}
+
+/// Forwards SIGTERM and SIGINT as `BrokerEvent::Shutdown` to the kernel.
+/// A second signal terminates the process immediately.
+fn listen_to_signals(mut signals: Signals, sender_to_kernel: BoxedSender) -> JoinHandle<()> {
+    thread::spawn(move || {
+        let mut shutdown_requested = false;
+        for signal in signals.forever() {
+            if shutdown_requested {
+                warn!("received signal {} again, exit immediately", signal);
+                std::process::exit(1);
+            }
+            info!("received signal {}, start graceful shutdown", signal);
+            shutdown_requested = true;
+            sender_to_kernel.send(BrokerEvent::Shutdown);
+        }
+    })
+}
+
+/// Joins the threads that stopped within `JOIN_TIMEOUT_MS`, the others are left behind.
+fn join_threads(threads: Vec<(InternalServerId, JoinHandle<()>)>) {
+    let deadline = SystemTime::now() + Duration::from_millis(JOIN_TIMEOUT_MS);
+    for (id, handle) in threads {
+        while !handle.is_finished() && SystemTime::now() < deadline {
+            thread::sleep(Duration::from_millis(10));
+        }
+        if !handle.is_finished() {
+            warn!("{} thread did not stop, it will not be joined", id);
+        } else if handle.join().is_err() {
+            warn!("{} thread panicked", id);
+        } else {
+            debug!("{} thread joined", id);
+        }
+    }
 }
 
 /// This is the main function to start the scheduler.
 ///
 /// This function gets invoked in the `bootstrap` function in the start phase of CERK.
-pub fn threading_scheduler_start(start_options: StartOptions, start_kernel: KernelFn) {
+///
+/// SIGTERM and SIGINT trigger a graceful shutdown of the router.
+/// The function returns the result of the Kernel after it stopped and all component threads were joined.
+pub fn threading_scheduler_start(
+    start_options: StartOptions,
+    start_kernel: KernelFn,
+) -> Result<()> {
     info!("start threading scheduler");
 
     let (sender_to_scheduler, receiver_from_kernel) = new_channel_kernel_to_component();
     let (sender_to_kernel, receiver_from_scheduler) = new_channel_with_size(CHANNEL_TO_KERNEL_SIZE);
+    let exit_sender = sender_to_scheduler.clone_boxed();
 
-    thread::spawn(move || {
-        start_kernel(start_options, receiver_from_scheduler, sender_to_scheduler);
+    let kernel = thread::spawn(move || {
+        start_kernel(start_options, receiver_from_scheduler, sender_to_scheduler)
     });
 
+    let signals = Signals::new([SIGTERM, SIGINT]).expect("failed to register signal handlers");
+    let signals_handle = signals.handle();
+    let signal_listener = listen_to_signals(signals, sender_to_kernel.clone_boxed());
+
+    let mut threads: Vec<(InternalServerId, JoinHandle<()>)> = Vec::new();
     loop {
         let event = receiver_from_kernel.receive();
         match event {
             BrokerEvent::ScheduleInternalServer(event) => {
-                schedule(event.id, event.function, &sender_to_kernel)
+                let handle = schedule(
+                    event.id.clone(),
+                    event.function,
+                    &sender_to_kernel,
+                    &exit_sender,
+                );
+                threads.retain(|(id, handle)| !(id.eq(&event.id) && handle.is_finished()));
+                threads.push((event.id, handle));
             }
+            BrokerEvent::InternalServerExited(id) => {
+                warn!("{} exited, notify kernel", id);
+                sender_to_kernel.send(BrokerEvent::InternalServerExited(id));
+            }
+            BrokerEvent::Ready => info!("all components are started"),
+            BrokerEvent::Shutdown => break,
             _ => warn!("Unknown event"),
         }
     }
+
+    info!("kernel stopped, join all threads");
+    signals_handle.close();
+    // the Kernel notified the Scheduler right before it returns
+    let result = kernel
+        .join()
+        .unwrap_or_else(|_| Err(anyhow::anyhow!("kernel thread panicked")));
+    threads.push((String::from("signal listener"), signal_listener));
+    join_threads(threads);
+    info!("threading scheduler stopped");
+    result
 }
 
 /// This is the pointer for the main function to start the scheduler.
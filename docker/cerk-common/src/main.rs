@@ -9,6 +9,7 @@ use cerk_port_health_check_http::PORT_HEALTH_CHECK_HTTP;
 use cerk_port_mqtt::PORT_MQTT;
 use cerk_port_mqtt_mosquitto::PORT_MQTT_MOSQUITTO;
 use cerk_port_unix_socket::{PORT_INPUT_UNIX_SOCKET, PORT_OUTPUT_UNIX_SOCKET};
+use cerk_processor_transform::PROCESSOR_TRANSFORM;
 use cerk_router_broadcast::ROUTER_BROADCAST;
 use cerk_router_rule_based::ROUTER_RULE_BASED;
 use cerk_runtime_threading::THREADING_SCHEDULER;
@@ -19,6 +20,7 @@ fn main() {
     start(ComponentStartLinks {
         schedulers: fn_to_links![THREADING_SCHEDULER],
         routers: fn_to_links![ROUTER_BROADCAST, ROUTER_RULE_BASED],
+        processors: fn_to_links![PROCESSOR_TRANSFORM],
         config_loaders: fn_to_links![CONFIG_LOADER_FILE],
         ports: fn_to_links![
             PORT_AMQP,
@@ -2,7 +2,10 @@
 extern crate log;
 use env_logger::Env;
 
-use cerk::kernel::{bootstrap, BrokerEvent, Config, ScheduleInternalServer, StartOptions};
+use cerk::kernel::{
+    bootstrap, BrokerEvent, Config, HealthCheckStatus, KernelOptions, ScheduleInternalServer,
+    StartOptions,
+};
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerId};
 use cerk_port_dummies::PORT_SEQUENCE_GENERATOR;
@@ -48,6 +51,17 @@ fn static_config_loader_start(
                     String::from(MQTT_OUTPUT),
                 ));
             }
+            BrokerEvent::Shutdown => {
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::ConfigApplied(_) => (),
+            BrokerEvent::ConfigRejected(component_id, reason) => {
+                error!("{} rejected its config: {}", component_id, reason)
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
@@ -59,6 +73,7 @@ fn main() {
     let start_options = StartOptions {
         scheduler: THREADING_SCHEDULER,
         router: ROUTER_BROADCAST,
+        processors: vec![],
         config_loader: &(static_config_loader_start as InternalServerFn),
         ports: vec![
             ScheduleInternalServer {
@@ -70,6 +85,7 @@ fn main() {
                 function: PORT_MQTT,
             },
         ],
+        kernel: KernelOptions::default(),
     };
     bootstrap(start_options);
 }
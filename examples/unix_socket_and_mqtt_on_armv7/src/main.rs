@@ -4,7 +4,10 @@ extern crate log;
 use env_logger::Env;
 use std::env;
 
-use cerk::kernel::{bootstrap, BrokerEvent, Config, ScheduleInternalServer, StartOptions};
+use cerk::kernel::{
+    bootstrap, BrokerEvent, CircuitBreakerOptions, Config, HealthCheckStatus, KernelOptions,
+    PortOptions, ScheduleInternalServer, SpoolOptions, SpoolOverflow, StartOptions,
+};
 use cerk::runtime::channel::{BoxedReceiver, BoxedSender};
 use cerk::runtime::{InternalServerFn, InternalServerId};
 use cerk_port_mqtt::PORT_MQTT;
@@ -13,6 +16,7 @@ use cerk_router_rule_based::{CloudEventFields, RoutingRules, RoutingTable, ROUTE
 use cerk_runtime_threading::THREADING_SCHEDULER;
 use std::collections::HashMap;
 use std::fs::remove_file;
+use std::path::PathBuf;
 
 const PORT_UNIX_INPUT: &str = "unix-json-input";
 const PORT_UNIX_OUTPUT: &str = "unix-json-output";
@@ -20,6 +24,8 @@ const PORT_MQTT_OUTPUT: &str = "mqtt-output";
 
 const SOCKET_PATH_IN: &str = "./cloud-events-in";
 const SOCKET_PATH_OUT: &str = "./cloud-events-out";
+const SPOOL_DIRECTORY: &str = "./spool";
+const SPOOL_MAX_BYTES: u64 = 64 * 1024 * 1024;
 
 fn static_config_loader_start(
     id: InternalServerId,
@@ -79,11 +85,43 @@ fn static_config_loader_start(
                     ),
                 ]));
             }
+            BrokerEvent::Shutdown => {
+                sender_to_kernel.send(BrokerEvent::ShutdownComplete(id));
+                return;
+            }
+            BrokerEvent::ConfigApplied(_) => (),
+            BrokerEvent::ConfigRejected(component_id, reason) => {
+                error!("{} rejected its config: {}", component_id, reason)
+            }
+            BrokerEvent::HealthCheckRequest(event) => {
+                sender_to_kernel.send(event.respond(HealthCheckStatus::Unsupported))
+            }
             broker_event => warn!("event {} not implemented", broker_event),
         }
     }
 }
 
+/// Spools the CloudEvents for MQTT while the uplink is down, the oldest ones are dropped if the spool is full.
+fn kernel_options() -> KernelOptions {
+    let mut options = KernelOptions {
+        circuit_breaker: Some(CircuitBreakerOptions::default()),
+        ..KernelOptions::default()
+    };
+    options.ports.insert(
+        String::from(PORT_MQTT_OUTPUT),
+        PortOptions {
+            spool: Some(SpoolOptions {
+                directory: PathBuf::from(SPOOL_DIRECTORY),
+                max_bytes: SPOOL_MAX_BYTES,
+                overflow: SpoolOverflow::DropOldest,
+                sync: false,
+            }),
+            ..PortOptions::default()
+        },
+    );
+    options
+}
+
 fn main() {
     env_logger::from_env(Env::default().default_filter_or("debug")).init();
     let _ = remove_file(SOCKET_PATH_IN);
@@ -93,6 +131,7 @@ fn main() {
     let start_options = StartOptions {
         scheduler: THREADING_SCHEDULER,
         router: ROUTER_RULE_BASED,
+        processors: vec![],
         config_loader: &(static_config_loader_start as InternalServerFn),
         ports: vec![
             ScheduleInternalServer {
@@ -108,6 +147,7 @@ fn main() {
                 function: PORT_MQTT,
             },
         ],
+        kernel: kernel_options(),
     };
     bootstrap(start_options);
 }
@@ -15,6 +15,7 @@ fn main() {
     start(ComponentStartLinks {
         schedulers: fn_to_links![THREADING_SCHEDULER],
         routers: fn_to_links![ROUTER_BROADCAST],
+        processors: fn_to_links![],
         config_loaders: fn_to_links![CONFIG_LOADER_FILE],
         ports: fn_to_links![PORT_MQTT_MOSQUITTO],
     });